@@ -2,7 +2,7 @@
 //! JWT / OIDC authentication, claim mapping, and session SQL generation.
 
 use crate::config::{AppConfig, AuthMode};
-use crate::error::Error;
+use crate::error::{Error, AUTH_REQUIRED};
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -293,7 +293,7 @@ pub async fn authenticate_async(
                     if config.anon_role.is_some() {
                         return Ok(None);
                     } else {
-                        return Err(Error::Unauthorized("Authentication required".to_string()));
+                        return Err(Error::Unauthorized(AUTH_REQUIRED.to_string()));
                     }
                 }
             };
@@ -332,7 +332,7 @@ fn authenticate_hs256(
             if config.anon_role.is_some() {
                 return Ok(None);
             } else {
-                return Err(Error::Unauthorized("Authentication required".to_string()));
+                return Err(Error::Unauthorized(AUTH_REQUIRED.to_string()));
             }
         }
     };
@@ -350,9 +350,8 @@ fn authenticate_hs256(
 
 // ─── Claim Mapping ──────────────────────────────────────────
 
-/// Resolve role from JWT claims using dot-notation path and role_map.
-pub fn resolve_role(claims: &Claims, config: &AppConfig) -> Option<String> {
-    // Build a combined JSON value of all claims
+/// All claims as one JSON object, for dot-notation lookups.
+fn claims_json(claims: &Claims) -> serde_json::Value {
     let mut all_claims = serde_json::Map::new();
     if let Some(ref role) = claims.role {
         all_claims.insert("role".to_string(), serde_json::Value::String(role.clone()));
@@ -363,8 +362,19 @@ pub fn resolve_role(claims: &Claims, config: &AppConfig) -> Option<String> {
     for (k, v) in &claims.extra {
         all_claims.insert(k.clone(), v.clone());
     }
+    serde_json::Value::Object(all_claims)
+}
 
-    let root = serde_json::Value::Object(all_claims);
+/// Look up a claim by dot-notation path (e.g. `org.tenant_id`).
+pub fn claim_value(claims: &Claims, path: &str) -> Option<serde_json::Value> {
+    navigate_claim(&claims_json(claims), path)
+        .filter(|v| !v.is_null())
+        .cloned()
+}
+
+/// Resolve role from JWT claims using dot-notation path and role_map.
+pub fn resolve_role(claims: &Claims, config: &AppConfig) -> Option<String> {
+    let root = claims_json(claims);
 
     // Navigate dot notation
     let value = navigate_claim(&root, &config.role_claim)?;
@@ -509,6 +519,132 @@ pub fn build_session_context_sql(claims: &Option<Claims>, config: &AppConfig) ->
     stmts
 }
 
+/// Maximum length (in characters) of a header value copied into session context.
+const MAX_CONTEXT_HEADER_LEN: usize = 256;
+
+/// Build SQL exposing allowlisted request headers as session context
+/// (`request.header.<name>`). Values are stripped of control characters and
+/// truncated to `MAX_CONTEXT_HEADER_LEN`. Headers the request doesn't send
+/// are set to NULL, so a pooled connection doesn't keep the previous
+/// request's value.
+pub fn build_header_context_sql(
+    headers: &axum::http::HeaderMap,
+    config: &AppConfig,
+) -> Vec<String> {
+    let mut stmts = Vec::new();
+    for name in &config.context_headers {
+        let safe_key = name.replace('\'', "''");
+        let Some(val) = headers.get(name.as_str()).and_then(|v| v.to_str().ok()) else {
+            stmts.push(format!(
+                "EXEC sp_set_session_context N'request.header.{}', NULL;",
+                safe_key
+            ));
+            continue;
+        };
+        let cleaned: String = val
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_CONTEXT_HEADER_LEN)
+            .collect();
+        let safe_val = cleaned.replace('\'', "''");
+        stmts.push(format!(
+            "EXEC sp_set_session_context N'request.header.{}', N'{}';",
+            safe_key, safe_val
+        ));
+    }
+    stmts
+}
+
+/// Build the full per-request session SQL: impersonation, claims, and headers.
+pub fn build_request_session_sql(
+    claims: &Option<Claims>,
+    headers: &axum::http::HeaderMap,
+    config: &AppConfig,
+) -> Vec<String> {
+    let mut stmts = build_session_context_sql(claims, config);
+    stmts.extend(build_header_context_sql(headers, config));
+    stmts
+}
+
+/// Build `SET LANGUAGE` / `SET DATEFORMAT` for a request. Each applies only
+/// when a default is configured, which every request then re-applies, so a
+/// client's `Prefer` override can't outlive its request on a pooled
+/// connection. Unknown overrides fall back to the default.
+pub fn build_locale_sql(
+    config: &AppConfig,
+    lang: Option<&str>,
+    dateformat: Option<&str>,
+) -> Vec<String> {
+    let mut stmts = Vec::new();
+    if let Some(default) = config.language.as_deref() {
+        if let Some(language) = lang
+            .and_then(sql_language)
+            .or_else(|| sql_language(default))
+        {
+            stmts.push(format!("SET LANGUAGE N'{}';", language));
+        }
+    }
+    // After SET LANGUAGE, which resets the date format to the language's own
+    if let Some(default) = config.dateformat.as_deref() {
+        let format = dateformat
+            .and_then(sql_dateformat)
+            .or_else(|| sql_dateformat(default));
+        if let Some(format) = format {
+            stmts.push(format!("SET DATEFORMAT {};", format));
+        }
+    }
+    stmts
+}
+
+/// SQL Server language for an ISO 639-1 code (`it`, `pt-br`) or a
+/// `sys.syslanguages` alias (`Italian`).
+pub fn sql_language(name: &str) -> Option<&'static str> {
+    let language = match name.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+        "en" | "en-us" | "english" | "us-english" => "us_english",
+        "en-gb" | "british" | "british english" => "British",
+        "de" | "german" => "German",
+        "fr" | "french" => "French",
+        "it" | "italian" => "Italian",
+        "es" | "spanish" => "Spanish",
+        "pt" | "pt-pt" | "portuguese" => "Portuguese",
+        "pt-br" | "brazilian" => "Brazilian",
+        "nl" | "dutch" => "Dutch",
+        "da" | "danish" => "Danish",
+        "sv" | "swedish" => "Swedish",
+        "nb" | "no" | "norwegian" => "Norwegian",
+        "fi" | "finnish" => "Finnish",
+        "cs" | "czech" => "Czech",
+        "hu" | "hungarian" => "Hungarian",
+        "pl" | "polish" => "Polish",
+        "ro" | "romanian" => "Romanian",
+        "hr" | "croatian" => "Croatian",
+        "sk" | "slovak" => "Slovak",
+        "sl" | "slovenian" => "Slovenian",
+        "el" | "greek" => "Greek",
+        "bg" | "bulgarian" => "Bulgarian",
+        "ru" | "russian" => "Russian",
+        "tr" | "turkish" => "Turkish",
+        "et" | "estonian" => "Estonian",
+        "lv" | "latvian" => "Latvian",
+        "lt" | "lithuanian" => "Lithuanian",
+        "ja" | "japanese" => "Japanese",
+        "ko" | "korean" => "Korean",
+        "zh" | "zh-cn" | "zh-hans" | "simplified chinese" => "Simplified Chinese",
+        "zh-tw" | "zh-hant" | "traditional chinese" => "Traditional Chinese",
+        "ar" | "arabic" => "Arabic",
+        "th" | "thai" => "Thai",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// A `SET DATEFORMAT` order (`mdy`, `dmy`, `ymd`, `ydm`, `myd`, `dym`).
+pub fn sql_dateformat(format: &str) -> Option<&'static str> {
+    ["mdy", "dmy", "ymd", "ydm", "myd", "dym"]
+        .into_iter()
+        .find(|f| f.eq_ignore_ascii_case(format.trim()))
+}
+
 /// Build REVERT SQL.
 pub fn build_revert_sql() -> &'static str {
     "IF EXISTS (SELECT 1 FROM sys.login_token WHERE usage = 'DENY ONLY') REVERT;"
@@ -0,0 +1,294 @@
+//! Brute-force protection: counts failed authentications per client address
+//! and per presented token, and bans offenders for a while with 429s.
+//!
+//! HTTP failures are read off responses: a 401 from a rejected credential
+//! (an `Authorization` header, a realtime `?token=` or a signed URL) counts,
+//! a 401 asking for one that wasn't sent doesn't. Tokens rejected by a
+//! realtime `auth` message never make a response, so the websocket handler
+//! reports them itself. Bans and failures are logged as structured events
+//! under the `lazypaw::security` target.
+
+use crate::config::AppConfig;
+use crate::error::{AuthFailure, Error};
+use crate::ipfilter::{self, IpNet};
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tracked clients before expired entries are swept.
+const SWEEP_AT: usize = 10_000;
+
+/// Who failed: a client address, or a token (by hash, never stored).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Offender {
+    Ip(IpAddr),
+    Token(u64),
+}
+
+impl std::fmt::Display for Offender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Offender::Ip(ip) => write!(f, "ip {}", ip),
+            Offender::Token(hash) => write!(f, "token {:016x}", hash),
+        }
+    }
+}
+
+/// The client address the guard saw, left on the request for handlers
+/// that report failures themselves.
+#[derive(Debug, Clone, Copy)]
+pub struct Client(pub IpAddr);
+
+#[derive(Debug)]
+struct Record {
+    window_start: Instant,
+    failures: u32,
+    banned_until: Option<Instant>,
+}
+
+pub struct AuthGuard {
+    max_failures: u32,
+    window: Duration,
+    ban: Duration,
+    trusted_proxies: Vec<IpNet>,
+    records: Mutex<HashMap<Offender, Record>>,
+}
+
+impl AuthGuard {
+    /// The guard from config; `None` when `auth_max_failures` is unset.
+    pub fn from_config(config: &AppConfig) -> Result<Option<Self>, String> {
+        let Some(max_failures) = config.auth_max_failures else {
+            return Ok(None);
+        };
+        Ok(Some(AuthGuard {
+            max_failures,
+            window: Duration::from_secs(config.auth_failure_window_secs.max(1)),
+            ban: Duration::from_secs(config.auth_ban_secs),
+            trusted_proxies: ipfilter::parse_list(&config.trusted_proxies)?,
+            records: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Time left on the longest active ban among `offenders`.
+    fn banned(&self, offenders: &[Offender], now: Instant) -> Option<Duration> {
+        let records = self.records.lock().unwrap();
+        offenders
+            .iter()
+            .filter_map(|o| records.get(o)?.banned_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Count a failure. Returns the offenders banned by it, with their
+    /// failure counts.
+    fn record_failure(&self, offenders: &[Offender], now: Instant) -> Vec<(Offender, u32)> {
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= SWEEP_AT {
+            let window = self.window;
+            records.retain(|_, r| {
+                r.banned_until.is_some_and(|until| until > now)
+                    || now.duration_since(r.window_start) < window
+            });
+        }
+        let mut banned = Vec::new();
+        for offender in offenders {
+            let record = records.entry(*offender).or_insert(Record {
+                window_start: now,
+                failures: 0,
+                banned_until: None,
+            });
+            if now.duration_since(record.window_start) >= self.window {
+                record.window_start = now;
+                record.failures = 0;
+            }
+            record.failures += 1;
+            if record.failures >= self.max_failures {
+                record.banned_until = Some(now + self.ban);
+                banned.push((*offender, record.failures));
+                record.window_start = now;
+                record.failures = 0;
+            }
+        }
+        banned
+    }
+
+    /// Count a failure and log it, with the bans it triggers. Returns
+    /// whether any offender was banned.
+    fn fail(&self, client: IpAddr, offenders: &[Offender], method: &str, path: &str) -> bool {
+        tracing::info!(
+            target: "lazypaw::security",
+            event = "auth_failure",
+            client = %client,
+            method = %method,
+            path = %path,
+            "Authentication failed"
+        );
+        let banned = self.record_failure(offenders, Instant::now());
+        for (offender, failures) in &banned {
+            tracing::warn!(
+                target: "lazypaw::security",
+                event = "auth_ban",
+                offender = %offender,
+                client = %client,
+                failures,
+                ban_secs = self.ban.as_secs(),
+                "Banned after repeated authentication failures"
+            );
+        }
+        !banned.is_empty()
+    }
+
+    /// Count a token rejected by a realtime `auth` message. Returns whether
+    /// the client is now banned, so the connection should close.
+    pub fn realtime_failure(&self, client: IpAddr, token: &str) -> bool {
+        let offenders = [
+            Offender::Ip(client),
+            Offender::Token(token_hash(token.as_bytes())),
+        ];
+        self.fail(client, &offenders, "WS", "/realtime")
+    }
+}
+
+fn token_hash(token: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The credential a request presents: its `Authorization` header, else a
+/// realtime `?token=` or a signed URL's `?sig=`.
+fn credential(request: &Request) -> Option<&[u8]> {
+    if let Some(header) = request.headers().get("authorization") {
+        return Some(header.as_bytes());
+    }
+    request.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        matches!(key, "token" | "sig").then_some(value.as_bytes())
+    })
+}
+
+/// Turns away banned clients with 429 and counts the auth failures of
+/// everyone else.
+pub async fn middleware(
+    State(guard): State<Arc<AuthGuard>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(peer) = ipfilter::peer_addr(&request) else {
+        return next.run(request).await;
+    };
+    let client = ipfilter::client_ip(
+        &guard.trusted_proxies,
+        peer.ip(),
+        ipfilter::forwarded_for(&request),
+    );
+    let mut offenders = vec![Offender::Ip(client)];
+    let presented = credential(&request).map(token_hash);
+    if let Some(hash) = presented {
+        offenders.push(Offender::Token(hash));
+    }
+
+    if let Some(remaining) = guard.banned(&offenders, Instant::now()) {
+        tracing::debug!(
+            target: "lazypaw::security",
+            event = "auth_banned_request",
+            client = %client,
+            "Request from banned client rejected"
+        );
+        let mut response =
+            Error::TooManyRequests("Too many failed authentication attempts".to_string())
+                .into_response();
+        // Round up so clients don't retry a moment too early
+        let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert("retry-after", HeaderValue::from(secs));
+        return response;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    request.extensions_mut().insert(Client(client));
+    let response = next.run(request).await;
+    // Only a credential that was sent can have been guessed
+    if presented.is_some() && response.extensions().get::<AuthFailure>().is_some() {
+        guard.fail(client, &offenders, method.as_str(), &path);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban_after_failures() {
+        let guard = AuthGuard {
+            max_failures: 3,
+            window: Duration::from_secs(60),
+            ban: Duration::from_secs(300),
+            trusted_proxies: Vec::new(),
+            records: Mutex::new(HashMap::new()),
+        };
+        let ip = Offender::Ip("192.0.2.1".parse().unwrap());
+        let other = Offender::Ip("192.0.2.2".parse().unwrap());
+        let start = Instant::now();
+
+        assert!(guard.record_failure(&[ip], start).is_empty());
+        // Failures outside the window start a new count
+        let later = start + Duration::from_secs(61);
+        assert!(guard.record_failure(&[ip], later).is_empty());
+        assert!(guard.record_failure(&[ip], later).is_empty());
+        assert_eq!(guard.record_failure(&[ip], later), vec![(ip, 3)]);
+
+        assert_eq!(
+            guard.banned(&[ip], later + Duration::from_secs(100)),
+            Some(Duration::from_secs(200))
+        );
+        assert_eq!(guard.banned(&[other], later), None);
+        assert_eq!(guard.banned(&[ip], later + Duration::from_secs(300)), None);
+    }
+
+    #[test]
+    fn test_credential() {
+        let request = |uri: &str, header: Option<&str>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some(header) = header {
+                builder = builder.header("authorization", header);
+            }
+            builder.body(axum::body::Body::empty()).unwrap()
+        };
+        let bearer = request("/orders", Some("Bearer abc"));
+        assert_eq!(credential(&bearer), Some(&b"Bearer abc"[..]));
+        let token = request("/realtime?vsn=1&token=abc", None);
+        assert_eq!(credential(&token), Some(&b"abc"[..]));
+        let signed = request("/orders?sig_exp=9&sig=xyz", None);
+        assert_eq!(credential(&signed), Some(&b"xyz"[..]));
+        assert_eq!(credential(&request("/admin/pool", None)), None);
+    }
+
+    #[test]
+    fn test_realtime_failure_bans() {
+        let guard = AuthGuard {
+            max_failures: 2,
+            window: Duration::from_secs(60),
+            ban: Duration::from_secs(300),
+            trusted_proxies: Vec::new(),
+            records: Mutex::new(HashMap::new()),
+        };
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(!guard.realtime_failure(client, "guess-1"));
+        assert!(guard.realtime_failure(client, "guess-2"));
+        assert!(guard
+            .banned(&[Offender::Ip(client)], Instant::now())
+            .is_some());
+    }
+}
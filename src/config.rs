@@ -3,13 +3,14 @@
 
 use clap::Parser;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 
 /// 😴 lazypaw — Instant REST API from your SQL Server database
 #[derive(Parser, Debug, Clone)]
 #[command(name = "lazypaw", version, about)]
 pub struct Args {
-    /// SQL Server hostname
+    /// SQL Server hostname; a comma-separated list of `host[:port]`
+    /// candidates rotates to the next one when connects fail
     #[arg(long, env = "LAZYPAW_SERVER", default_value = "localhost")]
     pub server: String,
 
@@ -37,6 +38,14 @@ pub struct Args {
     #[arg(long, env = "LAZYPAW_SCHEMA", default_value = "dbo")]
     pub schema: String,
 
+    /// Standby schema that `POST /admin/cutover` swaps with the default one
+    #[arg(long, env = "LAZYPAW_NEXT_SCHEMA")]
+    pub next_schema: Option<String>,
+
+    /// Database role allowed to call the `/admin` endpoints
+    #[arg(long, env = "LAZYPAW_ADMIN_ROLE")]
+    pub admin_role: Option<String>,
+
     /// JWT secret (HS256) for authentication
     #[arg(long, env = "LAZYPAW_JWT_SECRET")]
     pub jwt_secret: Option<String>,
@@ -81,6 +90,22 @@ pub struct Args {
     #[arg(long, env = "LAZYPAW_CONTEXT_CLAIMS")]
     pub context_claims: Option<String>,
 
+    /// Comma-separated request headers to inject as session context
+    #[arg(long, env = "LAZYPAW_CONTEXT_HEADERS")]
+    pub context_headers: Option<String>,
+
+    /// Comma-separated CIDR ranges allowed to call the API (default: all)
+    #[arg(long, env = "LAZYPAW_IP_ALLOW")]
+    pub ip_allow: Option<String>,
+
+    /// Comma-separated CIDR ranges rejected with 403, even if allowed
+    #[arg(long, env = "LAZYPAW_IP_DENY")]
+    pub ip_deny: Option<String>,
+
+    /// Comma-separated CIDR ranges of proxies whose X-Forwarded-For is trusted
+    #[arg(long, env = "LAZYPAW_TRUSTED_PROXIES")]
+    pub trusted_proxies: Option<String>,
+
     /// Database auth mode: "password", "managed-identity", "service-principal"
     #[arg(long, env = "LAZYPAW_DB_AUTH", default_value = "password")]
     pub db_auth: String,
@@ -121,6 +146,114 @@ pub struct Args {
     #[arg(long, env = "LAZYPAW_LOG_SLOW_QUERIES")]
     pub log_slow_queries: Option<u64>,
 
+    /// Error detail exposed to clients: "full", "safe", or "minimal"
+    #[arg(long, env = "LAZYPAW_ERROR_DETAIL")]
+    pub error_detail: Option<String>,
+
+    /// Comment tag on generated SQL: "off", "route", or "request" (adds a request ID)
+    #[arg(long, env = "LAZYPAW_SQL_TAG")]
+    pub sql_tag: Option<String>,
+
+    /// Ignore query params that don't name a column instead of rejecting them
+    #[arg(long, env = "LAZYPAW_LENIENT_FILTERS", default_value = "false")]
+    pub lenient_filters: bool,
+
+    /// Compare text filters (`eq`, `neq`, `in`, `like`, `match`)
+    /// case-insensitively, whatever the column collation
+    #[arg(
+        long,
+        env = "LAZYPAW_CASE_INSENSITIVE_FILTERS",
+        default_value = "false"
+    )]
+    pub case_insensitive_filters: bool,
+
+    /// Let SQL Server serialize JSON reads (`FOR JSON`) and pass the text
+    /// through, instead of converting rows in lazypaw
+    #[arg(long, env = "LAZYPAW_SERVER_JSON", default_value = "false")]
+    pub server_json: bool,
+
+    /// Session language for every request (`SET LANGUAGE`), e.g. "en" or "it";
+    /// enables `Prefer: lang=...` overrides
+    #[arg(long, env = "LAZYPAW_LANGUAGE")]
+    pub language: Option<String>,
+
+    /// Session date format for every request (`SET DATEFORMAT`), e.g. "ymd";
+    /// enables `Prefer: dateformat=...` overrides
+    #[arg(long, env = "LAZYPAW_DATEFORMAT")]
+    pub dateformat: Option<String>,
+
+    /// Readable replica for GETs (same credentials and database); writes and
+    /// RPC stay on --server
+    #[arg(long, env = "LAZYPAW_READ_SERVER")]
+    pub read_server: Option<String>,
+
+    /// How long a read with X-Consistency-Token waits for the replica to
+    /// catch up before falling back to the primary (ms)
+    #[arg(long, env = "LAZYPAW_CONSISTENCY_WAIT_MS", default_value = "200")]
+    pub consistency_wait_ms: u64,
+
+    /// Most rows a table GET returns, with or without `limit`; longer results
+    /// are cut short with a 206
+    #[arg(long, env = "LAZYPAW_MAX_ROWS")]
+    pub max_rows: Option<i64>,
+
+    /// Longest an RPC call may run before it's cancelled with a 504
+    /// (seconds); `[procedures.<name>]` can override it
+    #[arg(long, env = "LAZYPAW_RPC_TIMEOUT_SECS")]
+    pub rpc_timeout_secs: Option<u64>,
+
+    /// Most rows an RPC call may return before it fails;
+    /// `[procedures.<name>]` can override it
+    #[arg(long, env = "LAZYPAW_RPC_MAX_ROWS")]
+    pub rpc_max_rows: Option<i64>,
+
+    /// POST arrays of at least this many rows are bulk loaded (TDS bulk
+    /// copy) instead of INSERTed; unset or 0 disables it
+    #[arg(long, env = "LAZYPAW_BULK_INSERT_THRESHOLD")]
+    pub bulk_insert_threshold: Option<usize>,
+
+    /// Secret for signing temporary GET links (`POST /sign`); unset disables them
+    #[arg(long, env = "LAZYPAW_SIGNED_URL_SECRET")]
+    pub signed_url_secret: Option<String>,
+
+    /// Longest lifetime of a signed link (seconds)
+    #[arg(long, env = "LAZYPAW_SIGNED_URL_MAX_SECS", default_value = "3600")]
+    pub signed_url_max_secs: u64,
+
+    /// Database user to sample one anonymized row per table as, for OpenAPI
+    /// `example`s; unset leaves examples out (meant for development)
+    #[arg(long, env = "LAZYPAW_OPENAPI_EXAMPLES_ROLE")]
+    pub openapi_examples_role: Option<String>,
+
+    /// Failed authentications (401s) from one client address or token within
+    /// `--auth-failure-window-secs` before it is temporarily banned with 429s;
+    /// unset disables the guard
+    #[arg(long, env = "LAZYPAW_AUTH_MAX_FAILURES")]
+    pub auth_max_failures: Option<u32>,
+
+    /// Window over which authentication failures are counted (seconds)
+    #[arg(long, env = "LAZYPAW_AUTH_FAILURE_WINDOW_SECS", default_value = "60")]
+    pub auth_failure_window_secs: u64,
+
+    /// How long a client stays banned after too many failures (seconds)
+    #[arg(long, env = "LAZYPAW_AUTH_BAN_SECS", default_value = "300")]
+    pub auth_ban_secs: u64,
+
+    /// Longest the active database host goes without being re-resolved;
+    /// records with a shorter TTL are re-resolved when it expires. Idle
+    /// connections are retired when its addresses change (seconds, 0 to
+    /// disable)
+    #[arg(long, env = "LAZYPAW_DNS_REFRESH_SECS", default_value = "30")]
+    pub dns_refresh_secs: u64,
+
+    /// SQL run once on each new pooled connection (e.g. "SET ARITHABORT ON")
+    #[arg(long, env = "LAZYPAW_ON_CONNECT_SQL")]
+    pub on_connect_sql: Option<String>,
+
+    /// Include bound parameter values in query logs (debugging only)
+    #[arg(long, env = "LAZYPAW_LOG_PARAMS", default_value = "false")]
+    pub log_params: bool,
+
     /// Enable OpenTelemetry export
     #[arg(long, env = "LAZYPAW_OTEL_ENABLED", default_value = "false")]
     pub otel_enabled: bool,
@@ -136,6 +269,45 @@ pub struct Args {
     /// OpenTelemetry service name
     #[arg(long, env = "LAZYPAW_OTEL_SERVICE_NAME", default_value = "lazypaw")]
     pub otel_service_name: String,
+
+    /// Accept HTTP/2 cleartext (h2c) alongside HTTP/1.1
+    #[arg(long, env = "LAZYPAW_HTTP2", default_value = "false")]
+    pub http2: bool,
+
+    /// Seconds allowed to read an HTTP/1 request's headers
+    #[arg(long, env = "LAZYPAW_HEADER_READ_TIMEOUT")]
+    pub header_read_timeout: Option<u64>,
+
+    /// Seconds between HTTP/2 keep-alive pings, and to wait for each ping's
+    /// acknowledgement before closing the connection
+    #[arg(long, env = "LAZYPAW_HTTP2_KEEP_ALIVE_INTERVAL")]
+    pub http2_keep_alive_interval: Option<u64>,
+
+    /// Disable HTTP/1 keep-alive
+    #[arg(long, env = "LAZYPAW_NO_KEEP_ALIVE", default_value = "false")]
+    pub no_keep_alive: bool,
+
+    /// Max concurrent HTTP/2 streams per connection
+    #[arg(long, env = "LAZYPAW_HTTP2_MAX_STREAMS")]
+    pub http2_max_streams: Option<u32>,
+
+    /// Set TCP_NODELAY on accepted connections
+    #[arg(long, env = "LAZYPAW_TCP_NODELAY", default_value = "false")]
+    pub tcp_nodelay: bool,
+
+    /// Async worker threads handling requests (default: one per CPU core)
+    #[arg(long, env = "LAZYPAW_WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+
+    /// Most threads for blocking work such as DNS lookups and file reads
+    /// (default: 512)
+    #[arg(long, env = "LAZYPAW_MAX_BLOCKING_THREADS")]
+    pub max_blocking_threads: Option<usize>,
+
+    /// Run the Change Tracking poller and its sinks on a runtime of their
+    /// own with this many threads, apart from request handling
+    #[arg(long, env = "LAZYPAW_REALTIME_THREADS")]
+    pub realtime_threads: Option<usize>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -150,9 +322,10 @@ pub enum SubCommand {
         #[arg(long, default_value = "lazypaw_svc")]
         service_account: String,
     },
-    /// Generate typed client code from database schema
+    /// Generate typed client code, or an HTTP smoke test suite, from the
+    /// database schema
     Codegen {
-        /// Output language: typescript or python
+        /// Output language: typescript, python, or tests (JetBrains `.http`)
         #[arg(long)]
         lang: String,
 
@@ -160,6 +333,27 @@ pub enum SubCommand {
         #[arg(long)]
         output: String,
     },
+    /// Compare the live schema with a committed snapshot and exit non-zero
+    /// on drift
+    Diff {
+        /// Snapshot file (JSON)
+        #[arg(long)]
+        baseline: String,
+
+        /// Write the live schema to the snapshot file instead of comparing
+        #[arg(long, default_value = "false")]
+        write: bool,
+    },
+    /// Print an ER diagram of the tables and foreign keys
+    Graph {
+        /// Output format: mermaid or dot
+        #[arg(long, default_value = "mermaid")]
+        format: String,
+
+        /// Output file path (default: stdout)
+        #[arg(long)]
+        output: Option<String>,
+    },
     /// Initialize a new lazypaw project with config file
     Init {
         /// SQL Server hostname
@@ -206,6 +400,8 @@ pub struct FileConfig {
     pub database: Option<String>,
     pub listen_port: Option<u16>,
     pub schema: Option<String>,
+    pub next_schema: Option<String>,
+    pub admin_role: Option<String>,
     pub jwt_secret: Option<String>,
     pub anon_role: Option<String>,
     pub pool_size: Option<usize>,
@@ -213,6 +409,32 @@ pub struct FileConfig {
     pub schemas: Option<String>,
     pub auth: Option<FileAuthConfig>,
     pub db_config: Option<FileDatabaseConfig>,
+    pub tables: Option<HashMap<String, TableConfig>>,
+    pub columns: Option<HashMap<String, ColumnConfig>>,
+    pub context_headers: Option<Vec<String>>,
+    pub ip_allow: Option<Vec<String>>,
+    pub ip_deny: Option<Vec<String>>,
+    pub trusted_proxies: Option<Vec<String>>,
+    pub error_detail: Option<String>,
+    pub sql_tag: Option<String>,
+    pub on_connect_sql: Option<String>,
+    pub strict_filters: Option<bool>,
+    pub case_insensitive_filters: Option<bool>,
+    pub server_json: Option<bool>,
+    pub language: Option<String>,
+    pub dateformat: Option<String>,
+    pub read_server: Option<String>,
+    pub max_rows: Option<i64>,
+    pub rpc_timeout_secs: Option<u64>,
+    pub rpc_max_rows: Option<i64>,
+    pub bulk_insert_threshold: Option<usize>,
+    pub auth_max_failures: Option<u32>,
+    pub signed_url_secret: Option<String>,
+    pub openapi_examples_role: Option<String>,
+    pub procedures: Option<HashMap<String, ProcedureConfig>>,
+    pub filter_operators: Option<HashMap<String, String>>,
+    pub search_index: Option<SearchIndexConfig>,
+    pub redis_cache: Option<RedisCacheConfig>,
 }
 
 #[derive(Debug, Deserialize, Default, Clone)]
@@ -231,6 +453,241 @@ pub struct FileDatabaseConfig {
     pub auth: Option<String>,
 }
 
+/// Rows per record batch in Arrow IPC reads.
+pub const ARROW_BATCH_ROWS: usize = 65_536;
+
+/// Rows per record batch in Arrow IPC reads of `columnstore` tables: a full
+/// columnstore rowgroup.
+pub const COLUMNSTORE_ARROW_BATCH_ROWS: usize = 1_048_576;
+
+/// Per-table overrides, keyed by `schema.table` or bare table name.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct TableConfig {
+    /// Analytic (clustered columnstore) table: prefer batch-mode-friendly SQL
+    #[serde(default)]
+    pub columnstore: bool,
+    /// Optional MAXDOP hint appended to reads
+    pub maxdop: Option<u32>,
+    /// Rows per record batch in Arrow IPC reads
+    pub arrow_batch_rows: Option<usize>,
+    /// Columns forced on INSERT from the caller's JWT, e.g.
+    /// `tenant_id = "claims.tid"`. Client-sent values are overridden.
+    #[serde(default)]
+    pub inject: HashMap<String, String>,
+    /// Per-column rules checked on insert and update bodies
+    #[serde(default)]
+    pub validate: HashMap<String, ValidationRule>,
+    /// Column groups that must be sent together (all of them or none)
+    #[serde(default)]
+    pub required_together: Vec<Vec<String>>,
+    /// Columns `?q=` searches (every text column when empty)
+    #[serde(default)]
+    pub search: Vec<String>,
+    /// Extra response headers: a literal value, or an aggregate over the
+    /// rows a read matches, e.g. `X-Total-Amount = "sum(amount)"`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Scheduled for removal: `Deprecation` header and OpenAPI `deprecated`
+    #[serde(default)]
+    pub deprecated: bool,
+    /// Removal date, `YYYY-MM-DD` or an HTTP date, sent as `Sunset`
+    /// (implies `deprecated`)
+    pub sunset: Option<String>,
+    /// Origins allowed to call this table from a browser (any when empty)
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    /// `Cache-Control` for reads, e.g. `public, max-age=3600`
+    pub cache_control: Option<String>,
+    /// Allow explicit identity values on POST with `Prefer: identity=insert`
+    #[serde(default)]
+    pub identity_insert: bool,
+    /// Columns left out of the sampled OpenAPI example (`"*"` for all)
+    #[serde(default)]
+    pub example_exclude: Vec<String>,
+    /// Columns whose text filters ignore case, whatever their collation
+    #[serde(default)]
+    pub case_insensitive: Vec<String>,
+}
+
+impl TableConfig {
+    /// Rows per record batch in this table's Arrow IPC reads.
+    pub fn arrow_batch_rows(&self) -> usize {
+        match self.arrow_batch_rows {
+            Some(rows) if rows > 0 => rows,
+            _ if self.columnstore => COLUMNSTORE_ARROW_BATCH_ROWS,
+            _ => ARROW_BATCH_ROWS,
+        }
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated || self.sunset.is_some()
+    }
+
+    /// `sunset` as an HTTP date (midnight UTC for a bare date).
+    pub fn sunset_http_date(&self) -> Option<String> {
+        let sunset = self.sunset.as_deref()?.trim();
+        Some(
+            match chrono::NaiveDate::parse_from_str(sunset, "%Y-%m-%d") {
+                Ok(date) => date.format("%a, %d %b %Y 00:00:00 GMT").to_string(),
+                Err(_) => sunset.to_string(),
+            },
+        )
+    }
+}
+
+/// Key per-table overrides by lowercase name, so [`AppConfig::table_config`]
+/// can look them up directly. Of keys that differ only by case, the first in
+/// sort order is kept and the others are dropped with a warning.
+fn table_configs(tables: HashMap<String, TableConfig>) -> HashMap<String, TableConfig> {
+    let mut entries: Vec<(String, TableConfig)> = tables.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut configs = HashMap::with_capacity(entries.len());
+    for (key, config) in entries {
+        match configs.entry(key.to_lowercase()) {
+            Entry::Occupied(_) => {
+                tracing::warn!(
+                    "Ignoring [tables.\"{}\"]: another entry differs only by case",
+                    key
+                );
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(config);
+            }
+        }
+    }
+    configs
+}
+
+/// Per-procedure limits for `/rpc`, keyed by procedure name. Unset fields
+/// fall back to `--rpc-timeout-secs` and `--rpc-max-rows`.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ProcedureConfig {
+    /// Execution timeout in seconds
+    pub timeout_secs: Option<u64>,
+    /// Most rows the call may return
+    pub max_rows: Option<i64>,
+}
+
+/// Search engine kept in sync with tables through the change tracking
+/// poller (`[search_index]`).
+#[derive(Debug, Deserialize, Clone)]
+pub struct SearchIndexConfig {
+    pub kind: SearchEngineKind,
+    /// Base URL, e.g. `http://localhost:9200`
+    pub url: String,
+    /// Elasticsearch API key or Meilisearch key
+    pub api_key: Option<String>,
+    /// Indexed tables, keyed by `schema.table` or bare table name
+    #[serde(default)]
+    pub tables: HashMap<String, IndexedTable>,
+}
+
+/// Search engine behind `[search_index]`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchEngineKind {
+    Elasticsearch,
+    Meilisearch,
+}
+
+/// One table's documents in the search index.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct IndexedTable {
+    /// Index name (defaults to the table name)
+    pub index: Option<String>,
+    /// Columns copied into documents (every column when empty)
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+/// Redis mirror of small tables, one JSON value per row, kept current
+/// through the change tracking poller (`[redis_cache]`).
+#[derive(Debug, Deserialize, Clone)]
+pub struct RedisCacheConfig {
+    /// e.g. `redis://localhost:6379/0`
+    pub url: String,
+    /// Key prefix (default `lazypaw`); keys are `<prefix>:<schema.table>:<id>`
+    pub prefix: Option<String>,
+    /// Mirrored tables, keyed by `schema.table` or bare table name
+    #[serde(default)]
+    pub tables: HashMap<String, MirroredTable>,
+}
+
+/// One table's rows in Redis.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct MirroredTable {
+    /// Columns copied into values (every column when empty)
+    #[serde(default)]
+    pub columns: Vec<String>,
+}
+
+/// A declarative column check, for databases that can't take CHECK
+/// constraints.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ValidationRule {
+    /// Regex string values must match (invalid patterns fail config loading)
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub pattern: Option<regex::Regex>,
+    /// Inclusive numeric lower bound
+    pub min: Option<f64>,
+    /// Inclusive numeric upper bound
+    pub max: Option<f64>,
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<regex::Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    regex::Regex::new(&pattern)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Per-column serialization overrides, keyed by `schema.table.column` or
+/// `table.column`.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ColumnConfig {
+    /// `json`, `base64url`, `data_uri` or `string`
+    pub format: Option<String>,
+    /// MIME type for `data_uri` (default `application/octet-stream`)
+    pub mime: Option<String>,
+    /// Fixed decimal places for `string`
+    pub scale: Option<u32>,
+}
+
+/// How a configured column is rendered in JSON responses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnFormat {
+    /// Parse string content (e.g. nvarchar holding JSON) into JSON
+    Json,
+    /// Binary as unpadded base64url instead of standard base64
+    Base64Url,
+    /// Binary as a `data:<mime>;base64,...` URI
+    DataUri(String),
+    /// Numbers as strings, optionally with a fixed number of decimals
+    String(Option<u32>),
+}
+
+impl ColumnFormat {
+    fn from_config(key: &str, cfg: &ColumnConfig) -> Option<Self> {
+        match cfg.format.as_deref() {
+            Some("json") => Some(ColumnFormat::Json),
+            Some("base64url") => Some(ColumnFormat::Base64Url),
+            Some("data_uri") => Some(ColumnFormat::DataUri(
+                cfg.mime
+                    .clone()
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+            )),
+            Some("string") => Some(ColumnFormat::String(cfg.scale)),
+            other => {
+                tracing::warn!("Ignoring unknown format {:?} for column {}", other, key);
+                None
+            }
+        }
+    }
+}
+
 /// Auth mode enumeration.
 #[derive(Debug, Clone, PartialEq)]
 pub enum AuthMode {
@@ -247,6 +704,29 @@ pub enum DbAuthMode {
     ServicePrincipal,
 }
 
+/// How much error detail reaches API clients.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ErrorDetail {
+    /// Raw error text (credentials scrubbed) — development only
+    Full,
+    /// Client-caused errors keep their message; server-side errors are generic
+    #[default]
+    Safe,
+    /// Generic message for every error
+    Minimal,
+}
+
+/// Comment tag prepended to generated SQL for Query Store / trace attribution.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SqlTag {
+    Off,
+    /// Route and method only: statement text stays stable, so plans are reused
+    #[default]
+    Route,
+    /// Also the request ID (`X-Request-Id` or generated); every batch is unique
+    Request,
+}
+
 /// Merged configuration.
 #[derive(Debug, Clone)]
 pub struct AppConfig {
@@ -257,6 +737,8 @@ pub struct AppConfig {
     pub database: Option<String>,
     pub listen_port: u16,
     pub default_schema: String,
+    pub next_schema: Option<String>,
+    pub admin_role: Option<String>,
     pub jwt_secret: Option<String>,
     pub anon_role: Option<String>,
     pub pool_size: usize,
@@ -267,6 +749,11 @@ pub struct AppConfig {
     pub oidc_audience: Option<String>,
     pub role_claim: String,
     pub context_claims: Vec<String>,
+    pub context_headers: Vec<String>,
+    /// Client address rules (CIDR or single address), see `ipfilter`
+    pub ip_allow: Vec<String>,
+    pub ip_deny: Vec<String>,
+    pub trusted_proxies: Vec<String>,
     pub role_map: HashMap<String, String>,
     pub db_auth: DbAuthMode,
     pub sp_tenant_id: Option<String>,
@@ -277,12 +764,124 @@ pub struct AppConfig {
     pub log_level: String,
     pub log_format: String,
     pub log_slow_queries: Option<u64>,
+    pub log_params: bool,
+    pub error_detail: ErrorDetail,
+    pub sql_tag: SqlTag,
+    pub on_connect_sql: Option<String>,
+    /// Reject unknown filter columns and values that don't fit the column type
+    pub strict_filters: bool,
+    /// Text filters ignore case on every column (see `TableConfig::case_insensitive`)
+    pub case_insensitive_filters: bool,
+    /// JSON reads serialized by SQL Server, see `query::into_json_array`
+    pub server_json: bool,
+    pub language: Option<String>,
+    pub dateformat: Option<String>,
+    pub read_server: Option<String>,
+    pub consistency_wait_ms: u64,
+    pub dns_refresh_secs: u64,
+    pub max_rows: Option<i64>,
+    pub rpc_timeout_secs: Option<u64>,
+    pub rpc_max_rows: Option<i64>,
+    pub bulk_insert_threshold: Option<usize>,
+    /// Auth failure guard, see `auth_guard`
+    pub auth_max_failures: Option<u32>,
+    pub auth_failure_window_secs: u64,
+    pub auth_ban_secs: u64,
+    /// Signed GET links, see `signed_url`
+    pub signed_url_secret: Option<String>,
+    pub signed_url_max_secs: u64,
+    /// Sample OpenAPI examples as this database user, see `openapi`
+    pub openapi_examples_role: Option<String>,
+    pub procedures: HashMap<String, ProcedureConfig>,
+    /// Extra filter operators: name → T-SQL template, see `filters::CustomOperator`
+    pub filter_operators: HashMap<String, String>,
+    pub search_index: Option<SearchIndexConfig>,
+    pub redis_cache: Option<RedisCacheConfig>,
     pub otel_enabled: bool,
     pub otel_endpoint: String,
     pub otel_service_name: String,
+    pub tables: HashMap<String, TableConfig>,
+    pub columns: HashMap<String, ColumnFormat>,
+    pub http2: bool,
+    pub header_read_timeout: Option<u64>,
+    pub http2_keep_alive_interval: Option<u64>,
+    pub keep_alive: bool,
+    pub http2_max_streams: Option<u32>,
+    pub tcp_nodelay: bool,
 }
 
 impl AppConfig {
+    /// Whether a schema may be served (`schemas` unset exposes all of them).
+    pub fn is_schema_exposed(&self, schema: &str) -> bool {
+        self.schemas
+            .as_ref()
+            .is_none_or(|exposed| exposed.iter().any(|s| s.eq_ignore_ascii_case(schema)))
+    }
+
+    /// Look up per-table overrides: `schema.table` first, then bare table name.
+    pub fn table_config(&self, schema: &str, table: &str) -> Option<&TableConfig> {
+        let qualified = format!("{}.{}", schema, table).to_lowercase();
+        self.tables
+            .get(&qualified)
+            .or_else(|| self.tables.get(&table.to_lowercase()))
+    }
+
+    /// Per-table config for a table endpoint path (`/<table>`,
+    /// `/<schema>/<table>`, `/<table>/<key>`), before the schema cache is
+    /// consulted. Unqualified tables are in `active_schema`, the one
+    /// serving them since the last cutover.
+    pub fn table_config_for_path(&self, path: &str, active_schema: &str) -> Option<&TableConfig> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        match segments.as_slice() {
+            [] => None,
+            [table] => self.table_config(active_schema, table),
+            [first, second, ..] => self
+                .is_schema_exposed(first)
+                .then(|| self.table_config(first, second))
+                .flatten()
+                .or_else(|| self.table_config(active_schema, first)),
+        }
+    }
+
+    /// Timeout and row cap for an RPC call: the procedure's own entry
+    /// first, then the global settings. Zero means no limit.
+    pub fn procedure_limits(&self, name: &str) -> (Option<u64>, Option<i64>) {
+        let own = self
+            .procedures
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v);
+        let timeout = own
+            .and_then(|p| p.timeout_secs)
+            .or(self.rpc_timeout_secs)
+            .filter(|secs| *secs > 0);
+        let max_rows = own
+            .and_then(|p| p.max_rows)
+            .or(self.rpc_max_rows)
+            .filter(|n| *n > 0);
+        (timeout, max_rows)
+    }
+
+    /// Serialization overrides for a table's columns, as (column, format).
+    /// `schema.table.column` keys win over `table.column`.
+    pub fn column_formats(&self, schema: &str, table: &str) -> Vec<(&str, &ColumnFormat)> {
+        let qualified = format!("{}.{}", schema, table);
+        let mut formats: Vec<(&str, &ColumnFormat)> = Vec::new();
+        for want in [qualified.as_str(), table] {
+            for (key, format) in &self.columns {
+                let Some((prefix, column)) = key.rsplit_once('.') else {
+                    continue;
+                };
+                if prefix.eq_ignore_ascii_case(want)
+                    && !formats.iter().any(|(c, _)| c.eq_ignore_ascii_case(column))
+                {
+                    formats.push((column, format));
+                }
+            }
+        }
+        formats
+    }
+
     /// Build config from CLI args, merging in TOML file if provided.
     pub fn from_args(args: Args) -> Self {
         let file_config = if let Some(ref path) = args.config {
@@ -343,7 +942,64 @@ impl AppConfig {
             file_auth.context_claims.unwrap_or_default()
         };
 
+        let context_headers: Vec<String> = if let Some(ref ch) = args.context_headers {
+            ch.split(',')
+                .map(|s| s.trim().to_ascii_lowercase())
+                .filter(|s| !s.is_empty())
+                .collect()
+        } else {
+            file_config
+                .context_headers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|s| s.to_ascii_lowercase())
+                .collect()
+        };
+
+        let ip_list = |arg: Option<String>, file: Option<Vec<String>>| -> Vec<String> {
+            match arg {
+                Some(list) => list
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+                None => file.unwrap_or_default(),
+            }
+        };
+        let ip_allow = ip_list(args.ip_allow, file_config.ip_allow);
+        let ip_deny = ip_list(args.ip_deny, file_config.ip_deny);
+        let trusted_proxies = ip_list(args.trusted_proxies, file_config.trusted_proxies);
+
+        let error_detail = match args
+            .error_detail
+            .clone()
+            .or(file_config.error_detail.clone())
+            .as_deref()
+        {
+            Some("full") => ErrorDetail::Full,
+            Some("minimal") => ErrorDetail::Minimal,
+            _ => ErrorDetail::Safe,
+        };
+
+        let sql_tag = match args
+            .sql_tag
+            .clone()
+            .or(file_config.sql_tag.clone())
+            .as_deref()
+        {
+            Some("off") => SqlTag::Off,
+            Some("request") => SqlTag::Request,
+            _ => SqlTag::Route,
+        };
+
         let role_map = file_auth.role_map.unwrap_or_default();
+        let tables = table_configs(file_config.tables.unwrap_or_default());
+        let columns = file_config
+            .columns
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(key, cfg)| ColumnFormat::from_config(key, cfg).map(|f| (key.clone(), f)))
+            .collect();
 
         // DB auth mode
         let db_auth_str = if args.db_auth != "password" {
@@ -404,6 +1060,8 @@ impl AppConfig {
             } else {
                 file_config.schema.unwrap_or(args.schema)
             },
+            next_schema: args.next_schema.or(file_config.next_schema),
+            admin_role: args.admin_role.or(file_config.admin_role),
             jwt_secret,
             anon_role,
             pool_size: if args.pool_size != 10 {
@@ -418,6 +1076,10 @@ impl AppConfig {
             oidc_audience,
             role_claim,
             context_claims,
+            context_headers,
+            ip_allow,
+            ip_deny,
+            trusted_proxies,
             role_map,
             db_auth,
             sp_tenant_id: args.sp_tenant_id,
@@ -428,9 +1090,58 @@ impl AppConfig {
             log_level: args.log_level,
             log_format: args.log_format,
             log_slow_queries: args.log_slow_queries,
+            log_params: args.log_params,
+            error_detail,
+            sql_tag,
+            on_connect_sql: args
+                .on_connect_sql
+                .or(file_config.on_connect_sql)
+                .filter(|sql| !sql.trim().is_empty()),
+            strict_filters: !args.lenient_filters && file_config.strict_filters.unwrap_or(true),
+            case_insensitive_filters: args.case_insensitive_filters
+                || file_config.case_insensitive_filters.unwrap_or(false),
+            server_json: args.server_json || file_config.server_json.unwrap_or(false),
+            language: args.language.or(file_config.language),
+            dateformat: args.dateformat.or(file_config.dateformat),
+            read_server: args.read_server.or(file_config.read_server),
+            consistency_wait_ms: args.consistency_wait_ms,
+            dns_refresh_secs: args.dns_refresh_secs,
+            max_rows: args.max_rows.or(file_config.max_rows).filter(|n| *n > 0),
+            rpc_timeout_secs: args.rpc_timeout_secs.or(file_config.rpc_timeout_secs),
+            rpc_max_rows: args.rpc_max_rows.or(file_config.rpc_max_rows),
+            bulk_insert_threshold: args
+                .bulk_insert_threshold
+                .or(file_config.bulk_insert_threshold)
+                .filter(|n| *n > 0),
+            auth_max_failures: args
+                .auth_max_failures
+                .or(file_config.auth_max_failures)
+                .filter(|n| *n > 0),
+            auth_failure_window_secs: args.auth_failure_window_secs,
+            auth_ban_secs: args.auth_ban_secs,
+            signed_url_secret: args
+                .signed_url_secret
+                .or(file_config.signed_url_secret)
+                .filter(|s| !s.is_empty()),
+            signed_url_max_secs: args.signed_url_max_secs,
+            openapi_examples_role: args
+                .openapi_examples_role
+                .or(file_config.openapi_examples_role),
+            procedures: file_config.procedures.unwrap_or_default(),
+            filter_operators: file_config.filter_operators.unwrap_or_default(),
+            search_index: file_config.search_index,
+            redis_cache: file_config.redis_cache,
             otel_enabled: args.otel_enabled,
             otel_endpoint: args.otel_endpoint,
             otel_service_name: args.otel_service_name,
+            tables,
+            columns,
+            http2: args.http2,
+            header_read_timeout: args.header_read_timeout,
+            http2_keep_alive_interval: args.http2_keep_alive_interval,
+            keep_alive: !args.no_keep_alive,
+            http2_max_streams: args.http2_max_streams,
+            tcp_nodelay: args.tcp_nodelay,
         }
     }
 }
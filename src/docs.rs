@@ -0,0 +1,258 @@
+//! Human-readable data catalog served at `/docs`.
+//!
+//! Rendered from the schema cache plus `MS_Description` extended properties:
+//! one section per table with its columns, relationships and example
+//! requests. HTML by default, Markdown for `Accept: text/markdown`.
+
+use crate::config::AppConfig;
+use crate::schema::{Dml, EmbedInfo, EmbedJoinType, SchemaCache, TableInfo};
+
+/// Everything shown for one table, independent of output format.
+struct TableDoc {
+    path: String,
+    title: String,
+    kind: &'static str,
+    description: Option<String>,
+    /// name, type, nullable, notes, description
+    columns: Vec<[String; 5]>,
+    relationships: Vec<String>,
+    examples: Vec<String>,
+}
+
+/// Render the catalog as a standalone HTML page.
+pub fn render_html(schema: &SchemaCache, config: &AppConfig) -> String {
+    let docs = collect(schema, config);
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\"/>\n\
+         <title>lazypaw data catalog</title>\n<style>\n\
+         body { font-family: sans-serif; max-width: 960px; margin: 2em auto; }\n\
+         table { border-collapse: collapse; width: 100%; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+         code, pre { background: #f4f4f4; }\n\
+         </style>\n</head>\n<body>\n<h1>Data catalog</h1>\n<ul>\n",
+    );
+    for doc in &docs {
+        out.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a> ({})</li>\n",
+            escape_html(&doc.title),
+            escape_html(&doc.title),
+            doc.kind
+        ));
+    }
+    out.push_str("</ul>\n");
+
+    for doc in &docs {
+        out.push_str(&format!(
+            "<h2 id=\"{}\">{}</h2>\n<p><code>{}</code> ({})</p>\n",
+            escape_html(&doc.title),
+            escape_html(&doc.title),
+            escape_html(&doc.path),
+            doc.kind
+        ));
+        if let Some(desc) = &doc.description {
+            out.push_str(&format!("<p>{}</p>\n", escape_html(desc)));
+        }
+        out.push_str(
+            "<table>\n<tr><th>Column</th><th>Type</th><th>Nullable</th>\
+             <th>Notes</th><th>Description</th></tr>\n",
+        );
+        for row in &doc.columns {
+            out.push_str("<tr>");
+            for cell in row {
+                out.push_str(&format!("<td>{}</td>", escape_html(cell)));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+        if !doc.relationships.is_empty() {
+            out.push_str("<h3>Relationships</h3>\n<ul>\n");
+            for rel in &doc.relationships {
+                out.push_str(&format!("<li>{}</li>\n", escape_html(rel)));
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("<h3>Examples</h3>\n<pre>");
+        out.push_str(&escape_html(&doc.examples.join("\n")));
+        out.push_str("</pre>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Render the catalog as Markdown.
+pub fn render_markdown(schema: &SchemaCache, config: &AppConfig) -> String {
+    let docs = collect(schema, config);
+    let mut out = String::from("# Data catalog\n");
+    for doc in &docs {
+        out.push_str(&format!(
+            "\n## {}\n\n`{}` ({})\n\n",
+            doc.title, doc.path, doc.kind
+        ));
+        if let Some(desc) = &doc.description {
+            out.push_str(&format!("{}\n\n", desc));
+        }
+        out.push_str("| Column | Type | Nullable | Notes | Description |\n");
+        out.push_str("|--------|------|----------|-------|-------------|\n");
+        for row in &doc.columns {
+            let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+        if !doc.relationships.is_empty() {
+            out.push_str("\n### Relationships\n\n");
+            for rel in &doc.relationships {
+                out.push_str(&format!("- {}\n", rel));
+            }
+        }
+        out.push_str(&format!(
+            "\n### Examples\n\n```bash\n{}\n```\n",
+            doc.examples.join("\n")
+        ));
+    }
+    out
+}
+
+/// Build per-table docs, sorted by schema and name.
+fn collect(schema: &SchemaCache, config: &AppConfig) -> Vec<TableDoc> {
+    let multi_schema = schema.has_multiple_schemas();
+    let mut tables: Vec<&TableInfo> = schema
+        .tables
+        .values()
+        .filter(|t| config.is_schema_exposed(&t.schema))
+        .collect();
+    tables.sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+
+    tables
+        .into_iter()
+        .map(|table| {
+            let default_schema = table.schema.eq_ignore_ascii_case(&config.default_schema);
+            let path = if !multi_schema || default_schema {
+                format!("/{}", table.name)
+            } else {
+                format!("/{}/{}", table.schema, table.name)
+            };
+            TableDoc {
+                title: format!("{}.{}", table.schema, table.name),
+                kind: if table.is_view { "view" } else { "table" },
+                description: table.description.clone(),
+                columns: column_rows(table),
+                relationships: relationships(schema, table, config),
+                examples: examples(schema, table, config, &path),
+                path,
+            }
+        })
+        .collect()
+}
+
+fn column_rows(table: &TableInfo) -> Vec<[String; 5]> {
+    table
+        .columns
+        .iter()
+        .map(|col| {
+            let mut notes = Vec::new();
+            if table
+                .primary_key
+                .iter()
+                .any(|pk| pk.eq_ignore_ascii_case(&col.name))
+            {
+                notes.push("primary key");
+            }
+            if let Some(reason) = col.read_only_reason() {
+                notes.push(reason);
+            }
+            if col.has_default {
+                notes.push("default");
+            }
+            if col.is_fulltext_indexed {
+                notes.push("full-text");
+            }
+            [
+                col.name.clone(),
+                col.type_decl(),
+                if col.is_nullable { "yes" } else { "no" }.to_string(),
+                notes.join(", "),
+                col.description.clone().unwrap_or_default(),
+            ]
+        })
+        .collect()
+}
+
+/// Embeddable relationships whose target schema is exposed.
+fn exposed_relationships(
+    schema: &SchemaCache,
+    table: &TableInfo,
+    config: &AppConfig,
+) -> Vec<(String, EmbedInfo)> {
+    schema
+        .embed_relationships(&table.schema, &table.name)
+        .into_iter()
+        .filter(|(_, info)| config.is_schema_exposed(&info.target_schema))
+        .collect()
+}
+
+fn relationships(schema: &SchemaCache, table: &TableInfo, config: &AppConfig) -> Vec<String> {
+    exposed_relationships(schema, table, config)
+        .into_iter()
+        .map(|(name, info)| {
+            let cardinality = match info.join_type {
+                EmbedJoinType::ManyToOne => "many-to-one",
+                EmbedJoinType::OneToMany => "one-to-many",
+            };
+            format!(
+                "({}) → {}.{} ({}) ({}, embed as `{}`)",
+                info.source_columns.join(", "),
+                info.target_schema,
+                info.target_table,
+                info.target_columns.join(", "),
+                cardinality,
+                name
+            )
+        })
+        .collect()
+}
+
+fn examples(
+    schema: &SchemaCache,
+    table: &TableInfo,
+    config: &AppConfig,
+    path: &str,
+) -> Vec<String> {
+    let mut examples = vec![format!("GET {}?limit=10", path)];
+    if let Some(col) = table.columns.first() {
+        examples.push(format!(
+            "GET {}?select={}&order={}.asc",
+            path, col.name, col.name
+        ));
+    }
+    let embed = exposed_relationships(schema, table, config)
+        .into_iter()
+        .next()
+        .map(|(name, _)| name);
+    if let Some(embed) = embed {
+        examples.push(format!("GET {}?select=*,{}(*)", path, embed));
+    }
+    if table.accepts(Dml::Insert) {
+        let body: Vec<String> = table
+            .insertable_columns()
+            .iter()
+            .take(3)
+            .map(|c| format!("\"{}\": …", c.name))
+            .collect();
+        examples.push(format!("POST {} {{{}}}", path, body.join(", ")));
+    }
+    if let Some(pk) = table.primary_key.first() {
+        if table.accepts(Dml::Update) {
+            examples.push(format!("PATCH {}?{}=eq.1 {{…}}", path, pk));
+        }
+        if table.accepts(Dml::Delete) {
+            examples.push(format!("DELETE {}?{}=eq.1", path, pk));
+        }
+    }
+    examples
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,220 @@
+//! Schema drift detection for `lazypaw diff`.
+//!
+//! A snapshot records what API clients depend on — the exposed tables, their
+//! column types and their foreign keys — as stable JSON meant to be committed
+//! next to the generated clients. Comparing it with the live schema lists
+//! every table, column and relationship added, removed or changed since.
+
+use crate::config::AppConfig;
+use crate::schema::{SchemaCache, TableInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The API-facing shape of the schema, keyed by `schema.table`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tables: BTreeMap<String, TableSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableSnapshot {
+    #[serde(default)]
+    pub view: bool,
+    /// Column name → type declaration, with ` null` for nullable columns
+    pub columns: BTreeMap<String, String>,
+    /// Foreign key name → `(columns) -> schema.table(columns)`
+    #[serde(default)]
+    pub relationships: BTreeMap<String, String>,
+}
+
+impl TableSnapshot {
+    fn from_table(table: &TableInfo) -> Self {
+        let columns = table
+            .columns
+            .iter()
+            .map(|c| {
+                let null = if c.is_nullable { " null" } else { "" };
+                (c.name.clone(), format!("{}{}", c.type_decl(), null))
+            })
+            .collect();
+        let relationships = table
+            .foreign_keys
+            .iter()
+            .map(|fk| {
+                let target = format!(
+                    "({}) -> {}.{}({})",
+                    fk.columns.join(", "),
+                    fk.ref_schema,
+                    fk.ref_table,
+                    fk.ref_columns.join(", ")
+                );
+                (fk.constraint_name.clone(), target)
+            })
+            .collect();
+        TableSnapshot {
+            view: table.is_view,
+            columns,
+            relationships,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Snapshot of the exposed schemas.
+    pub fn from_schema(schema: &SchemaCache, config: &AppConfig) -> Self {
+        let tables = schema
+            .tables
+            .values()
+            .filter(|t| config.is_schema_exposed(&t.schema))
+            .map(|t| {
+                (
+                    format!("{}.{}", t.schema, t.name),
+                    TableSnapshot::from_table(t),
+                )
+            })
+            .collect();
+        Snapshot { tables }
+    }
+}
+
+/// One difference between the baseline and the live schema.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed {
+        item: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(item) => write!(f, "+ {}", item),
+            Change::Removed(item) => write!(f, "- {}", item),
+            Change::Changed { item, from, to } => write!(f, "~ {}: {} → {}", item, from, to),
+        }
+    }
+}
+
+/// Changes from `baseline` to `live`, ordered by table.
+pub fn diff(baseline: &Snapshot, live: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut names: Vec<&String> = baseline.tables.keys().chain(live.tables.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (baseline.tables.get(name), live.tables.get(name)) {
+            (Some(_), None) => changes.push(Change::Removed(format!("table {}", name))),
+            (None, Some(_)) => changes.push(Change::Added(format!("table {}", name))),
+            (Some(old), Some(new)) => {
+                diff_entries(
+                    &format!("column {}", name),
+                    &old.columns,
+                    &new.columns,
+                    &mut changes,
+                );
+                diff_entries(
+                    &format!("relationship {}", name),
+                    &old.relationships,
+                    &new.relationships,
+                    &mut changes,
+                );
+            }
+            (None, None) => {}
+        }
+    }
+    changes
+}
+
+/// Added, removed and changed entries of one table's columns or
+/// relationships, labelled `<kind> <table>.<entry>`.
+fn diff_entries(
+    label: &str,
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+    changes: &mut Vec<Change>,
+) {
+    for (key, from) in old {
+        let item = format!("{}.{}", label, key);
+        match new.get(key) {
+            None => changes.push(Change::Removed(format!("{} {}", item, from))),
+            Some(to) if to != from => changes.push(Change::Changed {
+                item,
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, to) in new {
+        if !old.contains_key(key) {
+            changes.push(Change::Added(format!("{}.{} {}", label, key, to)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(columns: &[(&str, &str)], relationships: &[(&str, &str)]) -> TableSnapshot {
+        let map = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        TableSnapshot {
+            view: false,
+            columns: map(columns),
+            relationships: map(relationships),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let fk = ("FK_orders_users", "(user_id) -> dbo.users(id)");
+        let mut baseline = Snapshot::default();
+        baseline.tables.insert(
+            "dbo.orders".to_string(),
+            table(&[("id", "int"), ("total", "decimal(10,2)")], &[fk]),
+        );
+        baseline
+            .tables
+            .insert("dbo.audit".to_string(), table(&[("id", "int")], &[]));
+        let mut live = Snapshot::default();
+        live.tables.insert(
+            "dbo.orders".to_string(),
+            table(
+                &[
+                    ("id", "int"),
+                    ("total", "money null"),
+                    ("note", "nvarchar(200) null"),
+                ],
+                &[],
+            ),
+        );
+        live.tables
+            .insert("dbo.users".to_string(), table(&[("id", "int")], &[]));
+
+        let changes: Vec<String> = diff(&baseline, &live)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "- table dbo.audit",
+                "~ column dbo.orders.total: decimal(10,2) → money null",
+                "+ column dbo.orders.note nvarchar(200) null",
+                "- relationship dbo.orders.FK_orders_users (user_id) -> dbo.users(id)",
+                "+ table dbo.users",
+            ]
+        );
+        assert!(diff(&live, &live).is_empty());
+    }
+}
@@ -4,9 +4,20 @@
 //! Provides a PostgREST-compatible error format and maps SQL Server
 //! errors to appropriate HTTP status codes.
 
+use crate::config::ErrorDetail;
+use crate::redact;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::Serialize;
+use std::sync::OnceLock;
+
+/// Process-wide error detail level, set once at startup.
+static ERROR_DETAIL: OnceLock<ErrorDetail> = OnceLock::new();
+
+/// Configure how much error detail reaches clients.
+pub fn set_error_detail(level: ErrorDetail) {
+    let _ = ERROR_DETAIL.set(level);
+}
 
 /// PostgREST-compatible error body.
 #[derive(Debug, Serialize)]
@@ -28,6 +39,10 @@ pub enum Error {
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    /// A bad request with a suggestion for fixing it.
+    #[error("Bad request: {message}")]
+    BadRequestHint { message: String, hint: String },
+
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
 
@@ -48,13 +63,33 @@ pub enum Error {
 
     #[error("Single object expected but got {0} rows")]
     SingleObjectExpected(usize),
+
+    #[error("Validation failed: {}", .0.join("; "))]
+    Validation(Vec<String>),
+
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    /// `If-Match` named a version the row no longer has.
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// A conditional header the write needs was missing.
+    #[error("Precondition required: {0}")]
+    PreconditionRequired(String),
 }
 
 impl Error {
     pub fn status_code(&self) -> StatusCode {
         match self {
             Error::NotFound(_) => StatusCode::NOT_FOUND,
-            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::BadRequest(_) | Error::BadRequestHint { .. } => StatusCode::BAD_REQUEST,
             Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             Error::Forbidden(_) => StatusCode::FORBIDDEN,
             Error::Conflict(_) => StatusCode::CONFLICT,
@@ -62,13 +97,19 @@ impl Error {
             Error::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
             Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Error::SingleObjectExpected(_) => StatusCode::NOT_ACCEPTABLE,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Error::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
         }
     }
 
     pub fn code(&self) -> &str {
         match self {
             Error::NotFound(_) => "PGRST116",
-            Error::BadRequest(_) => "PGRST100",
+            Error::BadRequest(_) | Error::BadRequestHint { .. } => "PGRST100",
             Error::Unauthorized(_) => "PGRST301",
             Error::Forbidden(_) => "PGRST302",
             Error::Conflict(_) => "PGRST209",
@@ -76,10 +117,72 @@ impl Error {
             Error::Pool(_) => "PGRST503",
             Error::Internal(_) => "PGRST500",
             Error::SingleObjectExpected(_) => "PGRST116",
+            Error::Validation(_) => "PGRST102",
+            Error::MethodNotAllowed(_) => "PGRST105",
+            Error::Timeout(_) => "PGRST504",
+            Error::TooManyRequests(_) => "PGRST429",
+            Error::PreconditionFailed(_) => "PGRST412",
+            Error::PreconditionRequired(_) => "PGRST428",
         }
     }
 
-    pub fn to_api_error(&self) -> ApiError {
+    /// Whether the error describes a problem with the client's request,
+    /// as opposed to a database or server-side failure.
+    pub fn is_client_error(&self) -> bool {
+        !matches!(self, Error::Sql(_) | Error::Pool(_) | Error::Internal(_))
+    }
+
+    /// The same error with `context` in front of its message, e.g. which
+    /// operation of a batch failed. The status code is unchanged.
+    pub fn context(self, context: &str) -> Self {
+        let prefix = |msg: String| format!("{}: {}", context, msg);
+        match self {
+            Error::NotFound(msg) => Error::NotFound(prefix(msg)),
+            Error::BadRequest(msg) => Error::BadRequest(prefix(msg)),
+            Error::BadRequestHint { message, hint } => Error::BadRequestHint {
+                message: prefix(message),
+                hint,
+            },
+            Error::Unauthorized(msg) => Error::Unauthorized(prefix(msg)),
+            Error::Forbidden(msg) => Error::Forbidden(prefix(msg)),
+            Error::Conflict(msg) => Error::Conflict(prefix(msg)),
+            Error::Sql(msg) => Error::Sql(prefix(msg)),
+            Error::Pool(msg) => Error::Pool(prefix(msg)),
+            Error::Internal(msg) => Error::Internal(prefix(msg)),
+            Error::Validation(problems) => {
+                Error::Validation(problems.into_iter().map(prefix).collect())
+            }
+            Error::MethodNotAllowed(msg) => Error::MethodNotAllowed(prefix(msg)),
+            Error::Timeout(msg) => Error::Timeout(prefix(msg)),
+            Error::TooManyRequests(msg) => Error::TooManyRequests(prefix(msg)),
+            Error::PreconditionFailed(msg) => Error::PreconditionFailed(prefix(msg)),
+            Error::PreconditionRequired(msg) => Error::PreconditionRequired(prefix(msg)),
+            Error::SingleObjectExpected(_) => self,
+        }
+    }
+
+    /// The error text without the variant prefix.
+    fn detail_message(&self) -> String {
+        match self {
+            Error::NotFound(msg)
+            | Error::BadRequest(msg)
+            | Error::Unauthorized(msg)
+            | Error::Forbidden(msg)
+            | Error::Conflict(msg)
+            | Error::Sql(msg)
+            | Error::Pool(msg)
+            | Error::Internal(msg)
+            | Error::MethodNotAllowed(msg)
+            | Error::Timeout(msg)
+            | Error::TooManyRequests(msg)
+            | Error::PreconditionFailed(msg)
+            | Error::PreconditionRequired(msg)
+            | Error::BadRequestHint { message: msg, .. } => msg.clone(),
+            Error::SingleObjectExpected(_) | Error::Validation(_) => self.to_string(),
+        }
+    }
+
+    pub fn to_api_error(&self, level: ErrorDetail, correlation_id: &str) -> ApiError {
         let sanitized_message = match self.status_code() {
             StatusCode::BAD_REQUEST => "Bad request",
             StatusCode::UNAUTHORIZED => "Unauthorized",
@@ -87,32 +190,88 @@ impl Error {
             StatusCode::NOT_FOUND => "Not found",
             StatusCode::NOT_ACCEPTABLE => "Not acceptable",
             StatusCode::CONFLICT => "Conflict",
+            StatusCode::METHOD_NOT_ALLOWED => "Method not allowed",
+            StatusCode::GATEWAY_TIMEOUT => "Timed out",
+            StatusCode::TOO_MANY_REQUESTS => "Too many requests",
+            StatusCode::PRECONDITION_FAILED => "Precondition failed",
+            StatusCode::PRECONDITION_REQUIRED => "Precondition required",
             _ => "Internal server error",
         };
+        let expose = match level {
+            ErrorDetail::Full => true,
+            ErrorDetail::Safe => self.is_client_error(),
+            ErrorDetail::Minimal => false,
+        };
+        let message = if expose {
+            redact::scrub(&self.detail_message())
+        } else {
+            sanitized_message.to_string()
+        };
+        // Per-field validation problems are about the client's own input,
+        // so they are safe to echo back.
+        let details = match self {
+            Error::Validation(problems) => Some(redact::scrub(&problems.join("; "))),
+            Error::MethodNotAllowed(msg) => Some(redact::scrub(msg)),
+            _ => None,
+        };
+        // When the real cause is withheld, hand out the ID it was logged under.
+        let hint = match self {
+            Error::BadRequestHint { hint, .. } => Some(hint.clone()),
+            _ => (!expose || !self.is_client_error())
+                .then(|| format!("Correlation ID: {}", correlation_id)),
+        };
         ApiError {
             code: self.code().to_string(),
-            message: sanitized_message.to_string(),
-            details: None,
-            hint: None,
+            message,
+            details,
+            hint,
         }
     }
 }
 
+/// Message of the 401 for a request that sent no credential where one is
+/// needed. It isn't an auth failure: nothing was guessed.
+pub const AUTH_REQUIRED: &str = "Authentication required";
+
+/// Response extension marking a rejected credential, as opposed to other
+/// 401s (e.g. the service's own database login failing, or no credential
+/// sent at all).
+#[derive(Debug, Clone, Copy)]
+pub struct AuthFailure;
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let status = self.status_code();
-        // Log the full error details server-side
-        tracing::error!("HTTP {} — {}", status.as_u16(), self);
-        let body = serde_json::to_string(&self.to_api_error()).unwrap_or_default();
-        (
+        let correlation_id = uuid::Uuid::new_v4().to_string();
+        // Log the full error details server-side, minus credentials
+        tracing::error!(
+            correlation_id = %correlation_id,
+            "HTTP {} — {}",
+            status.as_u16(),
+            redact::scrub(&self.to_string())
+        );
+        let level = ERROR_DETAIL.get().copied().unwrap_or_default();
+        let body =
+            serde_json::to_string(&self.to_api_error(level, &correlation_id)).unwrap_or_default();
+        let mut response = (
             status,
-            [(
-                axum::http::header::CONTENT_TYPE,
-                "application/json; charset=utf-8",
-            )],
+            [
+                (
+                    axum::http::header::CONTENT_TYPE,
+                    "application/json; charset=utf-8".to_string(),
+                ),
+                (
+                    axum::http::header::HeaderName::from_static("x-correlation-id"),
+                    correlation_id,
+                ),
+            ],
             body,
         )
-            .into_response()
+            .into_response();
+        if matches!(&self, Error::Unauthorized(msg) if msg != AUTH_REQUIRED) {
+            response.extensions_mut().insert(AuthFailure);
+        }
+        response
     }
 }
 
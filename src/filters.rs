@@ -1,9 +1,15 @@
 //! PostgREST-compatible filter parser.
 //!
 //! Parses query parameters like `?col=eq.value`, `?col=gt.5`,
-//! `?or=(col1.eq.a,col2.gt.5)` into a structured filter tree.
+//! `?col=like(any).{*a*,*b*}`, `?or=(col1.eq.a,col2.gt.5)` into a
+//! structured filter tree. Column names may be double-quoted
+//! (`?"Order Date"=gte.2024-01-01`, `?or=("Unit Price".gt.5,…)`).
 
 use crate::error::Error;
+use crate::select;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
 /// A single filter condition.
 #[derive(Debug, Clone)]
@@ -15,7 +21,7 @@ pub struct Filter {
 }
 
 /// Filter operators.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FilterOp {
     Eq,
     Gt,
@@ -27,7 +33,42 @@ pub enum FilterOp {
     Ilike,
     In,
     Is,
-    Fts, // full text search (basic)
+    /// `between.(low,high)`: inclusive range
+    Between,
+    /// `match`: PATINDEX pattern, case-sensitive
+    Match,
+    /// `imatch`: PATINDEX pattern, collation default (case-insensitive)
+    Imatch,
+    /// Case-insensitive equality: `eq` on a column configured to ignore case
+    Ieq,
+    /// `fts` / `fts(lang)`: CONTAINS
+    Fts(Option<String>),
+    /// `plfts` / `plfts(lang)`: FREETEXT (plain, inflectional matching)
+    Plfts(Option<String>),
+    /// `wfts` / `wfts(lang)`: CONTAINS with weighted ISABOUT terms
+    Wfts(Option<String>),
+    /// An operator from `[filter_operators]`, see `CustomOperator`
+    Custom(String),
+}
+
+impl FilterOp {
+    /// How a plain comparison (`eq`, `gt`, …) compares; `None` for other
+    /// operators.
+    pub fn comparison(&self) -> Option<&'static Comparison> {
+        OPERATORS
+            .iter()
+            .find(|b| b.op == *self)?
+            .comparison
+            .as_ref()
+    }
+
+    /// Whether this is a full-text predicate (requires a full-text index).
+    pub fn is_full_text(&self) -> bool {
+        matches!(
+            self,
+            FilterOp::Fts(_) | FilterOp::Plfts(_) | FilterOp::Wfts(_)
+        )
+    }
 }
 
 /// Filter value types.
@@ -35,6 +76,17 @@ pub enum FilterOp {
 pub enum FilterValue {
     Single(String),
     List(Vec<String>),
+    /// `op(any).{a,b}` / `op(all).{a,b}`: the operator applied to each value
+    Quantified(Quantifier, Vec<String>),
+}
+
+/// How a quantified filter combines its per-value conditions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantifier {
+    /// Match if any value matches (OR)
+    Any,
+    /// Match only if every value matches (AND)
+    All,
 }
 
 /// A group of filters combined with AND or OR.
@@ -43,102 +95,432 @@ pub enum FilterNode {
     Condition(Filter),
     And(Vec<FilterNode>),
     Or(Vec<FilterNode>),
+    /// `not.or(...)` / `not.and(...)`: a negated group
+    Not(Box<FilterNode>),
+    Exists(ExistsFilter),
+    Distinct(DistinctFilter),
+}
+
+/// Correlated `EXISTS` that keeps only parent rows with matching embedded
+/// rows (`!inner` embeds).
+#[derive(Debug, Clone)]
+pub struct ExistsFilter {
+    /// Fully qualified embedded table, e.g. `[dbo].[orders]`
+    pub table: String,
+    /// Alias for the embedded table inside the subquery, so the join of a
+    /// self-referencing embed compares embedded rows with the parent row
+    pub alias: String,
+    /// Join pairs: column on the embedded table and the fully qualified
+    /// parent column it matches, e.g. `("user_id", "[dbo].[users].[id]")`
+    pub join: Vec<(String, String)>,
+    /// Filters applied to the embedded rows
+    pub filters: Vec<FilterNode>,
+}
+
+/// Keeps the first row of each group (`?distinct_on=`): a correlated
+/// `EXISTS` against the table's rows ranked with `ROW_NUMBER()` per group.
+#[derive(Debug, Clone)]
+pub struct DistinctFilter {
+    /// Fully qualified table, e.g. `[dbo].[orders]`
+    pub table: String,
+    /// Columns the rows are grouped by
+    pub partition: Vec<String>,
+    /// `ORDER BY` list ranking the rows within a group
+    pub order: String,
+    /// Primary key columns matching the ranked rows back to the table
+    pub key: Vec<String>,
+    /// Filters applied before ranking
+    pub filters: Vec<FilterNode>,
 }
 
 /// Parse a PostgREST filter expression string (e.g., "eq.value", "in.(a,b,c)")
 /// into a Filter for the given column.
 pub fn parse_filter(column: &str, expr: &str) -> Result<Filter, Error> {
+    let column = &select::unquote_column(column);
     let (negated, rest) = if let Some(stripped) = expr.strip_prefix("not.") {
         (true, stripped)
     } else {
         (false, expr)
     };
 
-    // Parse operator and value
-    if let Some(value) = rest.strip_prefix("eq.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Eq,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("neq.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Neq,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("gt.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Gt,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("gte.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Gte,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("lt.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Lt,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("lte.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Lte,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("like.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Like,
-            value: FilterValue::Single(value.replace('*', "%")),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("ilike.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Ilike,
-            value: FilterValue::Single(value.replace('*', "%")),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("in.") {
-        let items = parse_list(value)?;
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::In,
-            value: FilterValue::List(items),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("is.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Is,
-            value: FilterValue::Single(value.to_string()),
-            negated,
-        })
-    } else if let Some(value) = rest.strip_prefix("fts.") {
-        Ok(Filter {
-            column: column.to_string(),
-            operator: FilterOp::Fts,
-            value: FilterValue::Single(value.to_string()),
-            negated,
+    // Quantified modifiers: eq(any).{a,b}, like(all).{*a*,*b*}
+    if let Some((op, values)) = rest.split_once('.') {
+        if let Some((base, quantifier)) = parse_quantifier(op) {
+            return parse_quantified(column, base, quantifier, values, negated);
+        }
+    }
+
+    // Full-text operators with optional language: fts(english).term
+    if let Some((op, value)) = rest.split_once('.') {
+        if let Some(operator) = parse_fts_op(op)? {
+            return Ok(Filter {
+                column: column.to_string(),
+                operator,
+                value: FilterValue::Single(value.to_string()),
+                negated,
+            });
+        }
+    }
+
+    let unknown = || Error::BadRequest(format!("Unknown filter expression: {}", expr));
+    let (op, value) = rest.split_once('.').ok_or_else(unknown)?;
+    let (operator, value) = if let Some(builtin) = OPERATORS.iter().find(|b| b.name == op) {
+        (builtin.op.clone(), builtin.shape.read(value)?)
+    } else if let Some(custom) = custom_operator(op) {
+        (FilterOp::Custom(custom.name.clone()), custom.read(value)?)
+    } else {
+        return Err(unknown());
+    };
+    Ok(Filter {
+        column: column.to_string(),
+        operator,
+        value,
+        negated,
+    })
+}
+
+/// A built-in operator: its name, how it reads its value, and for plain
+/// comparisons how it compares.
+struct Builtin {
+    name: &'static str,
+    op: FilterOp,
+    shape: ValueShape,
+    comparison: Option<Comparison>,
+}
+
+/// A plain comparison of a column with one value: the T-SQL operator, and
+/// the orderings of the column's value against the operand that match
+/// (realtime evaluates filters on changed rows in Rust).
+#[derive(Debug)]
+pub struct Comparison {
+    pub sql: &'static str,
+    pub matches: &'static [Ordering],
+}
+
+const fn builtin(name: &'static str, op: FilterOp, shape: ValueShape) -> Builtin {
+    Builtin {
+        name,
+        op,
+        shape,
+        comparison: None,
+    }
+}
+
+const fn comparison(
+    name: &'static str,
+    op: FilterOp,
+    sql: &'static str,
+    matches: &'static [Ordering],
+) -> Builtin {
+    Builtin {
+        name,
+        op,
+        shape: ValueShape::Single,
+        comparison: Some(Comparison { sql, matches }),
+    }
+}
+
+/// Built-in operators by name. Full-text operators take a language
+/// qualifier and go through `parse_fts_op`; config-defined ones through
+/// `custom_operator`. A comparison needs only its row here.
+const OPERATORS: &[Builtin] = &[
+    comparison("eq", FilterOp::Eq, "=", &[Ordering::Equal]),
+    comparison(
+        "neq",
+        FilterOp::Neq,
+        "<>",
+        &[Ordering::Less, Ordering::Greater],
+    ),
+    comparison("gt", FilterOp::Gt, ">", &[Ordering::Greater]),
+    comparison(
+        "gte",
+        FilterOp::Gte,
+        ">=",
+        &[Ordering::Greater, Ordering::Equal],
+    ),
+    comparison("lt", FilterOp::Lt, "<", &[Ordering::Less]),
+    comparison(
+        "lte",
+        FilterOp::Lte,
+        "<=",
+        &[Ordering::Less, Ordering::Equal],
+    ),
+    builtin("like", FilterOp::Like, ValueShape::Like),
+    builtin("ilike", FilterOp::Ilike, ValueShape::Like),
+    builtin("in", FilterOp::In, ValueShape::List),
+    builtin("match", FilterOp::Match, ValueShape::Pattern),
+    builtin("imatch", FilterOp::Imatch, ValueShape::Pattern),
+    builtin("between", FilterOp::Between, ValueShape::Range),
+    builtin("is", FilterOp::Is, ValueShape::Single),
+];
+
+/// How an operator reads the value after its name.
+#[derive(Debug, Clone, Copy)]
+enum ValueShape {
+    /// Taken as is
+    Single,
+    /// LIKE pattern, `*` as the `%` wildcard
+    Like,
+    /// PATINDEX pattern, see `patindex_pattern`
+    Pattern,
+    /// `(a,b,c)`
+    List,
+    /// `(low,high)`
+    Range,
+}
+
+impl ValueShape {
+    fn read(self, value: &str) -> Result<FilterValue, Error> {
+        Ok(match self {
+            ValueShape::Single => FilterValue::Single(value.to_string()),
+            ValueShape::Like => FilterValue::Single(value.replace('*', "%")),
+            ValueShape::Pattern => FilterValue::Single(patindex_pattern(value)),
+            ValueShape::List => FilterValue::List(parse_list(value)?),
+            ValueShape::Range => {
+                let items = parse_list(value)?;
+                if items.len() != 2 {
+                    return Err(Error::BadRequest(format!(
+                        "between expects exactly two values (low,high): {}",
+                        value
+                    )));
+                }
+                FilterValue::List(items)
+            }
         })
+    }
+}
+
+/// An operator defined in config (`[filter_operators]`): a T-SQL template
+/// with a `{column}` slot and positional value slots `{0}`, `{1}`, …
+#[derive(Debug, Clone)]
+pub struct CustomOperator {
+    pub name: String,
+    pub template: String,
+    /// Values the operator takes: one past the highest `{n}` slot
+    pub arity: usize,
+}
+
+/// Part of a custom operator template.
+enum Slot<'a> {
+    Text(&'a str),
+    Column,
+    Value(usize),
+}
+
+impl CustomOperator {
+    /// Read `near.(52.5,13.4,1000)` style values: a single value as is,
+    /// several as a list of exactly `arity` items.
+    fn read(&self, value: &str) -> Result<FilterValue, Error> {
+        if self.arity == 1 {
+            return Ok(FilterValue::Single(value.to_string()));
+        }
+        let items = if self.arity == 0 {
+            Vec::new()
+        } else {
+            parse_list(value)?
+        };
+        if items.len() != self.arity {
+            return Err(Error::BadRequest(format!(
+                "{} expects {} values: {}",
+                self.name, self.arity, value
+            )));
+        }
+        Ok(FilterValue::List(items))
+    }
+
+    /// Fill in the template with the column expression and one bind
+    /// placeholder per value.
+    pub fn render(&self, column: &str, placeholders: &[String]) -> String {
+        let mut sql = String::with_capacity(self.template.len());
+        // Validated when the operator was registered
+        for slot in template_slots(&self.template).unwrap_or_default() {
+            match slot {
+                Slot::Text(text) => sql.push_str(text),
+                Slot::Column => sql.push_str(column),
+                Slot::Value(n) => sql.push_str(placeholders.get(n).map_or("NULL", String::as_str)),
+            }
+        }
+        sql
+    }
+}
+
+/// Split a template into literal text, `{column}` and `{n}` slots.
+fn template_slots(template: &str) -> Result<Vec<Slot<'_>>, String> {
+    let mut slots = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            slots.push(Slot::Text(&rest[..open]));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| "unclosed '{'".to_string())?
+            + open;
+        let name = &rest[open + 1..close];
+        slots.push(match name {
+            "column" => Slot::Column,
+            _ => Slot::Value(
+                name.parse()
+                    .map_err(|_| format!("unknown slot {{{}}}", name))?,
+            ),
+        });
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        slots.push(Slot::Text(rest));
+    }
+    Ok(slots)
+}
+
+/// Operators from config, registered once at startup.
+static CUSTOM_OPERATORS: OnceLock<Vec<CustomOperator>> = OnceLock::new();
+
+/// Register the `[filter_operators]` from config: name → T-SQL template.
+/// Names must be new lowercase identifiers and templates must use
+/// `{column}`. Operators are registered once; a second call is an error.
+pub fn register_operators(defs: &HashMap<String, String>) -> Result<(), Error> {
+    let reserved = ["fts", "plfts", "wfts", "not", "or", "and"];
+    let mut operators = Vec::with_capacity(defs.len());
+    for (name, template) in defs {
+        let invalid = |reason: String| {
+            Error::BadRequest(format!("Invalid filter operator {}: {}", name, reason))
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(invalid("names use a-z, 0-9 and _".to_string()));
+        }
+        if OPERATORS.iter().any(|b| b.name == name.as_str()) || reserved.contains(&name.as_str()) {
+            return Err(invalid("name is taken by a built-in operator".to_string()));
+        }
+        let slots = template_slots(template).map_err(invalid)?;
+        if !slots.iter().any(|slot| matches!(slot, Slot::Column)) {
+            return Err(invalid("template has no {column} slot".to_string()));
+        }
+        let arity = slots
+            .iter()
+            .filter_map(|slot| match slot {
+                Slot::Value(n) => Some(n + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        operators.push(CustomOperator {
+            name: name.clone(),
+            template: template.clone(),
+            arity,
+        });
+    }
+    CUSTOM_OPERATORS
+        .set(operators)
+        .map_err(|_| Error::Internal("Filter operators are already registered".to_string()))
+}
+
+/// A config-defined operator by name.
+pub fn custom_operator(name: &str) -> Option<&'static CustomOperator> {
+    CUSTOM_OPERATORS.get()?.iter().find(|op| op.name == name)
+}
+
+/// Turn a `match`/`imatch` value into a PATINDEX pattern. Patterns use T-SQL
+/// wildcards (`%`, `_`, `[0-9]`, `[^a-z]`); like POSIX regex they match
+/// anywhere unless anchored with a leading `^` / trailing `$`.
+fn patindex_pattern(value: &str) -> String {
+    let (start, body) = match value.strip_prefix('^') {
+        Some(rest) => ("", rest),
+        None => ("%", value),
+    };
+    let (body, end) = match body.strip_suffix('$') {
+        Some(rest) => (rest, ""),
+        None => (body, "%"),
+    };
+    format!("{}{}{}", start, body, end)
+}
+
+/// Parse `fts`, `plfts`, `wfts`, each optionally with a `(language)` qualifier.
+fn parse_fts_op(op: &str) -> Result<Option<FilterOp>, Error> {
+    let (name, language) = match op.split_once('(') {
+        Some((name, rest)) => match rest.strip_suffix(')') {
+            Some(lang) => (name, Some(lang)),
+            None => return Ok(None),
+        },
+        None => (op, None),
+    };
+    let make: fn(Option<String>) -> FilterOp = match name {
+        "fts" => FilterOp::Fts,
+        "plfts" => FilterOp::Plfts,
+        "wfts" => FilterOp::Wfts,
+        _ => return Ok(None),
+    };
+    // Inlined as the LANGUAGE term: a language name or LCID
+    if let Some(lang) = language {
+        if lang.is_empty() || !lang.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::BadRequest(format!(
+                "Invalid full-text language: {}",
+                lang
+            )));
+        }
+    }
+    Ok(Some(make(language.map(str::to_string))))
+}
+
+/// Split `eq(any)` into its base operator and quantifier.
+fn parse_quantifier(op: &str) -> Option<(&str, Quantifier)> {
+    if let Some(base) = op.strip_suffix("(any)") {
+        Some((base, Quantifier::Any))
     } else {
-        Err(Error::BadRequest(format!(
-            "Unknown filter expression: {}",
-            expr
-        )))
+        op.strip_suffix("(all)").map(|base| (base, Quantifier::All))
+    }
+}
+
+/// Parse the `{a,b,c}` value list of a quantified filter, applying the base
+/// operator's own value handling (e.g. `*` → `%` for like) to each item.
+fn parse_quantified(
+    column: &str,
+    base: &str,
+    quantifier: Quantifier,
+    values: &str,
+    negated: bool,
+) -> Result<Filter, Error> {
+    if !matches!(
+        base,
+        "eq" | "neq" | "gt" | "gte" | "lt" | "lte" | "like" | "ilike"
+    ) {
+        return Err(Error::BadRequest(format!(
+            "Operator {} does not support any/all modifiers",
+            base
+        )));
+    }
+    let list = values
+        .trim()
+        .strip_prefix('{')
+        .and_then(|v| v.strip_suffix('}'))
+        .ok_or_else(|| Error::BadRequest(format!("Expected {{a,b,...}} value list: {}", values)))?;
+    let items = parse_list(list)?;
+    if items.is_empty() {
+        return Err(Error::BadRequest(format!(
+            "Empty value list for {} filter on {}",
+            base, column
+        )));
     }
+
+    let mut operator = FilterOp::Eq;
+    let mut parsed = Vec::with_capacity(items.len());
+    for item in &items {
+        let f = parse_filter(column, &format!("{}.{}", base, item))?;
+        if let FilterValue::Single(v) = f.value {
+            parsed.push(v);
+        }
+        operator = f.operator;
+    }
+
+    Ok(Filter {
+        column: column.to_string(),
+        operator,
+        value: FilterValue::Quantified(quantifier, parsed),
+        negated,
+    })
 }
 
 /// Parse a parenthesized list: "(a,b,c)" -> vec!["a", "b", "c"]
@@ -157,6 +539,41 @@ fn parse_list(s: &str) -> Result<Vec<String>, Error> {
         .collect())
 }
 
+/// Parse a top-level `or` / `and` / `not.or` / `not.and` query parameter
+/// into a group node. Returns `None` for any other key.
+pub fn parse_logic_param(key: &str, value: &str) -> Result<Option<FilterNode>, Error> {
+    let (negated, op) = match key.strip_prefix("not.") {
+        Some(rest) => (true, rest),
+        None => (false, key),
+    };
+    let node = match op {
+        "or" => FilterNode::Or(parse_logic_group(value)?),
+        "and" => FilterNode::And(parse_logic_group(value)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(negate_if(node, negated)))
+}
+
+/// `(…)` following `or` / `and` inside a group; anything else isn't a group.
+fn nested_group(
+    expr: &str,
+    make: fn(Vec<FilterNode>) -> FilterNode,
+) -> Result<Option<FilterNode>, Error> {
+    if expr.starts_with('(') && expr.ends_with(')') {
+        Ok(Some(make(parse_logic_group(expr)?)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn negate_if(node: FilterNode, negated: bool) -> FilterNode {
+    if negated {
+        FilterNode::Not(Box::new(node))
+    } else {
+        node
+    }
+}
+
 /// Parse an OR/AND group expression: "(col1.eq.a,col2.gt.5)"
 /// Supports nested and/or: "(status.eq.waiting,and(score.gt.50,name.like.*cat*))"
 pub fn parse_logic_group(expr: &str) -> Result<Vec<FilterNode>, Error> {
@@ -176,23 +593,24 @@ pub fn parse_logic_group(expr: &str) -> Result<Vec<FilterNode>, Error> {
         if part.is_empty() {
             continue;
         }
-        // Check for nested or(...) / and(...)
-        if let Some(inner_expr) = part.strip_prefix("or") {
-            if inner_expr.starts_with('(') && inner_expr.ends_with(')') {
-                let children = parse_logic_group(inner_expr)?;
-                nodes.push(FilterNode::Or(children));
-                continue;
-            }
-        }
-        if let Some(inner_expr) = part.strip_prefix("and") {
-            if inner_expr.starts_with('(') && inner_expr.ends_with(')') {
-                let children = parse_logic_group(inner_expr)?;
-                nodes.push(FilterNode::And(children));
-                continue;
-            }
+        // Check for nested or(...) / and(...), optionally negated: not.or(...)
+        let (negated, group) = match part.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, part),
+        };
+        let nested = if let Some(inner_expr) = group.strip_prefix("or") {
+            nested_group(inner_expr, FilterNode::Or)?
+        } else if let Some(inner_expr) = group.strip_prefix("and") {
+            nested_group(inner_expr, FilterNode::And)?
+        } else {
+            None
+        };
+        if let Some(node) = nested {
+            nodes.push(negate_if(node, negated));
+            continue;
         }
         // Find first dot that separates column from operator
-        if let Some(dot_pos) = part.find('.') {
+        if let Some(dot_pos) = select::find_unquoted(part, ".") {
             let col = &part[..dot_pos];
             let rest = &part[dot_pos + 1..];
             let filter = parse_filter(col, rest)?;
@@ -213,9 +631,17 @@ fn split_respecting_parens(s: &str) -> Vec<String> {
     let mut parts = Vec::new();
     let mut current = String::new();
     let mut depth = 0;
+    // A quoted column name at the start of a condition; quotes in values
+    // are left alone
+    let mut quoted = false;
 
     for ch in s.chars() {
         match ch {
+            '"' if quoted || current.trim_start().is_empty() => {
+                quoted = !quoted;
+                current.push(ch);
+            }
+            _ if quoted => current.push(ch),
             '(' => {
                 depth += 1;
                 current.push(ch);
@@ -252,6 +678,21 @@ mod tests {
         assert!(matches!(f.value, FilterValue::Single(ref v) if v == "alice"));
     }
 
+    #[test]
+    fn test_comparisons() {
+        let gte = parse_filter("age", "gte.18")
+            .unwrap()
+            .operator
+            .comparison()
+            .unwrap();
+        assert_eq!(gte.sql, ">=");
+        assert!(gte.matches.contains(&Ordering::Equal));
+        assert!(!gte.matches.contains(&Ordering::Less));
+        assert_eq!(FilterOp::Neq.comparison().unwrap().sql, "<>");
+        assert!(FilterOp::Like.comparison().is_none());
+        assert!(FilterOp::Custom("near".to_string()).comparison().is_none());
+    }
+
     #[test]
     fn test_parse_not_eq() {
         let f = parse_filter("name", "not.eq.alice").unwrap();
@@ -277,6 +718,31 @@ mod tests {
         assert!(matches!(f.value, FilterValue::Single(ref v) if v == "%alice%"));
     }
 
+    #[test]
+    fn test_parse_quantified() {
+        let f = parse_filter("name", "like(any).{*cat*,*dog*}").unwrap();
+        assert!(matches!(f.operator, FilterOp::Like));
+        match &f.value {
+            FilterValue::Quantified(Quantifier::Any, items) => {
+                assert_eq!(items, &["%cat%", "%dog%"])
+            }
+            _ => panic!("Expected quantified value"),
+        }
+        assert!(parse_filter("id", "in(any).{1,2}").is_err());
+    }
+
+    #[test]
+    fn test_parse_fts_language() {
+        let f = parse_filter("body", "plfts(english).fat cats").unwrap();
+        assert!(matches!(f.operator, FilterOp::Plfts(Some(ref l)) if l == "english"));
+        assert!(matches!(f.value, FilterValue::Single(ref v) if v == "fat cats"));
+        assert!(matches!(
+            parse_filter("body", "fts.cat").unwrap().operator,
+            FilterOp::Fts(None)
+        ));
+        assert!(parse_filter("body", "wfts(en');--).x").is_err());
+    }
+
     #[test]
     fn test_parse_is_null() {
         let f = parse_filter("deleted_at", "is.null").unwrap();
@@ -312,4 +778,41 @@ mod tests {
             _ => panic!("Expected And"),
         }
     }
+
+    #[test]
+    fn test_quoted_columns() {
+        let f = parse_filter(r#""Order Date""#, "gte.2024-01-01").unwrap();
+        assert_eq!(f.column, "Order Date");
+        let f = parse_filter(r#""Meta Data"->>tag"#, "eq.x").unwrap();
+        assert_eq!(f.column, "Meta Data->>tag");
+        let nodes =
+            parse_logic_group(r#"("a.b,c".eq.1,"Unit Price".not.gt.5,name.eq.5")"#).unwrap();
+        assert_eq!(nodes.len(), 3);
+        match &nodes[0] {
+            FilterNode::Condition(f) => assert_eq!(f.column, "a.b,c"),
+            _ => panic!("Expected Condition"),
+        }
+        match &nodes[2] {
+            FilterNode::Condition(f) => {
+                assert!(matches!(&f.value, FilterValue::Single(v) if v == "5\""))
+            }
+            _ => panic!("Expected Condition"),
+        }
+    }
+
+    #[test]
+    fn test_negated_logic_group() {
+        let nodes = parse_logic_group("(a.eq.1,not.and(b.eq.2,c.eq.3))").unwrap();
+        match &nodes[1] {
+            FilterNode::Not(inner) => {
+                assert!(matches!(**inner, FilterNode::And(ref c) if c.len() == 2))
+            }
+            _ => panic!("Expected Not"),
+        }
+        let top = parse_logic_param("not.or", "(a.eq.1,b.eq.2)").unwrap();
+        assert!(
+            matches!(top, Some(FilterNode::Not(ref inner)) if matches!(**inner, FilterNode::Or(_)))
+        );
+        assert!(parse_logic_param("status", "eq.1").unwrap().is_none());
+    }
 }
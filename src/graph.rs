@@ -0,0 +1,200 @@
+//! Entity-relationship diagram of the introspected schema.
+//!
+//! Served at `/schema/graph` and printed by `lazypaw graph`: one entity per
+//! exposed table or view and one edge per foreign key, as Mermaid
+//! (`erDiagram`) or Graphviz DOT.
+
+use crate::config::AppConfig;
+use crate::schema::{ForeignKey, SchemaCache, TableInfo};
+
+/// Output format for the diagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Mermaid,
+    Dot,
+}
+
+impl GraphFormat {
+    /// Parse a format name (`mermaid` or `dot`/`graphviz`).
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mermaid" | "mmd" => Some(GraphFormat::Mermaid),
+            "dot" | "graphviz" => Some(GraphFormat::Dot),
+            _ => None,
+        }
+    }
+
+    /// Content type for HTTP responses.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            GraphFormat::Mermaid => "text/vnd.mermaid; charset=utf-8",
+            GraphFormat::Dot => "text/vnd.graphviz; charset=utf-8",
+        }
+    }
+}
+
+/// Render the diagram in the given format.
+pub fn render(schema: &SchemaCache, config: &AppConfig, format: GraphFormat) -> String {
+    let tables = exposed_tables(schema, config);
+    match format {
+        GraphFormat::Mermaid => render_mermaid(&tables, config),
+        GraphFormat::Dot => render_dot(&tables, config),
+    }
+}
+
+/// Exposed tables sorted by schema and name.
+fn exposed_tables<'a>(schema: &'a SchemaCache, config: &AppConfig) -> Vec<&'a TableInfo> {
+    let mut tables: Vec<&TableInfo> = schema
+        .tables
+        .values()
+        .filter(|t| config.is_schema_exposed(&t.schema))
+        .collect();
+    tables.sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+    tables
+}
+
+/// Foreign keys whose referenced table is exposed too.
+fn exposed_fks<'a>(
+    table: &'a TableInfo,
+    config: &'a AppConfig,
+) -> impl Iterator<Item = &'a ForeignKey> {
+    table
+        .foreign_keys
+        .iter()
+        .filter(|fk| config.is_schema_exposed(&fk.ref_schema))
+}
+
+fn render_mermaid(tables: &[&TableInfo], config: &AppConfig) -> String {
+    let mut out = String::from("erDiagram\n");
+    for table in tables {
+        out.push_str(&format!(
+            "    {} {{\n",
+            mermaid_ident(&table.schema, &table.name)
+        ));
+        for col in &table.columns {
+            let mut keys = Vec::new();
+            if is_primary_key(table, &col.name) {
+                keys.push("PK");
+            }
+            if table
+                .foreign_keys
+                .iter()
+                .any(|fk| fk.columns.iter().any(|c| c.eq_ignore_ascii_case(&col.name)))
+            {
+                keys.push("FK");
+            }
+            out.push_str(&format!(
+                "        {} {} {}\n",
+                mermaid_word(&col.data_type),
+                mermaid_word(&col.name),
+                keys.join(",")
+            ));
+        }
+        out.push_str("    }\n");
+    }
+    for table in tables {
+        for fk in exposed_fks(table, config) {
+            // Many children to exactly one parent, or zero-or-one when the
+            // FK columns allow NULL
+            let parent_side = if fk_nullable(table, fk) { "o|" } else { "||" };
+            out.push_str(&format!(
+                "    {} }}o--{} {} : \"{}\"\n",
+                mermaid_ident(&table.schema, &table.name),
+                parent_side,
+                mermaid_ident(&fk.ref_schema, &fk.ref_table),
+                fk.columns.join(", ").replace('"', "'")
+            ));
+        }
+    }
+    out
+}
+
+fn render_dot(tables: &[&TableInfo], config: &AppConfig) -> String {
+    let mut out = String::from("digraph lazypaw {\n    rankdir=LR;\n    node [shape=record];\n");
+    for table in tables {
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|col| {
+                let pk = if is_primary_key(table, &col.name) {
+                    " (PK)"
+                } else {
+                    ""
+                };
+                format!(
+                    "{} : {}{}\\l",
+                    dot_record_escape(&col.name),
+                    dot_record_escape(&col.data_type),
+                    pk
+                )
+            })
+            .collect();
+        out.push_str(&format!(
+            "    \"{}\" [label=\"{{{}|{}}}\"];\n",
+            dot_escape(&format!("{}.{}", table.schema, table.name)),
+            dot_record_escape(&format!("{}.{}", table.schema, table.name)),
+            columns.join("")
+        ));
+    }
+    for table in tables {
+        for fk in exposed_fks(table, config) {
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{} → {}\"];\n",
+                dot_escape(&format!("{}.{}", table.schema, table.name)),
+                dot_escape(&format!("{}.{}", fk.ref_schema, fk.ref_table)),
+                dot_escape(&fk.columns.join(", ")),
+                dot_escape(&fk.ref_columns.join(", "))
+            ));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn is_primary_key(table: &TableInfo, column: &str) -> bool {
+    table
+        .primary_key
+        .iter()
+        .any(|pk| pk.eq_ignore_ascii_case(column))
+}
+
+/// Whether any of the FK's columns is nullable (the parent is optional).
+fn fk_nullable(table: &TableInfo, fk: &ForeignKey) -> bool {
+    fk.columns
+        .iter()
+        .any(|c| table.column(c).is_some_and(|col| col.is_nullable))
+}
+
+/// Mermaid entity name: `schema_table` with anything but letters, digits,
+/// `_` and `-` replaced.
+fn mermaid_ident(schema: &str, table: &str) -> String {
+    mermaid_word(&format!("{}_{}", schema, table))
+}
+
+fn mermaid_word(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escape the characters that structure a DOT record label.
+fn dot_record_escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in dot_escape(s).chars() {
+        if matches!(c, '{' | '}' | '|' | '<' | '>') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
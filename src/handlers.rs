@@ -1,15 +1,18 @@
 //! Request handlers for GET, POST, PATCH, DELETE, and RPC.
 
 use crate::auth;
-use crate::config::AppConfig;
-use crate::error::Error;
-use crate::filters::{self, FilterNode};
-use crate::pool::Pool;
-use crate::query::{self, escape_ident};
-use crate::response::{self, Preferences, ResponseFormat, ReturnMode, TxPreference};
-use crate::schema::SchemaCache;
+use crate::config::{AppConfig, SqlTag, ARROW_BATCH_ROWS};
+use crate::error::{Error, AUTH_REQUIRED};
+use crate::filters::{self, FilterNode, FilterOp, FilterValue};
+use crate::pool::{Pool, PooledConnection};
+use crate::query::{self, escape_ident, SqlParam};
+use crate::redact;
+use crate::response::{self, CountMode, Preferences, ResponseFormat, ReturnMode, TxPreference};
+use crate::schema::{Dml, EmbedLookupError, SchemaCache};
 use crate::select::{self, EmbedSelect, SelectNode};
+use crate::signed_url;
 use crate::types;
+use crate::validate;
 use axum::body::Bytes;
 use axum::extract::{Path, Query as AxumQuery, State};
 use axum::http::{HeaderMap, StatusCode};
@@ -18,14 +21,284 @@ use claw::{RowWriter, SqlValue};
 use serde_json::Value as JsonValue;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, MutexGuard, RwLock};
 
 /// Shared application state.
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Arc<Pool>,
+    /// Readable replica for GETs, when `read_server` is configured
+    pub read_pool: Option<Arc<Pool>>,
     pub schema: Arc<RwLock<SchemaCache>>,
     pub config: AppConfig,
+    pub profiles: Arc<std::sync::RwLock<SchemaProfiles>>,
+    /// Change Tracking poller, when `--realtime` is on
+    pub realtime: Option<Arc<crate::realtime::RealtimeEngine>>,
+    /// Exports kept so a resumed download is sliced, not queried again
+    pub exports: Arc<response::ExportCache>,
+}
+
+/// Which schema serves unqualified `/<table>` paths, plus the standby one
+/// `POST /admin/cutover` switches to.
+#[derive(Debug, Clone)]
+pub struct SchemaProfiles {
+    pub active: String,
+    pub next: Option<String>,
+}
+
+impl SchemaProfiles {
+    pub fn from_config(config: &AppConfig) -> Self {
+        SchemaProfiles {
+            active: config.default_schema.clone(),
+            next: config.next_schema.clone(),
+        }
+    }
+}
+
+/// A request's query string. It reads like a map, where a repeated key's
+/// last value wins; filters go through every pair instead, so repeated
+/// filters on one column (`?age=gte.18&age=lt.65`) all apply.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(from = "Vec<(String, String)>")]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+    map: HashMap<String, String>,
+}
+
+impl QueryParams {
+    /// Every parameter in request order, repeats included.
+    pub fn pairs(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.pairs.iter().map(|(key, value)| (key, value))
+    }
+}
+
+impl From<Vec<(String, String)>> for QueryParams {
+    fn from(pairs: Vec<(String, String)>) -> Self {
+        let map = pairs.iter().cloned().collect();
+        QueryParams { pairs, map }
+    }
+}
+
+impl std::ops::Deref for QueryParams {
+    type Target = HashMap<String, String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl AppState {
+    /// The schema currently serving unqualified paths.
+    pub fn active_schema(&self) -> String {
+        self.profiles.read().unwrap().active.clone()
+    }
+
+    /// The config with `default_schema` set to the active schema, for
+    /// generated documents that print table paths.
+    pub fn serving_config(&self) -> AppConfig {
+        let mut config = self.config.clone();
+        config.default_schema = self.active_schema();
+        config
+    }
+}
+
+/// Per-request SQL context: session setup statements plus the comment tag
+/// that attributes generated statements to the API route.
+struct SqlContext {
+    session: Vec<String>,
+    tag: String,
+    /// Connection pinned for a snapshot transaction spanning all reads
+    pinned: Option<Mutex<PooledConnection>>,
+    /// Replica the reads go to instead of the primary
+    read_pool: Option<Arc<Pool>>,
+    /// Role the request runs as, shown against its connection in `/admin/pool`
+    role: Option<String>,
+}
+
+/// Either the request's pinned snapshot connection or a fresh pooled one.
+enum ConnGuard<'a> {
+    Pinned(MutexGuard<'a, PooledConnection>),
+    Owned(PooledConnection),
+}
+
+/// A connection whose session state (an open transaction, `EXECUTE AS`,
+/// a `SET` option) was changed by a plain batch. It's closed on drop,
+/// including when the request future is cancelled, unless `release` was
+/// called after that state was reset.
+struct SessionConnection<'a> {
+    conn: ConnGuard<'a>,
+    released: bool,
+}
+
+impl<'a> SessionConnection<'a> {
+    fn new(conn: impl Into<ConnGuard<'a>>) -> Self {
+        SessionConnection {
+            conn: conn.into(),
+            released: false,
+        }
+    }
+
+    fn client(&mut self) -> &mut claw::TcpClient {
+        self.conn.client()
+    }
+
+    /// The session is back to its pooled state; return it to the pool.
+    fn release(&mut self) {
+        self.released = true;
+    }
+}
+
+impl Drop for SessionConnection<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.conn.discard();
+        }
+    }
+}
+
+impl ConnGuard<'_> {
+    fn client(&mut self) -> &mut claw::TcpClient {
+        match self {
+            ConnGuard::Pinned(conn) => conn.client(),
+            ConnGuard::Owned(conn) => conn.client(),
+        }
+    }
+
+    fn discard(&self) {
+        match self {
+            ConnGuard::Pinned(conn) => conn.discard(),
+            ConnGuard::Owned(conn) => conn.discard(),
+        }
+    }
+}
+
+impl From<PooledConnection> for ConnGuard<'_> {
+    fn from(conn: PooledConnection) -> Self {
+        ConnGuard::Owned(conn)
+    }
+}
+
+/// Ends a pinned snapshot read before the connection goes back to the pool.
+/// The session setup ran in a plain batch, so its `EXECUTE AS` is reverted
+/// here too.
+fn end_snapshot_sql() -> String {
+    format!(
+        "IF @@TRANCOUNT > 0 COMMIT TRANSACTION;\n\
+         SET TRANSACTION ISOLATION LEVEL READ COMMITTED;\n{}",
+        auth::build_revert_sql()
+    )
+}
+
+impl SqlContext {
+    fn new(
+        config: &AppConfig,
+        claims: &Option<auth::Claims>,
+        headers: &HeaderMap,
+        method: &str,
+        route: &str,
+    ) -> Self {
+        let request_id = match config.sql_tag {
+            SqlTag::Request => Some(
+                headers
+                    .get("x-request-id")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+            ),
+            _ => None,
+        };
+        let tag = match config.sql_tag {
+            SqlTag::Off => String::new(),
+            _ => query::sql_comment(method, route, request_id.as_deref()),
+        };
+        let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+        let mut session =
+            auth::build_locale_sql(config, prefer.lang.as_deref(), prefer.dateformat.as_deref());
+        session.extend(auth::build_request_session_sql(claims, headers, config));
+        let role = claims
+            .as_ref()
+            .and_then(|c| auth::resolve_role(c, config))
+            .or_else(|| config.anon_role.clone());
+        SqlContext {
+            session,
+            tag,
+            pinned: None,
+            read_pool: None,
+            role,
+        }
+    }
+
+    /// Check a connection out of `pool`, labelled with the request's role.
+    async fn checkout(&self, pool: &Arc<Pool>) -> Result<PooledConnection, Error> {
+        let conn = pool.get().await?;
+        conn.set_role(self.role.as_deref());
+        Ok(conn)
+    }
+
+    /// Pin one connection in a snapshot-isolation transaction so every read
+    /// of this request (count, rows, embeds) sees the same point in time.
+    ///
+    /// Session setup runs once here instead of before each statement.
+    async fn begin_snapshot(&mut self, state: &AppState) -> Result<(), Error> {
+        let mut conn = self.checkout(self.pool(state)).await?;
+        let sql = format!(
+            "SET NOCOUNT ON;\n{}\nSET TRANSACTION ISOLATION LEVEL SNAPSHOT;\nBEGIN TRANSACTION;",
+            self.session.join("\n")
+        );
+        // A plain batch, not sp_executesql: the isolation level and open
+        // transaction must outlive this statement
+        let mut sink = claw::ArrowRowWriter::new();
+        if let Err(e) = conn.client().batch_into(&sql, &mut sink).await {
+            // The batch may have stopped after EXECUTE AS
+            conn.discard();
+            return Err(Error::Sql(e.to_string()));
+        }
+        self.session.clear();
+        self.pinned = Some(Mutex::new(conn));
+        Ok(())
+    }
+
+    /// The connection to run this request's reads on.
+    async fn connection(&self, state: &AppState) -> Result<ConnGuard<'_>, Error> {
+        match &self.pinned {
+            Some(conn) => Ok(ConnGuard::Pinned(conn.lock().await)),
+            None => Ok(ConnGuard::Owned(self.checkout(self.pool(state)).await?)),
+        }
+    }
+
+    /// The replica when reads were routed to one, else the primary.
+    fn pool<'a>(&'a self, state: &'a AppState) -> &'a Arc<Pool> {
+        self.read_pool.as_ref().unwrap_or(&state.pool)
+    }
+
+    /// Prefix a generated statement with the request tag.
+    fn tagged(&self, sql: &str) -> String {
+        if self.tag.is_empty() {
+            sql.to_string()
+        } else {
+            format!("{}\n{}", self.tag, sql)
+        }
+    }
+}
+
+impl Drop for SqlContext {
+    fn drop(&mut self) {
+        // Close the snapshot on every exit path, including errors
+        if let Some(conn) = self.pinned.take() {
+            let mut conn = conn.into_inner();
+            tokio::spawn(async move {
+                let mut sink = claw::ArrowRowWriter::new();
+                if conn
+                    .client()
+                    .batch_into(&end_snapshot_sql(), &mut sink)
+                    .await
+                    .is_err()
+                {
+                    conn.discard();
+                }
+            });
+        }
+    }
 }
 
 /// GET handler for table/view queries.
@@ -33,9 +306,10 @@ pub async fn handle_get(
     State(state): State<AppState>,
     Path(path_params): Path<Vec<(String, String)>>,
     headers: HeaderMap,
-    AxumQuery(query_params): AxumQuery<HashMap<String, String>>,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
 ) -> Result<Response, Error> {
-    let (schema_name, table_name) = resolve_table_path(&path_params, &state.config)?;
+    let profile = headers.get("accept-profile").and_then(|v| v.to_str().ok());
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, &state)?;
     let schema_cache = state.schema.read().await;
     let table = schema_cache
         .get_table(&schema_name, &table_name)
@@ -43,14 +317,62 @@ pub async fn handle_get(
             Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name))
         })?;
 
-    // Auth
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let claims = auth::authenticate(auth_header, &state.config)?;
+    // Auth: a signed link stands in for the header of the caller who minted it
+    let signed = signed_url::verify(
+        state.config.signed_url_secret.as_deref(),
+        (&schema_name, &table_name),
+        profile,
+        &query_params,
+    )?;
+    let claims = match signed {
+        Some(claims) => Some(claims),
+        None => {
+            let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+            auth::authenticate(auth_header, &state.config)?
+        }
+    };
+    let mut ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "GET",
+        &format!("/{}", table_name),
+    );
 
     // Parse parameters
     let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
     let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
 
+    // Exports can be resumed part-way with `Range: bytes=`. One still kept
+    // for this caller and request is sliced without running the query; of
+    // a larger one only the rows from the segment the range starts in are
+    // read again
+    let byte_range = headers.get("range").and_then(|v| v.to_str().ok());
+    let if_range = headers.get("if-range").and_then(|v| v.to_str().ok());
+    let export_key = export_key(
+        (&schema_name, &table_name),
+        &claims,
+        &ctx.session,
+        &headers,
+        &query_params,
+    );
+    let mut resume = None;
+    if let (Some(range), Some(tag)) = (byte_range, if_range) {
+        if let Some(export) = state.exports.get(&export_key, tag) {
+            if export.is_whole() {
+                return Ok(response::build_export_response(
+                    export, byte_range, if_range,
+                ));
+            }
+            resume = export.resume_row(range).map(|row| (export, row));
+        }
+    }
+
+    ctx.read_pool = route_read(&state, &headers).await?;
+    if prefer.snapshot {
+        ctx.begin_snapshot(&state).await?;
+    }
+
     let select_str = query_params
         .get("select")
         .map(|s| s.as_str())
@@ -70,79 +392,137 @@ pub async fn handle_get(
     let final_offset = offset.or(range_offset);
 
     let order_str = query_params.get("order").map(|s| s.as_str()).unwrap_or("");
-    let order = query::parse_order(order_str)?;
+    let mut order = query::parse_order(order_str)?;
 
     // Build filters from query params
-    let filter_nodes = build_filters_from_params(&query_params, table)?;
-
-    // Ensure embed join columns are included in the select
-    let embeds_preview = select::select_embeds(&select_nodes);
-    let mut extra_join_cols: Vec<String> = Vec::new();
-    for embed in &embeds_preview {
-        if let Some(embed_info) = schema_cache.find_embed(
-            &schema_name,
-            &table_name,
-            &embed.name,
-            embed.fk_hint.as_deref(),
-        ) {
-            extra_join_cols.push(embed_info.source_column.clone());
-        }
+    let mut filter_nodes = build_filters_from_params(&query_params, table, &state.config)?;
+
+    // `?tree=` walks a self-referencing FK instead of a flat read
+    if let Some(root) = query_params.get("tree") {
+        return handle_tree(&state, table, root, &query_params, filter_nodes, &ctx).await;
     }
 
-    // Augment select nodes with join columns if they're not already selected
-    let augmented_select = if !extra_join_cols.is_empty() {
-        let selected_cols = select::select_columns(&select_nodes);
-        let mut augmented = select_nodes.clone();
-        for col in &extra_join_cols {
-            if !selected_cols.iter().any(|c| c.eq_ignore_ascii_case(col))
-                && !selected_cols.contains(&"*")
-            {
-                augmented.push(select::SelectNode::Column(col.clone()));
-            }
-        }
-        augmented
-    } else {
-        select_nodes.clone()
-    };
+    // Resolve embeds into correlated subqueries (`!inner` ones also add an
+    // EXISTS filter on the parent rows)
+    let mut alias_counter = 0;
+    let embeds = resolve_embeds(
+        &state.config,
+        &schema_cache,
+        &query_params,
+        table,
+        &table.full_name(),
+        "",
+        &select_nodes,
+        &mut alias_counter,
+        &mut filter_nodes,
+    )?;
+
+    // `?distinct_on=` ranks the filtered rows, so it wraps every filter
+    if let Some(columns) = query_params.get("distinct_on") {
+        let node = query::distinct_on_filter(table, columns, &order, filter_nodes)?;
+        filter_nodes = vec![node];
+    }
+
+    // `?resume_after=` continues an interrupted export after the last key
+    if let Some(after) = query_params.get("resume_after") {
+        let (node, key_order) = query::resume_after_filter(table, &order, after)?;
+        filter_nodes.push(node);
+        order = key_order;
+    }
+
+    let table_cfg = state
+        .config
+        .table_config(&schema_name, &table_name)
+        .cloned()
+        .unwrap_or_default();
+
+    // A resumed export reads on from its segment's first row
+    let skip = resume.as_ref().map_or(0, |(_, row)| *row as i64);
+    let max_rows = state.config.max_rows.map(|max| (max - skip).max(0));
 
     // Build and execute main query
-    let built = query::build_select(
+    let mut built = query::build_select(
         table,
-        &augmented_select,
+        &select_nodes,
+        &embeds,
         &filter_nodes,
         &order,
-        final_limit,
-        final_offset,
+        final_limit.map(|limit| (limit - skip).max(0)),
+        (skip > 0)
+            .then(|| final_offset.unwrap_or(0) + skip)
+            .or(final_offset),
+        max_rows,
         false,
     )?;
 
+    // `--server-json`: SQL Server writes the JSON array itself, unless rows
+    // need formatting or capping here
+    let server_json = state.config.server_json
+        && format == ResponseFormat::Json
+        && state.config.max_rows.is_none()
+        && state.config.columns.is_empty()
+        && server_serializable(table, &embeds);
+
+    // An exact count rides along with a JSON page (see
+    // `query::with_total_count`); Arrow batches and server-side JSON keep
+    // it separate
+    let inline_count = prefer.count == Some(CountMode::Exact)
+        && !server_json
+        && !matches!(
+            format,
+            ResponseFormat::ArrowIpcStream | ResponseFormat::ArrowJson
+        )
+        && query::with_total_count(&mut built, table, &filter_nodes)?;
+    if server_json {
+        query::into_json_array(&mut built);
+    }
+    query::apply_maxdop(&mut built, table_cfg.maxdop);
+
     // Get count if requested
-    let total_count = if prefer.count {
-        let count_query =
-            query::build_select(table, &select_nodes, &filter_nodes, &[], None, None, true)?;
-        Some(execute_count(&state, &count_query, &claims).await?)
-    } else {
-        None
+    let (mut total_count, count_mode) = match prefer.count {
+        Some(_) if inline_count => (None, Some(CountMode::Exact)),
+        Some(mode) => {
+            let (count, applied) =
+                count_rows(&state, table, &filter_nodes, table_cfg.maxdop, mode, &ctx).await?;
+            (Some(count), Some(applied))
+        }
+        None => (None, None),
+    };
+
+    // A page cut short by --max-rows is a 206 (the Content-Range shows how
+    // far it got)
+    let status = |truncated: bool| {
+        if truncated {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        }
     };
 
     // Execute query using Arrow path or standard path based on Accept header
-    match format {
+    let mut response = match format {
         ResponseFormat::ArrowIpcStream | ResponseFormat::ArrowJson => {
-            let batch = execute_arrow_query(&state, &built, &claims).await?;
+            let batch = execute_arrow_query(&state, &built, &ctx).await?;
+            let (kept, truncated) = query::capped_rows(batch.num_rows(), state.config.max_rows);
+            let batch = batch.slice(0, kept);
             match format {
                 ResponseFormat::ArrowIpcStream => {
-                    let bytes = response::record_batch_to_ipc(&batch)?;
+                    let bytes =
+                        response::record_batch_to_ipc(&batch, table_cfg.arrow_batch_rows())?;
                     let range = build_content_range(
                         final_offset.unwrap_or(0),
                         batch.num_rows() as i64,
                         total_count,
                     );
-                    Ok(response::build_response(
+                    let export = response::Export::new(
                         bytes,
                         "application/vnd.apache.arrow.stream",
-                        StatusCode::OK,
-                        Some(range),
-                        None,
+                        status(truncated),
+                        range,
+                    );
+                    state.exports.insert(export_key, export.clone());
+                    Ok(response::build_export_response(
+                        export, byte_range, if_range,
                     ))
                 }
                 ResponseFormat::ArrowJson => {
@@ -155,7 +535,7 @@ pub async fn handle_get(
                     Ok(response::build_response(
                         json.into_bytes(),
                         "application/vnd.apache.arrow+json",
-                        StatusCode::OK,
+                        status(truncated),
                         Some(range),
                         None,
                     ))
@@ -163,26 +543,54 @@ pub async fn handle_get(
                 _ => unreachable!(),
             }
         }
+        _ if server_json => {
+            let mut rows = execute_query_to_json(&state, &built, &ctx).await?;
+            let json = match rows
+                .first_mut()
+                .and_then(|row| row.remove(query::JSON_COLUMN))
+            {
+                Some(JsonValue::String(json)) => json,
+                _ => "[]".to_string(),
+            };
+            let range = build_content_range(
+                final_offset.unwrap_or(0),
+                response::json_array_len(&json) as i64,
+                total_count,
+            );
+            Ok(response::build_response(
+                json.into_bytes(),
+                "application/json; charset=utf-8",
+                StatusCode::OK,
+                Some(range),
+                None,
+            ))
+        }
         _ => {
-            let mut rows = execute_query_to_json(&state, &built, &claims).await?;
-
-            // Handle embeddings
-            let embeds = select::select_embeds(&select_nodes);
-            if !embeds.is_empty() {
-                handle_embeds(
-                    &state,
-                    &schema_cache,
-                    &schema_name,
-                    &table_name,
-                    &embeds,
-                    &mut rows,
-                    &query_params,
-                    &claims,
-                    &extra_join_cols,
-                    &select_nodes,
-                )
-                .await?;
+            let mut rows = execute_query_to_json(&state, &built, &ctx).await?;
+            if inline_count {
+                total_count = match take_total_count(&mut rows) {
+                    Some(total) => Some(total),
+                    None if final_offset.unwrap_or(0) == 0 => Some(0),
+                    // Paged past the end: nothing carried the total
+                    None => Some(
+                        count_rows(
+                            &state,
+                            table,
+                            &filter_nodes,
+                            table_cfg.maxdop,
+                            CountMode::Exact,
+                            &ctx,
+                        )
+                        .await?
+                        .0,
+                    ),
+                };
             }
+            let (kept, truncated) = query::capped_rows(rows.len(), max_rows);
+            rows.truncate(kept);
+
+            decode_embeds(&state.config, &mut rows, &embeds);
+            format_columns(&state.config, &schema_name, &table_name, &mut rows);
 
             let row_count = rows.len() as i64;
             let range = build_content_range(final_offset.unwrap_or(0), row_count, total_count);
@@ -193,27 +601,67 @@ pub async fn handle_get(
                         return Err(Error::SingleObjectExpected(rows.len()));
                     }
                     let json = serde_json::to_string(&rows[0]).unwrap_or_default();
-                    Ok(response::build_response(
+                    let mut response = response::build_response(
                         json.into_bytes(),
                         "application/vnd.pgrst.object+json; charset=utf-8",
-                        StatusCode::OK,
+                        status(truncated),
                         Some(range),
                         None,
-                    ))
+                    );
+                    add_row_etag(&table, &rows, &mut response);
+                    Ok(response)
                 }
                 ResponseFormat::Csv => {
-                    let columns: Vec<String> = if rows.is_empty() {
-                        table.columns.iter().map(|c| c.name.clone()).collect()
-                    } else {
-                        rows[0].keys().cloned().collect()
+                    let columns = |rows: &[serde_json::Map<String, JsonValue>]| -> Vec<String> {
+                        if rows.is_empty() {
+                            table.columns.iter().map(|c| c.name.clone()).collect()
+                        } else {
+                            rows[0].keys().cloned().collect()
+                        }
                     };
-                    let csv_str = response::rows_to_csv(&rows, &columns)?;
-                    Ok(response::build_response(
-                        csv_str.into_bytes(),
-                        "text/csv; charset=utf-8",
-                        StatusCode::OK,
-                        Some(range),
-                        None,
+                    let (rows, truncated, range, byte_range) = match (&resume, byte_range) {
+                        (Some((export, row)), Some(byte_range)) => {
+                            let (tail, row_starts) =
+                                response::csv_rows(&rows, &columns(&rows), false)?;
+                            if let Some(response) =
+                                export.resume(*row, tail, &row_starts, byte_range)
+                            {
+                                return Ok(response);
+                            }
+                            // The rows changed since: read the whole export
+                            // again, as `If-Range` asks
+                            let mut full = query::build_select(
+                                table,
+                                &select_nodes,
+                                &embeds,
+                                &filter_nodes,
+                                &order,
+                                final_limit,
+                                final_offset,
+                                state.config.max_rows,
+                                false,
+                            )?;
+                            query::apply_maxdop(&mut full, table_cfg.maxdop);
+                            let mut rows = execute_query_to_json(&state, &full, &ctx).await?;
+                            let (kept, truncated) =
+                                query::capped_rows(rows.len(), state.config.max_rows);
+                            rows.truncate(kept);
+                            decode_embeds(&state.config, &mut rows, &embeds);
+                            format_columns(&state.config, &schema_name, &table_name, &mut rows);
+                            let range = build_content_range(
+                                final_offset.unwrap_or(0),
+                                rows.len() as i64,
+                                total_count,
+                            );
+                            (rows, truncated, range, None)
+                        }
+                        _ => (rows, truncated, range, byte_range),
+                    };
+                    let (csv, row_starts) = response::csv_rows(&rows, &columns(&rows), true)?;
+                    let export = response::Export::csv(csv, &row_starts, status(truncated), range);
+                    state.exports.insert(export_key, export.clone());
+                    Ok(response::build_export_response(
+                        export, byte_range, if_range,
                     ))
                 }
                 _ => {
@@ -221,14 +669,30 @@ pub async fn handle_get(
                     Ok(response::build_response(
                         json.into_bytes(),
                         "application/json; charset=utf-8",
-                        StatusCode::OK,
+                        status(truncated),
                         Some(range),
                         None,
                     ))
                 }
             }
         }
+    }?;
+
+    if let Some(mode) = count_mode {
+        response.headers_mut().insert(
+            "Preference-Applied",
+            axum::http::HeaderValue::from_static(mode.as_str()),
+        );
     }
+    add_table_headers(
+        &state,
+        table,
+        &ctx,
+        Some(filter_nodes.as_slice()),
+        &mut response,
+    )
+    .await?;
+    Ok(response)
 }
 
 /// POST handler for inserts.
@@ -236,27 +700,72 @@ pub async fn handle_post(
     State(state): State<AppState>,
     Path(path_params): Path<Vec<(String, String)>>,
     headers: HeaderMap,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
     body: Bytes,
 ) -> Result<Response, Error> {
-    let (schema_name, table_name) = resolve_table_path(&path_params, &state.config)?;
+    let profile = headers.get("content-profile").and_then(|v| v.to_str().ok());
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, &state)?;
     let schema_cache = state.schema.read().await;
     let table = schema_cache
         .get_table(&schema_name, &table_name)
         .ok_or_else(|| Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name)))?
         .clone();
     drop(schema_cache);
+    ensure_updatable(&table, Dml::Insert)?;
 
     let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
     let claims = auth::authenticate(auth_header, &state.config)?;
-    let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    let ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "POST",
+        &format!("/{}", table_name),
+    );
+    let mut prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    instead_of_minimal(&table, Dml::Insert, &mut prefer);
     let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
 
-    let body_str = String::from_utf8(body.to_vec())
-        .map_err(|_| Error::BadRequest("Invalid UTF-8 body".to_string()))?;
-    let json: JsonValue = serde_json::from_str(&body_str)
-        .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?;
+    let is_csv = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.trim_start().starts_with("text/csv"));
+    let mut json = if is_csv {
+        csv_body(&body)?
+    } else {
+        let body_str = String::from_utf8(body.to_vec())
+            .map_err(|_| Error::BadRequest("Invalid UTF-8 body".to_string()))?;
+        serde_json::from_str(&body_str)
+            .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?
+    };
+    if let Some(allowed) = column_whitelist(&table, &query_params)? {
+        let objects: Vec<&mut JsonValue> = match &mut json {
+            JsonValue::Array(arr) => arr.iter_mut().collect(),
+            value => vec![value],
+        };
+        for obj in objects.into_iter().filter_map(JsonValue::as_object_mut) {
+            obj.retain(|k, _| allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+        }
+    }
+    inject_claim_columns(&state.config, &table, &claims, &mut json)?;
 
     let is_upsert = prefer.resolution.as_deref() == Some("merge-duplicates");
+    if is_upsert && table.instead_of.any() {
+        // MERGE can't target a view whose writes go through INSTEAD OF triggers
+        return Err(Error::MethodNotAllowed(format!(
+            "{}.{} is written through INSTEAD OF triggers and does not support upsert",
+            table.schema, table.name
+        )));
+    }
+    let on_conflict: Option<Vec<String>> = query_params
+        .get("on_conflict")
+        .map(|cols| select::split_idents(cols));
+    if on_conflict.is_some() && !is_upsert {
+        return Err(Error::BadRequestHint {
+            message: "on_conflict only applies to upserts".to_string(),
+            hint: "Send Prefer: resolution=merge-duplicates".to_string(),
+        });
+    }
 
     // Normalize to array of objects
     let objects: Vec<&serde_json::Map<String, JsonValue>> = match &json {
@@ -278,30 +787,224 @@ pub async fn handle_post(
     if objects.is_empty() {
         return Err(Error::BadRequest("Empty body".to_string()));
     }
+    check_body(&state.config, &table, &objects, !is_upsert)?;
 
-    // Get columns from the first object
-    let columns: Vec<String> = objects[0].keys().cloned().collect();
-
-    // Build SQL
-    let built = if is_upsert {
-        query::build_upsert(&table, &columns, objects.len())?
+    // Columns are the union of keys across all objects, in first-seen order
+    let mut keys: Vec<String> = Vec::new();
+    for obj in &objects {
+        for key in obj.keys() {
+            if !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+    }
+    // Identity values are only written with `Prefer: identity=insert` on
+    // tables that allow it; otherwise plain inserts drop them with a warning
+    // (upserts keep rejecting them, since dropping a key changes the match)
+    let (identity_keys, keys): (Vec<String>, Vec<String>) = keys
+        .into_iter()
+        .partition(|k| !is_upsert && table.column(k).is_some_and(|c| c.is_identity));
+    let identity_insert = prefer.identity_insert && !identity_keys.is_empty();
+    if identity_insert {
+        let allowed = state
+            .config
+            .table_config(&schema_name, &table_name)
+            .is_some_and(|t| t.identity_insert);
+        if !allowed {
+            return Err(Error::Forbidden(format!(
+                "Identity insert is not enabled for {}.{}",
+                schema_name, table_name
+            )));
+        }
+        if prefer.resolution.is_some() {
+            return Err(Error::BadRequest(
+                "identity=insert can't be combined with resolution".to_string(),
+            ));
+        }
+    }
+    let mut columns = writable_columns(&table, keys, &prefer)?;
+    let ignored_identity = if identity_insert {
+        columns.extend(identity_keys);
+        Vec::new()
     } else {
-        query::build_insert(&table, &columns, objects.len())?
+        identity_keys
+    };
+    // The identity insert wrapper resets @@ROWCOUNT, so it always reads back
+    let returning = identity_insert || needs_output(&state.config, &table, Dml::Insert, &prefer);
+    let build_insert = |present: &[Vec<bool>]| {
+        if identity_insert {
+            query::build_identity_insert(&table, &columns, present, returning)
+        } else {
+            query::build_insert(&table, &columns, present, returning)
+        }
+        .map(|built| counted(built, returning))
     };
+    let schema_cache = state.schema.read().await;
+    let selection =
+        MutationSelect::resolve(&state.config, &schema_cache, &query_params, &table, &prefer)?;
 
-    // Collect all parameter values
-    let mut param_values: Vec<String> = Vec::new();
-    for obj in &objects {
-        for col in &columns {
-            let val = obj.get(col).unwrap_or(&JsonValue::Null);
-            param_values.push(json_value_to_sql_string(val));
+    if prefer.resolution.as_deref() == Some("continue-on-error") {
+        // Rows only come back for return=representation; the statuses always do
+        let read_back = returning && prefer.return_mode == ReturnMode::Representation;
+        let shape = read_back.then(|| match &selection {
+            Some(selection) => (selection.nodes.as_slice(), selection.embeds.as_slice()),
+            None => (&[][..], &[][..]),
+        });
+        let batch_rows = query::insert_batch_rows(columns.len());
+        let reset = (query::INSERT_EACH_RESET.to_string(), Vec::new());
+        let mut statements = vec![reset.clone()];
+        for (n, chunk) in objects.chunks(batch_rows).enumerate() {
+            let present = presence(chunk, &columns);
+            let built =
+                query::build_insert_each(&table, &columns, &present, n * batch_rows, shape)?;
+            let mut params = collect_insert_params(&table, chunk, &columns)?;
+            params.extend(built.params);
+            statements.push((built.sql, params));
+        }
+        statements.push(reset);
+        let mut rows = execute_dml_batches(&state, &statements, &ctx, &prefer).await?;
+        if let Some(selection) = &selection {
+            decode_embeds(&state.config, &mut rows, &selection.embeds);
         }
+        drop(schema_cache);
+        format_columns(&state.config, &schema_name, &table_name, &mut rows);
+
+        let mut response = build_row_status_response(rows, read_back)?;
+        warn_ignored_identity(&mut response, &ignored_identity);
+        add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+        return Ok(with_consistency_token(&state, response).await);
+    }
+
+    // Big arrays are bulk loaded; nothing comes back from a bulk copy, so
+    // the response is always return=minimal. Rows must all send the same
+    // keys: a bulk copy can't give one row a column's DEFAULT and another
+    // a value.
+    let bulk_threshold = state.config.bulk_insert_threshold.unwrap_or(usize::MAX);
+    if !is_upsert
+        && !identity_insert
+        && objects.len() >= bulk_threshold
+        && bulk_loadable(&table)
+        && objects
+            .iter()
+            .all(|obj| columns.iter().all(|c| obj.contains_key(c)))
+    {
+        if let Some(unknown) = columns.iter().find(|c| table.column(c).is_none()) {
+            return Err(Error::BadRequest(format!(
+                "{} has no column {}",
+                table.name, unknown
+            )));
+        }
+        let rows = bulk_insert(&state, &table, &objects, &columns, &ctx, &prefer).await?;
+        tracing::debug!("Bulk loaded {} rows into {}", rows, table.full_name());
+        let mut response = minimal_response(rows as usize);
+        response.headers_mut().insert(
+            "Preference-Applied",
+            axum::http::HeaderValue::from_static("return=minimal"),
+        );
+        warn_ignored_identity(&mut response, &ignored_identity);
+        add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+        return Ok(with_consistency_token(&state, response).await);
+    }
+
+    if is_upsert {
+        ensure_distinct_upsert_keys(&table, &objects, on_conflict.as_deref())?;
+    }
+    // Each batch is one INSERT or MERGE over a VALUES source; bodies too big
+    // for one statement go in several, in one transaction
+    let build_batch = |chunk: &[&serde_json::Map<String, JsonValue>]| -> Result<_, Error> {
+        let present = presence(chunk, &columns);
+        let built = if is_upsert {
+            counted(
+                query::build_upsert(
+                    &table,
+                    &columns,
+                    &present,
+                    on_conflict.as_deref(),
+                    returning,
+                )?,
+                returning,
+            )
+        } else {
+            build_insert(&present)?
+        };
+        let mut params = collect_insert_params(&table, chunk, &columns)?;
+        let sql = select_returning(selection.as_ref(), &table, built.sql, &mut params)?;
+        Ok((sql, params))
+    };
+    let batch_rows = query::insert_batch_rows(columns.len());
+    let mut rows = if objects.len() > batch_rows {
+        let batches = objects
+            .chunks(batch_rows)
+            .map(build_batch)
+            .collect::<Result<Vec<_>, Error>>()?;
+        execute_dml_batches(&state, &batches, &ctx, &prefer).await?
+    } else {
+        let (sql, param_values) = build_batch(objects.as_slice())?;
+        execute_dml_query(&state, &sql, &param_values, &ctx, &prefer).await?
+    };
+    if let Some(selection) = &selection {
+        decode_embeds(&state.config, &mut rows, &selection.embeds);
+    }
+    drop(schema_cache);
+    format_columns(&state.config, &schema_name, &table_name, &mut rows);
+
+    let mut response = build_mutation_response(
+        rows,
+        &prefer,
+        &format,
+        StatusCode::CREATED,
+        arrow_batch_rows(&state.config, &table),
+    )?;
+    if identity_insert {
+        response.headers_mut().insert(
+            "Preference-Applied",
+            axum::http::HeaderValue::from_static("identity=insert"),
+        );
     }
+    warn_ignored_identity(&mut response, &ignored_identity);
+    add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+    Ok(with_consistency_token(&state, response).await)
+}
 
-    // Execute
-    let rows = execute_dml_query(&state, &built.sql, &param_values, &claims, &prefer).await?;
+/// MERGE can't touch a row twice in one statement, and across batches the
+/// later object would silently win, so an upsert body may name each row
+/// (by the key it's matched on) only once.
+fn ensure_distinct_upsert_keys(
+    table: &crate::schema::TableInfo,
+    objects: &[&serde_json::Map<String, JsonValue>],
+    on_conflict: Option<&[String]>,
+) -> Result<(), Error> {
+    let key_columns = query::upsert_key(table, on_conflict)?;
+    let mut seen = std::collections::HashSet::new();
+    for (index, obj) in objects.iter().enumerate() {
+        let key: Vec<String> = key_columns
+            .iter()
+            .filter_map(|c| obj.get(c).filter(|v| !v.is_null()))
+            .map(JsonValue::to_string)
+            .collect();
+        if key.len() == key_columns.len() && !seen.insert(key) {
+            return Err(Error::BadRequest(format!(
+                "Row {} repeats the {} of an earlier row",
+                index,
+                key_columns.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
 
-    build_mutation_response(rows, &prefer, &format, StatusCode::CREATED)
+/// `Warning` header naming identity columns dropped from an insert body.
+fn warn_ignored_identity(response: &mut Response, columns: &[String]) {
+    if columns.is_empty() {
+        return;
+    }
+    let warning = format!(
+        "199 lazypaw \"Ignored identity column {}; send Prefer: identity=insert to set it\"",
+        columns.join(", ")
+    );
+    if let Ok(value) = axum::http::HeaderValue::from_str(&warning) {
+        response.headers_mut().insert("Warning", value);
+    }
 }
 
 /// PATCH handler for updates.
@@ -309,230 +1012,2951 @@ pub async fn handle_patch(
     State(state): State<AppState>,
     Path(path_params): Path<Vec<(String, String)>>,
     headers: HeaderMap,
-    AxumQuery(query_params): AxumQuery<HashMap<String, String>>,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
     body: Bytes,
 ) -> Result<Response, Error> {
-    let (schema_name, table_name) = resolve_table_path(&path_params, &state.config)?;
+    let profile = headers.get("content-profile").and_then(|v| v.to_str().ok());
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, &state)?;
     let schema_cache = state.schema.read().await;
     let table = schema_cache
         .get_table(&schema_name, &table_name)
         .ok_or_else(|| Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name)))?
         .clone();
     drop(schema_cache);
+    ensure_updatable(&table, Dml::Update)?;
 
     let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
     let claims = auth::authenticate(auth_header, &state.config)?;
-    let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    let ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "PATCH",
+        &format!("/{}", table_name),
+    );
+    let mut prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    instead_of_minimal(&table, Dml::Update, &mut prefer);
     let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
 
     let body_str = String::from_utf8(body.to_vec())
         .map_err(|_| Error::BadRequest("Invalid UTF-8 body".to_string()))?;
-    let obj: serde_json::Map<String, JsonValue> = serde_json::from_str(&body_str)
+    let json: JsonValue = serde_json::from_str(&body_str)
         .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?;
+    let mut obj = match json {
+        JsonValue::Object(obj) => obj,
+        JsonValue::Array(items) => {
+            if if_match_filter(&table, &headers)?.is_some() {
+                return Err(Error::BadRequestHint {
+                    message: "An ETag in If-Match names one row; an array PATCH can't check it"
+                        .to_string(),
+                    hint: "PATCH rows one at a time with their ETags, or send If-Match: *"
+                        .to_string(),
+                });
+            }
+            let mut rows = patch_rows(&state, &table, &query_params, items, &ctx, &prefer).await?;
+            format_columns(&state.config, &schema_name, &table_name, &mut rows);
+            let mut response = build_mutation_response(
+                rows,
+                &prefer,
+                &format,
+                StatusCode::OK,
+                arrow_batch_rows(&state.config, &table),
+            )?;
+            add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+            return Ok(with_consistency_token(&state, response).await);
+        }
+        _ => {
+            return Err(Error::BadRequest(
+                "PATCH takes an object, or an array of keyed objects".to_string(),
+            ))
+        }
+    };
+    if let Some(allowed) = column_whitelist(&table, &query_params)? {
+        obj.retain(|k, _| allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+    }
 
-    let columns: Vec<String> = obj.keys().cloned().collect();
-    let filter_nodes = build_filters_from_params(&query_params, &table)?;
-
-    let built = query::build_update(&table, &columns, &filter_nodes)?;
-
-    // Collect SET values + WHERE params
-    let mut param_values: Vec<String> = columns
+    check_body(&state.config, &table, &[&plain_values(&obj)], false)?;
+    let columns = writable_columns(&table, obj.keys().cloned().collect(), &prefer)?;
+    let filter_nodes = build_filters_from_params(&query_params, &table, &state.config)?;
+    let limit = mutation_limit(&query_params, &filter_nodes)?;
+    let if_match = if_match_filter(&table, &headers)?;
+    let conditional: Vec<FilterNode> = filter_nodes
         .iter()
-        .map(|col| {
-            let val = obj.get(col).unwrap_or(&JsonValue::Null);
-            json_value_to_sql_string(val)
-        })
+        .cloned()
+        .chain(if_match.clone())
         .collect();
-    param_values.extend(built.params.clone());
 
-    let rows = execute_dml_query(&state, &built.sql, &param_values, &claims, &prefer).await?;
-
-    build_mutation_response(rows, &prefer, &format, StatusCode::OK)
-}
+    // Collect SET values + WHERE params. Only keys present in the body are
+    // SET; an explicit null binds as SQL NULL.
+    let (assignments, mut param_values) = update_assignments(&table, &columns, &obj)?;
+    // Conditional writes read the rows back to tell a stale ETag from no match
+    let returning = if_match.is_some() || needs_output(&state.config, &table, Dml::Update, &prefer);
+    let built = counted(
+        query::build_update(
+            &table,
+            &assignments,
+            &conditional,
+            limit.as_ref(),
+            returning,
+        )?,
+        returning,
+    );
+    param_values.extend(built.params.iter().cloned());
 
-/// DELETE handler.
-pub async fn handle_delete(
-    State(state): State<AppState>,
-    Path(path_params): Path<Vec<(String, String)>>,
-    headers: HeaderMap,
-    AxumQuery(query_params): AxumQuery<HashMap<String, String>>,
-) -> Result<Response, Error> {
-    let (schema_name, table_name) = resolve_table_path(&path_params, &state.config)?;
     let schema_cache = state.schema.read().await;
-    let table = schema_cache
-        .get_table(&schema_name, &table_name)
-        .ok_or_else(|| Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name)))?
-        .clone();
-    drop(schema_cache);
-
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let claims = auth::authenticate(auth_header, &state.config)?;
-    let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
-    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
+    let selection =
+        MutationSelect::resolve(&state.config, &schema_cache, &query_params, &table, &prefer)?;
+    let sql = select_returning(selection.as_ref(), &table, built.sql, &mut param_values)?;
 
-    let filter_nodes = build_filters_from_params(&query_params, &table)?;
-
-    let built = query::build_delete(&table, &filter_nodes)?;
-
-    let rows = execute_dml_query(&state, &built.sql, &built.params, &claims, &prefer).await?;
+    let mut rows = execute_dml_query(&state, &sql, &param_values, &ctx, &prefer).await?;
+    if if_match.is_some() && rows.is_empty() {
+        check_precondition(&state, &table, &filter_nodes, &ctx).await?;
+    }
+    if let Some(selection) = &selection {
+        decode_embeds(&state.config, &mut rows, &selection.embeds);
+    }
+    drop(schema_cache);
+    format_columns(&state.config, &schema_name, &table_name, &mut rows);
 
-    build_mutation_response(rows, &prefer, &format, StatusCode::OK)
+    let etag_rows = rows.clone();
+    let mut response = build_mutation_response(
+        rows,
+        &prefer,
+        &format,
+        StatusCode::OK,
+        arrow_batch_rows(&state.config, &table),
+    )?;
+    add_row_etag(&table, &etag_rows, &mut response);
+    add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+    Ok(with_consistency_token(&state, response).await)
 }
 
-/// POST /rpc/<procedure> handler.
-pub async fn handle_rpc(
-    State(state): State<AppState>,
-    Path(proc_name): Path<String>,
-    headers: HeaderMap,
-    body: Bytes,
-) -> Result<Response, Error> {
-    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
-    let claims = auth::authenticate(auth_header, &state.config)?;
-    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
+/// Bulk PATCH: each object names a row by its primary key and carries the
+/// columns to change. All rows are applied by one keyed `UPDATE ... FROM
+/// (VALUES ...)`, split into batches in one transaction when they exceed
+/// the parameter limit. Keys a row leaves out keep their current value.
+async fn patch_rows(
+    state: &AppState,
+    table: &crate::schema::TableInfo,
+    query_params: &QueryParams,
+    items: Vec<JsonValue>,
+    ctx: &SqlContext,
+    prefer: &Preferences,
+) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
+    let filters = build_filters_from_params(query_params, table, &state.config)?;
+    if !filters.is_empty() || query_params.contains_key("limit") {
+        return Err(Error::BadRequest(
+            "An array PATCH matches rows by primary key and takes no filters".to_string(),
+        ));
+    }
+    let mut objects = items
+        .into_iter()
+        .map(|item| match item {
+            JsonValue::Object(obj) => Ok(obj),
+            _ => Err(Error::BadRequest(
+                "Array PATCH elements must be objects".to_string(),
+            )),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if objects.is_empty() {
+        return Ok(Vec::new());
+    }
+    let is_key = |k: &str| {
+        table
+            .primary_key
+            .iter()
+            .any(|pk| pk.eq_ignore_ascii_case(k))
+    };
+    if let Some(allowed) = column_whitelist(table, query_params)? {
+        for obj in &mut objects {
+            obj.retain(|k, _| is_key(k) || allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+        }
+    }
+    if objects
+        .iter()
+        .any(|obj| obj.values().any(|v| update_operator(v).is_some()))
+    {
+        return Err(Error::BadRequest(
+            "Update operators are not supported in array PATCH bodies".to_string(),
+        ));
+    }
+    let refs: Vec<&serde_json::Map<String, JsonValue>> = objects.iter().collect();
+    check_body(&state.config, table, &refs, false)?;
+
+    // Key columns go first and aren't subject to the write rules: they're
+    // only matched on
+    let mut keys: Vec<String> = Vec::new();
+    for obj in &objects {
+        for key in obj.keys() {
+            if !is_key(key) && !keys.contains(key) {
+                keys.push(key.clone());
+            }
+        }
+    }
+    let mut columns = table.primary_key.clone();
+    columns.extend(writable_columns(table, keys, prefer)?);
+
+    // A row named twice would be updated from either value, so refuse it
+    let mut seen = std::collections::HashSet::new();
+    for (index, obj) in objects.iter().enumerate() {
+        let key = table
+            .primary_key
+            .iter()
+            .map(|pk| match obj.get(pk) {
+                Some(value) if !value.is_null() => Ok(value.to_string()),
+                _ => Err(Error::BadRequest(format!(
+                    "Row {} is missing {}, the primary key rows are matched on",
+                    index, pk
+                ))),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if !seen.insert(key.clone()) {
+            return Err(Error::BadRequest(format!(
+                "Row {} appears more than once",
+                key.join(", ")
+            )));
+        }
+    }
+
+    let returning = needs_output(&state.config, table, Dml::Update, prefer);
+    let batch_rows = query::insert_batch_rows(columns.len());
+    let batches = refs
+        .chunks(batch_rows)
+        .map(|chunk| {
+            let present = presence(chunk, &columns);
+            let built = counted(
+                query::build_bulk_update(table, &columns, &present, returning)?,
+                returning,
+            );
+            let params = collect_insert_params(table, chunk, &columns)?;
+            Ok((built.sql, params))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    match batches.as_slice() {
+        [(sql, params)] => execute_dml_query(state, sql, params, ctx, prefer).await,
+        _ => execute_dml_batches(state, &batches, ctx, prefer).await,
+    }
+}
+
+/// PUT handler: idempotent full-row upsert of the row named by its primary
+/// key, as `?id=eq.42` or `/<table>/42` for single-column keys. 201 when the
+/// row was created, 200 when it was replaced.
+pub async fn handle_put(
+    State(state): State<AppState>,
+    Path(path_params): Path<Vec<(String, String)>>,
+    headers: HeaderMap,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
+    body: Bytes,
+) -> Result<Response, Error> {
+    // A trailing segment is the key: `/<table>/<key>`, `/<schema>/<table>/<key>`.
+    // Two segments name a schema and table when that table exists.
+    let (path_params, path_key) = match path_params.len() {
+        3 => (path_params[..2].to_vec(), Some(path_params[2].1.clone())),
+        2 => {
+            let (first, second) = (&path_params[0].1, &path_params[1].1);
+            let is_table = state.config.is_schema_exposed(first)
+                && state.schema.read().await.get_table(first, second).is_some();
+            if is_table {
+                (path_params, None)
+            } else {
+                let table = vec![("table".to_string(), first.clone())];
+                (table, Some(second.clone()))
+            }
+        }
+        _ => (path_params, None),
+    };
+
+    let profile = headers.get("content-profile").and_then(|v| v.to_str().ok());
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, &state)?;
+    let schema_cache = state.schema.read().await;
+    let table = schema_cache
+        .get_table(&schema_name, &table_name)
+        .ok_or_else(|| Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name)))?
+        .clone();
+    drop(schema_cache);
+    ensure_updatable(&table, Dml::Update)?;
+    if table.instead_of.any() {
+        return Err(Error::MethodNotAllowed(format!(
+            "{}.{} is written through INSTEAD OF triggers and does not support PUT",
+            table.schema, table.name
+        )));
+    }
+    if table.primary_key.is_empty() {
+        return Err(Error::MethodNotAllowed(format!(
+            "{}.{} has no primary key to PUT by",
+            table.schema, table.name
+        )));
+    }
+    if let Some(key) = table.primary_key.iter().find(|k| {
+        table
+            .column(k)
+            .is_some_and(|c| c.read_only_reason().is_some())
+    }) {
+        return Err(Error::BadRequestHint {
+            message: format!(
+                "{}.{} is generated by the server and can't be set with PUT",
+                table.name, key
+            ),
+            hint: "Use POST to insert and PATCH to update".to_string(),
+        });
+    }
+
+    if if_match_filter(&table, &headers)?.is_some() {
+        return Err(Error::BadRequestHint {
+            message: format!(
+                "PUT can't check the row version of {}.{}",
+                table.schema, table.name
+            ),
+            hint: "PATCH with If-Match to update a versioned row, or send If-Match: *".to_string(),
+        });
+    }
+
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "PUT",
+        &format!("/{}", table_name),
+    );
+    let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
+
+    let key_values = put_key_values(&table, path_key, &query_params)?;
+
+    let body_str = String::from_utf8(body.to_vec())
+        .map_err(|_| Error::BadRequest("Invalid UTF-8 body".to_string()))?;
+    let mut json: JsonValue = serde_json::from_str(&body_str)
+        .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?;
+    if let (Some(allowed), JsonValue::Object(obj)) =
+        (column_whitelist(&table, &query_params)?, &mut json)
+    {
+        obj.retain(|k, _| allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+    }
+    inject_claim_columns(&state.config, &table, &claims, &mut json)?;
+    let JsonValue::Object(mut obj) = json else {
+        return Err(Error::BadRequest("PUT takes a single object".to_string()));
+    };
+
+    // The body may repeat the key, but only with the URL's values
+    for (pk, value) in &key_values {
+        let sent = obj.iter().find(|(k, _)| k.eq_ignore_ascii_case(pk));
+        match sent.map(|(_, v)| v) {
+            None => {
+                obj.insert(pk.clone(), JsonValue::String(value.clone()));
+            }
+            Some(JsonValue::String(s)) if s.eq_ignore_ascii_case(value) => {}
+            Some(v) if !v.is_string() && v.to_string() == *value => {}
+            Some(_) => {
+                return Err(Error::BadRequest(format!(
+                    "Payload value for {} does not match the URL",
+                    pk
+                )))
+            }
+        }
+    }
+
+    check_body(&state.config, &table, &[&obj], false)?;
+    let columns = writable_columns(&table, obj.keys().cloned().collect(), &prefer)?;
+
+    // Full row: every writable column must be sent (null to clear it)
+    let missing: Vec<&str> = table
+        .columns
+        .iter()
+        .filter(|c| c.read_only_reason().is_none())
+        .filter(|c| !columns.iter().any(|k| k.eq_ignore_ascii_case(&c.name)))
+        .map(|c| c.name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::BadRequestHint {
+            message: format!("PUT needs every column; missing {}", missing.join(", ")),
+            hint: "Send null to clear a column, or PATCH to change only some".to_string(),
+        });
+    }
+
+    let returning = needs_output(&state.config, &table, Dml::Update, &prefer);
+    let built = query::build_put(&table, &columns, returning)?;
+    let mut param_values = collect_insert_params(&table, &[&obj], &columns)?;
+
+    let schema_cache = state.schema.read().await;
+    let selection =
+        MutationSelect::resolve(&state.config, &schema_cache, &query_params, &table, &prefer)?;
+    let sql = select_returning(selection.as_ref(), &table, built.sql, &mut param_values)?;
+
+    let mut rows = execute_dml_query(&state, &sql, &param_values, &ctx, &prefer).await?;
+    if let Some(selection) = &selection {
+        decode_embeds(&state.config, &mut rows, &selection.embeds);
+    }
+    drop(schema_cache);
+    let created = rows
+        .first()
+        .and_then(|row| row.get("__action"))
+        .and_then(JsonValue::as_str)
+        == Some("INSERT");
+    for row in &mut rows {
+        row.remove("__action");
+    }
+    format_columns(&state.config, &schema_name, &table_name, &mut rows);
+
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    let mut response = build_mutation_response(
+        rows,
+        &prefer,
+        &format,
+        status,
+        arrow_batch_rows(&state.config, &table),
+    )?;
+    add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+    Ok(with_consistency_token(&state, response).await)
+}
+
+/// Query parameters PUT takes besides the key: they shape the row, not
+/// which row is written.
+const PUT_OPTIONS: [&str; 2] = ["select", "columns"];
+
+/// The row a PUT writes: its key from the path (`/table/<key>`) or from
+/// `eq` filters on every primary key column.
+fn put_key_values(
+    table: &crate::schema::TableInfo,
+    path_key: Option<String>,
+    query_params: &QueryParams,
+) -> Result<Vec<(String, String)>, Error> {
+    let is_option = |k: &str| PUT_OPTIONS.contains(&k);
+    match path_key {
+        Some(value) => {
+            if table.primary_key.len() != 1 {
+                return Err(Error::BadRequest(format!(
+                    "{}.{} has a composite primary key: address the row with ?<column>=eq.<value>",
+                    table.schema, table.name
+                )));
+            }
+            if query_params.keys().any(|k| !is_option(k)) {
+                return Err(Error::BadRequest(
+                    "PUT takes the key in the path or in filters, not both".to_string(),
+                ));
+            }
+            Ok(vec![(table.primary_key[0].clone(), value)])
+        }
+        None => {
+            if let Some(other) = query_params.keys().find(|k| {
+                !is_option(k)
+                    && !table
+                        .primary_key
+                        .iter()
+                        .any(|pk| pk.eq_ignore_ascii_case(k))
+            }) {
+                return Err(Error::BadRequest(format!(
+                    "PUT only takes eq filters on the primary key, not {}",
+                    other
+                )));
+            }
+            table
+                .primary_key
+                .iter()
+                .map(|pk| {
+                    query_params
+                        .iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(pk))
+                        .and_then(|(_, v)| v.strip_prefix("eq."))
+                        .map(|v| (pk.clone(), v.to_string()))
+                        .ok_or_else(|| {
+                            Error::BadRequest(format!("PUT needs {}=eq.<value> in the URL", pk))
+                        })
+                })
+                .collect()
+        }
+    }
+}
+
+/// DELETE handler.
+pub async fn handle_delete(
+    State(state): State<AppState>,
+    Path(path_params): Path<Vec<(String, String)>>,
+    headers: HeaderMap,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
+) -> Result<Response, Error> {
+    let profile = headers.get("content-profile").and_then(|v| v.to_str().ok());
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, &state)?;
+    let schema_cache = state.schema.read().await;
+    let table = schema_cache
+        .get_table(&schema_name, &table_name)
+        .ok_or_else(|| Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name)))?
+        .clone();
+    drop(schema_cache);
+    ensure_updatable(&table, Dml::Delete)?;
+
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "DELETE",
+        &format!("/{}", table_name),
+    );
+    let mut prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    instead_of_minimal(&table, Dml::Delete, &mut prefer);
+    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
+
+    let filter_nodes = build_filters_from_params(&query_params, &table, &state.config)?;
+    let limit = mutation_limit(&query_params, &filter_nodes)?;
+    let if_match = if_match_filter(&table, &headers)?;
+    let conditional: Vec<FilterNode> = filter_nodes
+        .iter()
+        .cloned()
+        .chain(if_match.clone())
+        .collect();
+
+    let returning = if_match.is_some() || needs_output(&state.config, &table, Dml::Delete, &prefer);
+    let built = counted(
+        query::build_delete(&table, &conditional, limit.as_ref(), returning)?,
+        returning,
+    );
+    let mut params = built.params;
+
+    let schema_cache = state.schema.read().await;
+    let selection =
+        MutationSelect::resolve(&state.config, &schema_cache, &query_params, &table, &prefer)?;
+    let sql = select_returning(selection.as_ref(), &table, built.sql, &mut params)?;
+
+    let mut rows = execute_dml_query(&state, &sql, &params, &ctx, &prefer).await?;
+    if if_match.is_some() && rows.is_empty() {
+        check_precondition(&state, &table, &filter_nodes, &ctx).await?;
+    }
+    if let Some(selection) = &selection {
+        decode_embeds(&state.config, &mut rows, &selection.embeds);
+    }
+    drop(schema_cache);
+    format_columns(&state.config, &schema_name, &table_name, &mut rows);
+
+    let mut response = build_mutation_response(
+        rows,
+        &prefer,
+        &format,
+        StatusCode::OK,
+        arrow_batch_rows(&state.config, &table),
+    )?;
+    add_table_headers(&state, &table, &ctx, None, &mut response).await?;
+    Ok(with_consistency_token(&state, response).await)
+}
+
+/// Most operations one `POST /batch` may carry.
+const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// One write in a `POST /batch` body.
+#[derive(Debug, serde::Deserialize)]
+pub struct BatchOperation {
+    /// `POST`, `PATCH` or `DELETE`
+    pub method: String,
+    /// Table path with query parameters, e.g. `/orders?id=eq.7`
+    pub path: String,
+    #[serde(default)]
+    pub body: Option<JsonValue>,
+    /// The operation's `If-Match`, for PATCH and DELETE on versioned tables
+    #[serde(default)]
+    pub if_match: Option<String>,
+}
+
+/// An operation compiled to statements: more than one when an insert is
+/// too big for SQL Server's parameter limit.
+struct BatchStatements {
+    schema: String,
+    table: String,
+    status: StatusCode,
+    statements: Vec<(String, Vec<SqlParam>)>,
+    /// False when an INSTEAD OF trigger takes the write over: only its
+    /// `@@ROWCOUNT` comes back
+    returning: bool,
+}
+
+/// POST /batch: run writes to any tables in one transaction on one
+/// connection. Either every operation commits or none does; the response
+/// holds each operation's status and rows, in order.
+pub async fn handle_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let ctx = SqlContext::new(&state.config, &claims, &headers, "POST", "/batch");
+    let prefer = response::parse_prefer(headers.get("prefer").and_then(|v| v.to_str().ok()));
+    let profile = headers.get("content-profile").and_then(|v| v.to_str().ok());
+
+    let operations: Vec<BatchOperation> = serde_json::from_slice(&body)
+        .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?;
+    if operations.is_empty() {
+        return Err(Error::BadRequest("Empty batch".to_string()));
+    }
+    if operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(Error::BadRequest(format!(
+            "A batch holds at most {} operations",
+            MAX_BATCH_OPERATIONS
+        )));
+    }
+
+    let schema_cache = state.schema.read().await;
+    let compiled = operations
+        .iter()
+        .enumerate()
+        .map(|(i, op)| {
+            batch_statements(&state, &schema_cache, profile, &claims, &prefer, op)
+                .map_err(|e| e.context(&format!("operation {}", i)))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    drop(schema_cache);
+
+    // Flatten, remembering which operation each statement belongs to
+    let owners: Vec<usize> = compiled
+        .iter()
+        .enumerate()
+        .flat_map(|(i, op)| std::iter::repeat_n(i, op.statements.len()))
+        .collect();
+    let statements: Vec<(String, Vec<SqlParam>)> = compiled
+        .iter()
+        .flat_map(|op| op.statements.iter().cloned())
+        .collect();
+    let results = execute_dml_statements(&state, &statements, &ctx, &prefer)
+        .await
+        .map_err(|(index, e)| {
+            let e = match e {
+                Error::Sql(msg) if msg.contains(STALE_ETAG) => {
+                    Error::PreconditionFailed(STALE_ETAG.to_string())
+                }
+                e => e,
+            };
+            match index {
+                Some(index) => e.context(&format!("operation {}", owners[index])),
+                None => e,
+            }
+        })?;
+
+    let mut rows_by_op: Vec<Vec<serde_json::Map<String, JsonValue>>> =
+        compiled.iter().map(|_| Vec::new()).collect();
+    for (owner, rows) in owners.into_iter().zip(results) {
+        rows_by_op[owner].extend(rows);
+    }
+    let results: Vec<JsonValue> = compiled
+        .iter()
+        .zip(rows_by_op)
+        .map(|(op, mut rows)| {
+            let mut result = serde_json::json!({
+                "status": op.status.as_u16(),
+                "count": affected_rows(&rows),
+            });
+            if op.returning && prefer.return_mode == ReturnMode::Representation {
+                format_columns(&state.config, &op.schema, &op.table, &mut rows);
+                result["rows"] =
+                    JsonValue::Array(rows.into_iter().map(JsonValue::Object).collect());
+            }
+            result
+        })
+        .collect();
+
+    let mut response = response::build_response(
+        JsonValue::Array(results).to_string().into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    );
+    if prefer.tx == TxPreference::Rollback {
+        response.headers_mut().insert(
+            "Preference-Applied",
+            axum::http::HeaderValue::from_static("tx=rollback"),
+        );
+    }
+    Ok(with_consistency_token(&state, response).await)
+}
+
+/// Compile one batch operation with the same checks as the single-table
+/// handlers: columns, validation, claim injection and the filter guard.
+fn batch_statements(
+    state: &AppState,
+    schema_cache: &SchemaCache,
+    profile: Option<&str>,
+    claims: &Option<auth::Claims>,
+    prefer: &Preferences,
+    op: &BatchOperation,
+) -> Result<BatchStatements, Error> {
+    let (path, query_string) = op.path.split_once('?').unwrap_or((&op.path, ""));
+    let path_params: Vec<(String, String)> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| Ok((String::new(), decode_path_segment(s)?)))
+        .collect::<Result<_, Error>>()?;
+    let (schema_name, table_name) = resolve_table_path(&path_params, profile, state)?;
+    let table = schema_cache
+        .get_table(&schema_name, &table_name)
+        .ok_or_else(|| {
+            Error::NotFound(format!("Table not found: {}.{}", schema_name, table_name))
+        })?;
+    let mut url =
+        reqwest::Url::parse("http://lazypaw/").map_err(|e| Error::Internal(e.to_string()))?;
+    url.set_query(Some(query_string));
+    let query_params = QueryParams::from(url.query_pairs().into_owned().collect::<Vec<_>>());
+
+    let body = || {
+        op.body
+            .clone()
+            .ok_or_else(|| Error::BadRequest(format!("{} needs a body", op.method)))
+    };
+    let dml = match op.method.to_ascii_uppercase().as_str() {
+        "POST" => Dml::Insert,
+        "PATCH" => Dml::Update,
+        "DELETE" => Dml::Delete,
+        other => {
+            return Err(Error::BadRequest(format!(
+                "Unsupported batch method {}; use POST, PATCH or DELETE",
+                other
+            )))
+        }
+    };
+    ensure_updatable(table, dml)?;
+    // Writes INSTEAD OF triggers take over are only counted
+    let returning = !table.instead_of.handles(dml);
+    let (status, statements) = match dml {
+        Dml::Insert => {
+            let mut json = body()?;
+            if let Some(allowed) = column_whitelist(table, &query_params)? {
+                let objects: Vec<&mut JsonValue> = match &mut json {
+                    JsonValue::Array(arr) => arr.iter_mut().collect(),
+                    value => vec![value],
+                };
+                for obj in objects.into_iter().filter_map(JsonValue::as_object_mut) {
+                    obj.retain(|k, _| allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+                }
+            }
+            inject_claim_columns(&state.config, table, claims, &mut json)?;
+            let objects: Vec<&serde_json::Map<String, JsonValue>> = match &json {
+                JsonValue::Array(arr) => arr
+                    .iter()
+                    .map(|v| {
+                        v.as_object().ok_or_else(|| {
+                            Error::BadRequest("Array must contain objects".to_string())
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                JsonValue::Object(obj) => vec![obj],
+                _ => {
+                    return Err(Error::BadRequest(
+                        "Body must be object or array".to_string(),
+                    ))
+                }
+            };
+            if objects.is_empty() {
+                return Err(Error::BadRequest("Empty body".to_string()));
+            }
+            check_body(&state.config, table, &objects, true)?;
+
+            let mut keys: Vec<String> = Vec::new();
+            for obj in &objects {
+                for key in obj.keys() {
+                    if !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+            let columns = writable_columns(table, keys, prefer)?;
+            let statements = objects
+                .chunks(query::insert_batch_rows(columns.len()))
+                .map(|chunk| {
+                    let present = presence(chunk, &columns);
+                    let built = counted(
+                        query::build_insert(table, &columns, &present, returning)?,
+                        returning,
+                    );
+                    let params = collect_insert_params(table, chunk, &columns)?;
+                    Ok((built.sql, params))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            (StatusCode::CREATED, statements)
+        }
+        Dml::Update => {
+            let mut obj = match body()? {
+                JsonValue::Object(obj) => obj,
+                _ => {
+                    return Err(Error::BadRequest(
+                        "PATCH body must be an object".to_string(),
+                    ))
+                }
+            };
+            if let Some(allowed) = column_whitelist(table, &query_params)? {
+                obj.retain(|k, _| allowed.iter().any(|c| c.eq_ignore_ascii_case(k)));
+            }
+            check_body(&state.config, table, &[&plain_values(&obj)], false)?;
+            let columns = writable_columns(table, obj.keys().cloned().collect(), prefer)?;
+            let filter_nodes = build_filters_from_params(&query_params, table, &state.config)?;
+            let limit = mutation_limit(&query_params, &filter_nodes)?;
+            let if_match = if_match_condition(table, op.if_match.as_deref())?;
+            let mut statements = stale_etag_check(table, &filter_nodes, if_match.as_ref())?;
+            let conditional: Vec<FilterNode> =
+                filter_nodes.iter().cloned().chain(if_match).collect();
+            let (assignments, mut params) = update_assignments(table, &columns, &obj)?;
+            let built = counted(
+                query::build_update(table, &assignments, &conditional, limit.as_ref(), returning)?,
+                returning,
+            );
+            params.extend(built.params);
+            statements.push((built.sql, params));
+            (StatusCode::OK, statements)
+        }
+        Dml::Delete => {
+            let filter_nodes = build_filters_from_params(&query_params, table, &state.config)?;
+            let limit = mutation_limit(&query_params, &filter_nodes)?;
+            let if_match = if_match_condition(table, op.if_match.as_deref())?;
+            let mut statements = stale_etag_check(table, &filter_nodes, if_match.as_ref())?;
+            let conditional: Vec<FilterNode> =
+                filter_nodes.iter().cloned().chain(if_match).collect();
+            let built = counted(
+                query::build_delete(table, &conditional, limit.as_ref(), returning)?,
+                returning,
+            );
+            statements.push((built.sql, built.params));
+            (StatusCode::OK, statements)
+        }
+    };
+    Ok(BatchStatements {
+        schema: schema_name,
+        table: table_name,
+        status,
+        statements,
+        returning,
+    })
+}
+
+/// Body of `POST /sign`.
+#[derive(Debug, serde::Deserialize)]
+pub struct SignRequest {
+    /// Table path and query to grant, e.g. `/orders?id=eq.7`
+    pub path: String,
+    /// Link lifetime in seconds (capped by `--signed-url-max-secs`)
+    pub expires_in: Option<u64>,
+}
+
+/// POST /sign: mint a short-lived link that performs one table GET as the
+/// caller, without an `Authorization` header.
+pub async fn handle_sign(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let secret = state
+        .config
+        .signed_url_secret
+        .as_deref()
+        .ok_or_else(|| Error::NotFound("Signed URLs are not enabled".to_string()))?;
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?
+        .ok_or_else(|| Error::Unauthorized(AUTH_REQUIRED.to_string()))?;
+    let request: SignRequest = serde_json::from_slice(&body)
+        .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?;
+    if !request.path.starts_with('/') {
+        return Err(Error::BadRequest(
+            "path must start with /, e.g. /orders?id=eq.7".to_string(),
+        ));
+    }
+    // The link is bound to the table the path names now
+    let path = request.path.split('?').next().unwrap_or_default();
+    let path_params: Vec<(String, String)> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| Ok((String::new(), decode_path_segment(s)?)))
+        .collect::<Result<_, Error>>()?;
+    let (schema_name, table_name) = resolve_table_path(&path_params, None, &state)?;
+
+    let expires = signed_url::expiry(&state.config, request.expires_in);
+    let url = signed_url::sign(
+        secret,
+        &request.path,
+        (&schema_name, &table_name),
+        &claims,
+        expires,
+    )?;
+    let json = serde_json::json!({ "url": url, "expires": expires }).to_string();
+    Ok(response::build_response(
+        json.into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// One anonymized row per exposed table, read as `openapi_examples_role`,
+/// for the OpenAPI `example`s (see [`redact::anonymize`]). Tables the role
+/// can't read, empty ones and `example_exclude = ["*"]` are left out. Run
+/// once per schema load, see [`SchemaCache::examples`].
+pub async fn sample_examples(
+    state: &AppState,
+    schema_cache: &SchemaCache,
+) -> HashMap<(String, String), JsonValue> {
+    let mut examples = HashMap::new();
+    let Some(role) = &state.config.openapi_examples_role else {
+        return examples;
+    };
+    let mut ctx = SqlContext::new(&state.config, &None, &HeaderMap::new(), "GET", "/");
+    ctx.session = vec![format!("EXECUTE AS USER = '{}';", role.replace('\'', "''"))];
+    ctx.role = Some(role.clone());
+
+    for (key, table) in &schema_cache.tables {
+        if !state.config.is_schema_exposed(&key.0) {
+            continue;
+        }
+        let exclude = state
+            .config
+            .table_config(&key.0, &table.name)
+            .map(|t| t.example_exclude.as_slice())
+            .unwrap_or_default();
+        if exclude.iter().any(|c| c == "*") {
+            continue;
+        }
+        let built = query::BuiltQuery {
+            sql: format!("SELECT TOP (1) * FROM {}", table.full_name()),
+            params: Vec::new(),
+        };
+        let row = match execute_query_to_json(state, &built, &ctx).await {
+            Ok(rows) => rows.into_iter().next(),
+            Err(e) => {
+                tracing::debug!("No OpenAPI example for {}: {}", table.full_name(), e);
+                continue;
+            }
+        };
+        let Some(row) = row else {
+            continue;
+        };
+        let example: serde_json::Map<String, JsonValue> = row
+            .iter()
+            .filter(|(column, _)| !exclude.iter().any(|c| c.eq_ignore_ascii_case(column)))
+            .filter_map(|(column, value)| Some((column.clone(), redact::anonymize(column, value)?)))
+            .collect();
+        examples.insert(key.clone(), JsonValue::Object(example));
+    }
+    examples
+}
+
+/// Privilege checks for `GET /tables`, evaluated as the request's database
+/// user (the session runs `EXECUTE AS` first).
+const TABLE_PERMS_SQL: &str = "SELECT s.name AS [schema], o.name AS [name], \
+     HAS_PERMS_BY_NAME(QUOTENAME(s.name) + '.' + QUOTENAME(o.name), 'OBJECT', 'INSERT') AS [insert], \
+     HAS_PERMS_BY_NAME(QUOTENAME(s.name) + '.' + QUOTENAME(o.name), 'OBJECT', 'UPDATE') AS [update], \
+     HAS_PERMS_BY_NAME(QUOTENAME(s.name) + '.' + QUOTENAME(o.name), 'OBJECT', 'DELETE') AS [delete] \
+     FROM sys.objects o JOIN sys.schemas s ON o.schema_id = s.schema_id \
+     WHERE o.type IN ('U', 'V') \
+     AND HAS_PERMS_BY_NAME(QUOTENAME(s.name) + '.' + QUOTENAME(o.name), 'OBJECT', 'SELECT') = 1";
+
+/// GET handler listing the tables and views the caller can SELECT from,
+/// with their write privileges, so UIs can render only what's accessible.
+pub async fn handle_tables(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let ctx = SqlContext::new(&state.config, &claims, &headers, "GET", "/tables");
+
+    let built = query::BuiltQuery {
+        sql: TABLE_PERMS_SQL.to_string(),
+        params: Vec::new(),
+    };
+    let rows = execute_query_to_json(&state, &built, &ctx).await?;
+
+    let active_schema = state.active_schema();
+    let schema_cache = state.schema.read().await;
+    let multi_schema = schema_cache.has_multiple_schemas();
+    let granted = |row: &serde_json::Map<String, JsonValue>, key: &str| matches!(row.get(key), Some(JsonValue::Number(n)) if n.as_i64() == Some(1));
+    let mut tables: Vec<JsonValue> = Vec::new();
+    for row in &rows {
+        let (Some(schema_name), Some(name)) = (
+            row.get("schema").and_then(JsonValue::as_str),
+            row.get("name").and_then(JsonValue::as_str),
+        ) else {
+            continue;
+        };
+        // Only what lazypaw actually serves
+        let Some(table) = schema_cache.get_table(schema_name, name) else {
+            continue;
+        };
+        if !state.config.is_schema_exposed(&table.schema) {
+            continue;
+        }
+        let path = if !multi_schema || table.schema.eq_ignore_ascii_case(&active_schema) {
+            format!("/{}", table.name)
+        } else {
+            format!("/{}/{}", table.schema, table.name)
+        };
+        tables.push(serde_json::json!({
+            "schema": table.schema,
+            "name": table.name,
+            "kind": if table.is_view { "view" } else { "table" },
+            "path": path,
+            "insert": table.accepts(Dml::Insert) && granted(row, "insert"),
+            "update": table.accepts(Dml::Update) && granted(row, "update"),
+            "delete": table.accepts(Dml::Delete) && granted(row, "delete"),
+        }));
+    }
+
+    let json = serde_json::to_string(&tables).unwrap_or_default();
+    Ok(response::build_response(
+        json.into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// GET /admin/cutover: the active and standby schemas.
+pub async fn handle_cutover_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+    let profiles = state.profiles.read().unwrap().clone();
+    Ok(profiles_response(&profiles))
+}
+
+/// POST /admin/cutover: swap the active and standby schemas, so unqualified
+/// paths switch to the new version in one step and the old one stays
+/// reachable as the standby (call again to roll back).
+pub async fn handle_cutover(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+
+    let next = state
+        .profiles
+        .read()
+        .unwrap()
+        .next
+        .clone()
+        .ok_or_else(|| Error::BadRequest("No next schema configured".to_string()))?;
+    if !state.config.is_schema_exposed(&next) {
+        return Err(Error::BadRequest(format!("Schema not exposed: {}", next)));
+    }
+    {
+        // Refuse to switch to a schema with nothing in it (typo, not deployed)
+        let schema_cache = state.schema.read().await;
+        if !schema_cache
+            .tables
+            .values()
+            .any(|t| t.schema.eq_ignore_ascii_case(&next))
+        {
+            return Err(Error::BadRequest(format!(
+                "Schema {} has no tables or views",
+                next
+            )));
+        }
+    }
+
+    let profiles = {
+        let mut profiles = state.profiles.write().unwrap();
+        // Re-check under the write lock: a concurrent cutover may have won
+        if profiles.next.as_deref() != Some(next.as_str()) {
+            return Err(Error::Conflict(
+                "Concurrent cutover; check /admin/cutover and retry".to_string(),
+            ));
+        }
+        let previous = std::mem::replace(&mut profiles.active, next);
+        profiles.next = Some(previous);
+        profiles.clone()
+    };
+    tracing::info!(
+        "Schema cutover: {} is now active ({} on standby)",
+        profiles.active,
+        profiles.next.as_deref().unwrap_or_default()
+    );
+    Ok(profiles_response(&profiles))
+}
+
+/// Admin endpoints need an authenticated caller whose role maps to
+/// `admin_role`; with no admin role configured they are disabled.
+fn require_admin(config: &AppConfig, headers: &HeaderMap) -> Result<(), Error> {
+    let admin_role = config
+        .admin_role
+        .as_deref()
+        .ok_or_else(|| Error::Forbidden("Admin endpoints are disabled".to_string()))?;
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, config)?;
+    let role = claims
+        .as_ref()
+        .and_then(|c| auth::resolve_role(c, config))
+        .ok_or_else(|| Error::Unauthorized(AUTH_REQUIRED.to_string()))?;
+    if !role.eq_ignore_ascii_case(admin_role) {
+        return Err(Error::Forbidden(format!(
+            "Role {} may not call admin endpoints",
+            role
+        )));
+    }
+    Ok(())
+}
+
+/// GET /admin/pool: every open database connection with its age, state,
+/// last use, role and server session ID, for matching against
+/// `sys.dm_exec_sessions` when chasing stuck sessions.
+pub async fn handle_pool_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+    let mut json = serde_json::json!({ "primary": state.pool.connections() });
+    if let Some(read_pool) = &state.read_pool {
+        json["replica"] = serde_json::json!(read_pool.connections());
+    }
+    Ok(response::build_response(
+        json.to_string().into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// GET /admin/realtime: subscriptions per table, delivery counters and
+/// how far the Change Tracking poller is behind.
+pub async fn handle_realtime_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+    let engine = state
+        .realtime
+        .as_ref()
+        .ok_or_else(|| Error::NotFound("Realtime is not enabled".to_string()))?;
+    let json = serde_json::to_string(&engine.stats().await).unwrap_or_default();
+    Ok(response::build_response(
+        json.into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// GET /metrics: Prometheus exposition of the realtime counters.
+pub async fn handle_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+    let body = match &state.realtime {
+        Some(engine) => engine.stats().await.to_prometheus(),
+        None => String::new(),
+    };
+    Ok(response::build_response(
+        body.into_bytes(),
+        "text/plain; version=0.0.4; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// POST /admin/pool/{id}/recycle: close one connection, immediately when
+/// idle or once its current request finishes. Connection IDs are unique
+/// across the primary and replica pools.
+pub async fn handle_pool_recycle(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    require_admin(&state.config, &headers)?;
+    let mut recycled = state.pool.recycle(id).await;
+    if !recycled {
+        if let Some(read_pool) = &state.read_pool {
+            recycled = read_pool.recycle(id).await;
+        }
+    }
+    if !recycled {
+        return Err(Error::NotFound(format!("No connection {}", id)));
+    }
+    Ok(response::build_response(
+        Vec::new(),
+        "application/json; charset=utf-8",
+        StatusCode::NO_CONTENT,
+        None,
+        None,
+    ))
+}
+
+fn profiles_response(profiles: &SchemaProfiles) -> Response {
+    let json = serde_json::json!({
+        "active": profiles.active,
+        "next": profiles.next,
+    });
+    response::build_response(
+        json.to_string().into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    )
+}
+
+/// POST /rpc/<procedure> handler.
+pub async fn handle_rpc(
+    State(state): State<AppState>,
+    Path(proc_name): Path<String>,
+    headers: HeaderMap,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "POST",
+        &format!("/rpc/{}", proc_name),
+    );
+    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
 
     let body_str = String::from_utf8(body.to_vec())
         .map_err(|_| Error::BadRequest("Invalid UTF-8 body".to_string()))?;
 
-    let params: serde_json::Map<String, JsonValue> = if body_str.is_empty() {
-        serde_json::Map::new()
-    } else {
-        serde_json::from_str(&body_str)
-            .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?
+    let params: serde_json::Map<String, JsonValue> = if body_str.is_empty() {
+        serde_json::Map::new()
+    } else {
+        serde_json::from_str(&body_str)
+            .map_err(|e| Error::BadRequest(format!("Invalid JSON: {}", e)))?
+    };
+
+    let routine = find_routine(&state, &proc_name).await?;
+
+    let call = rpc_call(&routine, &params)?;
+    let is_procedure = routine.kind == "P";
+    let mut sql = call.exec.clone();
+    let mut param_values = call.params.clone();
+
+    let (timeout_secs, max_rows) = state.config.procedure_limits(&proc_name);
+
+    // Query parameters filter, order and page the result set
+    let mut range_offset = None;
+    if !query_params.is_empty() {
+        let shape = describe_rpc(&state, &ctx, &proc_name).await?;
+        let table = rpc_result_table(&proc_name, &shape.columns);
+        let filter_nodes = build_filters_from_params(&query_params, &table, &state.config)?;
+        let order = query::parse_order(query_params.get("order").map_or("", |s| s.as_str()))?;
+        if let Some(spec) = order.iter().find(|o| table.column(&o.column).is_none()) {
+            return Err(Error::BadRequest(format!(
+                "Unknown column in order: {}",
+                spec.column
+            )));
+        }
+        let limit: Option<i64> = query_params.get("limit").and_then(|v| v.parse().ok());
+        let offset = query_params.get("offset").and_then(|v| v.parse().ok());
+        range_offset = Some(offset.unwrap_or(0));
+        // One row past the cap is enough to tell it was exceeded
+        let limit = match (limit, max_rows) {
+            (Some(l), Some(cap)) => Some(l.min(cap + 1)),
+            (l, cap) => l.or(cap.map(|c| c + 1)),
+        };
+
+        if shape.function {
+            // Functions compose directly: SELECT ... FROM fn(@P1, DEFAULT, ...)
+            let args = positional_args(&routine, &params)?;
+            param_values.clear();
+            let mut arg_sql = Vec::new();
+            for arg in args {
+                match arg {
+                    Some(val) => {
+                        param_values.push(val);
+                        arg_sql.push(format!("@P{}", param_values.len()));
+                    }
+                    None => arg_sql.push("DEFAULT".to_string()),
+                }
+            }
+            let source = format!("{}({})", routine.full_name(), arg_sql.join(", "));
+            let built = query::build_rpc_select(
+                &source,
+                &table,
+                &filter_nodes,
+                &order,
+                limit,
+                offset,
+                param_values.len(),
+            )?;
+            param_values.extend(built.params);
+            sql = built.sql;
+        } else {
+            // Procedures can't be selected from: capture the output first
+            let columns: Vec<String> = shape
+                .columns
+                .iter()
+                .map(|(name, ty)| format!("[{}] {} NULL", escape_ident(name), ty))
+                .collect();
+            let built = query::build_rpc_select(
+                "#rpc_result",
+                &table,
+                &filter_nodes,
+                &order,
+                limit,
+                offset,
+                param_values.len(),
+            )?;
+            param_values.extend(built.params);
+            sql = format!(
+                "IF OBJECT_ID('tempdb..#rpc_result') IS NOT NULL DROP TABLE #rpc_result;\n\
+                 CREATE TABLE #rpc_result ({});\n\
+                 INSERT INTO #rpc_result {};\n\
+                 {};\n\
+                 DROP TABLE #rpc_result;",
+                columns.join(", "),
+                sql,
+                built.sql
+            );
+        }
+    }
+
+    let what = format!("Procedure {}", proc_name);
+    if !is_procedure {
+        let json_rows = execute_rpc(
+            &state,
+            &ctx,
+            &what,
+            &sql,
+            &param_values,
+            timeout_secs,
+            max_rows,
+        )
+        .await?;
+        let range =
+            range_offset.map(|offset| build_content_range(offset, json_rows.len() as i64, None));
+        let response = rpc_response(format, &json_rows, range)?;
+        return Ok(with_consistency_token(&state, response).await);
+    }
+
+    let sql = call.batch(&sql);
+    let mut sets = execute_rpc_sets(
+        &state,
+        &ctx,
+        &what,
+        &sql,
+        &param_values,
+        timeout_secs,
+        max_rows,
+    )
+    .await?;
+    // The return value and OUTPUT parameters come last, after any rows
+    let mut out = sets
+        .pop()
+        .and_then(|rows| rows.into_iter().next())
+        .unwrap_or_default();
+    let json_rows = sets.into_iter().next().unwrap_or_default();
+    let return_value = out.remove(RETURN_COLUMN).unwrap_or(JsonValue::Null);
+    let out: serde_json::Map<String, JsonValue> = out
+        .into_iter()
+        .map(|(name, value)| (name.strip_prefix('@').unwrap_or(&name).to_string(), value))
+        .collect();
+
+    let range =
+        range_offset.map(|offset| build_content_range(offset, json_rows.len() as i64, None));
+    let mut response = if !call.outputs.is_empty() {
+        let json = serde_json::json!({
+            "rows": json_rows,
+            "return_value": return_value,
+            "output": out,
+        });
+        response::build_response(
+            json.to_string().into_bytes(),
+            "application/json; charset=utf-8",
+            StatusCode::OK,
+            range,
+            None,
+        )
+    } else {
+        rpc_response(format, &json_rows, range)?
+    };
+    if let Some(rc) = return_value.as_i64() {
+        response
+            .headers_mut()
+            .insert(RETURN_VALUE_HEADER, rc.into());
+    }
+    Ok(with_consistency_token(&state, response).await)
+}
+
+/// Header carrying a procedure's `RETURN` value.
+const RETURN_VALUE_HEADER: &str = "x-return-value";
+
+/// Column the return value is read back under. OUTPUT parameters are read
+/// back under their names with the `@`, so none can collide with it.
+const RETURN_COLUMN: &str = "return_value";
+
+/// A procedure call built from the body: the `EXEC` with the arguments
+/// the body names, typed for their parameters. OUTPUT parameters the body
+/// supplies are bound to variables (initialised from the body), read back
+/// after the call with the return value; ones it leaves out keep their
+/// declared defaults and aren't read back.
+struct RpcCall {
+    /// `EXEC @rpc_return = [schema].[proc] @a = @P1, @b = @rpc_out1 OUTPUT`
+    exec: String,
+    params: Vec<SqlParam>,
+    /// Variable declarations: the return value, then one per OUTPUT argument
+    declares: Vec<String>,
+    /// `@rpc_out1 AS [@name]` for each OUTPUT argument
+    outputs: Vec<String>,
+}
+
+impl RpcCall {
+    /// `sql` (the `EXEC`, or a statement wrapping it) between the variable
+    /// declarations and the `SELECT` reading back the return value and
+    /// OUTPUT parameters.
+    fn batch(&self, sql: &str) -> String {
+        let mut select = vec![format!("@rpc_return AS [{}]", RETURN_COLUMN)];
+        select.extend(self.outputs.iter().cloned());
+        format!(
+            "DECLARE {};\n{};\nSELECT {};",
+            self.declares.join(", "),
+            sql.trim_end_matches(';'),
+            select.join(", ")
+        )
+    }
+}
+
+fn rpc_call(
+    routine: &crate::schema::RoutineInfo,
+    body: &serde_json::Map<String, JsonValue>,
+) -> Result<RpcCall, Error> {
+    let mut args = Vec::new();
+    let mut params = Vec::new();
+    let mut declares = vec!["@rpc_return int".to_string()];
+    let mut outputs = Vec::new();
+    for (key, val) in body {
+        let param = routine.param(key).ok_or_else(|| {
+            Error::BadRequest(format!("Unknown argument for {}: {}", routine.name, key))
+        })?;
+        params.push(rpc_arg(routine, param, val)?);
+        if param.is_output {
+            let var = format!("@rpc_out{}", outputs.len() + 1);
+            declares.push(format!(
+                "{} {} = @P{}",
+                var,
+                param.type_decl(),
+                params.len()
+            ));
+            args.push(format!("@{} = {} OUTPUT", param.name, var));
+            outputs.push(format!("{} AS [@{}]", var, escape_ident(&param.name)));
+        } else {
+            args.push(format!("@{} = @P{}", param.name, params.len()));
+        }
+    }
+    let exec = if routine.kind == "P" {
+        format!("EXEC @rpc_return = {}", routine.full_name())
+    } else {
+        format!("EXEC {}", routine.full_name())
+    };
+    Ok(RpcCall {
+        exec: if args.is_empty() {
+            exec
+        } else {
+            format!("{} {}", exec, args.join(", "))
+        },
+        params,
+        declares,
+        outputs,
+    })
+}
+
+/// GET /rpc/<function>: call a scalar or table-valued function. Query
+/// parameters named after the function's parameters are its arguments
+/// (`?region=west`, or `?region=eq.west`); the rest filter, order and page
+/// a table-valued function's rows. A scalar function responds with its
+/// bare value.
+pub async fn handle_rpc_get(
+    State(state): State<AppState>,
+    Path(fn_name): Path<String>,
+    headers: HeaderMap,
+    AxumQuery(query_params): AxumQuery<QueryParams>,
+) -> Result<Response, Error> {
+    let auth_header = headers.get("authorization").and_then(|v| v.to_str().ok());
+    let claims = auth::authenticate(auth_header, &state.config)?;
+    let mut ctx = SqlContext::new(
+        &state.config,
+        &claims,
+        &headers,
+        "GET",
+        &format!("/rpc/{}", fn_name),
+    );
+    let format = response::parse_accept(headers.get("accept").and_then(|v| v.to_str().ok()));
+    // Functions can't write, so they may run on the replica
+    ctx.read_pool = route_read(&state, &headers).await?;
+
+    let routine = find_routine(&state, &fn_name).await?;
+    if !routine.is_function() {
+        return Err(Error::NotFound(format!(
+            "No function: {} (procedures are called with POST)",
+            fn_name
+        )));
+    }
+
+    // Split arguments from result filters
+    let mut args = serde_json::Map::new();
+    let mut rest = Vec::new();
+    for (key, value) in query_params.pairs() {
+        if routine.param(key).is_some() {
+            let value = rpc_get_arg(key, value)?;
+            args.insert(key.clone(), JsonValue::String(value.to_string()));
+        } else {
+            rest.push((key.clone(), value.clone()));
+        }
+    }
+    let mut param_values = Vec::new();
+    let mut arg_sql = Vec::new();
+    for arg in positional_args(&routine, &args)? {
+        match arg {
+            Some(val) => {
+                param_values.push(val);
+                arg_sql.push(format!("@P{}", param_values.len()));
+            }
+            None => arg_sql.push("DEFAULT".to_string()),
+        }
+    }
+    let call = format!("{}({})", routine.full_name(), arg_sql.join(", "));
+    let (timeout_secs, max_rows) = state.config.procedure_limits(&fn_name);
+
+    if routine.kind == "FN" {
+        if let Some((key, _)) = rest.first() {
+            return Err(Error::BadRequest(format!(
+                "Unknown argument for {}: {} (scalar functions take no filters)",
+                fn_name, key
+            )));
+        }
+        let sql = format!("SELECT {} AS [value]", call);
+        let rows = execute_rpc(
+            &state,
+            &ctx,
+            &format!("Function {}", fn_name),
+            &sql,
+            &param_values,
+            timeout_secs,
+            None,
+        )
+        .await?;
+        let value = rows
+            .into_iter()
+            .next()
+            .and_then(|mut row| row.remove("value"))
+            .unwrap_or(JsonValue::Null);
+        let response = match format {
+            // One row named after the function, as PostgREST does
+            ResponseFormat::SingleObjectJson | ResponseFormat::Csv => {
+                let mut row = serde_json::Map::new();
+                row.insert(routine.name.clone(), value);
+                rpc_response(format, &[row], None)?
+            }
+            _ => response::build_response(
+                value.to_string().into_bytes(),
+                "application/json; charset=utf-8",
+                StatusCode::OK,
+                None,
+                None,
+            ),
+        };
+        return Ok(with_consistency_token(&state, response).await);
+    }
+
+    let rest = QueryParams::from(rest);
+    let shape = describe_rpc(&state, &ctx, &fn_name).await?;
+    let table = rpc_result_table(&fn_name, &shape.columns);
+    let filter_nodes = build_filters_from_params(&rest, &table, &state.config)?;
+    let order = query::parse_order(rest.get("order").map_or("", |s| s.as_str()))?;
+    if let Some(spec) = order.iter().find(|o| table.column(&o.column).is_none()) {
+        return Err(Error::BadRequest(format!(
+            "Unknown column in order: {}",
+            spec.column
+        )));
+    }
+    let limit: Option<i64> = rest.get("limit").and_then(|v| v.parse().ok());
+    let offset = rest.get("offset").and_then(|v| v.parse().ok());
+    let limit = match (limit, max_rows) {
+        (Some(l), Some(cap)) => Some(l.min(cap + 1)),
+        (l, cap) => l.or(cap.map(|c| c + 1)),
+    };
+    let built = query::build_rpc_select(
+        &call,
+        &table,
+        &filter_nodes,
+        &order,
+        limit,
+        offset,
+        param_values.len(),
+    )?;
+    param_values.extend(built.params);
+
+    let json_rows = execute_rpc(
+        &state,
+        &ctx,
+        &format!("Function {}", fn_name),
+        &built.sql,
+        &param_values,
+        timeout_secs,
+        max_rows,
+    )
+    .await?;
+    let range = build_content_range(offset.unwrap_or(0), json_rows.len() as i64, None);
+    let response = rpc_response(format, &json_rows, Some(range))?;
+    Ok(with_consistency_token(&state, response).await)
+}
+
+/// The value of a function argument in a GET query string: bare or with
+/// `eq.`. Any other operator is a 400 rather than being passed on as part
+/// of the value.
+fn rpc_get_arg<'a>(key: &str, value: &'a str) -> Result<&'a str, Error> {
+    if let Some(value) = value.strip_prefix("eq.") {
+        return Ok(value);
+    }
+    if filters::parse_filter(key, value).is_ok() {
+        return Err(Error::BadRequestHint {
+            message: format!("Unsupported operator for argument {}: {}", key, value),
+            hint: format!("Pass arguments bare or with eq., e.g. {}=eq.<value>", key),
+        });
+    }
+    Ok(value)
+}
+
+/// Run an RPC statement within the procedure's timeout and row cap.
+/// `what` names the routine in errors, e.g. `Procedure dbo.sync`.
+async fn execute_rpc(
+    state: &AppState,
+    ctx: &SqlContext,
+    what: &str,
+    sql: &str,
+    param_values: &[SqlParam],
+    timeout_secs: Option<u64>,
+    max_rows: Option<i64>,
+) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
+    let sets =
+        execute_rpc_sets(state, ctx, what, sql, param_values, timeout_secs, max_rows).await?;
+    Ok(sets.into_iter().next().unwrap_or_default())
+}
+
+/// Every result set of an RPC statement, in order. Rows are read as they
+/// arrive, and the call fails as soon as the first result set passes
+/// `max_rows`, before the rest is read.
+async fn execute_rpc_sets(
+    state: &AppState,
+    ctx: &SqlContext,
+    what: &str,
+    sql: &str,
+    param_values: &[SqlParam],
+    timeout_secs: Option<u64>,
+    max_rows: Option<i64>,
+) -> Result<Vec<Vec<serde_json::Map<String, JsonValue>>>, Error> {
+    use futures_util::TryStreamExt;
+
+    tracing::debug!(
+        sql = %sql,
+        params = %redact::params(param_values, state.config.log_params),
+        "Executing procedure"
+    );
+
+    // Build context SQL
+    let ctx_stmts = &ctx.session;
+    let sql = ctx.tagged(sql);
+    let full_sql = if ctx_stmts.is_empty() {
+        format!("SET NOCOUNT ON;\n{}", sql)
+    } else {
+        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), sql)
+    };
+
+    let mut conn = ctx.checkout(ctx.pool(state)).await?;
+    let client = conn.client();
+
+    let mut query = claw::Query::new(full_sql);
+    for val in param_values {
+        bind_param(&mut query, val);
+    }
+
+    let run = async move {
+        let mut stream = query
+            .query(client)
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+        let mut sets: Vec<Vec<serde_json::Map<String, JsonValue>>> = Vec::new();
+        while let Some(item) = stream
+            .try_next()
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?
+        {
+            match item {
+                // Each result set starts with its column metadata
+                claw::QueryItem::Metadata(_) => sets.push(Vec::new()),
+                claw::QueryItem::Row(row) => {
+                    if sets.is_empty() {
+                        sets.push(Vec::new());
+                    }
+                    let last = sets.len() - 1;
+                    sets[last].push(types::row_to_json(&row));
+                    if last == 0 {
+                        check_rpc_rows(what, &sets[0], max_rows)?;
+                    }
+                }
+            }
+        }
+        Ok::<_, Error>(sets)
+    };
+    let sets = match timeout_secs {
+        Some(secs) => {
+            let outcome = tokio::time::timeout(std::time::Duration::from_secs(secs), run).await;
+            let Ok(sets) = outcome else {
+                // Closing the session makes the server abort the batch and
+                // roll back its open transaction
+                conn.discard();
+                return Err(Error::Timeout(format!(
+                    "{} ran longer than {}s",
+                    what, secs
+                )));
+            };
+            sets
+        }
+        None => run.await,
+    };
+    // A result cut off at the cap leaves the rest of the batch unread
+    if matches!(sets, Err(Error::BadRequestHint { .. })) {
+        conn.discard();
+    }
+    sets
+}
+
+/// Reject a result set over the procedure's row cap.
+fn check_rpc_rows(
+    what: &str,
+    rows: &[serde_json::Map<String, JsonValue>],
+    max_rows: Option<i64>,
+) -> Result<(), Error> {
+    if let Some(cap) = max_rows {
+        if rows.len() as i64 > cap {
+            return Err(Error::BadRequestHint {
+                message: format!("{} returned more than {} rows", what, cap),
+                hint: "Page the result with ?limit= and ?offset=".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// RPC rows as a JSON array, or a single object for
+/// `Accept: application/vnd.pgrst.object+json`.
+fn rpc_response(
+    format: ResponseFormat,
+    json_rows: &[serde_json::Map<String, JsonValue>],
+    range: Option<String>,
+) -> Result<Response, Error> {
+    match format {
+        ResponseFormat::SingleObjectJson => {
+            if json_rows.len() != 1 {
+                return Err(Error::SingleObjectExpected(json_rows.len()));
+            }
+            let json = serde_json::to_string(&json_rows[0]).unwrap_or_default();
+            Ok(response::build_response(
+                json.into_bytes(),
+                "application/vnd.pgrst.object+json; charset=utf-8",
+                StatusCode::OK,
+                range,
+                None,
+            ))
+        }
+        ResponseFormat::Csv => {
+            let columns: Vec<String> = json_rows
+                .first()
+                .map(|row| row.keys().cloned().collect())
+                .unwrap_or_default();
+            let csv_str = response::rows_to_csv(json_rows, &columns)?;
+            Ok(response::build_response(
+                csv_str.into_bytes(),
+                "text/csv; charset=utf-8",
+                StatusCode::OK,
+                range,
+                None,
+            ))
+        }
+        _ => {
+            let json = response::rows_to_json(json_rows);
+            Ok(response::build_response(
+                json.into_bytes(),
+                "application/json; charset=utf-8",
+                StatusCode::OK,
+                range,
+                None,
+            ))
+        }
+    }
+}
+
+/// Result columns of a table-returning routine: described by
+/// `sys.dm_exec_describe_first_result_set_for_object` for procedures, and
+/// from `sys.columns` for table-valued functions (`IF`/`TF`).
+const RPC_SHAPE_SQL: &str =
+    "SELECT o.type AS [kind], r.column_ordinal AS [ord], r.name AS [name], \
+     r.system_type_name AS [type], r.error_message AS [error] \
+     FROM sys.objects o \
+     CROSS APPLY sys.dm_exec_describe_first_result_set_for_object(o.object_id, 0) r \
+     WHERE o.object_id = OBJECT_ID(@P1) AND o.type = 'P' AND ISNULL(r.is_hidden, 0) = 0 \
+     UNION ALL \
+     SELECT o.type, c.column_id, c.name, TYPE_NAME(c.user_type_id), NULL \
+     FROM sys.objects o JOIN sys.columns c ON c.object_id = o.object_id \
+     WHERE o.object_id = OBJECT_ID(@P1) AND o.type IN ('IF', 'TF') \
+     ORDER BY [ord]";
+
+/// What an RPC returns, for filtering and paging it.
+struct RpcShape {
+    /// Table-valued function rather than a procedure
+    function: bool,
+    /// Result columns: name and type declaration (`nvarchar(50)`)
+    columns: Vec<(String, String)>,
+}
+
+async fn describe_rpc(state: &AppState, ctx: &SqlContext, name: &str) -> Result<RpcShape, Error> {
+    let built = query::BuiltQuery {
+        sql: RPC_SHAPE_SQL.to_string(),
+        params: vec![SqlParam::Text(format!("[{}]", query::escape_ident(name)))],
+    };
+    let rows = execute_query_to_json(state, &built, ctx).await?;
+    if rows.is_empty() {
+        return Err(Error::NotFound(format!(
+            "No procedure or table-valued function returning rows: {}",
+            name
+        )));
+    }
+    if let Some(error) = rows
+        .iter()
+        .find_map(|row| row.get("error").and_then(JsonValue::as_str))
+    {
+        return Err(Error::BadRequest(format!(
+            "Cannot filter or page {}: its result set can't be described ({})",
+            name, error
+        )));
+    }
+
+    let mut columns = Vec::with_capacity(rows.len());
+    for row in &rows {
+        let column = row.get("name").and_then(JsonValue::as_str);
+        let ty = row.get("type").and_then(JsonValue::as_str);
+        match (column, ty) {
+            (Some(column), Some(ty)) if !column.is_empty() => {
+                columns.push((column.to_string(), ty.to_string()))
+            }
+            _ => {
+                return Err(Error::BadRequest(format!(
+                    "Cannot filter or page {}: its result set has unnamed columns",
+                    name
+                )))
+            }
+        }
+    }
+    let kind = rows[0]
+        .get("kind")
+        .and_then(JsonValue::as_str)
+        .unwrap_or("");
+    Ok(RpcShape {
+        function: matches!(kind.trim(), "IF" | "TF"),
+        columns,
+    })
+}
+
+/// Positional arguments for a function call from the named ones in the
+/// body: `None` where the body leaves a parameter out (passed as
+/// `DEFAULT`). Names match with or without the leading `@`.
+fn positional_args(
+    routine: &crate::schema::RoutineInfo,
+    body: &serde_json::Map<String, JsonValue>,
+) -> Result<Vec<Option<SqlParam>>, Error> {
+    if let Some(unknown) = body.keys().find(|key| routine.param(key).is_none()) {
+        return Err(Error::BadRequest(format!(
+            "Unknown argument for {}: {}",
+            routine.name, unknown
+        )));
+    }
+    routine
+        .params
+        .iter()
+        .map(|param| {
+            body.iter()
+                .find(|(key, _)| {
+                    key.trim_start_matches('@')
+                        .eq_ignore_ascii_case(&param.name)
+                })
+                .map(|(_, val)| rpc_arg(routine, param, val))
+                .transpose()
+        })
+        .collect()
+}
+
+/// A procedure or function in the active schema, from the schema cache.
+async fn find_routine(state: &AppState, name: &str) -> Result<crate::schema::RoutineInfo, Error> {
+    let schema = state.active_schema();
+    let schema_cache = state.schema.read().await;
+    schema_cache
+        .get_routine(&schema, name)
+        .cloned()
+        .ok_or_else(|| Error::NotFound(format!("No procedure or function: {}", name)))
+}
+
+/// Bind value for an RPC argument, typed for its parameter. Values that
+/// can't convert are a 400 here rather than a conversion error from SQL
+/// Server.
+fn rpc_arg(
+    routine: &crate::schema::RoutineInfo,
+    param: &crate::schema::ParamInfo,
+    val: &JsonValue,
+) -> Result<SqlParam, Error> {
+    let invalid = |reason: String| {
+        Error::BadRequest(format!(
+            "Invalid argument for {}: @{} {}",
+            routine.name, param.name, reason
+        ))
+    };
+    if val.is_null() {
+        return if param.is_nullable {
+            Ok(SqlParam::null_for(&param.data_type))
+        } else {
+            Err(invalid("cannot be null".to_string()))
+        };
+    }
+    if param.data_type == "table" {
+        return Err(invalid(
+            "is a table-valued parameter, which /rpc can't pass".to_string(),
+        ));
+    }
+    let text = match val {
+        JsonValue::String(s) => Some(s.clone()),
+        JsonValue::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+    if let Some(text) = text {
+        if !types::value_fits_type(&param.data_type, &text) {
+            return Err(invalid(format!(
+                "is not a valid {}: {}",
+                param.data_type, text
+            )));
+        }
+    }
+    typed_json_param(&param.data_type, &param.name, val)
+}
+
+/// Column metadata for an RPC result, so it can go through the same filter
+/// and order handling as a table.
+fn rpc_result_table(name: &str, columns: &[(String, String)]) -> crate::schema::TableInfo {
+    let columns = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (column, ty))| crate::schema::ColumnInfo {
+            name: column.clone(),
+            data_type: ty.split('(').next().unwrap_or(ty).trim().to_string(),
+            max_length: None,
+            precision: None,
+            scale: None,
+            is_nullable: true,
+            ordinal_position: i as i32 + 1,
+            is_identity: false,
+            has_default: false,
+            is_computed: false,
+            is_fulltext_indexed: false,
+            collation: None,
+            description: None,
+        })
+        .collect();
+    crate::schema::TableInfo {
+        name: name.to_string(),
+        schema: String::new(),
+        columns,
+        primary_key: Vec::new(),
+        foreign_keys: Vec::new(),
+        unique_constraints: Vec::new(),
+        is_view: false,
+        change_tracking_enabled: false,
+        has_triggers: false,
+        is_updatable: false,
+        instead_of: Default::default(),
+        description: None,
+    }
+}
+
+// ──────────────────── Helper functions ────────────────────
+
+/// Header carrying the primary's change tracking version after a write, so
+/// a following read can wait for the replica to reach it.
+const CONSISTENCY_TOKEN_HEADER: &str = "x-consistency-token";
+
+/// Delay between replica version checks while a read waits.
+const CONSISTENCY_POLL_MS: u64 = 20;
+
+const CT_VERSION_SQL: &str = "SELECT CHANGE_TRACKING_CURRENT_VERSION() AS [version]";
+
+/// Pick the pool for a GET: the replica, unless the request carries an
+/// `X-Consistency-Token` the replica doesn't reach within
+/// `consistency_wait_ms`, in which case the primary serves it.
+async fn route_read(state: &AppState, headers: &HeaderMap) -> Result<Option<Arc<Pool>>, Error> {
+    let Some(read_pool) = &state.read_pool else {
+        return Ok(None);
+    };
+    let Some(token) = headers.get(CONSISTENCY_TOKEN_HEADER) else {
+        return Ok(Some(read_pool.clone()));
+    };
+    let token: i64 = token
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or_else(|| Error::BadRequest("Invalid X-Consistency-Token".to_string()))?;
+
+    let deadline = std::time::Instant::now()
+        + std::time::Duration::from_millis(state.config.consistency_wait_ms);
+    loop {
+        match change_tracking_version(read_pool).await {
+            Ok(Some(version)) if version >= token => return Ok(Some(read_pool.clone())),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("Replica version check failed, reading from primary: {}", e);
+                return Ok(None);
+            }
+        }
+        if std::time::Instant::now() >= deadline {
+            tracing::debug!("Replica behind token {}, reading from primary", token);
+            return Ok(None);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(CONSISTENCY_POLL_MS)).await;
+    }
+}
+
+/// `CHANGE_TRACKING_CURRENT_VERSION()` on a pool's server; `None` when
+/// change tracking isn't enabled for the database.
+async fn change_tracking_version(pool: &Arc<Pool>) -> Result<Option<i64>, Error> {
+    let mut conn = pool.get().await?;
+    let rows = claw::Query::new(CT_VERSION_SQL)
+        .query(conn.client())
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?
+        .into_first_result()
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+    Ok(rows
+        .first()
+        .map(types::row_to_json)
+        .and_then(|row| row.get("version").and_then(JsonValue::as_i64)))
+}
+
+/// Attach the primary's post-commit version as `X-Consistency-Token` when
+/// reads go to a replica. Best effort: without change tracking, or if the
+/// lookup fails, the write's response goes out without a token.
+async fn with_consistency_token(state: &AppState, mut response: Response) -> Response {
+    if state.read_pool.is_none() {
+        return response;
+    }
+    match change_tracking_version(&state.pool).await {
+        Ok(Some(version)) => {
+            response
+                .headers_mut()
+                .insert(CONSISTENCY_TOKEN_HEADER, version.into());
+        }
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Could not read consistency token: {}", e),
+    }
+    response
+}
+
+/// Resolve schema and table name from path.
+///
+/// `profile` is the `Accept-Profile` (reads) or `Content-Profile` (writes)
+/// header, which picks the schema for single-segment paths.
+fn resolve_table_path(
+    path_params: &[(String, String)],
+    profile: Option<&str>,
+    state: &AppState,
+) -> Result<(String, String), Error> {
+    let config = &state.config;
+    match path_params.len() {
+        1 => {
+            // Single segment: /<table> → profile header or active schema
+            let schema = match profile.map(str::trim).filter(|p| !p.is_empty()) {
+                Some(p) => {
+                    if !config.is_schema_exposed(p) {
+                        return Err(Error::BadRequest(format!("Schema not exposed: {}", p)));
+                    }
+                    p.to_string()
+                }
+                None => state.active_schema(),
+            };
+            Ok((schema, path_params[0].1.clone()))
+        }
+        2 => {
+            // Two segments: /<schema>/<table>
+            let schema = &path_params[0].1;
+            if !config.is_schema_exposed(schema) {
+                return Err(Error::BadRequest(format!("Schema not exposed: {}", schema)));
+            }
+            Ok((schema.clone(), path_params[1].1.clone()))
+        }
+        _ => Err(Error::BadRequest("Invalid path".to_string())),
+    }
+}
+
+/// Percent-decode a path segment written by hand, as in a `/batch`
+/// operation (`Order%20Details` → `Order Details`). Routed paths arrive
+/// decoded already.
+pub fn decode_path_segment(segment: &str) -> Result<String, Error> {
+    let invalid = || Error::BadRequest(format!("Invalid percent-encoding in path: {}", segment));
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// The `If-Match` condition for a PATCH or DELETE on a table with a
+/// `rowversion` column: only rows still at one of the listed versions are
+/// written. Such tables require the header; `If-Match: *` opts out. `None`
+/// for tables without a row version.
+fn if_match_filter(
+    table: &crate::schema::TableInfo,
+    headers: &HeaderMap,
+) -> Result<Option<FilterNode>, Error> {
+    let value = headers
+        .get("if-match")
+        .map(|v| v.to_str())
+        .transpose()
+        .map_err(|_| Error::BadRequest("Invalid If-Match header".to_string()))?;
+    if_match_condition(table, value)
+}
+
+/// [`if_match_filter`] for an `If-Match` value from elsewhere, such as a
+/// `/batch` operation's `if_match`.
+fn if_match_condition(
+    table: &crate::schema::TableInfo,
+    value: Option<&str>,
+) -> Result<Option<FilterNode>, Error> {
+    let Some(column) = table.rowversion_column() else {
+        return Ok(None);
     };
+    let Some(value) = value else {
+        return Err(Error::PreconditionRequired(format!(
+            "{}.{} is versioned by {}: send If-Match with the row's ETag (or *)",
+            table.schema, table.name, column.name
+        )));
+    };
+    if value.trim() == "*" {
+        return Ok(None);
+    }
+    // Weak tags never match: If-Match compares strongly
+    let versions = value
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.starts_with("W/"))
+        .map(|tag| {
+            use base64::Engine;
+            tag.strip_prefix('"')
+                .and_then(|t| t.strip_suffix('"'))
+                .and_then(|t| base64::engine::general_purpose::STANDARD.decode(t).ok())
+                .filter(|bytes| bytes.len() == 8)
+                .map(|bytes| bytes.iter().map(|b| format!("{:02x}", b)).collect())
+                .ok_or_else(|| Error::BadRequest(format!("Invalid ETag in If-Match: {}", tag)))
+        })
+        .collect::<Result<Vec<String>, Error>>()?;
+    if versions.is_empty() {
+        return Err(Error::PreconditionFailed(
+            "If-Match only takes strong ETags".to_string(),
+        ));
+    }
+    Ok(Some(FilterNode::Condition(filters::Filter {
+        column: column.name.clone(),
+        operator: FilterOp::In,
+        value: FilterValue::List(versions),
+        negated: false,
+    })))
+}
 
-    // Build EXEC statement
-    let safe_proc = proc_name.replace('\'', "''").replace(']', "]]");
-    let mut sql_parts = Vec::new();
-    let mut param_values: Vec<String> = Vec::new();
-
-    for (i, (key, val)) in params.iter().enumerate() {
-        let safe_key = key.replace(']', "]]");
-        sql_parts.push(format!("@{} = @P{}", safe_key, i + 1));
-        param_values.push(json_value_to_sql_string(val));
+/// After a conditional write touched no rows: 412 when rows matching the
+/// request's own filters exist (so their version moved on), otherwise the
+/// write simply matched nothing.
+async fn check_precondition(
+    state: &AppState,
+    table: &crate::schema::TableInfo,
+    filter_nodes: &[FilterNode],
+    ctx: &SqlContext,
+) -> Result<(), Error> {
+    let mut params = Vec::new();
+    let mut sql = format!("SELECT TOP (1) 1 AS [found] FROM {}", table.full_name());
+    let where_clause =
+        query::build_where_clause_with_offset(Some(table), filter_nodes, &mut params, 0)?;
+    if !where_clause.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause);
+    }
+    let built = query::BuiltQuery { sql, params };
+    if execute_query_to_json(state, &built, ctx).await?.is_empty() {
+        return Ok(());
     }
+    Err(Error::PreconditionFailed(STALE_ETAG.to_string()))
+}
 
-    let sql = if sql_parts.is_empty() {
-        format!("EXEC [{}]", safe_proc)
-    } else {
-        format!("EXEC [{}] {}", safe_proc, sql_parts.join(", "))
+/// Why a conditional write failed: the row moved on to another version.
+const STALE_ETAG: &str = "The row has changed since the ETag in If-Match was read";
+
+/// The [`check_precondition`] of a `/batch` PATCH or DELETE, run inside the
+/// transaction ahead of the write: it throws, failing the whole batch, when
+/// rows match the filters but none is still at a version in `if_match`.
+/// Empty without an ETag.
+fn stale_etag_check(
+    table: &crate::schema::TableInfo,
+    filter_nodes: &[FilterNode],
+    if_match: Option<&FilterNode>,
+) -> Result<Vec<(String, Vec<SqlParam>)>, Error> {
+    let Some(if_match) = if_match else {
+        return Ok(Vec::new());
+    };
+    let mut params = Vec::new();
+    let matched = query::build_where_clause_with_offset(Some(table), filter_nodes, &mut params, 0)?;
+    let conditional: Vec<FilterNode> = filter_nodes
+        .iter()
+        .cloned()
+        .chain([if_match.clone()])
+        .collect();
+    let current = query::build_where_clause_with_offset(Some(table), &conditional, &mut params, 0)?;
+    let rows = |clause: &str| {
+        let mut sql = format!(
+            "SELECT 1 FROM {} WITH (UPDLOCK, HOLDLOCK)",
+            table.full_name()
+        );
+        if !clause.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(clause);
+        }
+        sql
     };
+    let sql = format!(
+        "IF EXISTS ({}) AND NOT EXISTS ({})\n    THROW 50412, N'{}', 1;",
+        rows(&matched),
+        rows(&current),
+        STALE_ETAG
+    );
+    Ok(vec![(sql, params)])
+}
 
-    // Build context SQL
-    let ctx_stmts = auth::build_session_context_sql(&claims, &state.config);
-    let full_sql = if ctx_stmts.is_empty() {
-        format!("SET NOCOUNT ON;\n{}", sql)
-    } else {
-        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), sql)
+/// `ETag` carrying the row version of a single-row response, for the next
+/// conditional write.
+fn add_row_etag(
+    table: &crate::schema::TableInfo,
+    rows: &[serde_json::Map<String, JsonValue>],
+    response: &mut Response,
+) {
+    let (Some(column), [row]) = (table.rowversion_column(), rows) else {
+        return;
     };
+    let Some(version) = row.get(&column.name).and_then(JsonValue::as_str) else {
+        return;
+    };
+    if let Ok(value) = axum::http::HeaderValue::from_str(&format!("\"{}\"", version)) {
+        response.headers_mut().insert("ETag", value);
+    }
+}
 
-    let mut conn = state.pool.get().await?;
-    let client = conn.client();
+/// Reject mutations against views SQL Server can't update, and writes a
+/// view's INSTEAD OF triggers don't cover.
+fn ensure_updatable(table: &crate::schema::TableInfo, dml: Dml) -> Result<(), Error> {
+    if table.accepts(dml) {
+        return Ok(());
+    }
+    if table.instead_of.any() {
+        return Err(Error::MethodNotAllowed(format!(
+            "{}.{} has no INSTEAD OF {} trigger",
+            table.schema,
+            table.name,
+            dml.keyword()
+        )));
+    }
+    Err(Error::MethodNotAllowed(format!(
+        "{}.{} is a read-only view (it joins several tables or aggregates); \
+         add an INSTEAD OF trigger or write to the base table instead",
+        table.schema, table.name
+    )))
+}
 
-    let mut query = claw::Query::new(full_sql);
-    for val in &param_values {
-        query.bind(val.as_str());
+/// A write an INSTEAD OF trigger takes over can't OUTPUT what was written:
+/// the trigger decides that, and OUTPUT would only echo the request. It
+/// answers as `return=minimal`, counted by `@@ROWCOUNT`.
+fn instead_of_minimal(table: &crate::schema::TableInfo, dml: Dml, prefer: &mut Preferences) {
+    if table.instead_of.handles(dml) {
+        prefer.return_mode = ReturnMode::Minimal;
     }
+}
 
-    let stream = query
-        .query(client)
-        .await
-        .map_err(|e| Error::Sql(e.to_string()))?;
+/// Whether a mutation needs an OUTPUT clause. Columnstore tables skip it
+/// for `return=minimal` so the DML can stay in batch mode.
+/// Writes INSTEAD OF triggers take over skip it too (see [`instead_of_minimal`]).
+fn needs_output(
+    config: &AppConfig,
+    table: &crate::schema::TableInfo,
+    dml: Dml,
+    prefer: &Preferences,
+) -> bool {
+    let columnstore = config
+        .table_config(&table.schema, &table.name)
+        .map(|t| t.columnstore)
+        .unwrap_or(false);
+    !(prefer.return_mode == ReturnMode::Minimal && (columnstore || table.instead_of.handles(dml)))
+}
 
-    let rows = stream
-        .into_first_result()
-        .await
-        .map_err(|e| Error::Sql(e.to_string()))?;
+/// A mutation built without OUTPUT reads back its `@@ROWCOUNT` instead, for
+/// the `Content-Range` of its `return=minimal` response.
+fn counted(built: query::BuiltQuery, returning: bool) -> query::BuiltQuery {
+    if returning {
+        return built;
+    }
+    query::BuiltQuery {
+        sql: query::select_rowcount(&built.sql),
+        params: built.params,
+    }
+}
 
-    let json_rows: Vec<serde_json::Map<String, JsonValue>> =
-        rows.iter().map(types::row_to_json).collect();
+/// `?select=` on a mutation that returns rows: the shape to read the
+/// written rows back in, embeds included (see [`query::project_returning`]).
+struct MutationSelect<'a> {
+    nodes: Vec<SelectNode>,
+    embeds: Vec<query::EmbedQuery<'a>>,
+}
 
-    match format {
-        ResponseFormat::SingleObjectJson => {
-            if json_rows.len() != 1 {
-                return Err(Error::SingleObjectExpected(json_rows.len()));
-            }
-            let json = serde_json::to_string(&json_rows[0]).unwrap_or_default();
-            Ok(response::build_response(
-                json.into_bytes(),
-                "application/vnd.pgrst.object+json; charset=utf-8",
-                StatusCode::OK,
-                None,
-                None,
-            ))
+impl<'a> MutationSelect<'a> {
+    /// `None` without `select=`, or when the response carries no rows.
+    fn resolve(
+        config: &AppConfig,
+        schema_cache: &'a SchemaCache,
+        query_params: &QueryParams,
+        table: &crate::schema::TableInfo,
+        prefer: &Preferences,
+    ) -> Result<Option<Self>, Error> {
+        let Some(select_str) = query_params.get("select") else {
+            return Ok(None);
+        };
+        if prefer.return_mode != ReturnMode::Representation {
+            return Ok(None);
         }
-        _ => {
-            let json = response::rows_to_json(&json_rows);
-            Ok(response::build_response(
-                json.into_bytes(),
-                "application/json; charset=utf-8",
-                StatusCode::OK,
-                None,
-                None,
-            ))
+        let nodes = select::parse_select(select_str)?;
+        let mut alias_counter = 0;
+        // `!inner` embeds don't filter out rows that were already written
+        let mut inner_filters = Vec::new();
+        let embeds = resolve_embeds(
+            config,
+            schema_cache,
+            query_params,
+            table,
+            query::RETURNED_ALIAS,
+            "",
+            &nodes,
+            &mut alias_counter,
+            &mut inner_filters,
+        )?;
+        Ok(Some(MutationSelect { nodes, embeds }))
+    }
+}
+
+/// Rewrite a mutation to return `selection`, appending embed parameters to
+/// `params`. Without a selection the SQL is returned as-is.
+fn select_returning(
+    selection: Option<&MutationSelect>,
+    table: &crate::schema::TableInfo,
+    sql: String,
+    params: &mut Vec<SqlParam>,
+) -> Result<String, Error> {
+    let Some(selection) = selection else {
+        return Ok(sql);
+    };
+    let projected = query::project_returning(
+        table,
+        &sql,
+        params.len(),
+        &selection.nodes,
+        &selection.embeds,
+    )?;
+    params.extend(projected.params);
+    Ok(projected.sql)
+}
+
+/// `?columns=` on a write: the only body keys read, everything else is
+/// dropped. Naming a column the table doesn't have is an error.
+fn column_whitelist(
+    table: &crate::schema::TableInfo,
+    query_params: &HashMap<String, String>,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(list) = query_params.get("columns") else {
+        return Ok(None);
+    };
+    let columns = select::split_idents(list);
+    if let Some(unknown) = columns.iter().find(|c| table.column(c).is_none()) {
+        return Err(Error::BadRequest(format!(
+            "columns: {} has no column {}",
+            table.name, unknown
+        )));
+    }
+    Ok(Some(columns))
+}
+
+/// `?limit=`/`?order=` on PATCH and DELETE. Also guards against touching
+/// every row by accident: without a filter the request needs `?force=true`.
+fn mutation_limit(
+    query_params: &HashMap<String, String>,
+    filter_nodes: &[FilterNode],
+) -> Result<Option<query::MutationLimit>, Error> {
+    let force = query_params.get("force").is_some_and(|v| v == "true");
+    if filter_nodes.is_empty() && !force {
+        return Err(Error::BadRequestHint {
+            message: "Refusing to change every row without a filter".to_string(),
+            hint: "Add a filter, or ?force=true to apply it to the whole table".to_string(),
+        });
+    }
+    let order = query::parse_order(query_params.get("order").map_or("", |s| s.as_str()))?;
+    let Some(limit) = query_params.get("limit") else {
+        if !order.is_empty() {
+            return Err(Error::BadRequest(
+                "order on PATCH or DELETE requires limit".to_string(),
+            ));
         }
+        return Ok(None);
+    };
+    let limit = limit
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| Error::BadRequest(format!("Invalid limit: {}", limit)))?;
+    Ok(Some(query::MutationLimit { limit, order }))
+}
+
+/// Parse a `text/csv` body into objects keyed by its header row. Every
+/// field is text (typed per column when bound); empty fields are NULL.
+fn csv_body(body: &[u8]) -> Result<JsonValue, Error> {
+    let mut reader = csv::Reader::from_reader(body);
+    let header: Vec<String> = reader
+        .headers()
+        .map_err(|e| Error::BadRequest(format!("Invalid CSV: {}", e)))?
+        .iter()
+        .map(|h| h.trim().to_string())
+        .collect();
+    let mut objects = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| Error::BadRequest(format!("Invalid CSV: {}", e)))?;
+        let obj: serde_json::Map<String, JsonValue> = header
+            .iter()
+            .zip(record.iter())
+            .map(|(column, field)| {
+                let value = if field.is_empty() {
+                    JsonValue::Null
+                } else {
+                    JsonValue::String(field.to_string())
+                };
+                (column.clone(), value)
+            })
+            .collect();
+        objects.push(JsonValue::Object(obj));
     }
+    Ok(JsonValue::Array(objects))
 }
 
-// ──────────────────── Helper functions ────────────────────
+/// Check body columns against identity/computed/rowversion columns.
+///
+/// Rejects writes to read-only columns with one error per field, or drops
+/// them when the client sent `Prefer: missing=ignore`.
+fn writable_columns(
+    table: &crate::schema::TableInfo,
+    columns: Vec<String>,
+    prefer: &Preferences,
+) -> Result<Vec<String>, Error> {
+    let ignore = prefer.missing.as_deref() == Some("ignore");
+    let mut writable = Vec::with_capacity(columns.len());
+    let mut problems = Vec::new();
+    for col in columns {
+        match table.column(&col).and_then(|c| c.read_only_reason()) {
+            Some(_) if ignore => {}
+            Some(reason) => problems.push(format!("{}: cannot write {}", col, reason)),
+            None => writable.push(col),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(Error::Validation(problems));
+    }
+    Ok(writable)
+}
 
-/// Resolve schema and table name from path.
-fn resolve_table_path(
-    path_params: &[(String, String)],
+/// Check a write body against the table's columns and its configured
+/// validation rules. `insert` also requires NOT NULL columns without a
+/// default (upserts may be updating rows that have them).
+fn check_body(
     config: &AppConfig,
-) -> Result<(String, String), Error> {
-    match path_params.len() {
-        1 => {
-            // Single segment: /<table> → use default schema
-            Ok((config.default_schema.clone(), path_params[0].1.clone()))
-        }
-        2 => {
-            // Two segments: /<schema>/<table>
-            Ok((path_params[0].1.clone(), path_params[1].1.clone()))
+    table: &crate::schema::TableInfo,
+    objects: &[&serde_json::Map<String, JsonValue>],
+    insert: bool,
+) -> Result<(), Error> {
+    let table_cfg = config.table_config(&table.schema, &table.name);
+    validate::check_objects(table, table_cfg, objects, insert)
+}
+
+/// Force the table's configured `inject` columns onto every object in an
+/// insert body, overriding client-sent values. A missing claim rejects the
+/// request rather than letting the column fall back to the client or NULL.
+fn inject_claim_columns(
+    config: &AppConfig,
+    table: &crate::schema::TableInfo,
+    claims: &Option<auth::Claims>,
+    json: &mut JsonValue,
+) -> Result<(), Error> {
+    let Some(table_cfg) = config.table_config(&table.schema, &table.name) else {
+        return Ok(());
+    };
+    let mut injected = serde_json::Map::new();
+    for (column, source) in &table_cfg.inject {
+        if table.column(column).is_none() {
+            tracing::warn!(
+                "inject: {}.{} has no column {}; skipping",
+                table.schema,
+                table.name,
+                column
+            );
+            continue;
         }
-        _ => Err(Error::BadRequest("Invalid path".to_string())),
+        let path = source.strip_prefix("claims.").ok_or_else(|| {
+            // Fail closed: a typo must not silently drop a tenancy column
+            Error::Internal(format!(
+                "Invalid inject source {:?} for {}.{}: expected claims.<path>",
+                source, table.name, column
+            ))
+        })?;
+        let value = claims
+            .as_ref()
+            .and_then(|c| auth::claim_value(c, path))
+            .ok_or_else(|| {
+                Error::Forbidden(format!(
+                    "Missing claim {} required to write {}",
+                    path, column
+                ))
+            })?;
+        injected.insert(column.clone(), value);
+    }
+    if injected.is_empty() {
+        return Ok(());
+    }
+
+    let objects: Vec<&mut serde_json::Map<String, JsonValue>> = match json {
+        JsonValue::Array(arr) => arr
+            .iter_mut()
+            .filter_map(JsonValue::as_object_mut)
+            .collect(),
+        JsonValue::Object(obj) => vec![obj],
+        _ => Vec::new(),
+    };
+    for obj in objects {
+        // Drop differently-cased client keys so they can't sneak past
+        obj.retain(|key, _| !injected.keys().any(|c| c.eq_ignore_ascii_case(key)));
+        obj.extend(injected.clone());
     }
+    Ok(())
 }
 
 /// Build filter nodes from query parameters.
+/// In strict mode params that name no column, and values that don't fit
+/// their column's type, are rejected together instead of silently dropped.
 fn build_filters_from_params(
-    query_params: &HashMap<String, String>,
+    query_params: &QueryParams,
     table: &crate::schema::TableInfo,
+    config: &AppConfig,
 ) -> Result<Vec<FilterNode>, Error> {
-    let reserved = ["select", "order", "limit", "offset"];
+    let strict = config.strict_filters;
+    let reserved = [
+        "select",
+        "order",
+        "limit",
+        "offset",
+        "tree",
+        "distinct_on",
+        "columns",
+        "force",
+        "resume_after",
+        signed_url::SIGNATURE_PARAM,
+        signed_url::EXPIRES_PARAM,
+        signed_url::GRANT_PARAM,
+    ];
+
+    let mut filter_nodes: Vec<FilterNode> = Vec::new();
+    let mut problems: Vec<String> = Vec::new();
+
+    for (key, value) in query_params.pairs() {
+        // Handle "or" / "and" groups (and their not. forms) before reserved check
+        if let Some(node) = filters::parse_logic_param(key, value)? {
+            filter_nodes.push(node);
+            continue;
+        }
+
+        if reserved.contains(&key.as_str()) {
+            continue;
+        }
+
+        // Handle embed filters (e.g., orders.status=eq.active); dots in a
+        // quoted column name don't count
+        if select::find_unquoted(key, ".").is_some() {
+            // This is an embed filter — skip it for main query,
+            // it'll be handled in the embed query
+            continue;
+        }
+
+        // Check if this is a valid column (or a JSON path into one)
+        let column = select::unquote_column(key);
+        let base_column = column.split("->").next().unwrap_or(&column);
+        if key == "q" && table.column(key).is_none() {
+            let columns = config
+                .table_config(&table.schema, &table.name)
+                .map_or(&[][..], |cfg| cfg.search.as_slice());
+            filter_nodes.push(search_filter(table, columns, value)?);
+        } else if table.column(base_column).is_some() {
+            let filter = filters::parse_filter(key, value)?;
+            filter_nodes.push(FilterNode::Condition(filter));
+        } else if strict {
+            problems.push(format!("Unknown column: {}", key));
+        }
+    }
+
+    if strict {
+        check_filter_nodes(&filter_nodes, table, &mut problems);
+        if !problems.is_empty() {
+            problems.sort();
+            problems.dedup();
+            return Err(Error::Validation(problems));
+        }
+    }
+
+    ensure_full_text_indexed(&filter_nodes, table)?;
+    normalize_filter_values(&mut filter_nodes, table)?;
+    let configured = config
+        .table_config(&table.schema, &table.name)
+        .map_or(&[][..], |cfg| cfg.case_insensitive.as_slice());
+    if config.case_insensitive_filters || !configured.is_empty() {
+        let ignores_case = |column: &crate::schema::ColumnInfo| {
+            config.case_insensitive_filters
+                || configured
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&column.name))
+        };
+        fold_case_filters(&mut filter_nodes, table, &ignores_case);
+    }
+    Ok(filter_nodes)
+}
+
+/// Make text filters on case-insensitive columns (`--case-insensitive-filters`
+/// or the table's `case_insensitive` list) ignore case whatever the column's
+/// collation: `eq`/`neq`/`in` compare under its `_CI` sibling and `like`/
+/// `match` become `ilike`/`imatch`.
+fn fold_case_filters(
+    nodes: &mut [FilterNode],
+    table: &crate::schema::TableInfo,
+    ignores_case: &dyn Fn(&crate::schema::ColumnInfo) -> bool,
+) {
+    for node in nodes {
+        match node {
+            FilterNode::Condition(f) => {
+                // Only character columns have a collation to override
+                let Some(col) = table.column(&f.column) else {
+                    continue;
+                };
+                if col.collation.is_none() || !ignores_case(col) {
+                    continue;
+                }
+                match (f.operator.clone(), &mut f.value) {
+                    (FilterOp::Eq, _) => f.operator = FilterOp::Ieq,
+                    (FilterOp::Neq, value) => {
+                        // neq(any) holds unless every value is equal, and so on
+                        if let FilterValue::Quantified(quantifier, _) = value {
+                            *quantifier = match quantifier {
+                                filters::Quantifier::Any => filters::Quantifier::All,
+                                filters::Quantifier::All => filters::Quantifier::Any,
+                            };
+                        }
+                        f.operator = FilterOp::Ieq;
+                        f.negated = !f.negated;
+                    }
+                    (FilterOp::In, FilterValue::List(items)) if !items.is_empty() => {
+                        f.operator = FilterOp::Ieq;
+                        f.value = FilterValue::Quantified(
+                            filters::Quantifier::Any,
+                            std::mem::take(items),
+                        );
+                    }
+                    (FilterOp::Like, _) => f.operator = FilterOp::Ilike,
+                    (FilterOp::Match, _) => f.operator = FilterOp::Imatch,
+                    _ => {}
+                }
+            }
+            FilterNode::And(children) | FilterNode::Or(children) => {
+                fold_case_filters(children, table, ignores_case)
+            }
+            FilterNode::Not(inner) => {
+                fold_case_filters(std::slice::from_mut(inner.as_mut()), table, ignores_case)
+            }
+            FilterNode::Exists(_) | FilterNode::Distinct(_) => {}
+        }
+    }
+}
+
+/// `?q=` search box: an OR of a substring match on each searched column
+/// (`columns`, or every text column when empty). Full-text indexed columns
+/// use a `CONTAINS` prefix term instead of a `LIKE` scan.
+fn search_filter(
+    table: &crate::schema::TableInfo,
+    columns: &[String],
+    term: &str,
+) -> Result<FilterNode, Error> {
+    let searched: Vec<&crate::schema::ColumnInfo> = if columns.is_empty() {
+        table
+            .columns
+            .iter()
+            .filter(|c| {
+                matches!(
+                    c.data_type.as_str(),
+                    "char" | "varchar" | "nchar" | "nvarchar" | "text" | "ntext"
+                )
+            })
+            .collect()
+    } else {
+        columns
+            .iter()
+            .map(|name| {
+                table.column(name).ok_or_else(|| {
+                    Error::Internal(format!(
+                        "Search column {} not found on {}",
+                        name, table.name
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?
+    };
+    if searched.is_empty() {
+        return Err(Error::BadRequest(format!(
+            "{} has no text columns to search",
+            table.name
+        )));
+    }
+
+    // Taken literally: LIKE wildcards are escaped, full-text operators quoted
+    let pattern = term
+        .replace('[', "[[]")
+        .replace('%', "[%]")
+        .replace('_', "[_]");
+    let prefix = format!("\"{}*\"", term.replace('"', ""));
+    let conditions = searched
+        .into_iter()
+        .map(|col| {
+            let (operator, value) = if col.is_fulltext_indexed {
+                (FilterOp::Fts(None), prefix.clone())
+            } else {
+                (FilterOp::Ilike, format!("%{}%", pattern))
+            };
+            FilterNode::Condition(filters::Filter {
+                column: col.name.clone(),
+                operator,
+                value: FilterValue::Single(value),
+                negated: false,
+            })
+        })
+        .collect();
+    Ok(FilterNode::Or(conditions))
+}
+
+/// Strict-mode checks on parsed filters: columns named inside `or`/`and`
+/// groups must exist, and comparison values must convert to the column type.
+fn check_filter_nodes(
+    nodes: &[FilterNode],
+    table: &crate::schema::TableInfo,
+    problems: &mut Vec<String>,
+) {
+    for node in nodes {
+        match node {
+            FilterNode::Condition(f) => {
+                let base_column = f.column.split("->").next().unwrap_or(&f.column);
+                let Some(col) = table.column(base_column) else {
+                    problems.push(format!("Unknown column: {}", f.column));
+                    continue;
+                };
+                // Patterns, `is`, full-text terms, custom operator arguments
+                // and JSON paths aren't values of the column's type
+                if f.column.contains("->")
+                    || f.operator.is_full_text()
+                    || matches!(
+                        f.operator,
+                        FilterOp::Is
+                            | FilterOp::Like
+                            | FilterOp::Ilike
+                            | FilterOp::Match
+                            | FilterOp::Imatch
+                            | FilterOp::Custom(_)
+                    )
+                {
+                    continue;
+                }
+                let values = match &f.value {
+                    FilterValue::Single(v) => std::slice::from_ref(v),
+                    FilterValue::List(items) | FilterValue::Quantified(_, items) => {
+                        items.as_slice()
+                    }
+                };
+                for v in values {
+                    if !types::value_fits_type(&col.data_type, v) {
+                        problems.push(format!(
+                            "{}: {} is not a valid {}",
+                            col.name, v, col.data_type
+                        ));
+                    }
+                }
+            }
+            FilterNode::And(children) | FilterNode::Or(children) => {
+                check_filter_nodes(children, table, problems)
+            }
+            FilterNode::Not(inner) => {
+                check_filter_nodes(std::slice::from_ref(inner.as_ref()), table, problems)
+            }
+            FilterNode::Exists(_) | FilterNode::Distinct(_) => {}
+        }
+    }
+}
+
+/// Normalize filter values on `bit` / `uniqueidentifier` columns
+/// (see [`types::normalize_param`]). Pattern, `is`, custom operator and
+/// JSON path filters are left alone.
+fn normalize_filter_values(
+    nodes: &mut [FilterNode],
+    table: &crate::schema::TableInfo,
+) -> Result<(), Error> {
+    for node in nodes {
+        match node {
+            FilterNode::Condition(f)
+                if !f.operator.is_full_text()
+                    && !matches!(
+                        f.operator,
+                        FilterOp::Is
+                            | FilterOp::Like
+                            | FilterOp::Ilike
+                            | FilterOp::Match
+                            | FilterOp::Imatch
+                            | FilterOp::Custom(_)
+                    ) =>
+            {
+                let Some(col) = table.column(&f.column) else {
+                    continue;
+                };
+                let normalize = |v: &mut String| -> Result<(), Error> {
+                    *v = types::normalize_param(&col.data_type, &col.name, v)?;
+                    Ok(())
+                };
+                match &mut f.value {
+                    FilterValue::Single(v) => normalize(v)?,
+                    FilterValue::List(items) | FilterValue::Quantified(_, items) => {
+                        items.iter_mut().try_for_each(normalize)?
+                    }
+                }
+            }
+            FilterNode::And(children) | FilterNode::Or(children) => {
+                normalize_filter_values(children, table)?
+            }
+            FilterNode::Not(inner) => {
+                normalize_filter_values(std::slice::from_mut(inner.as_mut()), table)?
+            }
+            FilterNode::Condition(_) | FilterNode::Exists(_) | FilterNode::Distinct(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Reject full-text filters on columns without a full-text index, which
+/// SQL Server would otherwise report as an opaque error.
+fn ensure_full_text_indexed(
+    nodes: &[FilterNode],
+    table: &crate::schema::TableInfo,
+) -> Result<(), Error> {
+    for node in nodes {
+        match node {
+            FilterNode::Condition(f) if f.operator.is_full_text() => {
+                let indexed = table
+                    .column(&f.column)
+                    .is_some_and(|c| c.is_fulltext_indexed);
+                if !indexed {
+                    return Err(Error::BadRequestHint {
+                        message: format!(
+                            "Column {}.{} has no full-text index",
+                            table.name, f.column
+                        ),
+                        hint: format!(
+                            "CREATE FULLTEXT INDEX ON {} ([{}]) KEY INDEX <unique_index>",
+                            table.full_name(),
+                            escape_ident(&f.column)
+                        ),
+                    });
+                }
+            }
+            FilterNode::And(children) | FilterNode::Or(children) => {
+                ensure_full_text_indexed(children, table)?
+            }
+            FilterNode::Not(inner) => {
+                ensure_full_text_indexed(std::slice::from_ref(inner.as_ref()), table)?
+            }
+            FilterNode::Condition(_) | FilterNode::Exists(_) | FilterNode::Distinct(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// `<embed>.<param>` keys that page an embed rather than filter it.
+const EMBED_PAGING_PARAMS: [&str; 3] = ["limit", "offset", "order"];
 
+/// Build filter nodes for an embedded resource from `<embed>.<column>` and
+/// `<embed>.or` / `<embed>.and` query parameters.
+fn build_embed_filters_from_params(
+    query_params: &QueryParams,
+    embed_name: &str,
+    target: &crate::schema::TableInfo,
+) -> Result<Vec<FilterNode>, Error> {
     let mut filter_nodes: Vec<FilterNode> = Vec::new();
 
-    for (key, value) in query_params {
-        // Handle "or" and "and" groups before reserved check
-        if key == "or" {
-            let nodes = filters::parse_logic_group(value)?;
-            filter_nodes.push(FilterNode::Or(nodes));
-            continue;
-        }
-        if key == "and" {
-            let nodes = filters::parse_logic_group(value)?;
-            filter_nodes.push(FilterNode::And(nodes));
-            continue;
-        }
-
-        if reserved.contains(&key.as_str()) {
+    for (key, value) in query_params.pairs() {
+        // Embed names may themselves be dotted (`sales.orders.status`)
+        let column = match (key.get(..embed_name.len()), key.get(embed_name.len()..)) {
+            (Some(prefix), Some(rest)) if prefix.eq_ignore_ascii_case(embed_name) => {
+                rest.strip_prefix('.')
+            }
+            _ => None,
+        };
+        let Some(column) = column else {
             continue;
-        }
-
-        // Handle embed filters (e.g., orders.status=eq.active)
-        if key.contains('.') {
-            // This is an embed filter — skip it for main query,
-            // it'll be handled in the embed query
+        };
+        if EMBED_PAGING_PARAMS.contains(&column) {
             continue;
         }
-
-        // Check if this is a valid column
-        if table.column(key).is_some() {
-            let filter = filters::parse_filter(key, value)?;
+        if let Some(node) = filters::parse_logic_param(column, value)? {
+            filter_nodes.push(node);
+        } else if target
+            .column(
+                select::unquote_column(column)
+                    .split("->")
+                    .next()
+                    .unwrap_or_default(),
+            )
+            .is_some()
+        {
+            let filter = filters::parse_filter(column, value)?;
             filter_nodes.push(FilterNode::Condition(filter));
         }
     }
 
+    ensure_full_text_indexed(&filter_nodes, target)?;
+    normalize_filter_values(&mut filter_nodes, target)?;
     Ok(filter_nodes)
 }
 
@@ -540,21 +3964,27 @@ fn build_filters_from_params(
 async fn execute_query_to_json(
     state: &AppState,
     built: &query::BuiltQuery,
-    claims: &Option<auth::Claims>,
+    ctx: &SqlContext,
 ) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
-    let ctx_stmts = auth::build_session_context_sql(claims, &state.config);
+    tracing::debug!(
+        sql = %built.sql,
+        params = %redact::params(&built.params, state.config.log_params),
+        "Executing query"
+    );
+    let ctx_stmts = &ctx.session;
+    let sql = ctx.tagged(&built.sql);
     let full_sql = if ctx_stmts.is_empty() {
-        format!("SET NOCOUNT ON;\n{}", built.sql)
+        format!("SET NOCOUNT ON;\n{}", sql)
     } else {
-        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), built.sql)
+        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), sql)
     };
 
-    let mut conn = state.pool.get().await?;
+    let mut conn = ctx.connection(state).await?;
     let client = conn.client();
 
     let mut query = claw::Query::new(full_sql);
     for val in &built.params {
-        query.bind(val.as_str());
+        bind_param(&mut query, val);
     }
 
     let stream = query
@@ -574,19 +4004,25 @@ async fn execute_query_to_json(
 async fn execute_arrow_query(
     state: &AppState,
     built: &query::BuiltQuery,
-    claims: &Option<auth::Claims>,
+    ctx: &SqlContext,
 ) -> Result<arrow::record_batch::RecordBatch, Error> {
-    let ctx_stmts = auth::build_session_context_sql(claims, &state.config);
+    tracing::debug!(
+        sql = %built.sql,
+        params = %redact::params(&built.params, state.config.log_params),
+        "Executing query"
+    );
+    let ctx_stmts = &ctx.session;
+    let sql = ctx.tagged(&built.sql);
     let full_sql = if ctx_stmts.is_empty() {
-        format!("SET NOCOUNT ON;\n{}", built.sql)
+        format!("SET NOCOUNT ON;\n{}", sql)
     } else {
-        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), built.sql)
+        format!("SET NOCOUNT ON;\n{}\n{}", ctx_stmts.join("\n"), sql)
     };
 
     // For Arrow queries we currently can't use parameterized queries
     // (query_arrow takes raw SQL), so we need to inline params safely.
     // For now, fall back to the parameterized Query + ArrowRowWriter path.
-    let mut conn = state.pool.get().await?;
+    let mut conn = ctx.connection(state).await?;
     let client = conn.client();
 
     let mut writer = claw::ArrowRowWriter::new();
@@ -603,7 +4039,7 @@ async fn execute_arrow_query(
         // and convert to Arrow.
         let mut query = claw::Query::new(full_sql);
         for val in &built.params {
-            query.bind(val.as_str());
+            bind_param(&mut query, val);
         }
 
         let stream = query
@@ -712,9 +4148,9 @@ fn write_sql_value_to_arrow(writer: &mut claw::ArrowRowWriter, col: usize, val:
 async fn execute_count(
     state: &AppState,
     built: &query::BuiltQuery,
-    claims: &Option<auth::Claims>,
+    ctx: &SqlContext,
 ) -> Result<i64, Error> {
-    let rows = execute_query_to_json(state, built, claims).await?;
+    let rows = execute_query_to_json(state, built, ctx).await?;
     if let Some(first) = rows.first() {
         if let Some(count) = first.get("count") {
             return count
@@ -722,57 +4158,771 @@ async fn execute_count(
                 .ok_or_else(|| Error::Internal("Invalid count".to_string()));
         }
     }
-    Ok(0)
+    Ok(0)
+}
+
+/// Strip the `[__lazypaw_total]` column added by [`query::with_total_count`] from
+/// each row, returning its value (`None` for an empty page).
+fn take_total_count(rows: &mut [serde_json::Map<String, JsonValue>]) -> Option<i64> {
+    let mut total = None;
+    for row in rows.iter_mut() {
+        let value = row.remove(query::TOTAL_COLUMN);
+        total = total.or(value.and_then(|v| v.as_i64()));
+    }
+    total
+}
+
+/// Total for `Prefer: count=...`, with the mode actually applied.
+/// `estimated` only holds for unfiltered reads of objects with partition
+/// stats; filtered reads and views fall back to `planned`.
+async fn count_rows(
+    state: &AppState,
+    table: &crate::schema::TableInfo,
+    filters: &[FilterNode],
+    maxdop: Option<u32>,
+    mode: CountMode,
+    ctx: &SqlContext,
+) -> Result<(i64, CountMode), Error> {
+    if mode == CountMode::Estimated && filters.is_empty() {
+        let rows = execute_query_to_json(state, &query::build_estimated_count(table), ctx).await?;
+        let count = rows
+            .first()
+            .and_then(|row| row.get("count"))
+            .and_then(JsonValue::as_i64);
+        if let Some(count) = count {
+            return Ok((count, CountMode::Estimated));
+        }
+    }
+
+    if mode != CountMode::Exact {
+        let built = query::build_select(table, &[], &[], filters, &[], None, None, None, false)?;
+        let count = execute_planned_count(state, &built, ctx).await?;
+        return Ok((count, CountMode::Planned));
+    }
+
+    let mut count_query =
+        query::build_select(table, &[], &[], filters, &[], None, None, None, true)?;
+    query::apply_maxdop(&mut count_query, maxdop);
+    Ok((
+        execute_count(state, &count_query, ctx).await?,
+        CountMode::Exact,
+    ))
+}
+
+/// The optimizer's row estimate for a query, read from its estimated plan
+/// without running it. Session context isn't set: nothing executes under
+/// SHOWPLAN, and RLS predicates plan the same whatever the values.
+async fn execute_planned_count(
+    state: &AppState,
+    built: &query::BuiltQuery,
+    ctx: &SqlContext,
+) -> Result<i64, Error> {
+    tracing::debug!(
+        sql = %built.sql,
+        params = %redact::params(&built.params, state.config.log_params),
+        "Planning count"
+    );
+    // Closed rather than pooled unless SHOWPLAN_XML is switched back off,
+    // including when the request is cancelled in between
+    let mut conn = SessionConnection::new(ctx.connection(state).await?);
+    let client = conn.client();
+    let mut sink = claw::ArrowRowWriter::new();
+
+    client
+        .batch_into("SET SHOWPLAN_XML ON;", &mut sink)
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+    let mut query = claw::Query::new(ctx.tagged(&built.sql));
+    for val in &built.params {
+        bind_param(&mut query, val);
+    }
+    let plan = async {
+        let stream = query
+            .query(client)
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+        stream
+            .into_first_result()
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))
+    }
+    .await;
+    // Switch back before the connection returns to the pool, even on error
+    client
+        .batch_into("SET SHOWPLAN_XML OFF;", &mut sink)
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+    conn.release();
+
+    let rows = plan?;
+    let xml = rows
+        .first()
+        .map(types::row_to_json)
+        .and_then(|row| {
+            row.values()
+                .next()
+                .and_then(JsonValue::as_str)
+                .map(str::to_string)
+        })
+        .ok_or_else(|| Error::Internal("Empty query plan".to_string()))?;
+    query::plan_estimated_rows(&xml)
+        .ok_or_else(|| Error::Internal("No row estimate in query plan".to_string()))
+}
+
+/// Execute a DML query (INSERT/UPDATE/DELETE) with OUTPUT.
+async fn execute_dml_query(
+    state: &AppState,
+    sql: &str,
+    params: &[SqlParam],
+    ctx: &SqlContext,
+    prefer: &Preferences,
+) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
+    tracing::debug!(
+        sql = %sql,
+        params = %redact::params(params, state.config.log_params),
+        "Executing DML"
+    );
+    let ctx_stmts = &ctx.session;
+    let sql = ctx.tagged(sql);
+
+    let tx_begin = "BEGIN TRANSACTION;";
+    let tx_end = if prefer.tx == TxPreference::Rollback {
+        "ROLLBACK TRANSACTION;"
+    } else {
+        "COMMIT TRANSACTION;"
+    };
+
+    let full_sql = if ctx_stmts.is_empty() {
+        format!("SET NOCOUNT ON;\n{}\n{}\n{}", tx_begin, sql, tx_end)
+    } else {
+        format!(
+            "SET NOCOUNT ON;\n{}\n{}\n{}\n{}",
+            ctx_stmts.join("\n"),
+            tx_begin,
+            sql,
+            tx_end
+        )
+    };
+
+    let mut conn = ctx.checkout(&state.pool).await?;
+    let client = conn.client();
+
+    let mut query = claw::Query::new(full_sql);
+    for val in params {
+        bind_param(&mut query, val);
+    }
+
+    let stream = query
+        .query(client)
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+
+    let rows = stream
+        .into_first_result()
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+
+    Ok(rows.iter().map(types::row_to_json).collect())
+}
+
+/// Run several DML statements in one transaction on one connection, for
+/// writes that don't fit SQL Server's parameter limit in a single request.
+/// Rows returned by each statement are concatenated.
+async fn execute_dml_batches(
+    state: &AppState,
+    batches: &[(String, Vec<SqlParam>)],
+    ctx: &SqlContext,
+    prefer: &Preferences,
+) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
+    let results = execute_dml_statements(state, batches, ctx, prefer)
+        .await
+        .map_err(|(_, e)| e)?;
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Run DML statements in order in one transaction on one connection and
+/// return the rows of each. Any failure rolls them all back; the error
+/// carries the index of the statement that failed, if one did.
+async fn execute_dml_statements(
+    state: &AppState,
+    statements: &[(String, Vec<SqlParam>)],
+    ctx: &SqlContext,
+    prefer: &Preferences,
+) -> Result<Vec<Vec<serde_json::Map<String, JsonValue>>>, (Option<usize>, Error)> {
+    let mut conn = SessionConnection::new(ctx.checkout(&state.pool).await.map_err(|e| (None, e))?);
+    let client = conn.client();
+    let mut sink = claw::ArrowRowWriter::new();
+
+    // A plain batch, so the transaction stays open across the statements.
+    // Until it commits or rolls back cleanly, the connection is closed
+    // rather than pooled, which also rolls the transaction back.
+    let begin = format!(
+        "SET NOCOUNT ON;\n{}\nBEGIN TRANSACTION;",
+        ctx.session.join("\n")
+    );
+    client
+        .batch_into(&begin, &mut sink)
+        .await
+        .map_err(|e| (None, Error::Sql(e.to_string())))?;
+
+    let mut results = Vec::with_capacity(statements.len());
+    for (index, (sql, params)) in statements.iter().enumerate() {
+        tracing::debug!(
+            sql = %sql,
+            params = %redact::params(params, state.config.log_params),
+            "Executing DML batch"
+        );
+        let mut query = claw::Query::new(ctx.tagged(sql));
+        for val in params {
+            bind_param(&mut query, val);
+        }
+        let result = async {
+            query
+                .query(client)
+                .await
+                .map_err(|e| Error::Sql(e.to_string()))?
+                .into_first_result()
+                .await
+                .map_err(|e| Error::Sql(e.to_string()))
+        }
+        .await;
+        match result {
+            Ok(rows) => results.push(rows.iter().map(types::row_to_json).collect()),
+            Err(e) => return Err((Some(index), e)),
+        }
+    }
+
+    let end = if prefer.tx == TxPreference::Rollback {
+        "ROLLBACK TRANSACTION;"
+    } else {
+        "COMMIT TRANSACTION;"
+    };
+    let end = format!("{}\n{}", end, auth::build_revert_sql());
+    client
+        .batch_into(&end, &mut sink)
+        .await
+        .map_err(|e| (None, Error::Sql(e.to_string())))?;
+    conn.release();
+    Ok(results)
+}
+
+/// Whether a table can take the bulk load path: a plain table (bulk copy
+/// skips triggers) whose writable columns are all of types `bulk_value`
+/// can send. Others fall back to batched INSERTs.
+fn bulk_loadable(table: &crate::schema::TableInfo) -> bool {
+    !table.is_view
+        && !table.has_triggers
+        && table
+            .columns
+            .iter()
+            .filter(|c| c.read_only_reason().is_none())
+            .all(|c| {
+                matches!(
+                    c.data_type.to_lowercase().as_str(),
+                    "tinyint"
+                        | "smallint"
+                        | "int"
+                        | "bigint"
+                        | "bit"
+                        | "real"
+                        | "float"
+                        | "char"
+                        | "varchar"
+                        | "nchar"
+                        | "nvarchar"
+                        | "text"
+                        | "ntext"
+                        | "uniqueidentifier"
+                        | "binary"
+                        | "varbinary"
+                        | "image"
+                )
+            })
+}
+
+/// A body value as the bulk copy type of its column. Missing and null both
+/// send NULL.
+fn bulk_value(
+    col: &crate::schema::ColumnInfo,
+    val: Option<&JsonValue>,
+) -> Result<SqlValue<'static>, Error> {
+    let text = match val {
+        None | Some(JsonValue::Null) => None,
+        Some(JsonValue::String(s)) => Some(s.clone()),
+        Some(v) => Some(v.to_string()),
+    };
+    let invalid = || Error::BadRequest(format!("Invalid value for column {}", col.name));
+    fn parse<T: std::str::FromStr>(text: Option<String>) -> Result<Option<T>, ()> {
+        text.map(|t| t.trim().parse().map_err(|_| ())).transpose()
+    }
+    Ok(match col.data_type.to_lowercase().as_str() {
+        "tinyint" => SqlValue::U8(parse(text).map_err(|_| invalid())?),
+        "smallint" => SqlValue::I16(parse(text).map_err(|_| invalid())?),
+        "int" => SqlValue::I32(parse(text).map_err(|_| invalid())?),
+        "bigint" => SqlValue::I64(parse(text).map_err(|_| invalid())?),
+        "real" => SqlValue::F32(parse(text).map_err(|_| invalid())?),
+        "float" => SqlValue::F64(parse(text).map_err(|_| invalid())?),
+        "bit" => SqlValue::Bit(
+            text.map(|t| match t.trim().to_lowercase().as_str() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(invalid()),
+            })
+            .transpose()?,
+        ),
+        "uniqueidentifier" => SqlValue::Guid(
+            text.map(|t| uuid::Uuid::parse_str(t.trim()))
+                .transpose()
+                .map_err(|_| invalid())?,
+        ),
+        "binary" | "varbinary" | "image" => {
+            use base64::Engine;
+            let bytes = text
+                .map(|t| base64::engine::general_purpose::STANDARD.decode(t))
+                .transpose()
+                .map_err(|_| {
+                    Error::BadRequest(format!("Invalid base64 value for column {}", col.name))
+                })?;
+            SqlValue::Binary(bytes.map(std::borrow::Cow::Owned))
+        }
+        _ => SqlValue::String(text.map(std::borrow::Cow::Owned)),
+    })
+}
+
+/// Load rows with a TDS bulk copy: one streamed `INSERT BULK` of the body's
+/// `columns` instead of parameterized statements; the rest take their
+/// defaults. `CHECK_CONSTRAINTS` and `KEEP_NULLS` make it behave like the
+/// INSERT it replaces: CHECK and foreign key constraints are enforced and
+/// explicit nulls stay NULL. Runs in a transaction, so `Prefer: tx=rollback`
+/// still works; the connection is discarded if the copy fails partway.
+async fn bulk_insert(
+    state: &AppState,
+    table: &crate::schema::TableInfo,
+    objects: &[&serde_json::Map<String, JsonValue>],
+    columns: &[String],
+    ctx: &SqlContext,
+    prefer: &Preferences,
+) -> Result<u64, Error> {
+    let columns: Vec<&crate::schema::ColumnInfo> =
+        columns.iter().filter_map(|c| table.column(c)).collect();
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+
+    let mut conn = SessionConnection::new(ctx.checkout(&state.pool).await?);
+    let outcome = async {
+        let client = conn.client();
+        let mut sink = claw::ArrowRowWriter::new();
+        let begin = format!(
+            "SET NOCOUNT ON;\n{}\nBEGIN TRANSACTION;",
+            ctx.session.join("\n")
+        );
+        client
+            .batch_into(&begin, &mut sink)
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+
+        let mut request = client
+            .bulk_insert_with_options(
+                &table.full_name(),
+                &names,
+                claw::SqlBulkCopyOptions::CheckConstraints | claw::SqlBulkCopyOptions::KeepNulls,
+                &[],
+            )
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+        for obj in objects {
+            let mut row = claw::TokenRow::new();
+            for col in &columns {
+                let val = obj
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(&col.name))
+                    .map(|(_, v)| v);
+                row.push(bulk_value(col, val)?);
+            }
+            request
+                .send(row)
+                .await
+                .map_err(|e| Error::Sql(e.to_string()))?;
+        }
+        let result = request
+            .finalize()
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+
+        let end = if prefer.tx == TxPreference::Rollback {
+            "ROLLBACK TRANSACTION;"
+        } else {
+            "COMMIT TRANSACTION;"
+        };
+        let end = format!("{}\n{}", end, auth::build_revert_sql());
+        client
+            .batch_into(&end, &mut sink)
+            .await
+            .map_err(|e| Error::Sql(e.to_string()))?;
+        Ok(result.total())
+    }
+    .await;
+    if outcome.is_ok() {
+        conn.release();
+    }
+    outcome
+}
+
+/// Which of `columns` each object sends, for the `present` flags of inserts,
+/// upserts and bulk updates.
+fn presence(objects: &[&serde_json::Map<String, JsonValue>], columns: &[String]) -> Vec<Vec<bool>> {
+    objects
+        .iter()
+        .map(|obj| columns.iter().map(|c| obj.contains_key(c)).collect())
+        .collect()
+}
+
+/// Flatten insert bodies into bind values, row by row in column order.
+/// Missing keys and explicit nulls both bind as NULL here; the statement
+/// builders tell them apart through `presence` flags.
+fn collect_insert_params(
+    table: &crate::schema::TableInfo,
+    objects: &[&serde_json::Map<String, JsonValue>],
+    columns: &[String],
+) -> Result<Vec<SqlParam>, Error> {
+    let mut param_values: Vec<SqlParam> = Vec::new();
+    for obj in objects {
+        for col in columns {
+            param_values.push(body_param(table, col, obj.get(col))?);
+        }
+    }
+    Ok(param_values)
+}
+
+/// Bind value for a body field, typed and normalized for the column.
+/// Missing and null both bind as SQL NULL; binary columns take base64.
+fn body_param(
+    table: &crate::schema::TableInfo,
+    column: &str,
+    val: Option<&JsonValue>,
+) -> Result<SqlParam, Error> {
+    let col = table.column(column);
+    let Some(val) = val.filter(|v| !v.is_null()) else {
+        return Ok(col.map_or(SqlParam::Null, |c| SqlParam::null_for(&c.data_type)));
+    };
+    let Some(col) = col else {
+        return Ok(json_param(val));
+    };
+    typed_json_param(&col.data_type, &col.name, val)
+}
+
+/// A non-null JSON value bound as `data_type`, for the column or parameter
+/// `name`: strings normalized and typed, numbers typed, base64 decoded for
+/// binary types.
+fn typed_json_param(data_type: &str, name: &str, val: &JsonValue) -> Result<SqlParam, Error> {
+    let data_type = data_type.to_lowercase();
+    match val {
+        JsonValue::String(s) if matches!(data_type.as_str(), "binary" | "varbinary" | "image") => {
+            use base64::Engine;
+            base64::engine::general_purpose::STANDARD
+                .decode(s)
+                .map(SqlParam::Binary)
+                .map_err(|_| Error::BadRequest(format!("Invalid base64 value for column {}", name)))
+        }
+        JsonValue::String(s) => {
+            let s = types::normalize_param(&data_type, name, s)?;
+            Ok(SqlParam::typed(Some(&data_type), &s))
+        }
+        JsonValue::Number(n) => {
+            let s = types::normalize_param(&data_type, name, &n.to_string())?;
+            Ok(SqlParam::typed(Some(&data_type), &s))
+        }
+        JsonValue::Bool(b) if data_type == "bit" => Ok(SqlParam::Bit(*b)),
+        _ => Ok(json_param(val)),
+    }
+}
+
+/// Operators a PATCH value can apply to the current column value.
+const UPDATE_OPERATORS: [&str; 5] = [
+    "increment",
+    "decrement",
+    "multiply",
+    "json_append",
+    "json_set",
+];
+
+/// `{"increment": 5}` as `("increment", 5)`: an object whose only key is
+/// an update operator.
+fn update_operator(value: &JsonValue) -> Option<(&str, &JsonValue)> {
+    let obj = value.as_object().filter(|obj| obj.len() == 1)?;
+    let (key, operand) = obj.iter().next()?;
+    UPDATE_OPERATORS
+        .contains(&key.as_str())
+        .then_some((key.as_str(), operand))
+}
+
+/// The body without operator values, for the column rules: what an
+/// operator produces is only known to the database (and its constraints).
+fn plain_values(obj: &serde_json::Map<String, JsonValue>) -> serde_json::Map<String, JsonValue> {
+    obj.iter()
+        .filter(|(_, v)| update_operator(v).is_none())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// SET assignments for a PATCH body and their parameters, in column
+/// order: plain values, or operators such as `{"increment": 5}`,
+/// `{"json_append": "new"}` and `{"json_set": {"$.theme": "dark"}}`.
+fn update_assignments(
+    table: &crate::schema::TableInfo,
+    columns: &[String],
+    obj: &serde_json::Map<String, JsonValue>,
+) -> Result<(Vec<(String, query::UpdateOp)>, Vec<SqlParam>), Error> {
+    let mut assignments = Vec::with_capacity(columns.len());
+    let mut params = Vec::with_capacity(columns.len());
+    for column in columns {
+        let value = obj.get(column);
+        let Some((operator, operand)) = value.and_then(update_operator) else {
+            assignments.push((column.clone(), query::UpdateOp::Set));
+            params.push(body_param(table, column, value)?);
+            continue;
+        };
+        let data_type = table
+            .column(column)
+            .map(|c| c.data_type.to_lowercase())
+            .unwrap_or_default();
+        let is_json = |v: &JsonValue| v.is_object() || v.is_array();
+        let op = match operator {
+            "increment" | "decrement" | "multiply" => {
+                let (kind, _) = types::sql_type_to_openapi(&data_type);
+                if !matches!(kind, "integer" | "number") {
+                    return Err(Error::BadRequest(format!(
+                        "{}: {} needs a numeric column",
+                        column, operator
+                    )));
+                }
+                if !operand.is_number() {
+                    return Err(Error::BadRequest(format!(
+                        "{}: {} takes a number",
+                        column, operator
+                    )));
+                }
+                params.push(body_param(table, column, Some(operand))?);
+                match operator {
+                    "increment" => query::UpdateOp::Increment,
+                    "decrement" => query::UpdateOp::Decrement,
+                    _ => query::UpdateOp::Multiply,
+                }
+            }
+            _ => {
+                if !matches!(data_type.as_str(), "nvarchar" | "varchar" | "json") {
+                    return Err(Error::BadRequest(format!(
+                        "{}: {} needs a JSON text column",
+                        column, operator
+                    )));
+                }
+                if operator == "json_append" {
+                    params.push(json_param(operand));
+                    query::UpdateOp::JsonAppend(is_json(operand))
+                } else {
+                    let paths = operand.as_object().filter(|m| !m.is_empty()).ok_or_else(|| {
+                        Error::BadRequest(format!(
+                            "{}: json_set takes an object of JSON paths, e.g. {{\"$.theme\": \"dark\"}}",
+                            column
+                        ))
+                    })?;
+                    let mut flags = Vec::with_capacity(paths.len());
+                    for (path, v) in paths {
+                        if !path.starts_with('$') {
+                            return Err(Error::BadRequest(format!(
+                                "{}: JSON paths start with $, got {}",
+                                column, path
+                            )));
+                        }
+                        params.push(SqlParam::Text(path.clone()));
+                        params.push(json_param(v));
+                        flags.push(is_json(v));
+                    }
+                    query::UpdateOp::JsonSet(flags)
+                }
+            }
+        };
+        assignments.push((column.clone(), op));
+    }
+    Ok((assignments, params))
+}
+
+/// Turn `build_insert_each` output into a per-row status array, with each
+/// inserted row when `read_back`.
+///
+/// Responds 201 when every row went in, 207 when some failed.
+fn build_row_status_response(
+    rows: Vec<serde_json::Map<String, JsonValue>>,
+    read_back: bool,
+) -> Result<Response, Error> {
+    // A row that doomed the transaction undid the chunks before its own too
+    let doomed = rows.iter().find_map(|row| match row.get("__doomed") {
+        Some(JsonValue::String(err)) => Some(err.clone()),
+        _ => None,
+    });
+    let mut any_failed = false;
+    let statuses: Vec<JsonValue> = rows
+        .into_iter()
+        .map(|mut row| {
+            let index = row.remove("__idx").unwrap_or(JsonValue::Null);
+            row.remove("__doomed");
+            let error = match row.remove("__error") {
+                Some(JsonValue::String(err)) => Some(err),
+                _ => doomed.clone(),
+            };
+            match error {
+                Some(err) => {
+                    any_failed = true;
+                    let err = redact::scrub(&err);
+                    serde_json::json!({ "index": index, "status": "failed", "error": err })
+                }
+                None if read_back => {
+                    serde_json::json!({ "index": index, "status": "inserted", "row": row })
+                }
+                None => serde_json::json!({ "index": index, "status": "inserted" }),
+            }
+        })
+        .collect();
+
+    let status = if any_failed {
+        StatusCode::MULTI_STATUS
+    } else {
+        StatusCode::CREATED
+    };
+    let json = serde_json::to_string(&statuses).unwrap_or_default();
+    Ok(response::build_response(
+        json.into_bytes(),
+        "application/json; charset=utf-8",
+        status,
+        None,
+        None,
+    ))
+}
+
+/// Add the table's configured `headers`. Literal values go on every
+/// response; aggregates like `sum(amount)` only on reads, computed over the
+/// rows `filters` match, as does `cache_control`. Deprecated tables also
+/// get `Deprecation` and `Sunset`.
+async fn add_table_headers(
+    state: &AppState,
+    table: &crate::schema::TableInfo,
+    ctx: &SqlContext,
+    filters: Option<&[FilterNode]>,
+    response: &mut Response,
+) -> Result<(), Error> {
+    let Some(table_cfg) = state.config.table_config(&table.schema, &table.name) else {
+        return Ok(());
+    };
+    let mut aggregates = Vec::new();
+    let mut values: Vec<(&str, String)> = Vec::new();
+    for (name, value) in &table_cfg.headers {
+        match query::parse_header_aggregate(name, value) {
+            Some(agg) => aggregates.push(agg),
+            None => values.push((name.as_str(), value.clone())),
+        }
+    }
+    if let (Some(cache_control), Some(_)) = (&table_cfg.cache_control, filters) {
+        values.push(("Cache-Control", cache_control.clone()));
+    }
+    if table_cfg.is_deprecated() {
+        log_deprecated_use(table, ctx.role.as_deref());
+        values.push(("Deprecation", "true".to_string()));
+        if let Some(sunset) = table_cfg.sunset_http_date() {
+            values.push(("Sunset", sunset));
+        }
+    }
+
+    if let (Some(filters), false) = (filters, aggregates.is_empty()) {
+        let built = query::build_header_aggregates(table, &aggregates, filters)?;
+        let rows = execute_query_to_json(state, &built, ctx).await?;
+        if let Some(row) = rows.first() {
+            for (i, agg) in aggregates.iter().enumerate() {
+                match row.get(&format!("h{}", i)) {
+                    // SUM/MIN/MAX of no rows: leave the header out
+                    None | Some(JsonValue::Null) => {}
+                    Some(JsonValue::String(s)) => values.push((agg.header.as_str(), s.clone())),
+                    Some(v) => values.push((agg.header.as_str(), v.to_string())),
+                }
+            }
+        }
+    }
+
+    for (name, value) in values {
+        let header = (
+            axum::http::HeaderName::from_bytes(name.as_bytes()),
+            axum::http::HeaderValue::from_str(&value),
+        );
+        match header {
+            (Ok(name), Ok(value)) => {
+                response.headers_mut().insert(name, value);
+            }
+            _ => tracing::warn!("Skipping invalid header {} for {}", name, table.name),
+        }
+    }
+    Ok(())
+}
+
+/// Warn the first time each role uses a deprecated table, so the
+/// remaining consumers show up in the logs without flooding them.
+fn log_deprecated_use(table: &crate::schema::TableInfo, role: Option<&str>) {
+    static SEEN: std::sync::OnceLock<std::sync::Mutex<std::collections::HashSet<String>>> =
+        std::sync::OnceLock::new();
+    let role = role.unwrap_or("anonymous");
+    let key = format!("{}\n{}", table.full_name(), role);
+    let first = SEEN
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .insert(key);
+    if first {
+        tracing::warn!(
+            "Deprecated table {}.{} is still used by role {}",
+            table.schema,
+            table.name,
+            role
+        );
+    }
 }
 
-/// Execute a DML query (INSERT/UPDATE/DELETE) with OUTPUT.
-async fn execute_dml_query(
-    state: &AppState,
-    sql: &str,
-    params: &[String],
-    claims: &Option<auth::Claims>,
-    prefer: &Preferences,
-) -> Result<Vec<serde_json::Map<String, JsonValue>>, Error> {
-    let ctx_stmts = auth::build_session_context_sql(claims, &state.config);
+/// Apply configured per-column serialization overrides (`[columns."..."]`).
+fn format_columns(
+    config: &AppConfig,
+    schema: &str,
+    table: &str,
+    rows: &mut [serde_json::Map<String, JsonValue>],
+) {
+    types::apply_column_formats(rows, &config.column_formats(schema, table));
+}
 
-    let tx_begin = "BEGIN TRANSACTION;";
-    let tx_end = if prefer.tx == TxPreference::Rollback {
-        "ROLLBACK TRANSACTION;"
-    } else {
-        "COMMIT TRANSACTION;"
-    };
+/// 204 for `return=minimal`, reporting how many rows the write touched as
+/// `Content-Range: */<count>`.
+fn minimal_response(affected: usize) -> Response {
+    response::build_response(
+        Vec::new(),
+        "application/json",
+        StatusCode::NO_CONTENT,
+        Some(format!("*/{}", affected)),
+        None,
+    )
+}
 
-    let full_sql = if ctx_stmts.is_empty() {
-        format!("SET NOCOUNT ON;\n{}\n{}\n{}", tx_begin, sql, tx_end)
+/// Rows written, or the row counts of statements run without OUTPUT.
+fn affected_rows(rows: &[serde_json::Map<String, JsonValue>]) -> usize {
+    let counts: Vec<u64> = rows
+        .iter()
+        .filter_map(|row| row.get(query::ROWCOUNT_COLUMN)?.as_u64())
+        .collect();
+    if counts.is_empty() {
+        rows.len()
     } else {
-        format!(
-            "SET NOCOUNT ON;\n{}\n{}\n{}\n{}",
-            ctx_stmts.join("\n"),
-            tx_begin,
-            sql,
-            tx_end
-        )
-    };
-
-    let mut conn = state.pool.get().await?;
-    let client = conn.client();
-
-    let mut query = claw::Query::new(full_sql);
-    for val in params {
-        query.bind(val.as_str());
+        counts.iter().sum::<u64>() as usize
     }
+}
 
-    let stream = query
-        .query(client)
-        .await
-        .map_err(|e| Error::Sql(e.to_string()))?;
-
-    let rows = stream
-        .into_first_result()
-        .await
-        .map_err(|e| Error::Sql(e.to_string()))?;
-
-    Ok(rows.iter().map(types::row_to_json).collect())
+/// Rows per Arrow IPC record batch for `table`'s responses.
+fn arrow_batch_rows(config: &AppConfig, table: &crate::schema::TableInfo) -> usize {
+    config
+        .table_config(&table.schema, &table.name)
+        .map_or(ARROW_BATCH_ROWS, |t| t.arrow_batch_rows())
 }
 
 /// Build a mutation response based on Prefer header.
@@ -781,15 +4931,10 @@ fn build_mutation_response(
     prefer: &Preferences,
     format: &ResponseFormat,
     success_status: StatusCode,
+    batch_rows: usize,
 ) -> Result<Response, Error> {
     match prefer.return_mode {
-        ReturnMode::Minimal => Ok(response::build_response(
-            Vec::new(),
-            "application/json",
-            StatusCode::NO_CONTENT,
-            None,
-            None,
-        )),
+        ReturnMode::Minimal => Ok(minimal_response(affected_rows(&rows))),
         ReturnMode::HeadersOnly => {
             let range = format!("*/*/{}", rows.len());
             Ok(response::build_response(
@@ -814,6 +4959,41 @@ fn build_mutation_response(
                     None,
                 ))
             }
+            ResponseFormat::Csv => {
+                let columns: Vec<String> = rows
+                    .first()
+                    .map(|r| r.keys().cloned().collect())
+                    .unwrap_or_default();
+                let csv_str = response::rows_to_csv(&rows, &columns)?;
+                Ok(response::build_response(
+                    csv_str.into_bytes(),
+                    "text/csv; charset=utf-8",
+                    success_status,
+                    None,
+                    None,
+                ))
+            }
+            ResponseFormat::ArrowIpcStream => {
+                let batch = response::json_rows_to_record_batch(&rows)?;
+                Ok(response::build_response(
+                    response::record_batch_to_ipc(&batch, batch_rows)?,
+                    "application/vnd.apache.arrow.stream",
+                    success_status,
+                    None,
+                    None,
+                ))
+            }
+            ResponseFormat::ArrowJson => {
+                let batch = response::json_rows_to_record_batch(&rows)?;
+                let json = response::record_batch_to_arrow_json(&batch)?;
+                Ok(response::build_response(
+                    json.into_bytes(),
+                    "application/vnd.apache.arrow+json",
+                    success_status,
+                    None,
+                    None,
+                ))
+            }
             _ => {
                 let json = response::rows_to_json(&rows);
                 Ok(response::build_response(
@@ -828,32 +5008,213 @@ fn build_mutation_response(
     }
 }
 
-/// Handle embedding of related tables.
-#[allow(clippy::too_many_arguments)]
-async fn handle_embeds(
+/// Depth of `?tree=` queries without `tree.depth`, and the most allowed.
+const DEFAULT_TREE_DEPTH: u32 = 10;
+const MAX_TREE_DEPTH: u32 = 100;
+
+/// `?tree=<column>.<op>.<value>`: the matching rows plus their descendants
+/// through the table's self-referencing FK, nested under `children`.
+/// Other filters narrow the roots; `tree.depth` bounds the walk.
+async fn handle_tree(
     state: &AppState,
+    table: &crate::schema::TableInfo,
+    root: &str,
+    query_params: &HashMap<String, String>,
+    mut filter_nodes: Vec<FilterNode>,
+    ctx: &SqlContext,
+) -> Result<Response, Error> {
+    let (column, expr) = select::find_unquoted(root, ".")
+        .map(|dot| (&root[..dot], &root[dot + 1..]))
+        .filter(|(column, _)| table.column(&select::unquote_ident(column)).is_some())
+        .ok_or_else(|| {
+            Error::BadRequest(format!(
+                "Invalid tree root: {} (expected <column>.<op>.<value>)",
+                root
+            ))
+        })?;
+    let mut root_filter = vec![FilterNode::Condition(filters::parse_filter(column, expr)?)];
+    normalize_filter_values(&mut root_filter, table)?;
+    filter_nodes.extend(root_filter);
+
+    let max_depth = match query_params.get("tree.depth") {
+        Some(v) => v
+            .parse::<u32>()
+            .ok()
+            .filter(|d| *d <= MAX_TREE_DEPTH)
+            .ok_or_else(|| {
+                Error::BadRequest(format!(
+                    "Invalid tree.depth: expected 0 to {}, got '{}'",
+                    MAX_TREE_DEPTH, v
+                ))
+            })?,
+        None => DEFAULT_TREE_DEPTH,
+    };
+
+    let self_fk = table.foreign_keys.iter().find(|fk| {
+        fk.ref_schema.eq_ignore_ascii_case(&table.schema)
+            && fk.ref_table.eq_ignore_ascii_case(&table.name)
+    });
+    let (parent, key) = match self_fk.map(|fk| (fk.columns.as_slice(), fk.ref_columns.as_slice())) {
+        Some(([parent], [key])) => (parent, key),
+        Some(_) => {
+            return Err(Error::BadRequest(format!(
+                "{}.{}: tree queries need a single-column self-referencing foreign key",
+                table.schema, table.name
+            )))
+        }
+        None => {
+            return Err(Error::BadRequest(format!(
+                "{}.{} has no self-referencing foreign key",
+                table.schema, table.name
+            )))
+        }
+    };
+
+    let built = query::build_tree(table, &filter_nodes, key, parent, max_depth)?;
+    let mut rows = execute_query_to_json(state, &built, ctx).await?;
+    format_columns(&state.config, &table.schema, &table.name, &mut rows);
+
+    let json = serde_json::to_string(&nest_tree(rows, key, parent)).unwrap_or_default();
+    Ok(response::build_response(
+        json.into_bytes(),
+        "application/json; charset=utf-8",
+        StatusCode::OK,
+        None,
+        None,
+    ))
+}
+
+/// Rows of a tree query keyed for nesting: (parent key, depth).
+type TreeLevels = HashMap<(String, i64), Vec<serde_json::Map<String, JsonValue>>>;
+
+/// Nest tree-query rows: depth-0 rows are the roots, and each row's children
+/// are the next level's rows pointing at its key.
+fn nest_tree(
+    rows: Vec<serde_json::Map<String, JsonValue>>,
+    key: &str,
+    parent: &str,
+) -> Vec<JsonValue> {
+    let mut roots = Vec::new();
+    let mut levels: TreeLevels = HashMap::new();
+    for mut row in rows {
+        let depth = row
+            .remove(query::TREE_DEPTH_COLUMN)
+            .and_then(|d| d.as_i64())
+            .unwrap_or(0);
+        if depth == 0 {
+            roots.push(row);
+        } else if let Some(parent_key) = row.get(parent).and_then(tree_key) {
+            levels.entry((parent_key, depth)).or_default().push(row);
+        }
+    }
+    roots
+        .into_iter()
+        .map(|row| attach_children(row, 0, key, &mut levels))
+        .collect()
+}
+
+fn attach_children(
+    mut row: serde_json::Map<String, JsonValue>,
+    depth: i64,
+    key: &str,
+    levels: &mut TreeLevels,
+) -> JsonValue {
+    let children = row
+        .get(key)
+        .and_then(tree_key)
+        .and_then(|k| levels.remove(&(k, depth + 1)))
+        .unwrap_or_default();
+    let children = children
+        .into_iter()
+        .map(|child| attach_children(child, depth + 1, key, levels))
+        .collect();
+    row.insert("children".to_string(), JsonValue::Array(children));
+    JsonValue::Object(row)
+}
+
+fn tree_key(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::Null => None,
+        JsonValue::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Find the relationship behind an embed, refusing targets in schemas that
+/// aren't exposed.
+fn resolve_embed(
+    config: &AppConfig,
     schema_cache: &SchemaCache,
     schema_name: &str,
     table_name: &str,
-    embeds: &[&EmbedSelect],
-    rows: &mut [serde_json::Map<String, JsonValue>],
-    _query_params: &HashMap<String, String>,
-    claims: &Option<auth::Claims>,
-    extra_join_cols: &[String],
-    original_select_nodes: &[SelectNode],
-) -> Result<(), Error> {
-    for embed in embeds {
-        let embed_info = schema_cache
-            .find_embed(
-                schema_name,
-                table_name,
-                &embed.name,
-                embed.fk_hint.as_deref(),
-            )
-            .ok_or_else(|| {
+    embed: &EmbedSelect,
+) -> Result<crate::schema::EmbedInfo, Error> {
+    let embed_info = schema_cache
+        .find_embed(
+            schema_name,
+            table_name,
+            &embed.name,
+            embed.fk_hint.as_deref(),
+        )
+        .map_err(|e| match e {
+            EmbedLookupError::NotFound => {
                 Error::BadRequest(format!("No relationship found for embed: {}", embed.name))
-            })?;
+            }
+            EmbedLookupError::Ambiguous(options) => Error::BadRequestHint {
+                message: format!(
+                    "Embed {} is ambiguous: it matches tables in several schemas",
+                    embed.name
+                ),
+                hint: format!(
+                    "Qualify the name or add a !schema.constraint hint: {}",
+                    options.join(", ")
+                ),
+            },
+        })?;
+    if !config.is_schema_exposed(&embed_info.target_schema) {
+        return Err(Error::BadRequest(format!(
+            "Cannot embed {}: schema not exposed: {}",
+            embed.name, embed_info.target_schema
+        )));
+    }
+    Ok(embed_info)
+}
+
+/// Parse a non-negative `<embed>.limit` / `<embed>.offset` value.
+fn parse_embed_count(embed: &str, param: &str, value: &str) -> Result<i64, Error> {
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|n| *n >= 0)
+        .ok_or_else(|| {
+            Error::BadRequest(format!(
+                "Invalid {}.{}: expected a non-negative integer, got '{}'",
+                embed, param, value
+            ))
+        })
+}
 
+/// Resolve the embeds in a select (recursively) into correlated subqueries.
+///
+/// `parent_ref` qualifies the parent's columns in join conditions: the full
+/// table name at the top level, the parent embed's alias below it. Embed
+/// query parameters are keyed by the dotted path (`orders.items.qty`).
+/// `!inner` embeds push an EXISTS filter onto `parent_filters`.
+#[allow(clippy::too_many_arguments)]
+fn resolve_embeds<'a>(
+    config: &AppConfig,
+    schema_cache: &'a SchemaCache,
+    query_params: &QueryParams,
+    parent: &crate::schema::TableInfo,
+    parent_ref: &str,
+    prefix: &str,
+    nodes: &[SelectNode],
+    alias_counter: &mut usize,
+    parent_filters: &mut Vec<FilterNode>,
+) -> Result<Vec<query::EmbedQuery<'a>>, Error> {
+    let mut embeds = Vec::new();
+    for embed in select::select_embeds(nodes) {
+        let embed_info = resolve_embed(config, schema_cache, &parent.schema, &parent.name, embed)?;
         let target_table = schema_cache
             .get_table(&embed_info.target_schema, &embed_info.target_table)
             .ok_or_else(|| {
@@ -862,231 +5223,170 @@ async fn handle_embeds(
                     embed_info.target_schema, embed_info.target_table
                 ))
             })?;
+        let path = if prefix.is_empty() {
+            embed.output_name().to_string()
+        } else {
+            format!("{}.{}", prefix, embed.output_name())
+        };
 
-        // Collect source values for the join column
-        let source_values: Vec<String> = rows
-            .iter()
-            .filter_map(|row| {
-                row.get(&embed_info.source_column).and_then(|v| match v {
-                    JsonValue::Null => None,
-                    JsonValue::String(s) => Some(s.clone()),
-                    other => Some(other.to_string()),
-                })
-            })
-            .collect::<std::collections::HashSet<_>>()
-            .into_iter()
-            .collect();
+        // Apply embed filters (e.g. orders.status=eq.active)
+        let mut filters = build_embed_filters_from_params(query_params, &path, target_table)?;
 
-        if source_values.is_empty() {
-            // No values to join on — set all embeds to empty array
-            for row in rows.iter_mut() {
-                row.insert(embed.name.clone(), JsonValue::Array(Vec::new()));
+        // Per-parent paging: `orders.limit=5` overrides `orders(*).limit(5)`
+        let paging_param = |name: &str| query_params.get(&format!("{}.{}", path, name));
+        let limit = match paging_param("limit") {
+            Some(v) => Some(parse_embed_count(&path, "limit", v)?),
+            None => embed.limit,
+        };
+        let offset = match paging_param("offset") {
+            Some(v) => Some(parse_embed_count(&path, "offset", v)?),
+            None => embed.offset,
+        };
+        let order_str = paging_param("order")
+            .map(String::as_str)
+            .or(embed.order.as_deref());
+        let order = query::parse_order(order_str.unwrap_or(""))?;
+        for spec in &order {
+            if target_table.column(&spec.column).is_none() {
+                return Err(Error::BadRequest(format!(
+                    "Unknown column in {}.order: {}",
+                    path, spec.column
+                )));
             }
-            continue;
-        }
-
-        // Build embed column list — always include the join column
-        let mut embed_col_nodes = embed.columns.clone();
-        let embed_selected = select::select_columns(&embed_col_nodes);
-        if !embed_selected.is_empty()
-            && !select::has_star(&embed_col_nodes)
-            && !embed_selected
-                .iter()
-                .any(|c| c.eq_ignore_ascii_case(&embed_info.target_column))
-        {
-            embed_col_nodes.push(SelectNode::Column(embed_info.target_column.clone()));
         }
-        let embed_columns = build_embed_column_list(target_table, &embed_col_nodes);
 
-        // Build IN clause for batch fetch
-        let placeholders: Vec<String> = source_values
-            .iter()
-            .enumerate()
-            .map(|(i, _)| format!("@P{}", i + 1))
-            .collect();
-
-        let embed_sql = format!(
-            "SET NOCOUNT ON;\nSELECT {} FROM {} WHERE [{}] IN ({})",
-            embed_columns,
-            target_table.full_name(),
-            escape_ident(&embed_info.target_column),
-            placeholders.join(", ")
+        let many = matches!(
+            embed_info.join_type,
+            crate::schema::EmbedJoinType::OneToMany
         );
+        if embed.spread && many {
+            return Err(Error::BadRequest(format!(
+                "Cannot spread {}: only many-to-one embeds can be spread",
+                path
+            )));
+        }
+        if embed.spread && !select::select_embeds(&embed.columns).is_empty() {
+            return Err(Error::BadRequest(format!(
+                "Cannot embed inside spread {}",
+                path
+            )));
+        }
 
-        // Apply embed filters
-        let _embed_filter_prefix = format!("{}.", embed.name);
-
-        let ctx_stmts = auth::build_session_context_sql(claims, &state.config);
-        let full_sql = if ctx_stmts.is_empty() {
-            embed_sql
-        } else {
-            format!("{}\n{}", ctx_stmts.join("\n"), embed_sql)
-        };
-
-        let mut conn = state.pool.get().await?;
-        let client = conn.client();
-
-        let mut query = claw::Query::new(full_sql);
-
-        // Bind numeric PKs as integers, not strings, to match SQL Server column types
-        let target_col_is_numeric = target_table
-            .columns
+        *alias_counter += 1;
+        let alias = format!("e{}", alias_counter);
+        let join: Vec<(String, String)> = embed_info
+            .target_columns
             .iter()
-            .find(|c| c.name.eq_ignore_ascii_case(&embed_info.target_column))
-            .map(|c| {
-                matches!(
-                    c.data_type.to_lowercase().as_str(),
-                    "int"
-                        | "bigint"
-                        | "smallint"
-                        | "tinyint"
-                        | "numeric"
-                        | "decimal"
-                        | "float"
-                        | "real"
+            .zip(&embed_info.source_columns)
+            .map(|(target_col, source_col)| {
+                (
+                    target_col.clone(),
+                    format!("{}.[{}]", parent_ref, escape_ident(source_col)),
                 )
             })
-            .unwrap_or(false);
-
-        for val in &source_values {
-            if target_col_is_numeric {
-                if let Ok(n) = val.parse::<i64>() {
-                    query.bind(n);
-                } else if let Ok(n) = val.parse::<f64>() {
-                    query.bind(n);
-                } else {
-                    query.bind(val.as_str());
-                }
-            } else {
-                query.bind(val.as_str());
-            }
-        }
-
-        let stream = query
-            .query(client)
-            .await
-            .map_err(|e| Error::Sql(e.to_string()))?;
-
-        let embed_rows = stream
-            .into_first_result()
-            .await
-            .map_err(|e| Error::Sql(e.to_string()))?;
+            .collect();
 
-        let embed_json: Vec<serde_json::Map<String, JsonValue>> =
-            embed_rows.iter().map(types::row_to_json).collect();
-
-        // Group embed results by the join column
-        let mut grouped: HashMap<String, Vec<JsonValue>> = HashMap::new();
-        for erow in &embed_json {
-            if let Some(key_val) = erow.get(&embed_info.target_column) {
-                let key = match key_val {
-                    JsonValue::String(s) => s.clone(),
-                    JsonValue::Null => continue,
-                    other => other.to_string(),
-                };
-                grouped
-                    .entry(key)
-                    .or_default()
-                    .push(JsonValue::Object(erow.clone()));
-            }
+        // `!inner` embeds also restrict the parent rows
+        if embed.inner {
+            parent_filters.push(FilterNode::Exists(filters::ExistsFilter {
+                table: target_table.full_name(),
+                alias: format!("x{}", alias_counter),
+                join: join.clone(),
+                filters: filters.clone(),
+            }));
         }
 
-        // Attach to parent rows
-        for row in rows.iter_mut() {
-            let source_val = row
-                .get(&embed_info.source_column)
-                .map(|v| match v {
-                    JsonValue::String(s) => s.clone(),
-                    JsonValue::Null => String::new(),
-                    other => other.to_string(),
-                })
-                .unwrap_or_default();
-
-            let embedded = grouped.get(&source_val).cloned().unwrap_or_default();
-
-            match embed_info.join_type {
-                crate::schema::EmbedJoinType::ManyToOne => {
-                    // Many-to-one: embed as single object or null
-                    if let Some(first) = embedded.into_iter().next() {
-                        row.insert(embed.name.clone(), first);
-                    } else {
-                        row.insert(embed.name.clone(), JsonValue::Null);
-                    }
-                }
-                crate::schema::EmbedJoinType::OneToMany => {
-                    row.insert(embed.name.clone(), JsonValue::Array(embedded));
-                }
-            }
-        }
+        let nested = resolve_embeds(
+            config,
+            schema_cache,
+            query_params,
+            target_table,
+            &format!("[{}]", escape_ident(&alias)),
+            &path,
+            &embed.columns,
+            alias_counter,
+            &mut filters,
+        )?;
 
-        // Strip injected join column from embed results if not originally requested
-        let originally_selected: Vec<String> = select::select_columns(&embed.columns)
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-        let has_star_select = embed.columns.is_empty() || select::has_star(&embed.columns);
-        if !has_star_select
-            && !originally_selected
-                .iter()
-                .any(|c| c.eq_ignore_ascii_case(&embed_info.target_column))
-        {
-            for row in rows.iter_mut() {
-                if let Some(JsonValue::Array(arr)) = row.get_mut(&embed.name) {
-                    for item in arr.iter_mut() {
-                        if let JsonValue::Object(obj) = item {
-                            obj.remove(&embed_info.target_column);
-                        }
-                    }
-                } else if let Some(JsonValue::Object(obj)) = row.get_mut(&embed.name) {
-                    obj.remove(&embed_info.target_column);
-                }
-            }
-        }
+        embeds.push(query::EmbedQuery {
+            name: embed.output_name().to_string(),
+            table: target_table,
+            alias,
+            join,
+            many,
+            spread: embed.spread,
+            columns: embed.columns.clone(),
+            filters,
+            order,
+            limit,
+            offset,
+            embeds: nested,
+        });
     }
+    Ok(embeds)
+}
 
-    // Strip injected parent join columns
-    if !extra_join_cols.is_empty() {
-        let original_selected: Vec<String> = select::select_columns(original_select_nodes)
+/// Whether `FOR JSON` can serialize a read and its embeds: CLR types
+/// (`geography`, `geometry`, `hierarchyid`) can't be.
+fn server_serializable(table: &crate::schema::TableInfo, embeds: &[query::EmbedQuery]) -> bool {
+    let clr = |c: &crate::schema::ColumnInfo| {
+        matches!(
+            c.data_type.to_lowercase().as_str(),
+            "geography" | "geometry" | "hierarchyid"
+        )
+    };
+    !table.columns.iter().any(clr)
+        && embeds
             .iter()
-            .map(|s| s.to_string())
-            .collect();
-        let parent_has_star =
-            original_select_nodes.is_empty() || select::has_star(original_select_nodes);
-        if !parent_has_star {
-            for col in extra_join_cols {
-                if !original_selected
-                    .iter()
-                    .any(|c| c.eq_ignore_ascii_case(col))
-                {
-                    for row in rows.iter_mut() {
-                        row.remove(col.as_str());
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(())
+            .all(|e| server_serializable(e.table, &e.embeds))
 }
 
-/// Build column list for an embed query.
-fn build_embed_column_list(table: &crate::schema::TableInfo, nodes: &[SelectNode]) -> String {
-    if nodes.is_empty() || select::has_star(nodes) {
-        table
-            .columns
-            .iter()
-            .map(|c| format!("[{}]", escape_ident(&c.name)))
-            .collect::<Vec<_>>()
-            .join(", ")
-    } else {
-        let cols = select::select_columns(nodes);
-        if cols.is_empty() {
-            "*".to_string()
-        } else {
-            cols.iter()
-                .map(|c| format!("[{}]", escape_ident(c)))
-                .collect::<Vec<_>>()
-                .join(", ")
+/// Turn the JSON text returned by embed subqueries into nested values:
+/// one-to-many embeds become arrays (empty when nothing matched), many-to-one
+/// embeds an object or null. Column format overrides apply at every level.
+fn decode_embeds(
+    config: &AppConfig,
+    rows: &mut [serde_json::Map<String, JsonValue>],
+    embeds: &[query::EmbedQuery],
+) {
+    // Spread columns are already flat
+    for embed in embeds.iter().filter(|e| !e.spread) {
+        for row in rows.iter_mut() {
+            // Top-level results arrive as text; nested ones are already JSON
+            let raw = match row.get_mut(&embed.name).map(JsonValue::take) {
+                Some(JsonValue::String(text)) => serde_json::from_str(&text).unwrap_or_default(),
+                Some(value) => value,
+                None => JsonValue::Null,
+            };
+            let mut children: Vec<serde_json::Map<String, JsonValue>> = match raw {
+                JsonValue::Array(items) => items
+                    .into_iter()
+                    .filter_map(|item| match item {
+                        JsonValue::Object(obj) => Some(obj),
+                        _ => None,
+                    })
+                    .collect(),
+                JsonValue::Object(obj) => vec![obj],
+                _ => Vec::new(),
+            };
+            format_columns(
+                config,
+                &embed.table.schema,
+                &embed.table.name,
+                &mut children,
+            );
+            decode_embeds(config, &mut children, &embed.embeds);
+
+            let value = if embed.many {
+                JsonValue::Array(children.into_iter().map(JsonValue::Object).collect())
+            } else {
+                children
+                    .into_iter()
+                    .next()
+                    .map(JsonValue::Object)
+                    .unwrap_or(JsonValue::Null)
+            };
+            row.insert(embed.name.clone(), value);
         }
     }
 }
@@ -1105,6 +5405,35 @@ fn parse_range_header(headers: &HeaderMap) -> (Option<i64>, Option<i64>) {
     (None, None)
 }
 
+/// What a kept export is filed under: the table, the caller's claims and
+/// session setup, and everything in the request that shapes the rows or
+/// their rendering, so one caller's export never answers another's request.
+fn export_key(
+    (schema, table): (&str, &str),
+    claims: &Option<auth::Claims>,
+    session: &[String],
+    headers: &HeaderMap,
+    params: &QueryParams,
+) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+    };
+    let params: Vec<_> = params.pairs().collect();
+    serde_json::json!([
+        schema,
+        table,
+        claims,
+        session,
+        header("accept"),
+        header("prefer"),
+        params,
+    ])
+    .to_string()
+}
+
 /// Build Content-Range header value.
 fn build_content_range(offset: i64, count: i64, total: Option<i64>) -> String {
     let end = if count > 0 {
@@ -1118,20 +5447,188 @@ fn build_content_range(offset: i64, count: i64, total: Option<i64>) -> String {
     format!("{}-{}/{}", offset, end, total_str)
 }
 
-/// Convert a JSON value to a string suitable for SQL parameter binding.
-fn json_value_to_sql_string(val: &JsonValue) -> String {
+/// Bind value for a JSON value with no column type to go by (procedure
+/// arguments, unknown keys): typed from the JSON itself. Arrays and objects
+/// bind as their JSON text.
+fn json_param(val: &JsonValue) -> SqlParam {
     match val {
-        JsonValue::Null => String::new(), // Will be bound as empty string
-        JsonValue::Bool(b) => {
-            if *b {
-                "1".to_string()
-            } else {
-                "0".to_string()
-            }
+        JsonValue::Null => SqlParam::Null,
+        JsonValue::Bool(b) => SqlParam::Bit(*b),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => SqlParam::BigInt(i),
+            (None, Some(f)) if n.is_f64() => SqlParam::Float(f),
+            _ => SqlParam::Text(n.to_string()),
+        },
+        JsonValue::String(s) => SqlParam::Text(s.clone()),
+        JsonValue::Array(_) | JsonValue::Object(_) => SqlParam::Text(val.to_string()),
+    }
+}
+
+/// Bind a parameter with its native TDS type.
+fn bind_param<'a>(query: &mut claw::Query<'a>, param: &'a SqlParam) {
+    match param {
+        SqlParam::Null => query.bind(Option::<&str>::None),
+        SqlParam::NullBinary => query.bind(Option::<&[u8]>::None),
+        SqlParam::Bit(b) => query.bind(*b),
+        SqlParam::Int(v) => query.bind(*v),
+        SqlParam::BigInt(v) => query.bind(*v),
+        SqlParam::Float(v) => query.bind(*v),
+        SqlParam::Guid(v) => query.bind(uuid::Uuid::from_u128(*v)),
+        SqlParam::Binary(v) => query.bind(v.as_slice()),
+        SqlParam::Decimal(v) => query.bind(*v),
+        SqlParam::Text(s) => query.bind(s.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place_order() -> crate::schema::RoutineInfo {
+        let param = |name: &str, data_type: &str, is_output: bool| crate::schema::ParamInfo {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            max_length: None,
+            precision: None,
+            scale: None,
+            is_nullable: true,
+            is_output,
+        };
+        crate::schema::RoutineInfo {
+            name: "place_order".to_string(),
+            schema: "dbo".to_string(),
+            kind: "P".to_string(),
+            params: vec![
+                param("customer_id", "int", false),
+                param("order_id", "int", true),
+                param("return_value", "int", true),
+            ],
         }
-        JsonValue::Number(n) => n.to_string(),
-        JsonValue::String(s) => s.clone(),
-        JsonValue::Array(arr) => serde_json::to_string(arr).unwrap_or_default(),
-        JsonValue::Object(obj) => serde_json::to_string(obj).unwrap_or_default(),
+    }
+
+    #[test]
+    fn test_rpc_get_arg() {
+        assert_eq!(rpc_get_arg("country", "NL").unwrap(), "NL");
+        assert_eq!(rpc_get_arg("country", "eq.NL").unwrap(), "NL");
+        assert_eq!(rpc_get_arg("rate", "0.21").unwrap(), "0.21");
+        assert_eq!(rpc_get_arg("code", "eq.gt.5").unwrap(), "gt.5");
+        assert!(rpc_get_arg("total", "gt.1000").is_err());
+        assert!(rpc_get_arg("region", "in.(west,east)").is_err());
+        assert!(rpc_get_arg("region", "not.eq.west").is_err());
+    }
+
+    #[test]
+    fn test_rpc_call() {
+        let body = serde_json::json!({"customer_id": 7, "order_id": null});
+        let call = rpc_call(&place_order(), body.as_object().unwrap()).unwrap();
+        assert_eq!(
+            call.batch(&call.exec),
+            "DECLARE @rpc_return int, @rpc_out1 int = @P2;\n\
+             EXEC @rpc_return = [dbo].[place_order] @customer_id = @P1, \
+             @order_id = @rpc_out1 OUTPUT;\n\
+             SELECT @rpc_return AS [return_value], @rpc_out1 AS [@order_id];"
+        );
+        assert_eq!(call.params, vec![SqlParam::Int(7), SqlParam::Null]);
+    }
+
+    #[test]
+    fn test_rpc_call_outputs() {
+        // OUTPUT parameters left out keep their defaults and aren't read back
+        let body = serde_json::json!({"customer_id": 7});
+        let call = rpc_call(&place_order(), body.as_object().unwrap()).unwrap();
+        assert_eq!(
+            call.exec,
+            "EXEC @rpc_return = [dbo].[place_order] @customer_id = @P1"
+        );
+        assert!(call.outputs.is_empty());
+
+        // A parameter named like the return value reads back apart from it
+        let body = serde_json::json!({"return_value": 1});
+        let call = rpc_call(&place_order(), body.as_object().unwrap()).unwrap();
+        assert_eq!(call.outputs, vec!["@rpc_out1 AS [@return_value]"]);
+
+        let body = serde_json::json!({"nope": 1});
+        assert!(rpc_call(&place_order(), body.as_object().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_csv_body() {
+        let body = b"id, name,note\n1,Ann,\n2,\"Smith, Bob\",hi\n";
+        let parsed = csv_body(body).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!([
+                {"id": "1", "name": "Ann", "note": null},
+                {"id": "2", "name": "Smith, Bob", "note": "hi"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_csv_body_header_only() {
+        assert_eq!(csv_body(b"id,name\n").unwrap(), serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_csv_body_ragged_row() {
+        assert!(matches!(
+            csv_body(b"id,name\n1,Ann,extra\n"),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_ensure_updatable_instead_of() {
+        // A joined view with only an INSTEAD OF INSERT trigger
+        let mut view = rpc_result_table("order_lines", &[]);
+        view.is_view = true;
+        view.is_updatable = false;
+        view.instead_of.insert = true;
+        assert!(ensure_updatable(&view, Dml::Insert).is_ok());
+        assert!(matches!(
+            ensure_updatable(&view, Dml::Update),
+            Err(Error::MethodNotAllowed(msg)) if msg.ends_with("has no INSTEAD OF UPDATE trigger")
+        ));
+        assert!(ensure_updatable(&view, Dml::Delete).is_err());
+
+        // Its inserts answer as return=minimal
+        let mut prefer = Preferences::default();
+        instead_of_minimal(&view, Dml::Insert, &mut prefer);
+        assert_eq!(prefer.return_mode, ReturnMode::Minimal);
+    }
+
+    #[test]
+    fn test_put_select() {
+        let columns =
+            [("id", "int"), ("name", "nvarchar")].map(|(c, ty)| (c.to_string(), ty.to_string()));
+        let mut table = rpc_result_table("users", &columns);
+        table.schema = "dbo".to_string();
+        table.primary_key = vec!["id".to_string()];
+
+        // select= and columns= shape the row; they aren't filters
+        let params = QueryParams::from(vec![
+            ("id".to_string(), "eq.7".to_string()),
+            ("select".to_string(), "name".to_string()),
+            ("columns".to_string(), "id,name".to_string()),
+        ]);
+        let keys = put_key_values(&table, None, &params).unwrap();
+        assert_eq!(keys, vec![("id".to_string(), "7".to_string())]);
+        let params = QueryParams::from(vec![("select".to_string(), "name".to_string())]);
+        let keys = put_key_values(&table, Some("7".to_string()), &params).unwrap();
+        assert_eq!(keys, vec![("id".to_string(), "7".to_string())]);
+        let params = QueryParams::from(vec![("name".to_string(), "eq.Ann".to_string())]);
+        assert!(put_key_values(&table, Some("7".to_string()), &params).is_err());
+
+        // The written row comes back in the select= shape
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let built = query::build_put(&table, &columns, true).unwrap();
+        let selection = MutationSelect {
+            nodes: select::parse_select("name").unwrap(),
+            embeds: Vec::new(),
+        };
+        let mut params = vec![SqlParam::Int(7), SqlParam::Null];
+        let sql = select_returning(Some(&selection), &table, built.sql, &mut params).unwrap();
+        assert!(sql.ends_with("SELECT [__action], [name] FROM @lazypaw_out AS [__returned];"));
+        assert_eq!(params.len(), 2);
     }
 }
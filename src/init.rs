@@ -61,6 +61,8 @@ pub async fn run_init(args: InitArgs) -> Result<(), Box<dyn std::error::Error>>
         database: Some(database.clone()),
         listen_port: port,
         default_schema: "dbo".to_string(),
+        next_schema: None,
+        admin_role: None,
         jwt_secret: None,
         anon_role: None,
         pool_size: 2,
@@ -71,6 +73,10 @@ pub async fn run_init(args: InitArgs) -> Result<(), Box<dyn std::error::Error>>
         oidc_audience: None,
         role_claim: "role".to_string(),
         context_claims: Vec::new(),
+        context_headers: Vec::new(),
+        ip_allow: Vec::new(),
+        ip_deny: Vec::new(),
+        trusted_proxies: Vec::new(),
         role_map: std::collections::HashMap::new(),
         db_auth: crate::config::DbAuthMode::Password,
         sp_tenant_id: None,
@@ -81,9 +87,43 @@ pub async fn run_init(args: InitArgs) -> Result<(), Box<dyn std::error::Error>>
         log_level: "info".to_string(),
         log_format: "text".to_string(),
         log_slow_queries: None,
+        log_params: false,
+        error_detail: crate::config::ErrorDetail::Safe,
+        sql_tag: crate::config::SqlTag::Route,
+        on_connect_sql: None,
+        strict_filters: true,
+        case_insensitive_filters: false,
+        server_json: false,
+        language: None,
+        dateformat: None,
+        read_server: None,
+        consistency_wait_ms: 200,
+        dns_refresh_secs: 30,
+        max_rows: None,
+        rpc_timeout_secs: None,
+        rpc_max_rows: None,
+        bulk_insert_threshold: None,
+        auth_max_failures: None,
+        auth_failure_window_secs: 60,
+        auth_ban_secs: 300,
+        signed_url_secret: None,
+        signed_url_max_secs: 3600,
+        openapi_examples_role: None,
+        procedures: std::collections::HashMap::new(),
+        filter_operators: std::collections::HashMap::new(),
+        search_index: None,
+        redis_cache: None,
         otel_enabled: false,
         otel_endpoint: String::new(),
         otel_service_name: "lazypaw".to_string(),
+        tables: std::collections::HashMap::new(),
+        columns: std::collections::HashMap::new(),
+        http2: false,
+        header_read_timeout: None,
+        http2_keep_alive_interval: None,
+        keep_alive: true,
+        http2_max_streams: None,
+        tcp_nodelay: false,
     };
 
     let pool = Pool::new(config.clone());
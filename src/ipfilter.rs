@@ -0,0 +1,211 @@
+//! Client address allow/deny rules, checked before authentication.
+//!
+//! Rules are CIDR ranges (`10.0.0.0/8`, `2001:db8::/32`) or single
+//! addresses. Behind a load balancer the client is read from
+//! `X-Forwarded-For`, trusting only hops listed in `trusted_proxies`.
+
+use crate::config::AppConfig;
+use crate::error::Error;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// An address range, stored as the network address and prefix length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("Invalid address range: {}", s);
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+impl IpNet {
+    /// Whether `ip` is in the range. IPv4-mapped IPv6 addresses match
+    /// IPv4 ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                (u128::from(u32::from(net)), u128::from(u32::from(ip)), 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        let host_bits = bits - u32::from(self.prefix);
+        if host_bits >= 128 {
+            return true;
+        }
+        (net >> host_bits) == (ip >> host_bits)
+    }
+}
+
+/// Parsed `ip_allow`, `ip_deny` and `trusted_proxies`.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse a list of rules from config.
+pub fn parse_list(entries: &[String]) -> Result<Vec<IpNet>, String> {
+    entries.iter().map(|e| e.parse()).collect()
+}
+
+/// The client's address: the peer, or when the peer is a trusted proxy,
+/// the nearest untrusted hop in `X-Forwarded-For` (the leftmost one if
+/// every hop is trusted). Unparseable hops end the walk.
+pub fn client_ip(trusted_proxies: &[IpNet], peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or("").rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !is_trusted(ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// `X-Forwarded-For` as text, if present.
+pub fn forwarded_for(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+}
+
+/// The connection's peer address, inserted by the server per connection.
+pub fn peer_addr(request: &Request) -> Option<SocketAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0)
+}
+
+impl IpFilter {
+    /// Rules from config; `None` when there are none. An invalid entry is
+    /// an error so a typo can't silently open (or close) the API.
+    pub fn from_config(config: &AppConfig) -> Result<Option<Self>, String> {
+        let filter = IpFilter {
+            allow: parse_list(&config.ip_allow)?,
+            deny: parse_list(&config.ip_deny)?,
+            trusted_proxies: parse_list(&config.trusted_proxies)?,
+        };
+        if filter.allow.is_empty() && filter.deny.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(filter))
+    }
+
+    /// See [`client_ip`].
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        client_ip(&self.trusted_proxies, peer, forwarded_for)
+    }
+
+    /// Deny rules win; with allow rules, only listed addresses get through.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Error> {
+        let denied = self.deny.iter().any(|net| net.contains(ip))
+            || (!self.allow.is_empty() && !self.allow.iter().any(|net| net.contains(ip)));
+        if denied {
+            return Err(Error::Forbidden(format!(
+                "Requests from {} are not allowed",
+                ip
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Rejects requests from disallowed addresses with 403, ahead of auth and
+/// every handler.
+pub async fn middleware(
+    State(filter): State<Arc<IpFilter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(peer) = peer_addr(&request) else {
+        return Error::Internal("Client address unavailable".to_string()).into_response();
+    };
+    let client = filter.client_ip(peer.ip(), forwarded_for(&request));
+    if let Err(e) = filter.check(client) {
+        tracing::debug!("Rejected request from {} (peer {})", client, peer);
+        return e.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str], proxies: &[&str]) -> IpFilter {
+        let list = |l: &[&str]| l.iter().map(|s| s.parse().unwrap()).collect();
+        IpFilter {
+            allow: list(allow),
+            deny: list(deny),
+            trusted_proxies: list(proxies),
+        }
+    }
+
+    #[test]
+    fn test_ip_net_contains() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.200.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("192.0.2.1".parse().unwrap()));
+        let v6: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:1::5".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("office".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let f = filter(&["10.0.0.0/8"], &["10.9.0.0/16"], &["192.168.0.0/24"]);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(f.check(ip("10.1.2.3")).is_ok());
+        assert!(f.check(ip("10.9.2.3")).is_err());
+        assert!(f.check(ip("8.8.8.8")).is_err());
+
+        // Spoofed leftmost entry is ignored: the first untrusted hop wins
+        let xff = Some("10.1.1.1, 8.8.8.8, 192.168.0.7");
+        assert_eq!(f.client_ip(ip("192.168.0.5"), xff), ip("8.8.8.8"));
+        // Untrusted peers can't set their address
+        assert_eq!(f.client_ip(ip("8.8.4.4"), xff), ip("8.8.4.4"));
+    }
+}
@@ -5,22 +5,33 @@
 //! Handles SIGHUP for live schema reload.
 
 mod auth;
+mod auth_guard;
 mod codegen;
 mod config;
+mod docs;
+mod drift;
 mod error;
 mod filters;
+mod graph;
 mod handlers;
 mod init;
+mod ipfilter;
 mod openapi;
 mod pool;
 mod query;
 mod realtime;
 mod realtime_ws;
+mod redact;
+mod redis_sink;
 mod response;
 mod router;
 mod schema;
+mod search_sink;
 mod select;
+mod server;
+mod signed_url;
 mod types;
+mod validate;
 
 use clap::Parser;
 use config::{AppConfig, Args, SubCommand};
@@ -121,14 +132,47 @@ fn run_setup(roles: &str, service_account: &str) {
     println!("-- Done! Review and execute this script against your database.");
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // ── Runtimes ─────────────────────────────────────────────
+    let args = Args::parse();
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(n) = args.worker_threads.filter(|n| *n > 0) {
+        runtime.worker_threads(n);
+    }
+    if let Some(n) = args.max_blocking_threads.filter(|n| *n > 0) {
+        runtime.max_blocking_threads(n);
+    }
+    // Change fan-out gets its own threads so a burst can't starve requests
+    let realtime_runtime = match args.realtime_threads.filter(|n| *n > 0) {
+        Some(n) => Some(
+            tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(n)
+                .thread_name("lazypaw-realtime")
+                .enable_all()
+                .build()?,
+        ),
+        None => None,
+    };
+    let realtime_handle = realtime_runtime.as_ref().map(|rt| rt.handle().clone());
+    let result = runtime.build()?.block_on(run(args, realtime_handle));
+    if let Some(rt) = realtime_runtime {
+        rt.shutdown_background();
+    }
+    result
+}
+
+/// Everything after runtime setup. `realtime` runs the Change Tracking
+/// poller and its sinks when `--realtime-threads` is set; otherwise they
+/// share the main runtime.
+async fn run(
+    args: Args,
+    realtime: Option<tokio::runtime::Handle>,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Install rustls crypto provider
     let _ = rustls::crypto::ring::default_provider().install_default();
 
     // ── Config ───────────────────────────────────────────────
-    let args = Args::parse();
-
     // Handle init subcommand
     if let Some(SubCommand::Init {
         ref server,
@@ -184,9 +228,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let content = match lang.as_str() {
             "typescript" | "ts" => codegen::generate_typescript(&schema_cache, db_name),
             "python" | "py" => codegen::generate_python(&schema_cache, db_name),
+            "tests" | "http" => {
+                codegen::generate_http_tests(&schema_cache, db_name, &config.default_schema)
+            }
             other => {
                 eprintln!(
-                    "Unsupported language: {}. Use 'typescript' or 'python'.",
+                    "Unsupported language: {}. Use 'typescript', 'python' or 'tests'.",
                     other
                 );
                 std::process::exit(1);
@@ -197,8 +244,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    // Handle diff subcommand
+    if let Some(SubCommand::Diff { baseline, write }) = &args.subcmd.clone() {
+        let config = AppConfig::from_args(args);
+        let pool = Pool::new(config.clone());
+        let schema_cache = schema::load_schema(&pool).await?;
+        let live = drift::Snapshot::from_schema(&schema_cache, &config);
+        if *write {
+            let content = serde_json::to_string_pretty(&live)?;
+            std::fs::write(baseline, format!("{}\n", content))?;
+            println!(
+                "Wrote snapshot of {} tables → {}",
+                live.tables.len(),
+                baseline
+            );
+            return Ok(());
+        }
+        let snapshot: drift::Snapshot = match std::fs::read_to_string(baseline)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                eprintln!("Can't read baseline {}: {}", baseline, e);
+                std::process::exit(2);
+            }
+        };
+        let changes = drift::diff(&snapshot, &live);
+        if changes.is_empty() {
+            println!("No drift from {}", baseline);
+            return Ok(());
+        }
+        for change in &changes {
+            println!("{}", change);
+        }
+        eprintln!("{} changes from {}", changes.len(), baseline);
+        std::process::exit(1);
+    }
+
+    // Handle graph subcommand
+    if let Some(SubCommand::Graph { format, output }) = &args.subcmd.clone() {
+        let Some(graph_format) = graph::GraphFormat::parse(format) else {
+            eprintln!("Unsupported format: {}. Use 'mermaid' or 'dot'.", format);
+            std::process::exit(1);
+        };
+        let config = AppConfig::from_args(args);
+        let pool = Pool::new(config.clone());
+        let schema_cache = schema::load_schema(&pool).await?;
+        let content = graph::render(&schema_cache, &config, graph_format);
+        match output {
+            Some(path) => {
+                std::fs::write(path, &content)?;
+                println!("Generated {} ({} bytes) → {}", format, content.len(), path);
+            }
+            None => print!("{}", content),
+        }
+        return Ok(());
+    }
+
     // ── Tracing ──────────────────────────────────────────────
     let config = AppConfig::from_args(args);
+    error::set_error_detail(config.error_detail);
+    filters::register_operators(&config.filter_operators)?;
 
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
         EnvFilter::new(format!(
@@ -252,6 +359,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.database
     );
 
+    // Invalid address rules are fatal rather than silently ignored
+    let ip_filter = ipfilter::IpFilter::from_config(&config)?;
+    let auth_guard = auth_guard::AuthGuard::from_config(&config)?;
+
     // ── Connection pool ──────────────────────────────────────
     let pool = Pool::new(config.clone());
 
@@ -278,35 +389,98 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let schema = Arc::new(RwLock::new(schema_cache));
     tracing::info!("Schema loaded: {} tables/views ✓", table_count);
 
+    if let Some(language) = &config.language {
+        if auth::sql_language(language).is_none() {
+            tracing::warn!("Unknown language {:?}: SET LANGUAGE is skipped", language);
+        }
+    }
+    if let Some(format) = &config.dateformat {
+        if auth::sql_dateformat(format).is_none() {
+            tracing::warn!("Unknown dateformat {:?}: SET DATEFORMAT is skipped", format);
+        }
+    }
+    if config.dns_refresh_secs == 0 {
+        tracing::warn!(
+            "dns_refresh_secs is 0: a failover that moves the database's address goes unnoticed until connections fail"
+        );
+    }
+    if config.next_schema.is_some() && config.admin_role.is_none() {
+        tracing::warn!("next_schema is set but admin_role isn't: /admin/cutover is disabled");
+    }
+
     // ── Build app state & router ─────────────────────────────
-    let state = AppState {
-        pool: pool.clone(),
-        schema: schema.clone(),
-        config: config.clone(),
-    };
+    // Reads can go to a readable replica with the same credentials
+    let read_pool = config.read_server.as_ref().map(|server| {
+        tracing::info!("Routing reads to replica {}", server);
+        let mut read_config = config.clone();
+        read_config.server = server.clone();
+        Pool::new(read_config)
+    });
+
+    let profiles = Arc::new(std::sync::RwLock::new(
+        handlers::SchemaProfiles::from_config(&config),
+    ));
 
     // ── Realtime engine (optional) ───────────────────────────
-    let engine = if config.realtime {
+    // Also drives the search index and Redis sinks, with or without the WebSocket
+    let engine = if config.realtime || config.search_index.is_some() || config.redis_cache.is_some()
+    {
         tracing::info!("Realtime enabled — initializing Change Tracking poller...");
-        let engine = realtime::RealtimeEngine::new(pool.clone(), schema.clone(), config.clone());
+        let engine = realtime::RealtimeEngine::new(
+            pool.clone(),
+            schema.clone(),
+            config.clone(),
+            profiles.clone(),
+        );
         if let Err(e) = engine.init_version().await {
             tracing::warn!("Realtime CT version init failed (non-fatal): {}", e);
         }
+        let realtime = realtime.unwrap_or_else(tokio::runtime::Handle::current);
         let poll_engine = engine.clone();
         let poll_ms = config.realtime_poll_ms;
-        tokio::spawn(async move {
+        realtime.spawn(async move {
             poll_engine.poll_loop(poll_ms).await;
         });
         tracing::info!(
             "Realtime poller started (poll_ms={})",
             config.realtime_poll_ms
         );
+        if config.search_index.is_some() {
+            realtime.spawn(search_sink::run(
+                engine.clone(),
+                schema.clone(),
+                config.clone(),
+            ));
+        }
+        if config.redis_cache.is_some() {
+            realtime.spawn(redis_sink::run(
+                engine.clone(),
+                pool.clone(),
+                schema.clone(),
+                config.clone(),
+            ));
+        }
         Some(engine)
     } else {
         None
     };
 
-    let app = router::build_router(state, engine);
+    let state = AppState {
+        pool: pool.clone(),
+        read_pool,
+        schema: schema.clone(),
+        config: config.clone(),
+        profiles,
+        realtime: engine.clone(),
+        exports: Arc::new(response::ExportCache::default()),
+    };
+
+    let app = router::build_router(
+        state,
+        engine.filter(|_| config.realtime),
+        ip_filter,
+        auth_guard,
+    );
 
     // ── SIGHUP handler for schema reload ─────────────────────
     #[cfg(unix)]
@@ -343,7 +517,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.listen_port
     );
 
-    axum::serve(listener, app).await?;
+    server::serve(listener, app, &config).await?;
 
     Ok(())
 }
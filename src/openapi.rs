@@ -1,7 +1,7 @@
 //! OpenAPI 3.0 spec auto-generation from schema introspection.
 
 use crate::config::AppConfig;
-use crate::schema::{SchemaCache, TableInfo};
+use crate::schema::{Dml, EmbedJoinType, SchemaCache, TableInfo};
 use crate::types;
 use serde_json::{json, Map, Value};
 
@@ -12,14 +12,24 @@ pub fn generate_openapi(schema: &SchemaCache, config: &AppConfig) -> Value {
 
     let multi_schema = schema.has_multiple_schemas();
 
-    for ((schema_name, _table_name), table) in &schema.tables {
+    let examples = schema.examples.get();
+    for (key, table) in &schema.tables {
+        let schema_name = &key.0;
+        if !config.is_schema_exposed(schema_name) {
+            continue;
+        }
         let path = if !multi_schema || schema_name.eq_ignore_ascii_case(&config.default_schema) {
             format!("/{}", table.name)
         } else {
             format!("/{}/{}", schema_name, table.name)
         };
 
-        let (path_item, table_schema) = generate_table_paths(table, config);
+        let (path_item, mut table_schema) = generate_table_paths(table, config);
+        table_schema["x-relationships"] = relationships(schema, table, config);
+        // Sampled rows (`handlers::sample_examples`), when configured
+        if let Some(example) = examples.and_then(|e| e.get(key)) {
+            table_schema["example"] = example.clone();
+        }
         paths.insert(path.clone(), path_item);
         schemas.insert(table.name.clone(), table_schema);
     }
@@ -28,6 +38,26 @@ pub fn generate_openapi(schema: &SchemaCache, config: &AppConfig) -> Value {
     paths.insert(
         "/rpc/{procedure}".to_string(),
         json!({
+            "get": {
+                "summary": "Call scalar or table-valued function",
+                "description": "Query parameters named after the function's parameters are its arguments; the rest filter, order and page the rows of a table-valued function.",
+                "parameters": [{
+                    "name": "procedure",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Function result: rows, or the bare value of a scalar function",
+                        "content": {
+                            "application/json": {
+                                "schema": {}
+                            }
+                        }
+                    }
+                }
+            },
             "post": {
                 "summary": "Execute stored procedure",
                 "parameters": [{
@@ -48,10 +78,28 @@ pub fn generate_openapi(schema: &SchemaCache, config: &AppConfig) -> Value {
                 },
                 "responses": {
                     "200": {
-                        "description": "Procedure executed",
+                        "description": "Procedure executed: its rows, or rows, return_value and output for procedures with OUTPUT parameters",
+                        "headers": {
+                            "X-Return-Value": {
+                                "description": "The procedure's RETURN value",
+                                "schema": { "type": "integer" }
+                            }
+                        },
                         "content": {
                             "application/json": {
-                                "schema": { "type": "array", "items": { "type": "object" } }
+                                "schema": {
+                                    "oneOf": [
+                                        { "type": "array", "items": { "type": "object" } },
+                                        {
+                                            "type": "object",
+                                            "properties": {
+                                                "rows": { "type": "array", "items": { "type": "object" } },
+                                                "return_value": { "type": "integer" },
+                                                "output": { "type": "object" }
+                                            }
+                                        }
+                                    ]
+                                }
                             }
                         }
                     }
@@ -85,7 +133,7 @@ pub fn generate_openapi(schema: &SchemaCache, config: &AppConfig) -> Value {
 }
 
 /// Generate OpenAPI path item and schema for a table.
-fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value) {
+fn generate_table_paths(table: &TableInfo, config: &AppConfig) -> (Value, Value) {
     let schema_ref = format!("#/components/schemas/{}", table.name);
 
     // Build table schema
@@ -102,21 +150,27 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
         if col.is_nullable {
             prop.insert("nullable".to_string(), json!(true));
         }
-        if col.is_identity {
+        if col.read_only_reason().is_some() {
             prop.insert("readOnly".to_string(), json!(true));
         }
+        if let Some(desc) = &col.description {
+            prop.insert("description".to_string(), json!(desc));
+        }
         properties.insert(col.name.clone(), Value::Object(prop));
 
-        if !col.is_nullable && !col.is_identity && !col.has_default {
+        if !col.is_nullable && col.read_only_reason().is_none() && !col.has_default {
             required.push(json!(col.name));
         }
     }
 
-    let table_schema = json!({
+    let mut table_schema = json!({
         "type": "object",
         "properties": properties,
         "required": required
     });
+    if let Some(desc) = &table.description {
+        table_schema["description"] = json!(desc);
+    }
 
     // Build filter parameters
     let mut filter_params: Vec<Value> = Vec::new();
@@ -152,7 +206,13 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
         filter_params.push(json!({
             "name": col.name,
             "in": "query",
-            "description": format!("Filter on {} (e.g., eq.value, gt.5, in.(a,b))", col.name),
+            "description": format!(
+                "Filter on {} (e.g., eq.value, gt.5, in.(a,b)). match./imatch. take \
+                 T-SQL PATINDEX patterns, not POSIX regex: wildcards are %, _, [0-9] \
+                 and [^a-z]; ^ and $ anchor only at the ends; no alternation, \
+                 quantifiers or escapes",
+                col.name
+            ),
             "schema": { "type": "string" }
         }));
     }
@@ -193,8 +253,9 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
         }),
     );
 
-    // POST (not for views)
-    if !table.is_view {
+    // POST/PATCH/DELETE (not for read-only views, nor writes a view's
+    // INSTEAD OF triggers don't cover)
+    if table.accepts(Dml::Insert) {
         path_item.insert(
             "post".to_string(),
             json!({
@@ -226,8 +287,9 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
                 }
             }),
         );
+    }
 
-        // PATCH
+    if table.accepts(Dml::Update) {
         path_item.insert(
             "patch".to_string(),
             json!({
@@ -255,8 +317,9 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
                 }
             }),
         );
+    }
 
-        // DELETE
+    if table.accepts(Dml::Delete) {
         path_item.insert(
             "delete".to_string(),
             json!({
@@ -279,9 +342,46 @@ fn generate_table_paths(table: &TableInfo, _config: &AppConfig) -> (Value, Value
         );
     }
 
+    // Every operation of a deprecated table
+    if let Some(table_cfg) = config
+        .table_config(&table.schema, &table.name)
+        .filter(|t| t.is_deprecated())
+    {
+        for operation in path_item.values_mut() {
+            operation["deprecated"] = json!(true);
+            if let Some(sunset) = &table_cfg.sunset_http_date() {
+                operation["description"] = json!(format!("Removed after {}", sunset));
+            }
+        }
+    }
+
     (Value::Object(path_item), table_schema)
 }
 
+/// Embeddable relationships keyed by their default embed name.
+fn relationships(schema: &SchemaCache, table: &TableInfo, config: &AppConfig) -> Value {
+    let mut rels = Map::new();
+    for (name, info) in schema.embed_relationships(&table.schema, &table.name) {
+        if !config.is_schema_exposed(&info.target_schema) {
+            continue;
+        }
+        let cardinality = match info.join_type {
+            EmbedJoinType::ManyToOne => "many-to-one",
+            EmbedJoinType::OneToMany => "one-to-many",
+        };
+        rels.insert(
+            name,
+            json!({
+                "table": format!("{}.{}", info.target_schema, info.target_table),
+                "cardinality": cardinality,
+                "columns": info.source_columns,
+                "references": info.target_columns
+            }),
+        );
+    }
+    Value::Object(rels)
+}
+
 /// Generate a simple Swagger UI HTML page.
 pub fn swagger_ui_html(listen_port: u16) -> String {
     format!(
@@ -1,12 +1,21 @@
 //! Connection pool for claw TDS clients.
 //!
 //! Supports password auth, Azure managed identity, and service principal.
+//! `--server` may list several hosts; connects rotate between them and the
+//! active one is re-resolved so failover-group listener moves are picked up.
 
 use crate::config::{AppConfig, DbAuthMode};
 use crate::error::Error;
 use claw::{AuthMethod, Config, TcpClient};
-use serde::Deserialize;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveError;
+use hickory_resolver::TokioAsyncResolver;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::{Mutex, RwLock, Semaphore};
 
 // ─── Token Cache ────────────────────────────────────────────
@@ -131,11 +140,158 @@ impl AadTokenProvider {
     }
 }
 
+// ─── Endpoints ──────────────────────────────────────────────
+
+/// Longest an endpoint sits out after repeated connect failures.
+const MAX_ENDPOINT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A host candidate from `--server` and its connect health.
+#[derive(Debug)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+/// Host candidates, the one currently connected to, and the addresses last
+/// seen behind it.
+#[derive(Debug)]
+struct Endpoints {
+    list: Vec<Endpoint>,
+    active: usize,
+    addrs: Vec<SocketAddr>,
+    /// When the active host is due to be resolved again; `None` until it
+    /// has been resolved
+    next_check: Option<Instant>,
+}
+
+impl Endpoints {
+    /// Parse `host[:port]` candidates separated by commas.
+    fn parse(server: &str, default_port: u16) -> Self {
+        let list = server
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (host, port) = match s.rsplit_once(':') {
+                    Some((host, port)) if !host.contains(':') => match port.parse() {
+                        Ok(port) => (host, port),
+                        Err(_) => (s, default_port),
+                    },
+                    _ => (s, default_port),
+                };
+                Endpoint {
+                    host: host.to_string(),
+                    port,
+                    failures: 0,
+                    down_until: None,
+                }
+            })
+            .collect();
+        Self {
+            list,
+            active: 0,
+            addrs: Vec::new(),
+            next_check: None,
+        }
+    }
+
+    /// Indexes to try, starting at the active endpoint. Endpoints backing
+    /// off after failures go last rather than being skipped, so a single
+    /// host is always retried.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let n = self.list.len();
+        let (mut up, down): (Vec<usize>, Vec<usize>) = (0..n)
+            .map(|i| (self.active + i) % n)
+            .partition(|&i| self.list[i].down_until.is_none_or(|t| t <= now));
+        up.extend(down);
+        up
+    }
+}
+
+/// Never re-resolve more often than this, however short the TTL.
+const MIN_DNS_REFRESH: Duration = Duration::from_secs(1);
+
+/// Resolve a host, with how long the answer stays valid (the lowest TTL of
+/// its records).
+async fn resolve(
+    resolver: &TokioAsyncResolver,
+    host: &str,
+    port: u16,
+) -> Result<(Vec<SocketAddr>, Duration), ResolveError> {
+    let lookup = resolver.lookup_ip(host).await?;
+    let ttl = lookup
+        .valid_until()
+        .saturating_duration_since(Instant::now());
+    let mut addrs: Vec<SocketAddr> = lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+    addrs.sort();
+    addrs.dedup();
+    Ok((addrs, ttl))
+}
+
+// ─── Connection Audit ───────────────────────────────────────
+
+/// Bookkeeping for one open connection, idle or checked out.
+#[derive(Debug)]
+struct ConnectionStats {
+    spid: Option<i16>,
+    server: String,
+    opened: Instant,
+    last_used: SystemTime,
+    in_use: bool,
+    role: Option<String>,
+    /// Close on return instead of going back to the idle list
+    recycle: bool,
+}
+
+/// One connection as reported by `GET /admin/pool`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    /// Server session ID (`@@SPID`), as in `sys.dm_exec_sessions`
+    pub spid: Option<i16>,
+    pub server: String,
+    /// `idle` or `in_use`
+    pub state: &'static str,
+    pub age_secs: u64,
+    /// When it was last checked out or returned (RFC 3339)
+    pub last_used: String,
+    /// Role of the request holding it, or of the last one that did
+    pub role: Option<String>,
+    /// Marked to be closed as soon as it's returned
+    pub recycling: bool,
+}
+
+/// `@@SPID` of a fresh connection; `None` if the lookup fails.
+async fn session_id(client: &mut TcpClient) -> Option<i16> {
+    let rows = client
+        .execute("SELECT @@SPID AS [spid]", &[])
+        .await
+        .ok()?
+        .into_first_result()
+        .await
+        .ok()?;
+    rows.first()?.try_get::<i16, _>("spid").ok().flatten()
+}
+
 // ─── Pooled Connection ──────────────────────────────────────
 
+/// An idle connection waiting in the pool.
+struct IdleConnection {
+    client: TcpClient,
+    /// Pool generation the connection was opened in
+    generation: u64,
+    id: u64,
+}
+
 /// A pooled connection wrapper.
 pub struct PooledConnection {
     client: Option<TcpClient>,
+    /// Pool generation the connection was opened in
+    generation: u64,
+    id: u64,
     pool: Arc<Pool>,
 }
 
@@ -143,14 +299,34 @@ impl PooledConnection {
     pub fn client(&mut self) -> &mut TcpClient {
         self.client.as_mut().expect("connection taken")
     }
+
+    /// Record the role the connection is being used as, for `/admin/pool`.
+    pub fn set_role(&self, role: Option<&str>) {
+        if let Some(stats) = self.pool.stats.lock().unwrap().get_mut(&self.id) {
+            stats.role = role.map(str::to_string);
+        }
+    }
+
+    /// Close the connection when it's dropped instead of returning it to
+    /// the pool, e.g. after abandoning a query mid-stream.
+    pub fn discard(&self) {
+        if let Some(stats) = self.pool.stats.lock().unwrap().get_mut(&self.id) {
+            stats.recycle = true;
+        }
+    }
 }
 
 impl Drop for PooledConnection {
     fn drop(&mut self) {
         if let Some(client) = self.client.take() {
             let pool = self.pool.clone();
+            let idle = IdleConnection {
+                client,
+                generation: self.generation,
+                id: self.id,
+            };
             tokio::spawn(async move {
-                pool.return_connection(client).await;
+                pool.return_connection(idle).await;
             });
         }
     }
@@ -161,11 +337,22 @@ impl Drop for PooledConnection {
 /// Simple async connection pool for TDS connections.
 pub struct Pool {
     config: AppConfig,
-    connections: Mutex<Vec<TcpClient>>,
+    connections: Mutex<Vec<IdleConnection>>,
     semaphore: Semaphore,
     token_provider: Option<AadTokenProvider>,
+    endpoints: Mutex<Endpoints>,
+    resolver: TokioAsyncResolver,
+    /// Bumped when the active endpoint or its addresses change; idle
+    /// connections from older generations are closed instead of reused
+    generation: AtomicU64,
+    /// Every open connection, idle or in use, by ID
+    stats: std::sync::Mutex<HashMap<u64, ConnectionStats>>,
 }
 
+/// Connection IDs, shared by all pools so the primary's and the replica's
+/// don't collide.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
 impl Pool {
     /// Create a new pool with the given configuration.
     pub fn new(config: AppConfig) -> Arc<Self> {
@@ -176,11 +363,23 @@ impl Pool {
             }
             DbAuthMode::Password => None,
         };
+        let endpoints = Endpoints::parse(&config.server, config.port);
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            tracing::warn!(
+                "Could not read the system DNS config, using defaults: {}",
+                e
+            );
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
         Arc::new(Self {
             config,
             connections: Mutex::new(Vec::with_capacity(size)),
             semaphore: Semaphore::new(size),
             token_provider,
+            endpoints: Mutex::new(endpoints),
+            resolver,
+            generation: AtomicU64::new(0),
+            stats: std::sync::Mutex::new(HashMap::new()),
         })
     }
 
@@ -192,40 +391,241 @@ impl Pool {
             .await
             .map_err(|e| Error::Pool(e.to_string()))?;
 
+        self.refresh_dns().await;
+
         let existing = {
             let mut conns = self.connections.lock().await;
+            let current = self.generation.load(Ordering::Acquire);
+            let (keep, stale): (Vec<_>, Vec<_>) =
+                conns.drain(..).partition(|c| c.generation == current);
+            *conns = keep;
+            let mut stats = self.stats.lock().unwrap();
+            for conn in &stale {
+                stats.remove(&conn.id);
+            }
             conns.pop()
         };
 
-        let client = match existing {
+        let conn = match existing {
             Some(c) => c,
             None => self.create_connection().await?,
         };
+        if let Some(stats) = self.stats.lock().unwrap().get_mut(&conn.id) {
+            stats.in_use = true;
+            stats.last_used = SystemTime::now();
+            stats.role = None;
+        }
 
         std::mem::forget(_permit);
 
         Ok(PooledConnection {
-            client: Some(client),
+            client: Some(conn.client),
+            generation: conn.generation,
+            id: conn.id,
             pool: Arc::clone(self),
         })
     }
 
     /// Return a connection to the pool.
-    async fn return_connection(&self, client: TcpClient) {
+    async fn return_connection(&self, conn: IdleConnection) {
         {
             let mut conns = self.connections.lock().await;
-            if conns.len() < self.config.pool_size {
-                conns.push(client);
+            let current = self.generation.load(Ordering::Acquire);
+            let mut stats = self.stats.lock().unwrap();
+            let recycle = stats.get(&conn.id).is_none_or(|s| s.recycle);
+            if conn.generation == current && conns.len() < self.config.pool_size && !recycle {
+                if let Some(stats) = stats.get_mut(&conn.id) {
+                    stats.in_use = false;
+                    stats.last_used = SystemTime::now();
+                }
+                conns.push(conn);
+            } else {
+                stats.remove(&conn.id);
             }
         }
         self.semaphore.add_permits(1);
     }
 
-    /// Create a new TDS connection.
-    async fn create_connection(&self) -> Result<TcpClient, Error> {
+    /// Every open connection, oldest first.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let stats = self.stats.lock().unwrap();
+        let mut list: Vec<ConnectionInfo> = stats
+            .iter()
+            .map(|(id, s)| ConnectionInfo {
+                id: *id,
+                spid: s.spid,
+                server: s.server.clone(),
+                state: if s.in_use { "in_use" } else { "idle" },
+                age_secs: s.opened.elapsed().as_secs(),
+                last_used: chrono::DateTime::<chrono::Utc>::from(s.last_used).to_rfc3339(),
+                role: s.role.clone(),
+                recycling: s.recycle,
+            })
+            .collect();
+        list.sort_by_key(|c| c.id);
+        list
+    }
+
+    /// Close a connection: right away when idle, otherwise as soon as the
+    /// request holding it returns it. `false` if there's no such connection.
+    pub async fn recycle(&self, id: u64) -> bool {
+        let closed = {
+            let mut conns = self.connections.lock().await;
+            let mut stats = self.stats.lock().unwrap();
+            let Some(entry) = stats.get_mut(&id) else {
+                return false;
+            };
+            if entry.in_use {
+                entry.recycle = true;
+                None
+            } else {
+                stats.remove(&id);
+                conns
+                    .iter()
+                    .position(|c| c.id == id)
+                    .map(|i| conns.swap_remove(i))
+            }
+        };
+        // Dropping the client closes the session
+        drop(closed);
+        true
+    }
+
+    /// Re-resolve the active host when its record's TTL runs out, or after
+    /// `--dns-refresh-secs` if that's sooner. When the addresses behind it
+    /// change (a failover group moving its listener), idle connections to
+    /// the old address are retired.
+    async fn refresh_dns(&self) {
+        if self.config.dns_refresh_secs == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.dns_refresh_secs);
+        let (host, port) = {
+            let mut endpoints = self.endpoints.lock().await;
+            let now = Instant::now();
+            if endpoints.next_check.is_some_and(|t| now < t) {
+                return;
+            }
+            // Other requests don't look up again while this one does
+            endpoints.next_check = Some(now + interval);
+            let Some(active) = endpoints.list.get(endpoints.active) else {
+                return;
+            };
+            (active.host.clone(), active.port)
+        };
+
+        let (addrs, ttl) = match resolve(&self.resolver, &host, port).await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                tracing::warn!("DNS lookup for {} failed: {}", host, e);
+                return;
+            }
+        };
+
+        let mut endpoints = self.endpoints.lock().await;
+        if endpoints.list[endpoints.active].host != host {
+            // Rotated while resolving; the new endpoint starts its own baseline
+            return;
+        }
+        endpoints.next_check = Some(Instant::now() + ttl.clamp(MIN_DNS_REFRESH, interval));
+        if !endpoints.addrs.is_empty() && endpoints.addrs != addrs {
+            tracing::info!(
+                "{} now resolves to {:?}; retiring idle connections",
+                host,
+                addrs
+            );
+            self.generation.fetch_add(1, Ordering::AcqRel);
+        }
+        endpoints.addrs = addrs;
+    }
+
+    /// Open a connection to the first endpoint that accepts one, trying the
+    /// active endpoint first. A failing endpoint backs off exponentially (up
+    /// to 30s); when another one succeeds it becomes active and idle
+    /// connections to the old one are retired.
+    async fn create_connection(&self) -> Result<IdleConnection, Error> {
+        let generation = self.generation.load(Ordering::Acquire);
+        let candidates = self.endpoints.lock().await.candidates();
+        let mut last_error = None;
+
+        for index in candidates {
+            let (host, port) = {
+                let endpoints = self.endpoints.lock().await;
+                let endpoint = &endpoints.list[index];
+                (endpoint.host.clone(), endpoint.port)
+            };
+            // A failing on_connect_sql counts against the endpoint like a
+            // failed connect
+            let client = match self.connect(&host, port).await {
+                Ok(client) => self.init_connection(client).await,
+                Err(e) => Err(e),
+            };
+            match client {
+                Ok(mut client) => {
+                    let generation = self.mark_up(index).await.unwrap_or(generation);
+                    let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+                    let stats = ConnectionStats {
+                        spid: session_id(&mut client).await,
+                        server: format!("{}:{}", host, port),
+                        opened: Instant::now(),
+                        last_used: SystemTime::now(),
+                        in_use: false,
+                        role: None,
+                        recycle: false,
+                    };
+                    self.stats.lock().unwrap().insert(id, stats);
+                    return Ok(IdleConnection {
+                        client,
+                        generation,
+                        id,
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!("Connecting to {}:{} failed: {}", host, port, e);
+                    self.mark_down(index).await;
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| Error::Pool("No database server configured".to_string())))
+    }
+
+    /// Record a successful connect. Returns the new generation when the
+    /// active endpoint changed.
+    async fn mark_up(&self, index: usize) -> Option<u64> {
+        let mut endpoints = self.endpoints.lock().await;
+        let endpoint = &mut endpoints.list[index];
+        endpoint.failures = 0;
+        endpoint.down_until = None;
+        if endpoints.active == index {
+            return None;
+        }
+        tracing::info!(
+            "Switched database endpoint to {}:{}",
+            endpoints.list[index].host,
+            endpoints.list[index].port
+        );
+        endpoints.active = index;
+        endpoints.addrs.clear();
+        endpoints.next_check = None;
+        Some(self.generation.fetch_add(1, Ordering::AcqRel) + 1)
+    }
+
+    /// Record a failed connect and back the endpoint off: 1s, 2s, 4s, ...
+    async fn mark_down(&self, index: usize) {
+        let mut endpoints = self.endpoints.lock().await;
+        let endpoint = &mut endpoints.list[index];
+        endpoint.failures = endpoint.failures.saturating_add(1);
+        let backoff = Duration::from_secs(1 << endpoint.failures.min(6).saturating_sub(1));
+        endpoint.down_until = Some(Instant::now() + backoff.min(MAX_ENDPOINT_BACKOFF));
+    }
+
+    /// Create a new TDS connection to one endpoint.
+    async fn connect(&self, host: &str, port: u16) -> Result<TcpClient, Error> {
         let mut config = Config::new();
-        config.host(&self.config.server);
-        config.port(self.config.port);
+        config.host(host);
+        config.port(port);
 
         match self.config.db_auth {
             DbAuthMode::Password => {
@@ -252,9 +652,22 @@ impl Pool {
             config.database(db);
         }
 
-        let client = claw::connect(config)
+        claw::connect(config)
             .await
-            .map_err(|e| Error::Pool(format!("Connection failed: {}", e)))?;
+            .map_err(|e| Error::Pool(format!("Connection failed: {}", e)))
+    }
+
+    /// Prepare a fresh connection for the pool.
+    async fn init_connection(&self, mut client: TcpClient) -> Result<TcpClient, Error> {
+        // Session settings (ARITHABORT, DATEFORMAT, ...) must match across
+        // connections for cached plans to be reused
+        if let Some(ref sql) = self.config.on_connect_sql {
+            let mut sink = claw::ArrowRowWriter::new();
+            client
+                .batch_into(sql, &mut sink)
+                .await
+                .map_err(|e| Error::Pool(format!("on_connect_sql failed: {}", e)))?;
+        }
 
         Ok(client)
     }
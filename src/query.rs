@@ -4,25 +4,133 @@
 //! operations based on parsed filters, select, ordering, and pagination.
 
 use crate::error::Error;
-use crate::filters::{Filter, FilterNode, FilterOp, FilterValue};
-use crate::schema::TableInfo;
+use crate::filters::{DistinctFilter, Filter, FilterNode, FilterOp, FilterValue, Quantifier};
+use crate::schema::{ColumnInfo, TableInfo};
 use crate::select::{self, SelectNode};
 
 /// A built SQL query with parameterized values.
 #[derive(Debug)]
 pub struct BuiltQuery {
     pub sql: String,
-    pub params: Vec<String>,
+    pub params: Vec<SqlParam>,
+}
+
+/// A bind value, typed from the column it is compared with or written to.
+/// Binding everything as nvarchar makes SQL Server convert varchar, int or
+/// date columns to match, which turns index seeks into scans.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParam {
+    Null,
+    /// NULL for a `binary`/`varbinary`/`image` column: an nvarchar NULL
+    /// has no implicit conversion to binary (error 257)
+    NullBinary,
+    Bit(bool),
+    Int(i32),
+    BigInt(i64),
+    Float(f64),
+    /// `uniqueidentifier`, as the UUID's 128-bit value
+    Guid(u128),
+    Binary(Vec<u8>),
+    /// `money` / `smallmoney`, exact and independent of the server's locale
+    Decimal(rust_decimal::Decimal),
+    /// Strings, plus other decimals, dates and times, which have no lossless
+    /// native bind and are converted by SQL Server (the parameter side is
+    /// converted, so seeks survive)
+    Text(String),
+}
+
+impl SqlParam {
+    /// Type a text value for a column of `data_type`. Values that don't
+    /// parse stay text and SQL Server reports the conversion error.
+    pub fn typed(data_type: Option<&str>, value: &str) -> SqlParam {
+        let v = value.trim();
+        let typed = match data_type.map(str::to_lowercase).as_deref() {
+            Some("bit") => match v {
+                "1" => Some(SqlParam::Bit(true)),
+                "0" => Some(SqlParam::Bit(false)),
+                _ => None,
+            },
+            Some("tinyint" | "smallint" | "int") => v.parse().ok().map(SqlParam::Int),
+            Some("bigint") => v.parse().ok().map(SqlParam::BigInt),
+            Some("money" | "smallmoney") => rust_decimal::Decimal::from_str_exact(v)
+                .ok()
+                .map(SqlParam::Decimal),
+            Some("float" | "real") => v
+                .parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite())
+                .map(SqlParam::Float),
+            Some("uniqueidentifier") => {
+                let hex: String = v.chars().filter(|c| *c != '-').collect();
+                u128::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 32)
+                    .map(SqlParam::Guid)
+            }
+            // Row versions compare as their 8 bytes, written in hex
+            Some("timestamp" | "rowversion") => {
+                let hex = v.trim_start_matches("0x");
+                u64::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 16)
+                    .map(|n| SqlParam::Binary(n.to_be_bytes().to_vec()))
+            }
+            _ => None,
+        };
+        typed.unwrap_or_else(|| SqlParam::Text(value.to_string()))
+    }
+
+    /// NULL typed for a column of `data_type`.
+    pub fn null_for(data_type: &str) -> SqlParam {
+        match data_type.to_lowercase().as_str() {
+            "binary" | "varbinary" | "image" => SqlParam::NullBinary,
+            _ => SqlParam::Null,
+        }
+    }
+
+    /// The value as text for logs; `None` is NULL.
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            SqlParam::Null | SqlParam::NullBinary => None,
+            SqlParam::Bit(b) => Some(if *b { "1" } else { "0" }.to_string()),
+            SqlParam::Int(v) => Some(v.to_string()),
+            SqlParam::BigInt(v) => Some(v.to_string()),
+            SqlParam::Float(v) => Some(v.to_string()),
+            SqlParam::Guid(v) => Some(format!("{:032x}", v)),
+            SqlParam::Binary(v) => Some(format!("<{} bytes>", v.len())),
+            SqlParam::Decimal(v) => Some(v.to_string()),
+            SqlParam::Text(s) => Some(s.clone()),
+        }
+    }
 }
 
 /// Ordering specification.
 #[derive(Debug, Clone)]
 pub struct OrderSpec {
+    /// The physical column (the base of a JSON path)
     pub column: String,
+    /// Order by a scalar inside the column (`data->>score`)
+    pub json_path: Option<select::JsonPath>,
+    /// Order by the value cast to this type (`code::int`)
+    pub cast: Option<String>,
     pub direction: OrderDir,
     pub nulls: Option<NullsOrder>,
 }
 
+impl OrderSpec {
+    /// SQL for the sort key: the column, a JSON path, and/or a cast.
+    pub fn expr(&self) -> String {
+        let expr = match &self.json_path {
+            Some(path) => json_path_expr(path),
+            None => format!("[{}]", escape_ident(&self.column)),
+        };
+        match &self.cast {
+            Some(ty) => format!("CAST({} AS {})", expr, ty),
+            None => expr,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum OrderDir {
     Asc,
@@ -35,20 +143,37 @@ pub enum NullsOrder {
     Last,
 }
 
-/// Parse order query param: "name.asc,age.desc.nullsfirst"
+/// Parse order query param: "name.asc,age.desc.nullsfirst".
+///
+/// The sort key may be a JSON path or carry a cast, as in `select`:
+/// `data->>score.desc`, `code::int.asc.nullslast`. JSON paths always sort
+/// by scalar text.
 pub fn parse_order(order_str: &str) -> Result<Vec<OrderSpec>, Error> {
     let mut specs = Vec::new();
-    for part in order_str.split(',') {
+    // Commas inside a cast type (`decimal(10,2)`) don't separate keys
+    for part in select::split_top_level(order_str) {
         let part = part.trim();
         if part.is_empty() {
             continue;
         }
-        let segments: Vec<&str> = part.split('.').collect();
-        if segments.is_empty() {
-            continue;
-        }
+        // Neither JSON path keys nor cast types contain dots; quoted
+        // column names may
+        let segments = select::split_unquoted(part, '.');
 
-        let column = segments[0].to_string();
+        let (key, cast) = match select::rfind_unquoted(segments[0], "::") {
+            Some(pos) => (
+                &segments[0][..pos],
+                Some(select::parse_cast_type(&segments[0][pos + 2..])?),
+            ),
+            None => (segments[0], None),
+        };
+        let json_path = select::parse_json_path(key)?.map(|mut path| {
+            path.as_text = true;
+            path
+        });
+        let column = json_path
+            .as_ref()
+            .map_or_else(|| select::unquote_ident(key), |path| path.column.clone());
         let direction = if segments.len() > 1 {
             match segments[1].to_lowercase().as_str() {
                 "desc" => OrderDir::Desc,
@@ -70,6 +195,8 @@ pub fn parse_order(order_str: &str) -> Result<Vec<OrderSpec>, Error> {
 
         specs.push(OrderSpec {
             column,
+            json_path,
+            cast,
             direction,
             nulls,
         });
@@ -77,30 +204,77 @@ pub fn parse_order(order_str: &str) -> Result<Vec<OrderSpec>, Error> {
     Ok(specs)
 }
 
+/// An embedded resource, rendered as a correlated `FOR JSON` subquery in the
+/// parent's column list so the whole tree is fetched in one statement.
+#[derive(Debug, Clone)]
+pub struct EmbedQuery<'a> {
+    /// Output key in the parent row
+    pub name: String,
+    /// The embedded table
+    pub table: &'a TableInfo,
+    /// Alias of the embedded table inside its subquery, e.g. `e1`
+    pub alias: String,
+    /// Join pairs: column on the embedded table and the qualified parent
+    /// column it matches, e.g. `("user_id", "[dbo].[users].[id]")`
+    pub join: Vec<(String, String)>,
+    /// One-to-many (JSON array) rather than many-to-one (object or null)
+    pub many: bool,
+    /// Spread (many-to-one only): joined with `OUTER APPLY` and its columns
+    /// selected directly into the parent row
+    pub spread: bool,
+    /// Sub-select within the embedded table
+    pub columns: Vec<SelectNode>,
+    /// Filters applied to the embedded rows
+    pub filters: Vec<FilterNode>,
+    /// Per-parent ordering and paging (one-to-many only)
+    pub order: Vec<OrderSpec>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Embeds nested inside this one
+    pub embeds: Vec<EmbedQuery<'a>>,
+}
+
 /// Build a SELECT query from filters, select, ordering, and pagination.
+/// Embeds become `JSON_QUERY((SELECT ... FOR JSON PATH))` columns.
+///
+/// `max_rows` caps the page whether or not a limit was asked for. A capped
+/// read fetches one row past the cap so the caller can tell a truncated
+/// result from one that fit (see [`capped_rows`]).
+#[allow(clippy::too_many_arguments)]
 pub fn build_select(
     table: &TableInfo,
     select_nodes: &[SelectNode],
+    embeds: &[EmbedQuery],
     filters: &[FilterNode],
     order: &[OrderSpec],
     limit: Option<i64>,
     offset: Option<i64>,
+    max_rows: Option<i64>,
     count_only: bool,
 ) -> Result<BuiltQuery, Error> {
-    let mut params: Vec<String> = Vec::new();
+    let mut params: Vec<SqlParam> = Vec::new();
 
-    // Build column list
+    // Build column list (embed subqueries come first in the SQL text, so
+    // their parameters are numbered before the WHERE clause's)
+    let mut applies = String::new();
     let columns = if count_only {
         "COUNT(*) AS [count]".to_string()
     } else {
-        build_column_list(table, select_nodes)
+        let columns = build_column_list(table, select_nodes);
+        add_embed_columns(
+            &table.full_name(),
+            columns,
+            embeds,
+            &mut applies,
+            &mut params,
+        )?
     };
 
-    let mut sql = format!("SELECT {} FROM {}", columns, table.full_name());
+    let mut sql = format!("SELECT {} FROM {}{}", columns, table.full_name(), applies);
 
     // WHERE clause
     if !filters.is_empty() {
-        let where_clause = build_where_clause(filters, &mut params)?;
+        let where_clause = build_where_clause(Some(table), filters, &mut params)?;
         if !where_clause.is_empty() {
             sql.push_str(" WHERE ");
             sql.push_str(&where_clause);
@@ -111,35 +285,494 @@ pub fn build_select(
         return Ok(BuiltQuery { sql, params });
     }
 
+    let limit = match max_rows {
+        Some(max) if limit.is_none_or(|l| l > max) => Some(max + 1),
+        _ => limit,
+    };
+    sql.push_str(&build_order_and_page(table, order, limit, offset));
+
+    Ok(BuiltQuery { sql, params })
+}
+
+/// An aggregate a configured response header reports, e.g.
+/// `X-Total-Amount = "sum(amount)"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderAggregate {
+    pub header: String,
+    /// `count`, `sum`, `avg`, `min` or `max`
+    pub function: String,
+    /// `None` for `count(*)`
+    pub column: Option<String>,
+}
+
+/// Parse a `[tables.*.headers]` value as an aggregate; `None` means the
+/// value is a literal header value.
+pub fn parse_header_aggregate(header: &str, value: &str) -> Option<HeaderAggregate> {
+    let (function, rest) = value.trim().split_once('(')?;
+    let arg = rest.strip_suffix(')')?.trim();
+    let function = function.trim().to_lowercase();
+    if !matches!(function.as_str(), "count" | "sum" | "avg" | "min" | "max") {
+        return None;
+    }
+    let column = match arg {
+        "*" if function == "count" => None,
+        "" | "*" => return None,
+        col => Some(col.to_string()),
+    };
+    Some(HeaderAggregate {
+        header: header.to_string(),
+        function,
+        column,
+    })
+}
+
+/// One-row query computing header aggregates over every row the filters
+/// match (not just the returned page), as `[h0]`, `[h1]`, ….
+pub fn build_header_aggregates(
+    table: &TableInfo,
+    aggregates: &[HeaderAggregate],
+    filters: &[FilterNode],
+) -> Result<BuiltQuery, Error> {
+    let columns = aggregates
+        .iter()
+        .enumerate()
+        .map(|(i, agg)| {
+            let arg = match &agg.column {
+                None => "*".to_string(),
+                Some(col) => {
+                    let col = table.column(col).ok_or_else(|| {
+                        Error::Internal(format!(
+                            "Header {}: {} has no column {}",
+                            agg.header, table.name, col
+                        ))
+                    })?;
+                    format!("[{}]", escape_ident(&col.name))
+                }
+            };
+            Ok(format!(
+                "{}({}) AS [h{}]",
+                agg.function.to_uppercase(),
+                arg,
+                i
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut params = Vec::new();
+    let mut sql = format!("SELECT {} FROM {}", columns.join(", "), table.full_name());
+    let where_clause = build_where_clause(Some(table), filters, &mut params)?;
+    if !where_clause.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause);
+    }
+    Ok(BuiltQuery { sql, params })
+}
+
+/// How many of `fetched` rows to return under a `max_rows` cap, and
+/// whether the result was truncated (the probe row past the cap came back).
+pub fn capped_rows(fetched: usize, max_rows: Option<i64>) -> (usize, bool) {
+    match max_rows {
+        Some(max) if fetched as i64 > max => (max.max(0) as usize, true),
+        _ => (fetched, false),
+    }
+}
+
+/// Filter, order and page the rows an RPC returns. `source` is what they
+/// are selected from: a table-valued function call, or the temp table a
+/// procedure's output was captured in. `table` describes the result
+/// columns, and filter parameters are numbered after the routine's own
+/// `param_offset` arguments.
+pub fn build_rpc_select(
+    source: &str,
+    table: &TableInfo,
+    filters: &[FilterNode],
+    order: &[OrderSpec],
+    limit: Option<i64>,
+    offset: Option<i64>,
+    param_offset: usize,
+) -> Result<BuiltQuery, Error> {
+    let mut params: Vec<SqlParam> = Vec::new();
+    let mut sql = format!("SELECT * FROM {} AS [rpc]", source);
+    let where_clause =
+        build_where_clause_with_offset(Some(table), filters, &mut params, param_offset)?;
+    if !where_clause.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause);
+    }
+    sql.push_str(&build_order_and_page(table, order, limit, offset));
+    Ok(BuiltQuery { sql, params })
+}
+
+/// Filter keeping the first row of each `columns` group (a comma list) in
+/// `order`, or primary key order when none was given. Rows are matched
+/// back by primary key, so the table needs one.
+pub fn distinct_on_filter(
+    table: &TableInfo,
+    columns: &str,
+    order: &[OrderSpec],
+    filters: Vec<FilterNode>,
+) -> Result<FilterNode, Error> {
+    let partition = select::split_idents(columns);
+    if partition.is_empty() {
+        return Err(Error::BadRequest("distinct_on needs a column".to_string()));
+    }
+    if let Some(unknown) = partition.iter().find(|c| table.column(c).is_none()) {
+        return Err(Error::BadRequest(format!(
+            "Unknown column in distinct_on: {}",
+            unknown
+        )));
+    }
+    if table.primary_key.is_empty() {
+        return Err(Error::BadRequestHint {
+            message: format!("distinct_on needs a primary key on {}", table.name),
+            hint: "Rank the rows with ROW_NUMBER() in a view instead".to_string(),
+        });
+    }
+    let order = if order.is_empty() {
+        table
+            .primary_key
+            .iter()
+            .map(|c| format!("[{}] ASC", escape_ident(c)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else {
+        build_order_list(order)
+    };
+    Ok(FilterNode::Distinct(DistinctFilter {
+        table: table.full_name(),
+        partition,
+        order,
+        key: table.primary_key.clone(),
+        filters,
+    }))
+}
+
+/// Keyset continuation for `?resume_after=`: keeps the rows after `after`,
+/// the key of the last row a client received (one value per key column,
+/// comma separated). The key is the `order` columns, or the primary key
+/// ascending when none was given; returns the filter and the order to read
+/// in. Key columns must be unique together and not null.
+pub fn resume_after_filter(
+    table: &TableInfo,
+    order: &[OrderSpec],
+    after: &str,
+) -> Result<(FilterNode, Vec<OrderSpec>), Error> {
+    let order = if order.is_empty() {
+        if table.primary_key.is_empty() {
+            return Err(Error::BadRequestHint {
+                message: format!("resume_after needs an order on {}", table.name),
+                hint: "Add order= with the columns of a unique key".to_string(),
+            });
+        }
+        table
+            .primary_key
+            .iter()
+            .map(|c| OrderSpec {
+                column: c.clone(),
+                json_path: None,
+                cast: None,
+                direction: OrderDir::Asc,
+                nulls: None,
+            })
+            .collect()
+    } else {
+        order.to_vec()
+    };
+    if let Some(spec) = order
+        .iter()
+        .find(|o| o.json_path.is_some() || o.cast.is_some() || table.column(&o.column).is_none())
+    {
+        return Err(Error::BadRequest(format!(
+            "resume_after needs plain column keys, got order on {}",
+            spec.column
+        )));
+    }
+    let values: Vec<&str> = after.split(',').collect();
+    if values.len() != order.len() {
+        return Err(Error::BadRequest(format!(
+            "resume_after needs {} value(s), one per order column",
+            order.len()
+        )));
+    }
+
+    // (k1, k2) after (v1, v2): k1 past v1, or k1 = v1 and k2 past v2
+    let condition = |spec: &OrderSpec, operator: FilterOp, value: &str| {
+        FilterNode::Condition(Filter {
+            column: spec.column.clone(),
+            operator,
+            value: FilterValue::Single(value.to_string()),
+            negated: false,
+        })
+    };
+    let branches = (0..order.len())
+        .map(|i| {
+            let mut terms: Vec<FilterNode> = order[..i]
+                .iter()
+                .zip(&values)
+                .map(|(spec, value)| condition(spec, FilterOp::Eq, value))
+                .collect();
+            let past = match order[i].direction {
+                OrderDir::Asc => FilterOp::Gt,
+                OrderDir::Desc => FilterOp::Lt,
+            };
+            terms.push(condition(&order[i], past, values[i]));
+            FilterNode::And(terms)
+        })
+        .collect();
+    Ok((FilterNode::Or(branches), order))
+}
+
+/// Table row count from `sys.dm_db_partition_stats` (heap or clustered
+/// index rows). NULL for objects without stored rows, such as views.
+pub fn build_estimated_count(table: &TableInfo) -> BuiltQuery {
+    BuiltQuery {
+        sql: "SELECT CAST(SUM(row_count) AS bigint) AS [count] \
+              FROM sys.dm_db_partition_stats \
+              WHERE object_id = OBJECT_ID(@P1) AND index_id IN (0, 1)"
+            .to_string(),
+        params: vec![SqlParam::Text(table.full_name())],
+    }
+}
+
+/// Estimated row count of the first statement in a `SHOWPLAN_XML` document.
+pub fn plan_estimated_rows(plan: &str) -> Option<i64> {
+    const ATTR: &str = "StatementEstRows=\"";
+    let start = plan.find(ATTR)? + ATTR.len();
+    let len = plan[start..].find('"')?;
+    let rows: f64 = plan[start..start + len].parse().ok()?;
+    Some(rows.round() as i64)
+}
+
+/// Depth column added to each row of a tree query.
+pub const TREE_DEPTH_COLUMN: &str = "__depth";
+
+/// Build a recursive CTE walking a self-referencing FK: the rows matching
+/// `root_filters` at depth 0, then their descendants down to `max_depth`.
+pub fn build_tree(
+    table: &TableInfo,
+    root_filters: &[FilterNode],
+    key: &str,
+    parent: &str,
+    max_depth: u32,
+) -> Result<BuiltQuery, Error> {
+    let mut params: Vec<SqlParam> = Vec::new();
+    let columns = |alias: &str| -> String {
+        table
+            .columns
+            .iter()
+            .map(|c| format!("{}[{}]", alias, escape_ident(&c.name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut anchor = format!(
+        "SELECT {}, 0 AS [{}] FROM {}",
+        columns(""),
+        TREE_DEPTH_COLUMN,
+        table.full_name()
+    );
+    let where_clause = build_where_clause(Some(table), root_filters, &mut params)?;
+    if !where_clause.is_empty() {
+        anchor.push_str(" WHERE ");
+        anchor.push_str(&where_clause);
+    }
+
+    let sql = format!(
+        "WITH [tree] AS ({anchor} UNION ALL SELECT {child_cols}, p.[{depth}] + 1 \
+         FROM {table} AS c JOIN [tree] AS p ON c.[{parent}] = p.[{key}] \
+         WHERE p.[{depth}] < {max_depth}) \
+         SELECT * FROM [tree] OPTION (MAXRECURSION {max_depth})",
+        anchor = anchor,
+        child_cols = columns("c."),
+        depth = TREE_DEPTH_COLUMN,
+        table = table.full_name(),
+        parent = escape_ident(parent),
+        key = escape_ident(key),
+        max_depth = max_depth,
+    );
+    Ok(BuiltQuery { sql, params })
+}
+
+/// Correlated subquery returning an embed's rows as JSON: an array for
+/// one-to-many, a single object (or NULL) for many-to-one.
+fn build_embed_subquery(embed: &EmbedQuery, params: &mut Vec<SqlParam>) -> Result<String, Error> {
+    let alias = format!("[{}]", escape_ident(&embed.alias));
+    let mut applies = String::new();
+    let columns = build_column_list(embed.table, &embed.columns);
+    let columns = add_embed_columns(&alias, columns, &embed.embeds, &mut applies, params)?;
+
+    let join: Vec<String> = embed
+        .join
+        .iter()
+        .map(|(column, parent)| {
+            format!(
+                "[{}].[{}] = {}",
+                escape_ident(&embed.alias),
+                escape_ident(column),
+                parent
+            )
+        })
+        .collect();
+    let mut sql = format!(
+        "SELECT {}{} FROM {} AS {}{} WHERE {}",
+        if embed.many { "" } else { "TOP 1 " },
+        columns,
+        embed.table.full_name(),
+        alias,
+        applies,
+        join.join(" AND ")
+    );
+    let where_clause = build_where_clause(Some(embed.table), &embed.filters, params)?;
+    if !where_clause.is_empty() {
+        sql.push_str(" AND ");
+        sql.push_str(&where_clause);
+    }
+
+    if embed.many {
+        sql.push_str(&build_order_and_page(
+            embed.table,
+            &embed.order,
+            embed.limit,
+            embed.offset,
+        ));
+        // No matching rows is an empty array, not NULL
+        sql = format!("ISNULL(({} FOR JSON PATH, INCLUDE_NULL_VALUES), '[]')", sql);
+    } else {
+        sql = format!(
+            "({} FOR JSON PATH, WITHOUT_ARRAY_WRAPPER, INCLUDE_NULL_VALUES)",
+            sql
+        );
+    }
+    // JSON_QUERY keeps a nested result from being escaped as a string
+    Ok(format!("JSON_QUERY({})", sql))
+}
+
+/// Append embed columns to a column list: a `FOR JSON` subquery per nested
+/// embed, the hoisted columns of each spread (whose `OUTER APPLY` goes to
+/// `applies`). `source` qualifies a bare `*` so it can't pick up the
+/// applied columns.
+fn add_embed_columns(
+    source: &str,
+    mut columns: String,
+    embeds: &[EmbedQuery],
+    applies: &mut String,
+    params: &mut Vec<SqlParam>,
+) -> Result<String, Error> {
+    if columns == "*" && embeds.iter().any(|e| e.spread) {
+        columns = format!("{}.*", source);
+    }
+    for embed in embeds {
+        if embed.spread {
+            let (apply, hoisted) = build_spread(embed, params)?;
+            applies.push(' ');
+            applies.push_str(&apply);
+            for col in hoisted {
+                columns.push_str(", ");
+                columns.push_str(&col);
+            }
+        } else {
+            columns.push_str(&format!(
+                ", {} AS [{}]",
+                build_embed_subquery(embed, params)?,
+                escape_ident(&embed.name)
+            ));
+        }
+    }
+    Ok(columns)
+}
+
+/// `OUTER APPLY` fetching a spread embed's row, plus the parent-level
+/// column list reading from it. Applied columns are prefixed with the embed
+/// alias so they can't make the parent's unqualified names ambiguous.
+fn build_spread(
+    embed: &EmbedQuery,
+    params: &mut Vec<SqlParam>,
+) -> Result<(String, Vec<String>), Error> {
+    let alias = escape_ident(&embed.alias);
+    let applied = format!("{}_spread", alias);
+    let mut inner = Vec::new();
+    let mut hoisted = Vec::new();
+    for (expr, name) in spread_columns(embed.table, &embed.columns) {
+        let column = format!("{}_{}", alias, escape_ident(&name));
+        inner.push(format!("{} AS [{}]", expr, column));
+        hoisted.push(format!(
+            "[{}].[{}] AS [{}]",
+            applied,
+            column,
+            escape_ident(&name)
+        ));
+    }
+
+    let join: Vec<String> = embed
+        .join
+        .iter()
+        .map(|(column, parent)| format!("[{}].[{}] = {}", alias, escape_ident(column), parent))
+        .collect();
+    let mut sql = format!(
+        "OUTER APPLY (SELECT TOP 1 {} FROM {} AS [{}] WHERE {}",
+        inner.join(", "),
+        embed.table.full_name(),
+        alias,
+        join.join(" AND ")
+    );
+    let where_clause = build_where_clause(Some(embed.table), &embed.filters, params)?;
+    if !where_clause.is_empty() {
+        sql.push_str(" AND ");
+        sql.push_str(&where_clause);
+    }
+    sql.push_str(&format!(") AS [{}]", applied));
+    Ok((sql, hoisted))
+}
+
+/// (expression, output name) for each column a spread selects.
+fn spread_columns(table: &TableInfo, nodes: &[SelectNode]) -> Vec<(String, String)> {
+    let star = nodes.is_empty() || select::has_star(nodes);
+    let mut cols: Vec<(String, String)> = Vec::new();
+    if star {
+        cols.extend(
+            table
+                .columns
+                .iter()
+                .map(|c| (format!("[{}]", escape_ident(&c.name)), c.name.clone())),
+        );
+    }
+    for node in nodes {
+        match node {
+            SelectNode::Column(col) => {
+                if !cols.iter().any(|(_, name)| name.eq_ignore_ascii_case(col)) {
+                    cols.push((format!("[{}]", escape_ident(col)), col.clone()));
+                }
+            }
+            SelectNode::Field(field) => {
+                let mut expr = match &field.json_path {
+                    Some(path) => json_path_expr(path),
+                    None => format!("[{}]", escape_ident(&field.column)),
+                };
+                if let Some(ty) = &field.cast {
+                    expr = format!("CAST({} AS {})", expr, ty);
+                }
+                cols.push((expr, field.alias.clone()));
+            }
+            SelectNode::Star | SelectNode::Embed(_) => {}
+        }
+    }
+    cols
+}
+
+/// `ORDER BY` plus `OFFSET/FETCH`. Paging without an explicit order falls
+/// back to the primary key, since OFFSET/FETCH requires an ORDER BY.
+fn build_order_and_page(
+    table: &TableInfo,
+    order: &[OrderSpec],
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> String {
+    let mut sql = String::new();
+
     // ORDER BY
     if !order.is_empty() {
         sql.push_str(" ORDER BY ");
-        let order_parts: Vec<String> = order
-            .iter()
-            .map(|o| {
-                let dir = match o.direction {
-                    OrderDir::Asc => "ASC",
-                    OrderDir::Desc => "DESC",
-                };
-                let nulls = match &o.nulls {
-                    Some(NullsOrder::First) => {
-                        format!(
-                            "CASE WHEN [{}] IS NULL THEN 0 ELSE 1 END, ",
-                            escape_ident(&o.column)
-                        )
-                    }
-                    Some(NullsOrder::Last) => {
-                        format!(
-                            "CASE WHEN [{}] IS NULL THEN 1 ELSE 0 END, ",
-                            escape_ident(&o.column)
-                        )
-                    }
-                    None => String::new(),
-                };
-                format!("{}[{}] {}", nulls, escape_ident(&o.column), dir)
-            })
-            .collect();
-        sql.push_str(&order_parts.join(", "));
+        sql.push_str(&build_order_list(order));
     } else if limit.is_some() || offset.is_some() {
         // ORDER BY is required for OFFSET/FETCH
         if !table.primary_key.is_empty() {
@@ -165,106 +798,423 @@ pub fn build_select(
         sql.push_str(&format!(" OFFSET 0 ROWS FETCH NEXT {} ROWS ONLY", lim));
     }
 
-    Ok(BuiltQuery { sql, params })
+    sql
+}
+
+/// Render order specs as a comma-separated `ORDER BY` list (without the keyword).
+///
+/// SQL Server sorts NULLs first ascending and last descending. Other
+/// NULLS FIRST/LAST requests are emulated with a leading
+/// `CASE WHEN ... IS NULL` key; matching ones need nothing extra.
+pub fn build_order_list(order: &[OrderSpec]) -> String {
+    let order_parts: Vec<String> = order
+        .iter()
+        .map(|o| {
+            let expr = o.expr();
+            let nulls = match (&o.direction, &o.nulls) {
+                (OrderDir::Asc, Some(NullsOrder::Last)) => {
+                    format!("CASE WHEN {} IS NULL THEN 1 ELSE 0 END, ", expr)
+                }
+                (OrderDir::Desc, Some(NullsOrder::First)) => {
+                    format!("CASE WHEN {} IS NULL THEN 0 ELSE 1 END, ", expr)
+                }
+                _ => String::new(),
+            };
+            let dir = match o.direction {
+                OrderDir::Asc => "ASC",
+                OrderDir::Desc => "DESC",
+            };
+            format!("{}{} {}", nulls, expr, dir)
+        })
+        .collect();
+    order_parts.join(", ")
+}
+
+/// Reject identity, computed and rowversion columns in a write, which SQL
+/// Server would otherwise refuse with an opaque error. They stay readable,
+/// filterable and sortable.
+fn ensure_writable(table: &TableInfo, columns: &[String]) -> Result<(), Error> {
+    let problems: Vec<String> = columns
+        .iter()
+        .filter_map(|col| {
+            let reason = table.column(col)?.read_only_reason()?;
+            Some(format!("{}: cannot write {}", col, reason))
+        })
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Validation(problems))
+    }
 }
 
-/// Build an INSERT query.
+/// Build an INSERT query. `present` flags, per row and column, which
+/// columns the row sends; the others are inserted as `DEFAULT`.
 pub fn build_insert(
     table: &TableInfo,
     columns: &[String],
-    value_count: usize,
+    present: &[Vec<bool>],
+    returning: bool,
+) -> Result<BuiltQuery, Error> {
+    if columns.is_empty() {
+        return Err(Error::BadRequest("No columns to insert".to_string()));
+    }
+    ensure_writable(table, columns)?;
+    Ok(insert_statement(table, columns, present, returning))
+}
+
+/// Build an INSERT that may set identity columns (`Prefer: identity=insert`),
+/// with `IDENTITY_INSERT` switched on around it.
+pub fn build_identity_insert(
+    table: &TableInfo,
+    columns: &[String],
+    present: &[Vec<bool>],
+    returning: bool,
 ) -> Result<BuiltQuery, Error> {
     if columns.is_empty() {
         return Err(Error::BadRequest("No columns to insert".to_string()));
     }
+    let others: Vec<String> = columns
+        .iter()
+        .filter(|c| !table.column(c).is_some_and(|col| col.is_identity))
+        .cloned()
+        .collect();
+    ensure_writable(table, &others)?;
+    let mut built = insert_statement(table, columns, present, returning);
+    built.sql = with_identity_insert(table, &built.sql);
+    Ok(built)
+}
 
+/// One row's VALUES entries: a placeholder for each column the row sends
+/// and `DEFAULT` for the ones it leaves out, so database defaults such as
+/// `NEWID()` or `SYSUTCDATETIME()` apply even when other rows send the
+/// column. Every column takes a parameter number either way; those under
+/// `DEFAULT` are bound but unused.
+fn value_group(present: &[bool], columns: usize, param_idx: &mut usize) -> Vec<String> {
+    (0..columns)
+        .map(|i| {
+            let entry = if present.get(i).copied().unwrap_or(false) {
+                format!("@P{}", param_idx)
+            } else {
+                "DEFAULT".to_string()
+            };
+            *param_idx += 1;
+            entry
+        })
+        .collect()
+}
+
+fn insert_statement(
+    table: &TableInfo,
+    columns: &[String],
+    present: &[Vec<bool>],
+    returning: bool,
+) -> BuiltQuery {
     let col_list: Vec<String> = columns
         .iter()
         .map(|c| format!("[{}]", escape_ident(c)))
         .collect();
 
     let mut param_idx = 1;
-    let mut all_value_groups = Vec::new();
-
-    for _ in 0..value_count {
-        let group: Vec<String> = columns
-            .iter()
-            .map(|_| {
-                let p = format!("@P{}", param_idx);
-                param_idx += 1;
-                p
-            })
-            .collect();
-        all_value_groups.push(format!("({})", group.join(", ")));
-    }
-
-    // Build OUTPUT clause for all columns
-    let output_cols: Vec<String> = table
-        .columns
+    let all_value_groups: Vec<String> = present
         .iter()
-        .map(|c| format!("inserted.[{}]", escape_ident(&c.name)))
+        .map(|row| {
+            format!(
+                "({})",
+                value_group(row, columns.len(), &mut param_idx).join(", ")
+            )
+        })
         .collect();
 
     let sql = format!(
-        "INSERT INTO {} ({}) OUTPUT {} VALUES {}",
+        "INSERT INTO {} ({}){} VALUES {}",
         table.full_name(),
         col_list.join(", "),
-        output_cols.join(", "),
+        output_clause(table, "inserted", returning),
         all_value_groups.join(", ")
     );
+    let sql = wrap_output_into(table, returning, sql);
 
-    Ok(BuiltQuery {
+    BuiltQuery {
         sql,
         params: Vec::new(),
-    })
+    }
 }
 
-/// Build a MERGE (upsert) query.
-pub fn build_upsert(
+/// Most rows one INSERT can carry: SQL Server takes at most 1000 rows per
+/// VALUES list and 2100 parameters per request.
+pub fn insert_batch_rows(column_count: usize) -> usize {
+    const MAX_PARAMS: usize = 2000;
+    (MAX_PARAMS / column_count.max(1)).clamp(1, 1000)
+}
+
+/// Clears the doomed-transaction message [`build_insert_each`] chunks share
+/// through the session context. Runs before the first chunk and after the
+/// last, so it never outlives the request on a pooled connection.
+pub const INSERT_EACH_RESET: &str = "EXEC sp_set_session_context N'lazypaw_doomed', NULL;";
+
+/// Build a per-row INSERT batch where each row runs under its own savepoint,
+/// so one bad row doesn't fail the whole array. A body is split into chunks
+/// of [`insert_batch_rows`] run one after another in one transaction; rows
+/// are numbered from `first_index`.
+///
+/// `XACT_ABORT` is off for the batch (and put back after), so a row's error
+/// doesn't doom the transaction. One that dooms it anyway undoes every row:
+/// the transaction is rolled back and begun again for the caller's COMMIT,
+/// and the rows after it, in this chunk and later ones, fail with its
+/// message. Rows of earlier chunks are the caller's to fail: the message
+/// comes back as `__doomed`.
+///
+/// The batch returns one row per input object: `__idx`, `__error` (NULL when
+/// the row went in), `__doomed`, then with `returning` the inserted row in
+/// the `select=` shape (every column when empty) with its embeds. Embed
+/// parameters are returned for the caller to bind after the row values.
+pub fn build_insert_each(
     table: &TableInfo,
     columns: &[String],
-    _value_count: usize,
+    present: &[Vec<bool>],
+    first_index: usize,
+    returning: Option<(&[SelectNode], &[EmbedQuery])>,
 ) -> Result<BuiltQuery, Error> {
     if columns.is_empty() {
-        return Err(Error::BadRequest("No columns to upsert".to_string()));
+        return Err(Error::BadRequest("No columns to insert".to_string()));
     }
-
-    // Need PK or unique constraint for merge match
-    let match_cols = if !table.primary_key.is_empty() {
-        &table.primary_key
-    } else if let Some(uq) = table.unique_constraints.first() {
-        uq
-    } else {
-        return Err(Error::BadRequest(
-            "Table has no primary key or unique constraint for upsert".to_string(),
-        ));
-    };
+    ensure_writable(table, columns)?;
 
     let col_list: Vec<String> = columns
         .iter()
         .map(|c| format!("[{}]", escape_ident(c)))
         .collect();
+    let output_cols = returning.map(|_| {
+        table
+            .columns
+            .iter()
+            .map(|c| format!("inserted.[{}]", escape_ident(&c.name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    });
 
-    let source_cols: Vec<String> = columns
-        .iter()
-        .enumerate()
-        .map(|(i, _)| format!("@P{}", i + 1))
-        .collect();
-
-    let on_clause: Vec<String> = match_cols
-        .iter()
-        .map(|c| {
-            format!(
-                "target.[{}] = source.[{}]",
-                escape_ident(c),
-                escape_ident(c)
-            )
-        })
-        .collect();
+    let mut sql = String::new();
+    if returning.is_some() {
+        let out_decls: Vec<String> = table
+            .columns
+            .iter()
+            .map(|c| format!("[{}] {} NULL", escape_ident(&c.name), c.type_decl()))
+            .collect();
+        sql.push_str(&format!(
+            "DECLARE {} TABLE ([__idx] INT, {});\n",
+            OUTPUT_TABLE_VAR,
+            out_decls.join(", ")
+        ));
+    }
+    sql.push_str(
+        "DECLARE @lazypaw_status TABLE ([__idx] INT, [__error] NVARCHAR(4000));\n\
+         DECLARE @lazypaw_xact_abort BIT = IIF(@@OPTIONS & 16384 = 16384, 1, 0);\n\
+         DECLARE @lazypaw_doomed NVARCHAR(4000) = \
+         CAST(SESSION_CONTEXT(N'lazypaw_doomed') AS NVARCHAR(4000));\n\
+         SET XACT_ABORT OFF;\n",
+    );
 
-    let update_cols: Vec<String> = columns
+    let mut param_idx = 1;
+    for (i, row) in present.iter().enumerate() {
+        let i = first_index + i;
+        let group = value_group(row, columns.len(), &mut param_idx);
+        let output = output_cols
+            .as_ref()
+            .map(|cols| format!(" OUTPUT {i}, {cols} INTO {OUTPUT_TABLE_VAR}"))
+            .unwrap_or_default();
+        sql.push_str(&format!(
+            "IF @lazypaw_doomed IS NULL\n\
+             BEGIN\n\
+             SAVE TRANSACTION lazypaw_row;\n\
+             BEGIN TRY\n\
+             INSERT INTO {} ({}){} VALUES ({});\n\
+             INSERT INTO @lazypaw_status VALUES ({i}, NULL);\n\
+             END TRY\n\
+             BEGIN CATCH\n\
+             IF XACT_STATE() = -1\n\
+             BEGIN\n\
+             ROLLBACK TRANSACTION;\n\
+             BEGIN TRANSACTION;\n\
+             SET @lazypaw_doomed = ERROR_MESSAGE();\n\
+             EXEC sp_set_session_context N'lazypaw_doomed', @lazypaw_doomed;\n\
+             END\n\
+             ELSE IF XACT_STATE() = 1 ROLLBACK TRANSACTION lazypaw_row;\n\
+             INSERT INTO @lazypaw_status VALUES ({i}, ERROR_MESSAGE());\n\
+             END CATCH\n\
+             END\n\
+             ELSE INSERT INTO @lazypaw_status VALUES ({i}, @lazypaw_doomed);\n",
+            table.full_name(),
+            col_list.join(", "),
+            output,
+            group.join(", "),
+            i = i,
+        ));
+    }
+
+    // Table variables outlive a rollback: drop what it undid
+    sql.push_str(&format!(
+        "IF @lazypaw_doomed IS NOT NULL\n\
+         BEGIN\n\
+         {}UPDATE @lazypaw_status SET [__error] = @lazypaw_doomed WHERE [__error] IS NULL;\n\
+         END\n\
+         IF @lazypaw_xact_abort = 1 SET XACT_ABORT ON;\n",
+        if returning.is_some() {
+            format!("DELETE FROM {};\n", OUTPUT_TABLE_VAR)
+        } else {
+            String::new()
+        }
+    ));
+
+    let status = "SELECT s.[__idx], s.[__error], @lazypaw_doomed AS [__doomed]";
+    let Some((nodes, embeds)) = returning else {
+        sql.push_str(&format!(
+            "{} FROM @lazypaw_status s ORDER BY s.[__idx];",
+            status
+        ));
+        return Ok(BuiltQuery {
+            sql,
+            params: Vec::new(),
+        });
+    };
+    let param_count = param_idx - 1;
+    let mut params: Vec<SqlParam> = vec![SqlParam::Null; param_count];
+    let mut applies = String::new();
+    let columns = add_embed_columns(
+        RETURNED_ALIAS,
+        build_column_list(table, nodes),
+        embeds,
+        &mut applies,
+        &mut params,
+    )?;
+    sql.push_str(&format!(
+        "{status}, {columns} FROM @lazypaw_status s LEFT JOIN {out} AS {alias} \
+         ON {alias}.[__idx] = s.[__idx]{applies} ORDER BY s.[__idx];",
+        out = OUTPUT_TABLE_VAR,
+        alias = RETURNED_ALIAS,
+    ));
+
+    Ok(BuiltQuery {
+        sql,
+        params: params.split_off(param_count),
+    })
+}
+
+/// Columns an upsert matches rows on: the `on_conflict` columns, which must
+/// be the primary key or a unique constraint, else the primary key, else the
+/// first unique constraint.
+pub fn upsert_key<'a>(
+    table: &'a TableInfo,
+    on_conflict: Option<&[String]>,
+) -> Result<&'a [String], Error> {
+    let Some(wanted) = on_conflict else {
+        return if !table.primary_key.is_empty() {
+            Ok(&table.primary_key)
+        } else if let Some(uq) = table.unique_constraints.first() {
+            Ok(uq)
+        } else {
+            Err(Error::BadRequest(
+                "Table has no primary key or unique constraint for upsert".to_string(),
+            ))
+        };
+    };
+    let same_columns = |key: &[String]| {
+        key.len() == wanted.len()
+            && wanted
+                .iter()
+                .all(|w| key.iter().any(|k| k.eq_ignore_ascii_case(w)))
+    };
+    let keys = std::iter::once(&table.primary_key)
+        .filter(|pk| !pk.is_empty())
+        .chain(&table.unique_constraints);
+    if let Some(key) = keys.clone().find(|key| same_columns(key)) {
+        return Ok(key);
+    }
+    let available: Vec<String> = keys.map(|key| key.join(",")).collect();
+    Err(Error::BadRequestHint {
+        message: format!(
+            "on_conflict={} is not a primary key or unique constraint of {}",
+            wanted.join(","),
+            table.name
+        ),
+        hint: if available.is_empty() {
+            "The table has no primary key or unique constraint".to_string()
+        } else {
+            format!("Use one of: {}", available.join("; "))
+        },
+    })
+}
+
+/// Build a MERGE (upsert) query.
+///
+/// `present[row][col]` records whether each row's body actually carried the
+/// column. A key missing from a row leaves the existing value unchanged on
+/// update (and inserts NULL); an explicit null is bound and overwrites.
+/// `on_conflict` picks the key rows are matched on (see `upsert_key`).
+pub fn build_upsert(
+    table: &TableInfo,
+    columns: &[String],
+    present: &[Vec<bool>],
+    on_conflict: Option<&[String]>,
+    returning: bool,
+) -> Result<BuiltQuery, Error> {
+    if columns.is_empty() {
+        return Err(Error::BadRequest("No columns to upsert".to_string()));
+    }
+    ensure_writable(table, columns)?;
+    let match_cols = upsert_key(table, on_conflict)?;
+    if let Some(missing) = match_cols
+        .iter()
+        .find(|mc| !columns.iter().any(|c| c.eq_ignore_ascii_case(mc)))
+    {
+        return Err(Error::BadRequest(format!(
+            "Upsert rows must include {}, the column they are matched on",
+            missing
+        )));
+    }
+
+    let is_match_col = |c: &str| match_cols.iter().any(|mc| mc.eq_ignore_ascii_case(c));
+
+    // Columns some row leaves out get a presence flag in the source
+    let flagged: Vec<usize> = (0..columns.len())
+        .filter(|&i| !is_match_col(&columns[i]))
+        .filter(|&i| {
+            present
+                .iter()
+                .any(|row| !row.get(i).copied().unwrap_or(false))
+        })
+        .collect();
+
+    let col_list: Vec<String> = columns
+        .iter()
+        .map(|c| format!("[{}]", escape_ident(c)))
+        .collect();
+
+    let mut source_cols = col_list.clone();
+    source_cols.extend(flagged.iter().map(|i| format!("[__set{}]", i)));
+
+    let mut param_idx = 1;
+    let value_groups: Vec<String> = present
+        .iter()
+        .map(|row| {
+            let mut group: Vec<String> = columns
+                .iter()
+                .map(|_| {
+                    let p = format!("@P{}", param_idx);
+                    param_idx += 1;
+                    p
+                })
+                .collect();
+            for &i in &flagged {
+                let set = row.get(i).copied().unwrap_or(false);
+                group.push(if set { "1" } else { "0" }.to_string());
+            }
+            format!("({})", group.join(", "))
+        })
+        .collect();
+
+    let on_clause: Vec<String> = match_cols
         .iter()
-        .filter(|c| !match_cols.iter().any(|mc| mc.eq_ignore_ascii_case(c)))
         .map(|c| {
             format!(
                 "target.[{}] = source.[{}]",
@@ -274,41 +1224,142 @@ pub fn build_upsert(
         })
         .collect();
 
-    let output_cols: Vec<String> = table
-        .columns
+    let update_cols: Vec<String> = columns
         .iter()
-        .map(|c| format!("inserted.[{}]", escape_ident(&c.name)))
+        .enumerate()
+        .filter(|(_, c)| !is_match_col(c))
+        .map(|(i, c)| {
+            let c = escape_ident(c);
+            if flagged.contains(&i) {
+                format!(
+                    "target.[{c}] = CASE WHEN source.[__set{i}] = 1 \
+                     THEN source.[{c}] ELSE target.[{c}] END"
+                )
+            } else {
+                format!("target.[{c}] = source.[{c}]")
+            }
+        })
         .collect();
 
     let mut sql = format!(
-        "MERGE {} AS target USING (SELECT {}) AS source ({}) ON {} ",
+        "MERGE {} AS target USING (VALUES {}) AS source ({}) ON {} ",
         table.full_name(),
-        source_cols
+        value_groups.join(", "),
+        source_cols.join(", "),
+        on_clause.join(" AND ")
+    );
+
+    if !update_cols.is_empty() {
+        sql.push_str(&format!(
+            "WHEN MATCHED THEN UPDATE SET {} ",
+            update_cols.join(", ")
+        ));
+    }
+
+    sql.push_str(&format!(
+        "WHEN NOT MATCHED THEN INSERT ({}) VALUES ({}){};",
+        col_list.join(", "),
+        columns
             .iter()
-            .zip(columns.iter())
-            .map(|(p, c)| format!("{} AS [{}]", p, escape_ident(c)))
+            .map(|c| format!("source.[{}]", escape_ident(c)))
             .collect::<Vec<_>>()
             .join(", "),
+        output_clause(table, "inserted", returning)
+    ));
+    let sql = wrap_output_into(table, returning, sql);
+
+    Ok(BuiltQuery {
+        sql,
+        params: Vec::new(),
+    })
+}
+
+/// Build a full-row upsert of one row keyed on the primary key (PUT).
+///
+/// `columns` must include the key. Every row comes back with an
+/// `[__action]` column (`INSERT` or `UPDATE`), followed by the row itself
+/// when `returning`. Output always goes through the table variable, which
+/// also keeps it working on tables with triggers.
+pub fn build_put(
+    table: &TableInfo,
+    columns: &[String],
+    returning: bool,
+) -> Result<BuiltQuery, Error> {
+    ensure_writable(table, columns)?;
+    let is_key = |c: &str| table.primary_key.iter().any(|k| k.eq_ignore_ascii_case(c));
+    if table.primary_key.is_empty()
+        || !table
+            .primary_key
+            .iter()
+            .all(|k| columns.iter().any(|c| c.eq_ignore_ascii_case(k)))
+    {
+        return Err(Error::BadRequest(
+            "PUT needs every primary key column".to_string(),
+        ));
+    }
+
+    let col_list: Vec<String> = columns
+        .iter()
+        .map(|c| format!("[{}]", escape_ident(c)))
+        .collect();
+    let values: Vec<String> = (1..=columns.len()).map(|i| format!("@P{}", i)).collect();
+    let on_clause: Vec<String> = table
+        .primary_key
+        .iter()
+        .map(|k| {
+            let k = escape_ident(k);
+            format!("target.[{k}] = source.[{k}]")
+        })
+        .collect();
+    let update_cols: Vec<String> = columns
+        .iter()
+        .filter(|c| !is_key(c))
+        .map(|c| {
+            let c = escape_ident(c);
+            format!("target.[{c}] = source.[{c}]")
+        })
+        .collect();
+
+    let mut decls = vec!["[__action] nvarchar(10)".to_string()];
+    let mut outputs = vec!["$action".to_string()];
+    if returning {
+        for c in &table.columns {
+            decls.push(format!(
+                "[{}] {} NULL",
+                escape_ident(&c.name),
+                c.type_decl()
+            ));
+            outputs.push(format!("inserted.[{}]", escape_ident(&c.name)));
+        }
+    }
+
+    let mut sql = format!(
+        "DECLARE {out} TABLE ({});\n\
+         MERGE {} WITH (HOLDLOCK) AS target USING (VALUES ({})) AS source ({}) ON {} ",
+        decls.join(", "),
+        table.full_name(),
+        values.join(", "),
         col_list.join(", "),
-        on_clause.join(" AND ")
+        on_clause.join(" AND "),
+        out = OUTPUT_TABLE_VAR
     );
-
     if !update_cols.is_empty() {
         sql.push_str(&format!(
             "WHEN MATCHED THEN UPDATE SET {} ",
             update_cols.join(", ")
         ));
     }
-
     sql.push_str(&format!(
-        "WHEN NOT MATCHED THEN INSERT ({}) VALUES ({}) OUTPUT {};",
+        "WHEN NOT MATCHED THEN INSERT ({}) VALUES ({}) OUTPUT {} INTO {out};\n\
+         SELECT * FROM {out};",
         col_list.join(", "),
         columns
             .iter()
             .map(|c| format!("source.[{}]", escape_ident(c)))
             .collect::<Vec<_>>()
             .join(", "),
-        output_cols.join(", ")
+        outputs.join(", "),
+        out = OUTPUT_TABLE_VAR
     ));
 
     Ok(BuiltQuery {
@@ -317,134 +1368,624 @@ pub fn build_upsert(
     })
 }
 
-/// Build an UPDATE query with filters.
+/// `?limit=` (and optionally `?order=`) on PATCH and DELETE: only the
+/// first `limit` matching rows are touched.
+#[derive(Debug, Clone)]
+pub struct MutationLimit {
+    pub limit: i64,
+    pub order: Vec<OrderSpec>,
+}
+
+/// CTE a limited UPDATE/DELETE targets in place of the table.
+const LIMITED_TARGET: &str = "[__limited]";
+
+/// `WITH [__limited] AS (SELECT TOP (n) * ...)`, picking the rows a limited
+/// mutation may touch. Writing through the CTE changes only those rows.
+fn limited_cte(table: &TableInfo, where_clause: &str, limit: &MutationLimit) -> String {
+    let mut sql = format!(
+        "WITH {} AS (SELECT TOP ({}) * FROM {}",
+        LIMITED_TARGET,
+        limit.limit,
+        table.full_name()
+    );
+    if !where_clause.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(where_clause);
+    }
+    if !limit.order.is_empty() {
+        sql.push_str(" ORDER BY ");
+        sql.push_str(&build_order_list(&limit.order));
+    }
+    sql.push_str(") ");
+    sql
+}
+
+/// How PATCH sets a column: to the body value, or with an operator applied
+/// to the current value (`{"increment": 5}`), which saves clients a
+/// read-modify-write race.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateOp {
+    /// `[c] = @P1`
+    Set,
+    /// `[c] = [c] + @P1`
+    Increment,
+    /// `[c] = [c] - @P1`
+    Decrement,
+    /// `[c] = [c] * @P1`
+    Multiply,
+    /// Append to the JSON array in the column; `true` when the value is
+    /// itself JSON (an object or array) rather than a scalar
+    JsonAppend(bool),
+    /// Set JSON paths, a `(path, value)` parameter pair each, with a flag
+    /// per JSON value. A null value removes the path.
+    JsonSet(Vec<bool>),
+}
+
+impl UpdateOp {
+    /// Parameters the assignment binds.
+    pub fn param_count(&self) -> usize {
+        match self {
+            UpdateOp::JsonSet(values) => 2 * values.len(),
+            _ => 1,
+        }
+    }
+
+    /// `[column] = <expr>`, reading parameters from `@P{first}` on.
+    fn assignment(&self, column: &str, first: usize) -> String {
+        let col = format!("[{}]", escape_ident(column));
+        let value = |json: bool, p: usize| {
+            if json {
+                format!("JSON_QUERY(@P{})", p)
+            } else {
+                format!("@P{}", p)
+            }
+        };
+        let expr = match self {
+            UpdateOp::Set => format!("@P{}", first),
+            UpdateOp::Increment => format!("{} + @P{}", col, first),
+            UpdateOp::Decrement => format!("{} - @P{}", col, first),
+            UpdateOp::Multiply => format!("{} * @P{}", col, first),
+            UpdateOp::JsonAppend(json) => format!(
+                "JSON_MODIFY(COALESCE({}, N'[]'), 'append $', {})",
+                col,
+                value(*json, first)
+            ),
+            UpdateOp::JsonSet(values) => values.iter().enumerate().fold(
+                format!("COALESCE({}, N'{{}}')", col),
+                |expr, (i, json)| {
+                    let p = first + 2 * i;
+                    format!("JSON_MODIFY({}, @P{}, {})", expr, p, value(*json, p + 1))
+                },
+            ),
+        };
+        format!("{} = {}", col, expr)
+    }
+}
+
+/// Build an UPDATE query with filters, optionally limited to the first
+/// `limit` matching rows. Each column is set with its [`UpdateOp`]; their
+/// parameters come first, in column order, then the filters'.
 pub fn build_update(
     table: &TableInfo,
-    columns: &[String],
+    assignments: &[(String, UpdateOp)],
     filters: &[FilterNode],
+    limit: Option<&MutationLimit>,
+    returning: bool,
 ) -> Result<BuiltQuery, Error> {
-    if columns.is_empty() {
+    if assignments.is_empty() {
         return Err(Error::BadRequest("No columns to update".to_string()));
     }
+    let columns: Vec<String> = assignments.iter().map(|(c, _)| c.clone()).collect();
+    ensure_writable(table, &columns)?;
 
-    let mut params: Vec<String> = Vec::new();
+    let mut params: Vec<SqlParam> = Vec::new();
 
-    let set_clauses: Vec<String> = columns
+    let mut param_offset = 0;
+    let set_clauses: Vec<String> = assignments
         .iter()
-        .enumerate()
-        .map(|(i, c)| format!("[{}] = @P{}", escape_ident(c), i + 1))
+        .map(|(c, op)| {
+            let clause = op.assignment(c, param_offset + 1);
+            param_offset += op.param_count();
+            clause
+        })
         .collect();
 
-    let param_offset = columns.len();
+    let where_clause = if filters.is_empty() {
+        String::new()
+    } else {
+        build_where_clause_with_offset(Some(table), filters, &mut params, param_offset)?
+    };
 
-    let output_cols: Vec<String> = table
-        .columns
+    let sql = match limit {
+        Some(limit) => format!(
+            "{}UPDATE {} SET {}{}",
+            limited_cte(table, &where_clause, limit),
+            LIMITED_TARGET,
+            set_clauses.join(", "),
+            output_clause(table, "inserted", returning)
+        ),
+        None => {
+            let mut sql = format!(
+                "UPDATE {} SET {}{}",
+                table.full_name(),
+                set_clauses.join(", "),
+                output_clause(table, "inserted", returning)
+            );
+            if !where_clause.is_empty() {
+                sql.push_str(" WHERE ");
+                sql.push_str(&where_clause);
+            }
+            sql
+        }
+    };
+    let sql = wrap_output_into(table, returning, sql);
+
+    Ok(BuiltQuery { sql, params })
+}
+
+/// Build a keyed bulk UPDATE: every row carries its primary key and the
+/// columns it changes, and all of them are applied by joining the table to
+/// a VALUES list on the key.
+///
+/// `present[row][col]` works as in `build_upsert`: a column some row leaves
+/// out keeps that row's current value.
+pub fn build_bulk_update(
+    table: &TableInfo,
+    columns: &[String],
+    present: &[Vec<bool>],
+    returning: bool,
+) -> Result<BuiltQuery, Error> {
+    if table.primary_key.is_empty() {
+        return Err(Error::MethodNotAllowed(format!(
+            "{}.{} has no primary key to match rows on",
+            table.schema, table.name
+        )));
+    }
+    if let Some(missing) = table
+        .primary_key
         .iter()
-        .map(|c| format!("inserted.[{}]", escape_ident(&c.name)))
+        .find(|pk| !columns.iter().any(|c| c.eq_ignore_ascii_case(pk)))
+    {
+        return Err(Error::BadRequest(format!(
+            "Every row must include {}, the primary key rows are matched on",
+            missing
+        )));
+    }
+    let is_key = |c: &str| {
+        table
+            .primary_key
+            .iter()
+            .any(|pk| pk.eq_ignore_ascii_case(c))
+    };
+    let set_cols: Vec<usize> = (0..columns.len())
+        .filter(|&i| !is_key(&columns[i]))
         .collect();
+    if set_cols.is_empty() {
+        return Err(Error::BadRequest("No columns to update".to_string()));
+    }
+    let updated: Vec<String> = set_cols.iter().map(|&i| columns[i].clone()).collect();
+    ensure_writable(table, &updated)?;
 
-    let mut sql = format!(
-        "UPDATE {} SET {} OUTPUT {}",
-        table.full_name(),
+    let flagged: Vec<usize> = set_cols
+        .iter()
+        .copied()
+        .filter(|&i| {
+            present
+                .iter()
+                .any(|row| !row.get(i).copied().unwrap_or(false))
+        })
+        .collect();
+
+    let mut source_cols: Vec<String> = columns
+        .iter()
+        .map(|c| format!("[{}]", escape_ident(c)))
+        .collect();
+    source_cols.extend(flagged.iter().map(|i| format!("[__set{}]", i)));
+
+    let mut param_idx = 1;
+    let value_groups: Vec<String> = present
+        .iter()
+        .map(|row| {
+            let mut group: Vec<String> = columns
+                .iter()
+                .map(|_| {
+                    let p = format!("@P{}", param_idx);
+                    param_idx += 1;
+                    p
+                })
+                .collect();
+            for &i in &flagged {
+                let set = row.get(i).copied().unwrap_or(false);
+                group.push(if set { "1" } else { "0" }.to_string());
+            }
+            format!("({})", group.join(", "))
+        })
+        .collect();
+
+    let set_clauses: Vec<String> = set_cols
+        .iter()
+        .map(|&i| {
+            let c = escape_ident(&columns[i]);
+            if flagged.contains(&i) {
+                format!(
+                    "[{c}] = CASE WHEN source.[__set{i}] = 1 \
+                     THEN source.[{c}] ELSE target.[{c}] END"
+                )
+            } else {
+                format!("[{c}] = source.[{c}]")
+            }
+        })
+        .collect();
+
+    let on_clause: Vec<String> = table
+        .primary_key
+        .iter()
+        .map(|pk| {
+            format!(
+                "target.[{}] = source.[{}]",
+                escape_ident(pk),
+                escape_ident(pk)
+            )
+        })
+        .collect();
+
+    let sql = format!(
+        "UPDATE target SET {}{} FROM {} AS target INNER JOIN (VALUES {}) AS source ({}) ON {};",
         set_clauses.join(", "),
-        output_cols.join(", ")
+        output_clause(table, "inserted", returning),
+        table.full_name(),
+        value_groups.join(", "),
+        source_cols.join(", "),
+        on_clause.join(" AND ")
     );
+    let sql = wrap_output_into(table, returning, sql);
 
-    if !filters.is_empty() {
-        let where_clause = build_where_clause_with_offset(filters, &mut params, param_offset)?;
-        if !where_clause.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&where_clause);
+    Ok(BuiltQuery {
+        sql,
+        params: Vec::new(),
+    })
+}
+
+/// Build a DELETE query with filters, optionally limited to the first
+/// `limit` matching rows.
+pub fn build_delete(
+    table: &TableInfo,
+    filters: &[FilterNode],
+    limit: Option<&MutationLimit>,
+    returning: bool,
+) -> Result<BuiltQuery, Error> {
+    let mut params: Vec<SqlParam> = Vec::new();
+    let where_clause = if filters.is_empty() {
+        String::new()
+    } else {
+        build_where_clause(Some(table), filters, &mut params)?
+    };
+
+    let sql = match limit {
+        Some(limit) => format!(
+            "{}DELETE FROM {}{}",
+            limited_cte(table, &where_clause, limit),
+            LIMITED_TARGET,
+            output_clause(table, "deleted", returning)
+        ),
+        None => {
+            let mut sql = format!(
+                "DELETE FROM {}{}",
+                table.full_name(),
+                output_clause(table, "deleted", returning)
+            );
+            if !where_clause.is_empty() {
+                sql.push_str(" WHERE ");
+                sql.push_str(&where_clause);
+            }
+            sql
         }
-    }
+    };
+    let sql = wrap_output_into(table, returning, sql);
 
     Ok(BuiltQuery { sql, params })
 }
 
-/// Build a DELETE query with filters.
-pub fn build_delete(table: &TableInfo, filters: &[FilterNode]) -> Result<BuiltQuery, Error> {
-    let mut params: Vec<String> = Vec::new();
+/// Build the ` OUTPUT <prefix>.[col], ...` clause, or nothing when rows
+/// don't need to be returned (keeps DML batch-mode friendly).
+fn output_clause(table: &TableInfo, prefix: &str, returning: bool) -> String {
+    if !returning {
+        return String::new();
+    }
+    let output = plain_output_clause(table, prefix);
+    if table.has_triggers {
+        // Plain OUTPUT is rejected on tables with enabled triggers. Writes
+        // INSTEAD OF triggers take over aren't built with OUTPUT: it would
+        // capture the submitted values, not what the trigger wrote.
+        format!("{} INTO {}", output, OUTPUT_TABLE_VAR)
+    } else {
+        output
+    }
+}
 
+fn plain_output_clause(table: &TableInfo, prefix: &str) -> String {
     let output_cols: Vec<String> = table
         .columns
         .iter()
-        .map(|c| format!("deleted.[{}]", escape_ident(&c.name)))
+        .map(|c| format!("{}.[{}]", prefix, escape_ident(&c.name)))
         .collect();
+    format!(" OUTPUT {}", output_cols.join(", "))
+}
 
-    let mut sql = format!(
-        "DELETE FROM {} OUTPUT {}",
-        table.full_name(),
-        output_cols.join(", ")
+/// Column [`select_rowcount`] reports the affected-row count in.
+pub const ROWCOUNT_COLUMN: &str = "__rowcount";
+
+/// Follow a DML statement run without OUTPUT by its `@@ROWCOUNT`, so a
+/// `return=minimal` write can still say how many rows it touched.
+pub fn select_rowcount(sql: &str) -> String {
+    format!(
+        "{};\nSELECT @@ROWCOUNT AS [{}];",
+        sql.trim_end_matches(';'),
+        ROWCOUNT_COLUMN
+    )
+}
+
+/// Table variable that receives OUTPUT rows for triggered tables.
+const OUTPUT_TABLE_VAR: &str = "@lazypaw_out";
+
+/// For triggered tables, declare the OUTPUT table variable before the DML
+/// and select from it afterwards so the rows still come back.
+fn wrap_output_into(table: &TableInfo, returning: bool, sql: String) -> String {
+    if !returning || !table.has_triggers {
+        return sql;
+    }
+    declare_output(table, sql)
+}
+
+/// Declare the OUTPUT table variable around `sql` and read it back.
+fn declare_output(table: &TableInfo, sql: String) -> String {
+    let decls: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| format!("[{}] {} NULL", escape_ident(&c.name), c.type_decl()))
+        .collect();
+    format!(
+        "DECLARE {} TABLE ({});\n{};\nSELECT * FROM {};",
+        OUTPUT_TABLE_VAR,
+        decls.join(", "),
+        sql.trim_end_matches(';'),
+        OUTPUT_TABLE_VAR
+    )
+}
+
+/// Run an INSERT with `IDENTITY_INSERT` on, switching it back off even when
+/// the insert fails — it's a session setting and would otherwise leak to
+/// the next request on the pooled connection.
+fn with_identity_insert(table: &TableInfo, sql: &str) -> String {
+    let name = table.full_name();
+    format!(
+        "SET IDENTITY_INSERT {name} ON;\n\
+         BEGIN TRY\n{};\nEND TRY\n\
+         BEGIN CATCH\nSET IDENTITY_INSERT {name} OFF;\nTHROW;\nEND CATCH;\n\
+         SET IDENTITY_INSERT {name} OFF;",
+        sql.trim_end_matches(';'),
+    )
+}
+
+/// Alias the rows a mutation wrote are read back under by
+/// [`project_returning`]; embeds correlate against it.
+pub const RETURNED_ALIAS: &str = "[__returned]";
+
+/// Re-shape the rows a mutation returns by `select=`, embeds included. The
+/// OUTPUT is captured in the output table variable and read back through
+/// the select list. Embed parameters are numbered after the mutation's
+/// `param_count` and returned for the caller to bind after its own.
+pub fn project_returning(
+    table: &TableInfo,
+    sql: &str,
+    param_count: usize,
+    select_nodes: &[SelectNode],
+    embeds: &[EmbedQuery],
+) -> Result<BuiltQuery, Error> {
+    let read_back = format!("SELECT * FROM {};", OUTPUT_TABLE_VAR);
+    let sql = if sql.contains(&read_back) {
+        sql.to_string()
+    } else {
+        // Plain OUTPUT streams rows straight back; capture it instead
+        let output = ["inserted", "deleted"]
+            .iter()
+            .map(|prefix| plain_output_clause(table, prefix))
+            .find(|output| sql.contains(output.as_str()))
+            .ok_or_else(|| Error::Internal("Mutation returns no rows".to_string()))?;
+        let captured = sql.replacen(&output, &format!("{} INTO {}", output, OUTPUT_TABLE_VAR), 1);
+        declare_output(table, captured)
+    };
+
+    let mut params: Vec<SqlParam> = vec![SqlParam::Null; param_count];
+    let mut applies = String::new();
+    let mut columns = add_embed_columns(
+        RETURNED_ALIAS,
+        build_column_list(table, select_nodes),
+        embeds,
+        &mut applies,
+        &mut params,
+    )?;
+    // PUT reports whether it inserted or replaced through this column
+    if sql.contains("[__action]") {
+        columns = format!("[__action], {}", columns);
+    }
+    let projected = format!(
+        "SELECT {} FROM {} AS {}{};",
+        columns, OUTPUT_TABLE_VAR, RETURNED_ALIAS, applies
+    );
+    let Some(pos) = sql.rfind(&read_back) else {
+        return Err(Error::Internal("Mutation returns no rows".to_string()));
+    };
+    Ok(BuiltQuery {
+        sql: format!(
+            "{}{}{}",
+            &sql[..pos],
+            projected,
+            &sql[pos + read_back.len()..]
+        ),
+        params: params.split_off(param_count),
+    })
+}
+
+/// Column holding the page serialized by [`into_json_array`].
+pub const JSON_COLUMN: &str = "json";
+
+/// Have SQL Server serialize a read, embeds included, into one JSON array
+/// (`[]` when nothing matched) in a single `[json]` value. Wrapping the
+/// `FOR JSON` in a subquery keeps it from being split across rows.
+pub fn into_json_array(built: &mut BuiltQuery) {
+    built.sql = format!(
+        "SELECT ISNULL(({} FOR JSON PATH, INCLUDE_NULL_VALUES), '[]') AS [{}]",
+        built.sql, JSON_COLUMN
     );
+}
 
-    if !filters.is_empty() {
-        let where_clause = build_where_clause(filters, &mut params)?;
-        if !where_clause.is_empty() {
-            sql.push_str(" WHERE ");
-            sql.push_str(&where_clause);
-        }
+/// Column carrying a read's exact total, see [`with_total_count`].
+pub const TOTAL_COLUMN: &str = "__lazypaw_total";
+
+/// Put the exact filtered total of a read in front of its columns as
+/// `[__lazypaw_total]`, so `count=exact` shares the page's round trip
+/// instead of running a statement of its own. The count is an uncorrelated
+/// subquery, evaluated once; an empty page carries no total.
+///
+/// `false`, leaving the query alone, when the count can't ride along: the
+/// query isn't a plain SELECT, or already has a column by that name. The
+/// caller counts separately then.
+pub fn with_total_count(
+    built: &mut BuiltQuery,
+    table: &TableInfo,
+    filters: &[FilterNode],
+) -> Result<bool, Error> {
+    let Some(rest) = built.sql.strip_prefix("SELECT ") else {
+        return Ok(false);
+    };
+    if rest.to_lowercase().contains(&format!("[{}]", TOTAL_COLUMN)) {
+        return Ok(false);
     }
+    let rest = rest.to_string();
+    let mut count = format!("SELECT COUNT(*) FROM {}", table.full_name());
+    let where_clause = build_where_clause(Some(table), filters, &mut built.params)?;
+    if !where_clause.is_empty() {
+        count.push_str(" WHERE ");
+        count.push_str(&where_clause);
+    }
+    built.sql = format!("SELECT ({}) AS [{}], {}", count, TOTAL_COLUMN, rest);
+    Ok(true)
+}
 
-    Ok(BuiltQuery { sql, params })
+/// Append an `OPTION (MAXDOP n)` hint to a built query.
+pub fn apply_maxdop(built: &mut BuiltQuery, maxdop: Option<u32>) {
+    if let Some(n) = maxdop {
+        built.sql.push_str(&format!(" OPTION (MAXDOP {})", n));
+    }
 }
 
 /// Build the column list for SELECT from select nodes.
-fn build_column_list(table: &TableInfo, nodes: &[SelectNode]) -> String {
-    if nodes.is_empty() || select::has_star(nodes) {
+pub fn build_column_list(table: &TableInfo, nodes: &[SelectNode]) -> String {
+    let star = nodes.is_empty() || select::has_star(nodes);
+    let mut cols: Vec<String> = Vec::new();
+    if star {
         // Select all columns from the table (excluding embeds which are handled separately)
-        let explicit_cols = select::select_columns(nodes);
-        if explicit_cols.is_empty() {
-            return table
+        cols.extend(
+            table
                 .columns
                 .iter()
-                .map(|c| format!("[{}]", escape_ident(&c.name)))
-                .collect::<Vec<_>>()
-                .join(", ");
-        }
-        // Star + explicit columns
-        let mut cols: Vec<String> = table
-            .columns
-            .iter()
-            .map(|c| format!("[{}]", escape_ident(&c.name)))
-            .collect();
-        for col in explicit_cols {
-            if !table
-                .columns
-                .iter()
-                .any(|c| c.name.eq_ignore_ascii_case(col))
-            {
+                .map(|c| format!("[{}]", escape_ident(&c.name))),
+        );
+    }
+    for node in nodes {
+        match node {
+            SelectNode::Column(col) => {
+                if star
+                    && table
+                        .columns
+                        .iter()
+                        .any(|c| c.name.eq_ignore_ascii_case(col))
+                {
+                    continue;
+                }
                 cols.push(format!("[{}]", escape_ident(col)));
             }
+            SelectNode::Field(field) => {
+                let mut expr = match &field.json_path {
+                    Some(path) => json_path_expr(path),
+                    None => format!("[{}]", escape_ident(&field.column)),
+                };
+                if let Some(ty) = &field.cast {
+                    expr = format!("CAST({} AS {})", expr, ty);
+                }
+                cols.push(format!("{} AS [{}]", expr, escape_ident(&field.alias)));
+            }
+            SelectNode::Star | SelectNode::Embed(_) => {}
+        }
+    }
+    if cols.is_empty() {
+        "*".to_string()
+    } else {
+        cols.join(", ")
+    }
+}
+
+/// SQL for a JSON path: `JSON_VALUE` for `->>` (scalar text), `JSON_QUERY`
+/// for `->` (object/array fragment).
+pub fn json_path_expr(path: &select::JsonPath) -> String {
+    let mut json_path = String::from("$");
+    for key in &path.keys {
+        if key.chars().all(|c| c.is_ascii_digit()) {
+            json_path.push_str(&format!("[{}]", key));
+        } else if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            json_path.push('.');
+            json_path.push_str(key);
+        } else {
+            json_path.push_str(&format!(".\"{}\"", key));
         }
-        cols.join(", ")
+    }
+    let func = if path.as_text {
+        "JSON_VALUE"
     } else {
-        let cols = select::select_columns(nodes);
-        if cols.is_empty() {
-            "*".to_string()
-        } else {
-            cols.iter()
-                .map(|c| format!("[{}]", escape_ident(c)))
-                .collect::<Vec<_>>()
-                .join(", ")
+        "JSON_QUERY"
+    };
+    format!(
+        "{}([{}], '{}')",
+        func,
+        escape_ident(&path.column),
+        json_path
+    )
+}
+
+/// SQL for a filter column: a bracketed identifier, or `JSON_VALUE` when the
+/// column is a JSON path (filters always compare scalar text).
+fn filter_column_expr(column: &str) -> Result<String, Error> {
+    match select::parse_json_path(column)? {
+        Some(mut path) => {
+            path.as_text = true;
+            Ok(json_path_expr(&path))
         }
+        None => Ok(format!("[{}]", escape_ident(column))),
     }
 }
 
 /// Build WHERE clause from filter nodes.
-fn build_where_clause(filters: &[FilterNode], params: &mut Vec<String>) -> Result<String, Error> {
-    build_where_clause_with_offset(filters, params, 0)
+/// `table` types the parameters; without it they bind as text.
+fn build_where_clause(
+    table: Option<&TableInfo>,
+    filters: &[FilterNode],
+    params: &mut Vec<SqlParam>,
+) -> Result<String, Error> {
+    build_where_clause_with_offset(table, filters, params, 0)
 }
 
 /// Build WHERE clause from filter nodes with a parameter index offset.
-fn build_where_clause_with_offset(
+pub fn build_where_clause_with_offset(
+    table: Option<&TableInfo>,
     filters: &[FilterNode],
-    params: &mut Vec<String>,
+    params: &mut Vec<SqlParam>,
     offset: usize,
 ) -> Result<String, Error> {
     let mut parts = Vec::new();
 
     for node in filters {
-        let clause = build_filter_node(node, params, offset)?;
+        let clause = build_filter_node(table, node, params, offset)?;
         if !clause.is_empty() {
             parts.push(clause);
         }
@@ -455,16 +1996,17 @@ fn build_where_clause_with_offset(
 
 /// Build SQL from a single filter node.
 fn build_filter_node(
+    table: Option<&TableInfo>,
     node: &FilterNode,
-    params: &mut Vec<String>,
+    params: &mut Vec<SqlParam>,
     offset: usize,
 ) -> Result<String, Error> {
     match node {
-        FilterNode::Condition(filter) => build_single_filter(filter, params, offset),
+        FilterNode::Condition(filter) => build_single_filter(table, filter, params, offset),
         FilterNode::And(nodes) => {
             let parts: Result<Vec<String>, _> = nodes
                 .iter()
-                .map(|n| build_filter_node(n, params, offset))
+                .map(|n| build_filter_node(table, n, params, offset))
                 .collect();
             let parts = parts?;
             let non_empty: Vec<_> = parts.into_iter().filter(|p| !p.is_empty()).collect();
@@ -477,7 +2019,7 @@ fn build_filter_node(
         FilterNode::Or(nodes) => {
             let parts: Result<Vec<String>, _> = nodes
                 .iter()
-                .map(|n| build_filter_node(n, params, offset))
+                .map(|n| build_filter_node(table, n, params, offset))
                 .collect();
             let parts = parts?;
             let non_empty: Vec<_> = parts.into_iter().filter(|p| !p.is_empty()).collect();
@@ -487,70 +2029,195 @@ fn build_filter_node(
                 Ok(format!("({})", non_empty.join(" OR ")))
             }
         }
+        FilterNode::Not(inner) => {
+            let sql = build_filter_node(table, inner, params, offset)?;
+            if sql.is_empty() {
+                Ok(sql)
+            } else {
+                Ok(format!("NOT {}", sql))
+            }
+        }
+        FilterNode::Exists(exists) => {
+            let alias = format!("[{}]", escape_ident(&exists.alias));
+            let join: Vec<String> = exists
+                .join
+                .iter()
+                .map(|(column, parent)| {
+                    format!("{}.[{}] = {}", alias, escape_ident(column), parent)
+                })
+                .collect();
+            let mut sql = format!(
+                "EXISTS (SELECT 1 FROM {} AS {} WHERE {}",
+                exists.table,
+                alias,
+                join.join(" AND ")
+            );
+            // The embedded table's columns aren't known here: bind as text
+            let inner = build_where_clause_with_offset(None, &exists.filters, params, offset)?;
+            if !inner.is_empty() {
+                sql.push_str(" AND ");
+                sql.push_str(&inner);
+            }
+            sql.push(')');
+            Ok(sql)
+        }
+        FilterNode::Distinct(distinct) => {
+            let ident = |c: &String| format!("[{}]", escape_ident(c));
+            let key: Vec<String> = distinct.key.iter().map(ident).collect();
+            let partition: Vec<String> = distinct.partition.iter().map(ident).collect();
+            let mut ranked = format!(
+                "SELECT {}, ROW_NUMBER() OVER (PARTITION BY {} ORDER BY {}) AS [__rank] FROM {}",
+                key.join(", "),
+                partition.join(", "),
+                distinct.order,
+                distinct.table
+            );
+            let inner = build_where_clause_with_offset(table, &distinct.filters, params, offset)?;
+            if !inner.is_empty() {
+                ranked.push_str(" WHERE ");
+                ranked.push_str(&inner);
+            }
+            let matches: Vec<String> = distinct
+                .key
+                .iter()
+                .map(|c| {
+                    let c = escape_ident(c);
+                    format!("[ranked].[{}] = {}.[{}]", c, distinct.table, c)
+                })
+                .collect();
+            Ok(format!(
+                "EXISTS (SELECT 1 FROM ({}) AS [ranked] WHERE [ranked].[__rank] = 1 AND {})",
+                ranked,
+                matches.join(" AND ")
+            ))
+        }
+    }
+}
+
+/// Collations for case-sensitive (`like`, `match`) and case-insensitive
+/// (`ilike`, `imatch`) filters on columns whose own collation isn't known.
+const CASE_SENSITIVE_COLLATION: &str = "Latin1_General_100_CS_AS";
+const CASE_INSENSITIVE_COLLATION: &str = "Latin1_General_100_CI_AS";
+
+/// ` COLLATE ...` clause giving a pattern filter on `column` the requested
+/// case sensitivity. Empty when the column's collation already compares
+/// that way (so the filter can still seek) or when it has none (non-text);
+/// otherwise its `_CS`/`_CI` sibling, keeping code page and accent rules.
+/// Columns not in the schema (JSON paths, embedded tables) and binary
+/// collations fall back to Latin1_General.
+fn case_collation(column: Option<&ColumnInfo>, case_sensitive: bool) -> String {
+    let (from, to, fallback) = if case_sensitive {
+        ("CI", "CS", CASE_SENSITIVE_COLLATION)
+    } else {
+        ("CS", "CI", CASE_INSENSITIVE_COLLATION)
+    };
+    let collation = match column {
+        Some(c) => match &c.collation {
+            Some(collation) => collation,
+            None => return String::new(),
+        },
+        None => return format!(" COLLATE {}", fallback),
+    };
+
+    let tokens: Vec<&str> = collation.split('_').collect();
+    let has = |token: &str| tokens.iter().any(|t| t.eq_ignore_ascii_case(token));
+    let already = if case_sensitive {
+        has("CS") || has("BIN") || has("BIN2")
+    } else {
+        has("CI")
+    };
+    if already {
+        return String::new();
+    }
+    let valid = collation
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid || !has(from) {
+        return format!(" COLLATE {}", fallback);
+    }
+    let flipped: Vec<&str> = tokens
+        .iter()
+        .map(|t| if t.eq_ignore_ascii_case(from) { to } else { t })
+        .collect();
+    format!(" COLLATE {}", flipped.join("_"))
+}
+
+/// Placeholder for a filter value compared with `column`. Text is sent as
+/// nvarchar, which against a `char`/`varchar` column converts the column
+/// side (a scan instead of a seek), so text that reads the same in every
+/// code page (ASCII, at most 8000 bytes) is cast to varchar on the
+/// parameter side. Other text stays nvarchar: a code page would turn what
+/// it can't represent into `?` and match the wrong rows.
+fn placeholder(column: Option<&ColumnInfo>, param: &SqlParam, idx: usize) -> String {
+    let narrow =
+        column.is_some_and(|c| matches!(c.data_type.to_lowercase().as_str(), "char" | "varchar"));
+    match param {
+        SqlParam::Text(s) if narrow && s.is_ascii() && s.len() <= 8000 => {
+            format!("CAST(@P{} AS varchar(8000))", idx)
+        }
+        _ => format!("@P{}", idx),
     }
 }
 
 /// Build SQL for a single filter condition.
 fn build_single_filter(
+    table: Option<&TableInfo>,
     filter: &Filter,
-    params: &mut Vec<String>,
+    params: &mut Vec<SqlParam>,
     offset: usize,
 ) -> Result<String, Error> {
-    let col = format!("[{}]", escape_ident(&filter.column));
+    let col = filter_column_expr(&filter.column)?;
     let not_prefix = if filter.negated { "NOT " } else { "" };
+    // Comparisons bind in the column's type; patterns, full-text terms and
+    // JSON paths (no such column) stay text
+    let column = table.and_then(|t| t.column(&filter.column));
+    let data_type = column.map(|c| c.data_type.as_str());
+    let typed = |v: &str| SqlParam::typed(data_type, v);
+    let bind = |params: &mut Vec<SqlParam>, param: SqlParam| {
+        let placeholder = placeholder(column, &param, params.len() + 1 + offset);
+        params.push(param);
+        placeholder
+    };
 
-    match &filter.operator {
-        FilterOp::Eq => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} = @P{})", not_prefix, col, idx))
-        }
-        FilterOp::Neq => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} <> @P{})", not_prefix, col, idx))
-        }
-        FilterOp::Gt => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} > @P{})", not_prefix, col, idx))
-        }
-        FilterOp::Gte => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} >= @P{})", not_prefix, col, idx))
-        }
-        FilterOp::Lt => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} < @P{})", not_prefix, col, idx))
-        }
-        FilterOp::Lte => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} <= @P{})", not_prefix, col, idx))
+    // op(any)/op(all): expand to one condition per value, OR'd or AND'd
+    if let FilterValue::Quantified(quantifier, items) = &filter.value {
+        let joiner = match quantifier {
+            Quantifier::Any => " OR ",
+            Quantifier::All => " AND ",
+        };
+        let mut parts = Vec::with_capacity(items.len());
+        for item in items {
+            let single = Filter {
+                column: filter.column.clone(),
+                operator: filter.operator.clone(),
+                value: FilterValue::Single(item.clone()),
+                negated: false,
+            };
+            parts.push(build_single_filter(table, &single, params, offset)?);
         }
-        FilterOp::Like => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            Ok(format!("{}({} LIKE @P{})", not_prefix, col, idx))
+        return Ok(format!("{}({})", not_prefix, parts.join(joiner)));
+    }
+
+    if let Some(comparison) = filter.operator.comparison() {
+        let p = bind(params, typed(&filter_value_single(&filter.value)?));
+        return Ok(format!("{}({} {} {})", not_prefix, col, comparison.sql, p));
+    }
+
+    match &filter.operator {
+        FilterOp::Ieq => {
+            let p = bind(params, typed(&filter_value_single(&filter.value)?));
+            let collate = case_collation(column, false);
+            Ok(format!("{}({}{} = {})", not_prefix, col, collate, p))
         }
-        FilterOp::Ilike => {
-            params.push(filter_value_single(&filter.value)?);
-            let idx = params.len() + offset;
-            // SQL Server LIKE is case-insensitive by default with most collations
-            Ok(format!("{}({} LIKE @P{})", not_prefix, col, idx))
+        FilterOp::Like | FilterOp::Ilike => {
+            let p = bind(params, SqlParam::Text(filter_value_single(&filter.value)?));
+            let collate = case_collation(column, matches!(filter.operator, FilterOp::Like));
+            Ok(format!("{}({}{} LIKE {})", not_prefix, col, collate, p))
         }
         FilterOp::In => {
             if let FilterValue::List(items) = &filter.value {
-                let placeholders: Vec<String> = items
-                    .iter()
-                    .map(|item| {
-                        params.push(item.clone());
-                        let idx = params.len() + offset;
-                        format!("@P{}", idx)
-                    })
-                    .collect();
+                let placeholders: Vec<String> =
+                    items.iter().map(|item| bind(params, typed(item))).collect();
                 Ok(format!(
                     "{}({} IN ({}))",
                     not_prefix,
@@ -561,6 +2228,25 @@ fn build_single_filter(
                 Err(Error::BadRequest("IN requires a list value".to_string()))
             }
         }
+        FilterOp::Match | FilterOp::Imatch => {
+            let p = bind(params, SqlParam::Text(filter_value_single(&filter.value)?));
+            let collate = case_collation(column, matches!(filter.operator, FilterOp::Match));
+            Ok(format!(
+                "{}(PATINDEX({}, {}{}) > 0)",
+                not_prefix, p, col, collate
+            ))
+        }
+        FilterOp::Between => match &filter.value {
+            FilterValue::List(items) if items.len() == 2 => {
+                let low = bind(params, typed(&items[0]));
+                let high = bind(params, typed(&items[1]));
+                Ok(format!(
+                    "{}({} BETWEEN {} AND {})",
+                    not_prefix, col, low, high
+                ))
+            }
+            _ => Err(Error::BadRequest("BETWEEN requires two values".to_string())),
+        },
         FilterOp::Is => {
             let val = filter_value_single(&filter.value)?;
             match val.to_lowercase().as_str() {
@@ -591,12 +2277,110 @@ fn build_single_filter(
                 ))),
             }
         }
-        FilterOp::Fts => {
-            params.push(filter_value_single(&filter.value)?);
+        FilterOp::Fts(language) => {
+            params.push(SqlParam::Text(filter_value_single(&filter.value)?));
             let idx = params.len() + offset;
-            Ok(format!("{}CONTAINS({}, @P{})", not_prefix, col, idx))
+            Ok(format!(
+                "{}CONTAINS({}, @P{}{})",
+                not_prefix,
+                col,
+                idx,
+                language_term(language)
+            ))
         }
+        FilterOp::Plfts(language) => {
+            params.push(SqlParam::Text(filter_value_single(&filter.value)?));
+            let idx = params.len() + offset;
+            Ok(format!(
+                "{}FREETEXT({}, @P{}{})",
+                not_prefix,
+                col,
+                idx,
+                language_term(language)
+            ))
+        }
+        FilterOp::Wfts(language) => {
+            params.push(SqlParam::Text(weighted_search_condition(
+                &filter_value_single(&filter.value)?,
+            )?));
+            let idx = params.len() + offset;
+            Ok(format!(
+                "{}CONTAINS({}, @P{}{})",
+                not_prefix,
+                col,
+                idx,
+                language_term(language)
+            ))
+        }
+        FilterOp::Custom(name) => {
+            let operator = crate::filters::custom_operator(name)
+                .ok_or_else(|| Error::BadRequest(format!("Unknown filter operator: {}", name)))?;
+            let values = match &filter.value {
+                FilterValue::Single(v) => std::slice::from_ref(v),
+                FilterValue::List(items) | FilterValue::Quantified(_, items) => items.as_slice(),
+            };
+            let placeholders: Vec<String> = values
+                .iter()
+                .map(|v| bind(params, SqlParam::Text(v.clone())))
+                .collect();
+            Ok(format!(
+                "{}({})",
+                not_prefix,
+                operator.render(&col, &placeholders)
+            ))
+        }
+        // Plain comparisons are built above from their row in the operator
+        // table
+        op => Err(Error::Internal(format!(
+            "No SQL for filter operator {:?}",
+            op
+        ))),
+    }
+}
+
+/// `, LANGUAGE N'<lang>'` for full-text predicates (validated by the parser),
+/// or an LCID as a bare number.
+fn language_term(language: &Option<String>) -> String {
+    match language {
+        Some(lang) if lang.chars().all(|c| c.is_ascii_digit()) => format!(", LANGUAGE {}", lang),
+        Some(lang) => format!(", LANGUAGE N'{}'", lang),
+        None => String::new(),
+    }
+}
+
+/// Turn `term:weight term ...` into an ISABOUT search condition, e.g.
+/// `rust:0.8 async` → `ISABOUT ("rust" WEIGHT (0.8), "async")`.
+fn weighted_search_condition(value: &str) -> Result<String, Error> {
+    let mut terms = Vec::new();
+    for part in value.split_whitespace() {
+        let (term, weight) = match part.rsplit_once(':') {
+            Some((term, w)) => {
+                let weight: f64 = w
+                    .parse()
+                    .ok()
+                    .filter(|w| (0.0..=1.0).contains(w))
+                    .ok_or_else(|| {
+                        Error::BadRequest(format!("Weight must be between 0 and 1: {}", part))
+                    })?;
+                (term, Some(weight))
+            }
+            None => (part, None),
+        };
+        let term = term.replace('"', "");
+        if term.is_empty() {
+            continue;
+        }
+        terms.push(match weight {
+            Some(w) => format!("\"{}\" WEIGHT ({})", term, w),
+            None => format!("\"{}\"", term),
+        });
+    }
+    if terms.is_empty() {
+        return Err(Error::BadRequest(
+            "wfts requires at least one term".to_string(),
+        ));
     }
+    Ok(format!("ISABOUT ({})", terms.join(", ")))
 }
 
 /// Extract a single string value from a FilterValue.
@@ -612,7 +2396,29 @@ fn filter_value_single(val: &FilterValue) -> Result<String, Error> {
                 ))
             }
         }
+        FilterValue::Quantified(..) => Err(Error::BadRequest(
+            "Expected single value, got any/all list".to_string(),
+        )),
+    }
+}
+
+/// Structured comment attributing a statement to an API request,
+/// e.g. `/* lazypaw route=/orders method=GET req=abc123 */`.
+pub fn sql_comment(method: &str, route: &str, request_id: Option<&str>) -> String {
+    // Only identifier-ish characters survive, so the comment can't be closed early
+    let clean = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '/' | '_' | '-' | '.'))
+            .take(128)
+            .collect()
+    };
+    let mut tag = format!("/* lazypaw route={} method={}", clean(route), clean(method));
+    if let Some(id) = request_id {
+        tag.push_str(" req=");
+        tag.push_str(&clean(id));
     }
+    tag.push_str(" */");
+    tag
 }
 
 /// Escape a SQL Server identifier (remove brackets and re-wrap).
@@ -634,4 +2440,897 @@ mod tests {
         assert!(matches!(specs[1].direction, OrderDir::Desc));
         assert!(matches!(specs[1].nulls, Some(NullsOrder::First)));
     }
+
+    #[test]
+    fn test_total_count() {
+        let table = upsert_table();
+        let filters = vec![FilterNode::Condition(
+            crate::filters::parse_filter("name", "eq.bob").unwrap(),
+        )];
+        let mut built =
+            build_select(&table, &[], &[], &filters, &[], Some(10), None, None, false).unwrap();
+        assert!(with_total_count(&mut built, &table, &filters).unwrap());
+        assert_eq!(
+            built.sql,
+            "SELECT (SELECT COUNT(*) FROM [dbo].[users] WHERE ([name] = @P2)) \
+             AS [__lazypaw_total], [id], [name], [email] FROM [dbo].[users] \
+             WHERE ([name] = @P1) ORDER BY [id] ASC OFFSET 0 ROWS FETCH NEXT 10 ROWS ONLY"
+        );
+        assert_eq!(built.params, text_params(&["bob", "bob"]));
+    }
+
+    #[test]
+    fn test_total_count_not_inlined() {
+        let table = upsert_table();
+        let mut built = BuiltQuery {
+            sql: "WITH [t] AS (SELECT 1 AS [x]) SELECT [x] FROM [t]".to_string(),
+            params: vec![],
+        };
+        assert!(!with_total_count(&mut built, &table, &[]).unwrap());
+        assert!(built.sql.starts_with("WITH"));
+
+        let mut built = BuiltQuery {
+            sql: "SELECT [id] AS [__lazypaw_total] FROM [dbo].[users]".to_string(),
+            params: vec![],
+        };
+        assert!(!with_total_count(&mut built, &table, &[]).unwrap());
+        assert_eq!(
+            built.sql,
+            "SELECT [id] AS [__lazypaw_total] FROM [dbo].[users]"
+        );
+    }
+
+    #[test]
+    fn test_json_array() {
+        let table = upsert_table();
+        let mut built = build_select(&table, &[], &[], &[], &[], None, None, None, false).unwrap();
+        into_json_array(&mut built);
+        assert_eq!(
+            built.sql,
+            "SELECT ISNULL((SELECT [id], [name], [email] FROM [dbo].[users] \
+             FOR JSON PATH, INCLUDE_NULL_VALUES), '[]') AS [json]"
+        );
+    }
+
+    #[test]
+    fn test_rpc_select() {
+        let table = upsert_table();
+        let filters = vec![FilterNode::Condition(
+            crate::filters::parse_filter("name", "eq.bob").unwrap(),
+        )];
+        let built = build_rpc_select(
+            "#rpc_result",
+            &table,
+            &filters,
+            &parse_order("email.desc").unwrap(),
+            Some(10),
+            Some(20),
+            2,
+        )
+        .unwrap();
+        assert_eq!(
+            built.sql,
+            "SELECT * FROM #rpc_result AS [rpc] WHERE ([name] = @P3) \
+             ORDER BY [email] DESC OFFSET 20 ROWS FETCH NEXT 10 ROWS ONLY"
+        );
+        assert_eq!(built.params, text_params(&["bob"]));
+    }
+
+    #[test]
+    fn test_order_expressions() {
+        let order = |s: &str| build_order_list(&parse_order(s).unwrap());
+        assert_eq!(
+            order("data->>score.desc"),
+            "JSON_VALUE([data], '$.score') DESC"
+        );
+        assert_eq!(
+            order("amount::decimal(10,2).asc,code::int"),
+            "CAST([amount] AS decimal(10,2)) ASC, CAST([code] AS int) ASC"
+        );
+        assert_eq!(
+            order("data->rank::int.asc.nullslast"),
+            "CASE WHEN CAST(JSON_VALUE([data], '$.rank') AS int) IS NULL THEN 1 ELSE 0 END, \
+             CAST(JSON_VALUE([data], '$.rank') AS int) ASC"
+        );
+        // Already SQL Server's default placement: no CASE key
+        assert_eq!(order("age.asc.nullsfirst"), "[age] ASC");
+        assert_eq!(order("age.desc.nullslast"), "[age] DESC");
+        assert_eq!(
+            order("age.desc.nullsfirst"),
+            "CASE WHEN [age] IS NULL THEN 0 ELSE 1 END, [age] DESC"
+        );
+        assert_eq!(parse_order("data->>score").unwrap()[0].column, "data");
+        assert!(parse_order("code::blob").is_err());
+        assert_eq!(
+            order(r#""Order.Date".desc,"Unit]Price""#),
+            "[Order.Date] DESC, [Unit]]Price] ASC"
+        );
+    }
+
+    #[test]
+    fn test_exists_filter() {
+        let node = FilterNode::Exists(crate::filters::ExistsFilter {
+            table: "[dbo].[orders]".to_string(),
+            alias: "x1".to_string(),
+            join: vec![("user_id".to_string(), "[dbo].[users].[id]".to_string())],
+            filters: vec![FilterNode::Condition(
+                crate::filters::parse_filter("status", "eq.active").unwrap(),
+            )],
+        });
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "EXISTS (SELECT 1 FROM [dbo].[orders] AS [x1] WHERE [x1].[user_id] = \
+             [dbo].[users].[id] AND ([status] = @P1))"
+        );
+        assert_eq!(params, text_params(&["active"]));
+
+        // A self-referencing embed compares the aliased rows with the parent
+        let node = FilterNode::Exists(crate::filters::ExistsFilter {
+            table: "[dbo].[users]".to_string(),
+            alias: "x2".to_string(),
+            join: vec![("manager_id".to_string(), "[dbo].[users].[id]".to_string())],
+            filters: Vec::new(),
+        });
+        let sql = build_where_clause(None, &[node], &mut Vec::new()).unwrap();
+        assert_eq!(
+            sql,
+            "EXISTS (SELECT 1 FROM [dbo].[users] AS [x2] WHERE [x2].[manager_id] = \
+             [dbo].[users].[id])"
+        );
+    }
+
+    #[test]
+    fn test_distinct_on_filter() {
+        let table = upsert_table();
+        let filters = vec![FilterNode::Condition(
+            crate::filters::parse_filter("name", "eq.ann").unwrap(),
+        )];
+        let order = parse_order("email.desc").unwrap();
+        let node = distinct_on_filter(&table, "name", &order, filters).unwrap();
+        let mut params = Vec::new();
+        let sql = build_where_clause(Some(&table), &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "EXISTS (SELECT 1 FROM (SELECT [id], ROW_NUMBER() OVER (PARTITION BY [name] \
+             ORDER BY [email] DESC) AS [__rank] FROM [dbo].[users] WHERE ([name] = @P1)) \
+             AS [ranked] WHERE [ranked].[__rank] = 1 AND [ranked].[id] = [dbo].[users].[id])"
+        );
+        assert_eq!(params, text_params(&["ann"]));
+        assert!(distinct_on_filter(&table, "nope", &[], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_resume_after_filter() {
+        let table = upsert_table();
+        let (node, order) = resume_after_filter(&table, &[], "42").unwrap();
+        assert_eq!(order.len(), 1);
+        let mut params = Vec::new();
+        let sql = build_where_clause(Some(&table), &[node], &mut params).unwrap();
+        assert_eq!(sql, "((([id] > @P1)))");
+        assert_eq!(params, text_params(&["42"]));
+
+        let order = parse_order("name.asc,id.desc").unwrap();
+        let (node, _) = resume_after_filter(&table, &order, "ann,7").unwrap();
+        let mut params = Vec::new();
+        let sql = build_where_clause(Some(&table), &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "((([name] > @P1)) OR (([name] = @P2) AND ([id] < @P3)))"
+        );
+        assert_eq!(params, text_params(&["ann", "ann", "7"]));
+        assert!(resume_after_filter(&table, &order, "ann").is_err());
+    }
+
+    #[test]
+    fn test_quantified_filter() {
+        let node = FilterNode::Condition(
+            crate::filters::parse_filter("name", "like(any).{a*,*b}").unwrap(),
+        );
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "(([name] COLLATE Latin1_General_100_CS_AS LIKE @P1) OR \
+             ([name] COLLATE Latin1_General_100_CS_AS LIKE @P2))"
+        );
+        assert_eq!(params, text_params(&["a%", "%b"]));
+    }
+
+    #[test]
+    fn test_case_collation() {
+        let mut table = upsert_table();
+        let filter = |table: &TableInfo, op: &str| {
+            let node = FilterNode::Condition(crate::filters::parse_filter("name", op).unwrap());
+            build_where_clause(Some(table), &[node], &mut Vec::new()).unwrap()
+        };
+        // CI column: like flips to the CS sibling, ilike keeps the column's
+        assert_eq!(
+            filter(&table, "like.a*"),
+            "([name] COLLATE SQL_Latin1_General_CP1_CS_AS LIKE @P1)"
+        );
+        assert_eq!(filter(&table, "ilike.a*"), "([name] LIKE @P1)");
+        assert_eq!(filter(&table, "eq.a"), "([name] = @P1)");
+
+        table.columns[1].collation = Some("Latin1_General_100_CS_AS_SC_UTF8".to_string());
+        assert_eq!(filter(&table, "like.a*"), "([name] LIKE @P1)");
+        assert_eq!(
+            filter(&table, "ilike.a*"),
+            "([name] COLLATE Latin1_General_100_CI_AS_SC_UTF8 LIKE @P1)"
+        );
+        // Case-insensitive equality on a CS column
+        let node = FilterNode::Condition(Filter {
+            column: "name".to_string(),
+            operator: FilterOp::Ieq,
+            value: FilterValue::Quantified(Quantifier::Any, vec!["a".into(), "b".into()]),
+            negated: true,
+        });
+        assert_eq!(
+            build_where_clause(Some(&table), &[node], &mut Vec::new()).unwrap(),
+            "NOT (([name] COLLATE Latin1_General_100_CI_AS_SC_UTF8 = @P1) OR \
+             ([name] COLLATE Latin1_General_100_CI_AS_SC_UTF8 = @P2))"
+        );
+
+        table.columns[1].collation = Some("Latin1_General_BIN2".to_string());
+        assert_eq!(filter(&table, "like.a*"), "([name] LIKE @P1)");
+        assert_eq!(
+            filter(&table, "ilike.a*"),
+            "([name] COLLATE Latin1_General_100_CI_AS LIKE @P1)"
+        );
+
+        // Non-text columns have no collation to apply
+        table.columns[1].collation = None;
+        assert_eq!(filter(&table, "like.1*"), "([name] LIKE @P1)");
+    }
+
+    #[test]
+    fn test_varchar_placeholder() {
+        let mut table = upsert_table();
+        table.columns[1].data_type = "varchar".to_string();
+        let filter = |op: &str| {
+            let node = FilterNode::Condition(crate::filters::parse_filter("name", op).unwrap());
+            build_where_clause(Some(&table), &[node], &mut Vec::new()).unwrap()
+        };
+        // ASCII is cast on the parameter side so the column can seek
+        assert_eq!(filter("eq.ann"), "([name] = CAST(@P1 AS varchar(8000)))");
+        assert_eq!(
+            filter("in.(a,b)"),
+            "([name] IN (CAST(@P1 AS varchar(8000)), CAST(@P2 AS varchar(8000))))"
+        );
+        // Anything a code page could mangle stays nvarchar
+        assert_eq!(filter("eq.Zoë"), "([name] = @P1)");
+    }
+
+    #[test]
+    fn test_between_filter() {
+        let nodes =
+            crate::filters::parse_logic_group("(created.between.(2024-01-01,2024-12-31),id.eq.1)")
+                .unwrap();
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[FilterNode::Or(nodes)], &mut params).unwrap();
+        assert_eq!(sql, "(([created] BETWEEN @P1 AND @P2) OR ([id] = @P3))");
+        assert_eq!(params, text_params(&["2024-01-01", "2024-12-31", "1"]));
+        assert!(crate::filters::parse_filter("id", "between.(1)").is_err());
+    }
+
+    #[test]
+    fn test_negated_group() {
+        let node = crate::filters::parse_logic_param("not.or", "(a.eq.1,b.eq.2)")
+            .unwrap()
+            .unwrap();
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(sql, "NOT (([a] = @P1) OR ([b] = @P2))");
+    }
+
+    #[test]
+    fn test_match_filter() {
+        let node = FilterNode::Condition(
+            crate::filters::parse_filter("code", "match.^[A-Z][0-9]").unwrap(),
+        );
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "(PATINDEX(@P1, [code] COLLATE Latin1_General_100_CS_AS) > 0)"
+        );
+        assert_eq!(params, text_params(&["[A-Z][0-9]%"]));
+    }
+
+    #[test]
+    fn test_json_path_filter() {
+        let node = FilterNode::Condition(
+            crate::filters::parse_filter("data->settings->>theme", "eq.dark").unwrap(),
+        );
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(sql, "(JSON_VALUE([data], '$.settings.theme') = @P1)");
+    }
+
+    #[test]
+    fn test_custom_operator_filter() {
+        let defs = std::collections::HashMap::from([(
+            "near".to_string(),
+            "{column}.STDistance(geography::Point({0}, {1}, 4326)) <= {2}".to_string(),
+        )]);
+        crate::filters::register_operators(&defs).unwrap();
+        assert!(crate::filters::register_operators(&defs).is_err());
+        let node = FilterNode::Condition(
+            crate::filters::parse_filter("location", "not.near.(52.5,13.4,1000)").unwrap(),
+        );
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(
+            sql,
+            "NOT ([location].STDistance(geography::Point(@P1, @P2, 4326)) <= @P3)"
+        );
+        assert_eq!(params, text_params(&["52.5", "13.4", "1000"]));
+        assert!(crate::filters::parse_filter("location", "near.(52.5,13.4)").is_err());
+    }
+
+    #[test]
+    fn test_weighted_fts_filter() {
+        let node = FilterNode::Condition(
+            crate::filters::parse_filter("body", "wfts(english).rust:0.8 async").unwrap(),
+        );
+        let mut params = Vec::new();
+        let sql = build_where_clause(None, &[node], &mut params).unwrap();
+        assert_eq!(sql, "CONTAINS([body], @P1, LANGUAGE N'english')");
+        assert_eq!(
+            params,
+            text_params(&[r#"ISABOUT ("rust" WEIGHT (0.8), "async")"#])
+        );
+    }
+
+    #[test]
+    fn test_typed_params() {
+        assert_eq!(SqlParam::typed(Some("int"), "42"), SqlParam::Int(42));
+        assert_eq!(SqlParam::typed(Some("BIGINT"), "-7"), SqlParam::BigInt(-7));
+        assert_eq!(SqlParam::typed(Some("bit"), "1"), SqlParam::Bit(true));
+        assert_eq!(SqlParam::typed(Some("float"), "1.5"), SqlParam::Float(1.5));
+        assert_eq!(
+            SqlParam::typed(Some("money"), "-1234.5600"),
+            SqlParam::Decimal(rust_decimal::Decimal::new(-12345600, 4))
+        );
+        assert_eq!(
+            SqlParam::typed(Some("money"), "12,5"),
+            SqlParam::Text("12,5".to_string())
+        );
+        assert_eq!(
+            SqlParam::typed(
+                Some("uniqueidentifier"),
+                "6f9619ff-8b86-d011-b42d-00c04fc964ff"
+            ),
+            SqlParam::Guid(0x6f9619ff8b86d011b42d00c04fc964ff)
+        );
+        assert_eq!(
+            SqlParam::typed(Some("timestamp"), "00000000000007d1"),
+            SqlParam::Binary(vec![0, 0, 0, 0, 0, 0, 7, 0xd1])
+        );
+        // Unparseable values, decimals and unknown columns stay text
+        assert_eq!(
+            SqlParam::typed(Some("int"), "abc"),
+            SqlParam::Text("abc".to_string())
+        );
+        assert_eq!(
+            SqlParam::typed(Some("decimal"), "1.10"),
+            SqlParam::Text("1.10".to_string())
+        );
+        assert_eq!(
+            SqlParam::typed(None, "42"),
+            SqlParam::Text("42".to_string())
+        );
+    }
+
+    #[test]
+    fn test_typed_null() {
+        assert_eq!(SqlParam::null_for("VARBINARY"), SqlParam::NullBinary);
+        assert_eq!(SqlParam::null_for("image"), SqlParam::NullBinary);
+        assert_eq!(SqlParam::null_for("nvarchar"), SqlParam::Null);
+        assert_eq!(SqlParam::NullBinary.to_text(), None);
+    }
+
+    fn text_params(values: &[&str]) -> Vec<SqlParam> {
+        values
+            .iter()
+            .map(|v| SqlParam::Text(v.to_string()))
+            .collect()
+    }
+
+    fn upsert_table() -> TableInfo {
+        let column = |name: &str| crate::schema::ColumnInfo {
+            name: name.to_string(),
+            data_type: "nvarchar".to_string(),
+            max_length: Some(100),
+            precision: None,
+            scale: None,
+            is_nullable: true,
+            ordinal_position: 0,
+            is_identity: false,
+            has_default: false,
+            is_computed: false,
+            is_fulltext_indexed: false,
+            collation: Some("SQL_Latin1_General_CP1_CI_AS".to_string()),
+            description: None,
+        };
+        TableInfo {
+            name: "users".to_string(),
+            schema: "dbo".to_string(),
+            columns: vec![column("id"), column("name"), column("email")],
+            primary_key: vec!["id".to_string()],
+            foreign_keys: Vec::new(),
+            unique_constraints: Vec::new(),
+            is_view: false,
+            change_tracking_enabled: false,
+            has_triggers: false,
+            is_updatable: true,
+            instead_of: Default::default(),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_computed_columns_read_only() {
+        let mut table = upsert_table();
+        table.columns[2].is_computed = true;
+        let columns = vec!["name".to_string(), "email".to_string()];
+        assert!(matches!(
+            build_insert(&table, &columns, &[vec![true; columns.len()]], false),
+            Err(Error::Validation(ref p)) if p == &["email: cannot write computed column"]
+        ));
+        let set = |columns: &[String]| -> Vec<(String, UpdateOp)> {
+            columns.iter().map(|c| (c.clone(), UpdateOp::Set)).collect()
+        };
+        assert!(build_update(&table, &set(&columns), &[], None, false).is_err());
+        assert!(build_upsert(&table, &columns, &[vec![true, true]], None, false).is_err());
+        // Still filterable
+        let filter = FilterNode::Condition(crate::filters::parse_filter("email", "eq.a").unwrap());
+        assert!(build_update(&table, &set(&columns[..1]), &[filter], None, false).is_ok());
+    }
+
+    #[test]
+    fn test_limited_mutations() {
+        let table = upsert_table();
+        let filter = FilterNode::Condition(crate::filters::parse_filter("name", "eq.a").unwrap());
+        let limit = MutationLimit {
+            limit: 100,
+            order: parse_order("id.asc").unwrap(),
+        };
+        let built =
+            build_delete(&table, std::slice::from_ref(&filter), Some(&limit), false).unwrap();
+        assert!(select_rowcount(&built.sql).ends_with(";\nSELECT @@ROWCOUNT AS [__rowcount];"));
+        assert_eq!(
+            built.sql,
+            "WITH [__limited] AS (SELECT TOP (100) * FROM [dbo].[users] WHERE ([name] = @P1) \
+             ORDER BY [id] ASC) DELETE FROM [__limited]"
+        );
+
+        let assignments = vec![("email".to_string(), UpdateOp::Set)];
+        let built = build_update(&table, &assignments, &[filter], Some(&limit), false).unwrap();
+        assert_eq!(
+            built.sql,
+            "WITH [__limited] AS (SELECT TOP (100) * FROM [dbo].[users] WHERE ([name] = @P2) \
+             ORDER BY [id] ASC) UPDATE [__limited] SET [email] = @P1"
+        );
+    }
+
+    #[test]
+    fn test_update_operators() {
+        let table = upsert_table();
+        let filter = FilterNode::Condition(crate::filters::parse_filter("id", "eq.1").unwrap());
+        let assignments = vec![
+            ("name".to_string(), UpdateOp::Increment),
+            ("email".to_string(), UpdateOp::JsonSet(vec![false, true])),
+        ];
+        let built = build_update(&table, &assignments, &[filter], None, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "UPDATE [dbo].[users] SET [name] = [name] + @P1, [email] = \
+             JSON_MODIFY(JSON_MODIFY(COALESCE([email], N'{}'), @P2, @P3), @P4, JSON_QUERY(@P5)) \
+             WHERE ([id] = @P6)"
+        );
+        let append = vec![("email".to_string(), UpdateOp::JsonAppend(false))];
+        let built = build_update(&table, &append, &[], None, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "UPDATE [dbo].[users] SET [email] = JSON_MODIFY(COALESCE([email], N'[]'), 'append $', @P1)"
+        );
+    }
+
+    #[test]
+    fn test_identity_insert() {
+        let mut table = upsert_table();
+        table.columns[0].is_identity = true;
+        let columns = vec!["id".to_string(), "name".to_string()];
+        assert!(build_insert(&table, &columns, &[vec![true; columns.len()]], false).is_err());
+        let built =
+            build_identity_insert(&table, &columns, &[vec![true; columns.len()]], false).unwrap();
+        assert_eq!(
+            built.sql,
+            "SET IDENTITY_INSERT [dbo].[users] ON;\n\
+             BEGIN TRY\nINSERT INTO [dbo].[users] ([id], [name]) VALUES (@P1, @P2);\nEND TRY\n\
+             BEGIN CATCH\nSET IDENTITY_INSERT [dbo].[users] OFF;\nTHROW;\nEND CATCH;\n\
+             SET IDENTITY_INSERT [dbo].[users] OFF;"
+        );
+        // Other read-only columns are still rejected
+        table.columns[2].is_computed = true;
+        let columns = vec!["id".to_string(), "email".to_string()];
+        assert!(
+            build_identity_insert(&table, &columns, &[vec![true; columns.len()]], false).is_err()
+        );
+    }
+
+    #[test]
+    fn test_output_into_with_triggers() {
+        let mut table = upsert_table();
+        table.has_triggers = true;
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let declare = "DECLARE @lazypaw_out TABLE ([id] nvarchar(100) NULL, \
+                       [name] nvarchar(100) NULL, [email] nvarchar(100) NULL);\n";
+        let output = "OUTPUT inserted.[id], inserted.[name], inserted.[email] INTO @lazypaw_out";
+
+        let insert = build_insert(&table, &columns, &[vec![true; columns.len()]], true).unwrap();
+        assert!(insert.sql.starts_with(declare));
+        assert!(insert.sql.contains(output));
+        assert!(insert.sql.ends_with(";\nSELECT * FROM @lazypaw_out;"));
+
+        let present = vec![vec![true, true]];
+        let bulk = build_bulk_update(&table, &columns, &present, true).unwrap();
+        assert!(bulk.sql.starts_with(declare));
+        assert!(bulk.sql.contains(output));
+
+        // Without returning there's nothing to capture
+        let insert = build_insert(&table, &columns, &[vec![true; columns.len()]], false).unwrap();
+        assert!(!insert.sql.contains("@lazypaw_out"));
+    }
+
+    #[test]
+    fn test_insert_defaults() {
+        let table = upsert_table();
+        let columns = vec!["id".to_string(), "email".to_string()];
+        let present = vec![vec![true, true], vec![true, false]];
+        let built = build_insert(&table, &columns, &present, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "INSERT INTO [dbo].[users] ([id], [email]) VALUES (@P1, @P2), (@P3, DEFAULT)"
+        );
+        let built = build_insert_each(&table, &columns, &present, 0, None).unwrap();
+        assert!(built.sql.contains("VALUES (@P3, DEFAULT);"));
+    }
+
+    #[test]
+    fn test_insert_each_doomed_transaction() {
+        let table = upsert_table();
+        let columns = vec!["id".to_string()];
+        let built =
+            build_insert_each(&table, &columns, &[vec![true], vec![true]], 0, None).unwrap();
+        let sql = &built.sql;
+
+        // XACT_ABORT is off for the rows and put back after
+        let off = sql.find("SET XACT_ABORT OFF;").unwrap();
+        let first_row = sql.find("SAVE TRANSACTION lazypaw_row;").unwrap();
+        let restore = sql
+            .find("IF @lazypaw_xact_abort = 1 SET XACT_ABORT ON;")
+            .unwrap();
+        assert!(off < first_row && first_row < restore);
+        assert!(restore < sql.find("SELECT s.[__idx]").unwrap());
+
+        // A doomed transaction is rolled back and begun again, and no row
+        // after it tries a savepoint
+        assert_eq!(sql.matches("IF @lazypaw_doomed IS NULL\n").count(), 2);
+        assert_eq!(sql.matches("IF XACT_STATE() = -1\n").count(), 2);
+        assert!(sql.contains(
+            "ROLLBACK TRANSACTION;\n\
+             BEGIN TRANSACTION;\n\
+             SET @lazypaw_doomed = ERROR_MESSAGE();"
+        ));
+        assert!(sql.contains("ELSE INSERT INTO @lazypaw_status VALUES (1, @lazypaw_doomed);"));
+        assert!(sql.contains(
+            "UPDATE @lazypaw_status SET [__error] = @lazypaw_doomed WHERE [__error] IS NULL;"
+        ));
+
+        // Later chunks see the message through the session context
+        assert!(sql.contains(
+            "DECLARE @lazypaw_doomed NVARCHAR(4000) = \
+             CAST(SESSION_CONTEXT(N'lazypaw_doomed') AS NVARCHAR(4000));"
+        ));
+        assert!(sql.contains("EXEC sp_set_session_context N'lazypaw_doomed', @lazypaw_doomed;"));
+    }
+
+    #[test]
+    fn test_insert_each_chunk() {
+        let table = upsert_table();
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let present = vec![vec![true, true], vec![true, true]];
+
+        // Rows are numbered from the chunk's first index, values from @P1
+        let built = build_insert_each(&table, &columns, &present, 1000, None).unwrap();
+        assert!(built.sql.contains(
+            "INSERT INTO [dbo].[users] ([id], [name]) VALUES (@P1, @P2);\n\
+             INSERT INTO @lazypaw_status VALUES (1000, NULL);"
+        ));
+        assert!(built
+            .sql
+            .contains("VALUES (@P3, @P4);\nINSERT INTO @lazypaw_status VALUES (1001, NULL);"));
+        // return=minimal: statuses only
+        assert!(!built.sql.contains("OUTPUT"));
+        assert!(built.sql.ends_with(
+            "SELECT s.[__idx], s.[__error], @lazypaw_doomed AS [__doomed] \
+             FROM @lazypaw_status s ORDER BY s.[__idx];"
+        ));
+
+        let nodes = crate::select::parse_select("name").unwrap();
+        let built = build_insert_each(&table, &columns, &present, 0, Some((nodes.as_slice(), &[])))
+            .unwrap();
+        assert!(built.sql.contains(
+            "INSERT INTO [dbo].[users] ([id], [name]) OUTPUT 1, inserted.[id], inserted.[name], \
+             inserted.[email] INTO @lazypaw_out VALUES (@P3, @P4);"
+        ));
+        assert!(built.sql.ends_with(
+            "SELECT s.[__idx], s.[__error], @lazypaw_doomed AS [__doomed], [name] \
+             FROM @lazypaw_status s LEFT JOIN @lazypaw_out AS [__returned] \
+             ON [__returned].[__idx] = s.[__idx] ORDER BY s.[__idx];"
+        ));
+        assert!(built.params.is_empty());
+    }
+
+    #[test]
+    fn test_project_returning() {
+        let table = upsert_table();
+        let filter = FilterNode::Condition(crate::filters::parse_filter("id", "eq.1").unwrap());
+        let built = build_delete(&table, &[filter], None, true).unwrap();
+        let nodes = crate::select::parse_select("id,name").unwrap();
+        let projected = project_returning(&table, &built.sql, 1, &nodes, &[]).unwrap();
+        assert_eq!(
+            projected.sql,
+            "DECLARE @lazypaw_out TABLE ([id] nvarchar(100) NULL, [name] nvarchar(100) NULL, \
+             [email] nvarchar(100) NULL);\n\
+             DELETE FROM [dbo].[users] OUTPUT deleted.[id], deleted.[name], deleted.[email] \
+             INTO @lazypaw_out WHERE ([id] = @P1);\n\
+             SELECT [id], [name] FROM @lazypaw_out AS [__returned];"
+        );
+        assert!(projected.params.is_empty());
+    }
+
+    #[test]
+    fn test_build_put() {
+        let table = upsert_table();
+        let columns = vec!["name".to_string(), "id".to_string()];
+        let built = build_put(&table, &columns, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "DECLARE @lazypaw_out TABLE ([__action] nvarchar(10));\n\
+             MERGE [dbo].[users] WITH (HOLDLOCK) AS target USING (VALUES (@P1, @P2)) \
+             AS source ([name], [id]) ON target.[id] = source.[id] \
+             WHEN MATCHED THEN UPDATE SET target.[name] = source.[name] \
+             WHEN NOT MATCHED THEN INSERT ([name], [id]) VALUES (source.[name], source.[id]) \
+             OUTPUT $action INTO @lazypaw_out;\n\
+             SELECT * FROM @lazypaw_out;"
+        );
+        assert!(build_put(&table, &columns[..1], false).is_err());
+    }
+
+    #[test]
+    fn test_upsert_missing_keys_left_unchanged() {
+        let table = upsert_table();
+        let columns = vec!["id".to_string(), "name".to_string(), "email".to_string()];
+        // Row 2 omits `email`: its existing value must survive the update
+        let present = vec![vec![true, true, true], vec![true, true, false]];
+        let built = build_upsert(&table, &columns, &present, None, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "MERGE [dbo].[users] AS target USING (VALUES (@P1, @P2, @P3, 1), \
+             (@P4, @P5, @P6, 0)) AS source ([id], [name], [email], [__set2]) \
+             ON target.[id] = source.[id] WHEN MATCHED THEN UPDATE SET \
+             target.[name] = source.[name], target.[email] = CASE WHEN \
+             source.[__set2] = 1 THEN source.[email] ELSE target.[email] END \
+             WHEN NOT MATCHED THEN INSERT ([id], [name], [email]) \
+             VALUES (source.[id], source.[name], source.[email]);"
+        );
+    }
+
+    #[test]
+    fn test_bulk_update() {
+        let table = upsert_table();
+        let columns = vec!["id".to_string(), "name".to_string(), "email".to_string()];
+        // Row 2 omits `email`: its existing value must survive the update
+        let present = vec![vec![true, true, true], vec![true, true, false]];
+        let built = build_bulk_update(&table, &columns, &present, false).unwrap();
+        assert_eq!(
+            built.sql,
+            "UPDATE target SET [name] = source.[name], [email] = CASE WHEN \
+             source.[__set2] = 1 THEN source.[email] ELSE target.[email] END \
+             FROM [dbo].[users] AS target INNER JOIN (VALUES (@P1, @P2, @P3, 1), \
+             (@P4, @P5, @P6, 0)) AS source ([id], [name], [email], [__set2]) \
+             ON target.[id] = source.[id];"
+        );
+
+        let columns = vec!["name".to_string()];
+        assert!(matches!(
+            build_bulk_update(&table, &columns, &[vec![true]], false),
+            Err(Error::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_upsert_on_conflict() {
+        let mut table = upsert_table();
+        table.unique_constraints = vec![vec!["email".to_string()]];
+        let columns = vec!["email".to_string(), "name".to_string()];
+        let present = vec![vec![true, true]];
+        let key = ["EMAIL".to_string()];
+        let built = build_upsert(&table, &columns, &present, Some(&key), false).unwrap();
+        assert!(built.sql.contains("ON target.[email] = source.[email]"));
+        assert!(built
+            .sql
+            .contains("UPDATE SET target.[name] = source.[name]"));
+
+        let key = ["name".to_string()];
+        assert!(matches!(
+            build_upsert(&table, &columns, &present, Some(&key), false),
+            Err(Error::BadRequestHint { ref hint, .. }) if hint == "Use one of: id; email"
+        ));
+    }
+
+    #[test]
+    fn test_header_aggregates() {
+        assert_eq!(parse_header_aggregate("Sunset", "Sat, 01 Mar 2025"), None);
+        assert_eq!(parse_header_aggregate("X-Rows", "median(name)"), None);
+        let aggregates = vec![
+            parse_header_aggregate("X-Total", "SUM(Name)").unwrap(),
+            parse_header_aggregate("X-Rows", "count(*)").unwrap(),
+        ];
+        let filter = FilterNode::Condition(crate::filters::parse_filter("id", "gt.5").unwrap());
+        let built = build_header_aggregates(&upsert_table(), &aggregates, &[filter]).unwrap();
+        assert_eq!(
+            built.sql,
+            "SELECT SUM([name]) AS [h0], COUNT(*) AS [h1] FROM [dbo].[users] WHERE ([id] > @P1)"
+        );
+    }
+
+    #[test]
+    fn test_embed_subquery() {
+        let table = upsert_table();
+        // Self-referencing embed: the parent's full name stays visible
+        // because the embedded table is aliased
+        let embed = EmbedQuery {
+            name: "friends".to_string(),
+            table: &table,
+            alias: "e1".to_string(),
+            join: vec![("email".to_string(), "[dbo].[users].[id]".to_string())],
+            many: true,
+            spread: false,
+            columns: vec![SelectNode::Column("name".to_string())],
+            filters: vec![FilterNode::Condition(
+                crate::filters::parse_filter("name", "eq.bob").unwrap(),
+            )],
+            order: parse_order("name.desc").unwrap(),
+            limit: Some(2),
+            offset: None,
+            embeds: Vec::new(),
+        };
+        let filters = vec![FilterNode::Condition(
+            crate::filters::parse_filter("id", "eq.7").unwrap(),
+        )];
+        let built = build_select(
+            &table,
+            &[SelectNode::Column("id".to_string())],
+            &[embed],
+            &filters,
+            &[],
+            None,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            built.sql,
+            "SELECT [id], JSON_QUERY(ISNULL((SELECT [name] FROM [dbo].[users] AS [e1] \
+             WHERE [e1].[email] = [dbo].[users].[id] AND ([name] = @P1) \
+             ORDER BY [name] DESC OFFSET 0 ROWS FETCH NEXT 2 ROWS ONLY \
+             FOR JSON PATH, INCLUDE_NULL_VALUES), '[]')) AS [friends] \
+             FROM [dbo].[users] WHERE ([id] = @P2)"
+        );
+        assert_eq!(built.params, text_params(&["bob", "7"]));
+    }
+
+    #[test]
+    fn test_max_rows_cap() {
+        let table = upsert_table();
+        let select = |limit| {
+            build_select(&table, &[], &[], &[], &[], limit, None, Some(100), false)
+                .unwrap()
+                .sql
+        };
+        let page = |n: i64| {
+            format!(
+                " ORDER BY [id] ASC OFFSET 0 ROWS FETCH NEXT {} ROWS ONLY",
+                n
+            )
+        };
+        assert!(select(None).ends_with(&page(101)));
+        assert!(select(Some(500)).ends_with(&page(101)));
+        assert!(select(Some(20)).ends_with(&page(20)));
+
+        assert_eq!(capped_rows(101, Some(100)), (100, true));
+        assert_eq!(capped_rows(100, Some(100)), (100, false));
+        assert_eq!(capped_rows(5000, None), (5000, false));
+    }
+
+    #[test]
+    fn test_spread_embed() {
+        let table = upsert_table();
+        let embed = EmbedQuery {
+            name: "referrer".to_string(),
+            table: &table,
+            alias: "e1".to_string(),
+            join: vec![("id".to_string(), "[dbo].[users].[email]".to_string())],
+            many: false,
+            spread: true,
+            columns: vec![SelectNode::Column("name".to_string())],
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+            embeds: Vec::new(),
+        };
+        let built = build_select(
+            &table,
+            &[SelectNode::Column("id".to_string())],
+            &[embed],
+            &[],
+            &[],
+            None,
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            built.sql,
+            "SELECT [id], [e1_spread].[e1_name] AS [name] FROM [dbo].[users] \
+             OUTER APPLY (SELECT TOP 1 [name] AS [e1_name] FROM [dbo].[users] AS [e1] \
+             WHERE [e1].[id] = [dbo].[users].[email]) AS [e1_spread]"
+        );
+    }
+
+    #[test]
+    fn test_tree_query() {
+        let table = upsert_table();
+        let root = vec![FilterNode::Condition(
+            crate::filters::parse_filter("id", "eq.42").unwrap(),
+        )];
+        let built = build_tree(&table, &root, "id", "email", 3).unwrap();
+        assert_eq!(
+            built.sql,
+            "WITH [tree] AS (SELECT [id], [name], [email], 0 AS [__depth] FROM [dbo].[users] \
+             WHERE ([id] = @P1) UNION ALL SELECT c.[id], c.[name], c.[email], p.[__depth] + 1 \
+             FROM [dbo].[users] AS c JOIN [tree] AS p ON c.[email] = p.[id] \
+             WHERE p.[__depth] < 3) SELECT * FROM [tree] OPTION (MAXRECURSION 3)"
+        );
+        assert_eq!(built.params, text_params(&["42"]));
+    }
+
+    #[test]
+    fn test_plan_estimated_rows() {
+        let plan = r#"<ShowPlanXML><BatchSequence><Batch><Statements>
+            <StmtSimple StatementText="SELECT * FROM [dbo].[users]" StatementId="1"
+              StatementEstRows="1234.56" StatementType="SELECT">"#;
+        assert_eq!(plan_estimated_rows(plan), Some(1235));
+        assert_eq!(plan_estimated_rows("<ShowPlanXML/>"), None);
+    }
+
+    #[test]
+    fn test_sql_comment() {
+        assert_eq!(
+            sql_comment("GET", "/orders", Some("abc123")),
+            "/* lazypaw route=/orders method=GET req=abc123 */"
+        );
+        assert_eq!(
+            sql_comment("POST", "/rpc/x*/DROP", None),
+            "/* lazypaw route=/rpc/x/DROP method=POST */"
+        );
+    }
 }
@@ -2,7 +2,8 @@
 //! Realtime change notification engine using SQL Server Change Tracking.
 
 use crate::config::AppConfig;
-use crate::filters::{self, Filter, FilterOp, FilterValue};
+use crate::filters::{self, Filter, FilterOp, FilterValue, Quantifier};
+use crate::handlers::SchemaProfiles;
 use crate::pool::Pool;
 use crate::query::escape_ident;
 use crate::schema::SchemaCache;
@@ -41,10 +42,17 @@ pub enum ClientMessage {
         filter: Option<String>,
         #[serde(default)]
         events: Option<Vec<String>>,
+        /// With `"table": "*"`, only follow tables in this schema
+        #[serde(default)]
+        schema: Option<String>,
     },
     Unsubscribe {
         id: String,
     },
+    /// Swap in a fresh JWT without dropping subscriptions
+    Auth {
+        token: String,
+    },
     Ping,
 }
 
@@ -71,6 +79,19 @@ pub enum ServerMessage {
         #[serde(rename = "type")]
         type_: &'static str,
     },
+    /// A token from an `auth` message was accepted
+    Authenticated {
+        #[serde(rename = "type")]
+        type_: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exp: Option<u64>,
+    },
+    /// The connection's token runs out soon; send `auth` with a new one
+    TokenExpiring {
+        #[serde(rename = "type")]
+        type_: &'static str,
+        exp: u64,
+    },
     Change {
         #[serde(rename = "type")]
         type_: String,
@@ -80,12 +101,140 @@ pub enum ServerMessage {
     },
 }
 
+/// `table` value that subscribes to every change-tracked table.
+pub const ALL_TABLES: &str = "*";
+
 struct Subscription {
     id: String,
     table_key: String,
+    /// Schema filter for `ALL_TABLES` subscriptions
+    schema: Option<String>,
     client_tx: mpsc::Sender<ServerMessage>,
     filter: Option<Vec<Filter>>,
     events: HashSet<ChangeOp>,
+    /// A sink keeping a copy of the rows: the poller waits for room in its
+    /// buffer instead of dropping changes
+    lossless: bool,
+}
+
+/// Sink changes held back until the poll has released its locks.
+type Pending = Vec<(mpsc::Sender<ServerMessage>, ServerMessage)>;
+
+impl Subscription {
+    /// Queue a change for the subscriber. A websocket client whose buffer is
+    /// full misses it; a sink's is kept in `pending` to be sent with an
+    /// awaited send, so a slow sink holds the poller back instead.
+    fn deliver(&self, msg: ServerMessage, events: &mut EventCounts, pending: &mut Pending) {
+        if self.lossless {
+            pending.push((self.client_tx.clone(), msg));
+            events.delivered += 1;
+            return;
+        }
+        match self.client_tx.try_send(msg) {
+            Ok(()) => events.delivered += 1,
+            Err(mpsc::error::TrySendError::Full(_)) => events.dropped += 1,
+            // Client is going away
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// Events fanned out for one table since startup.
+#[derive(Debug, Default, Clone, Copy)]
+struct EventCounts {
+    delivered: u64,
+    /// Not queued because the client's buffer was full
+    dropped: u64,
+}
+
+/// Realtime state for `/admin/realtime` and `/metrics`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealtimeStats {
+    pub clients: usize,
+    /// `CHANGE_TRACKING_CURRENT_VERSION()` at the last poll
+    pub current_version: i64,
+    /// Last version whose changes were fanned out
+    pub processed_version: i64,
+    /// Versions the poller is behind
+    pub lag: i64,
+    pub tables: Vec<TableStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStats {
+    pub table: String,
+    pub subscriptions: usize,
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+impl RealtimeStats {
+    /// Prometheus text exposition.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (labels, value) in samples {
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+        let per_table = |value: fn(&TableStats) -> String| -> Vec<(String, String)> {
+            self.tables
+                .iter()
+                .map(|t| {
+                    let table = t.table.replace('\\', "\\\\").replace('"', "\\\"");
+                    (format!("{{table=\"{}\"}}", table), value(t))
+                })
+                .collect()
+        };
+        let single = |value: i64| vec![(String::new(), value.to_string())];
+        metric(
+            "lazypaw_realtime_clients",
+            "gauge",
+            "Connected realtime clients",
+            vec![(String::new(), self.clients.to_string())],
+        );
+        metric(
+            "lazypaw_realtime_subscriptions",
+            "gauge",
+            "Active subscriptions per table",
+            per_table(|t| t.subscriptions.to_string()),
+        );
+        metric(
+            "lazypaw_realtime_events_delivered_total",
+            "counter",
+            "Change events queued for clients",
+            per_table(|t| t.delivered.to_string()),
+        );
+        metric(
+            "lazypaw_realtime_events_dropped_total",
+            "counter",
+            "Change events dropped because a client's buffer was full",
+            per_table(|t| t.dropped.to_string()),
+        );
+        metric(
+            "lazypaw_realtime_ct_current_version",
+            "gauge",
+            "Change tracking version at the last poll",
+            single(self.current_version),
+        );
+        metric(
+            "lazypaw_realtime_ct_processed_version",
+            "gauge",
+            "Last change tracking version fanned out",
+            single(self.processed_version),
+        );
+        metric(
+            "lazypaw_realtime_lag_versions",
+            "gauge",
+            "Change tracking versions the poller is behind",
+            single(self.lag),
+        );
+        out
+    }
 }
 
 pub struct RealtimeEngine {
@@ -93,51 +242,128 @@ pub struct RealtimeEngine {
     all_subs: RwLock<HashMap<Uuid, Subscription>>,
     client_subs: RwLock<HashMap<Uuid, Vec<Uuid>>>,
     last_version: AtomicI64,
+    /// Newest version seen, processed or not
+    current_version: AtomicI64,
+    /// Per-table delivery counters, keyed like `table_subs`
+    counts: std::sync::Mutex<HashMap<String, EventCounts>>,
     pool: Arc<Pool>,
     schema: Arc<RwLock<SchemaCache>>,
     config: AppConfig,
+    /// Schema unqualified table names resolve against, moved by cutover
+    profiles: Arc<std::sync::RwLock<SchemaProfiles>>,
 }
 
 impl RealtimeEngine {
-    pub fn new(pool: Arc<Pool>, schema: Arc<RwLock<SchemaCache>>, config: AppConfig) -> Arc<Self> {
+    pub fn new(
+        pool: Arc<Pool>,
+        schema: Arc<RwLock<SchemaCache>>,
+        config: AppConfig,
+        profiles: Arc<std::sync::RwLock<SchemaProfiles>>,
+    ) -> Arc<Self> {
         Arc::new(Self {
             table_subs: RwLock::new(HashMap::new()),
             all_subs: RwLock::new(HashMap::new()),
             client_subs: RwLock::new(HashMap::new()),
             last_version: AtomicI64::new(-1),
+            current_version: AtomicI64::new(-1),
+            counts: std::sync::Mutex::new(HashMap::new()),
             pool,
             schema,
             config,
+            profiles,
         })
     }
 
+    /// Subscribe a client to one table, or with `ALL_TABLES` (admins only)
+    /// to every change-tracked table, optionally limited to one schema.
+    #[allow(clippy::too_many_arguments)]
     pub async fn subscribe(
         &self,
         client_id: Uuid,
         sub_id: String,
         table: &str,
+        schema: Option<&str>,
         filter_str: Option<&str>,
         events: Option<Vec<String>>,
+        admin: bool,
         tx: mpsc::Sender<ServerMessage>,
     ) -> Result<String, String> {
-        let schema_cache = self.schema.read().await;
+        self.add_subscription(
+            client_id, sub_id, table, schema, filter_str, events, admin, tx, false,
+        )
+        .await
+    }
 
-        let (schema_name, table_name) = if table.contains('.') {
-            let parts: Vec<&str> = table.splitn(2, '.').collect();
-            (parts[0].to_string(), parts[1].to_string())
+    /// Subscribe a sink to every change of one table. Unlike a client it
+    /// misses none: when its buffer is full the poller waits for it.
+    pub async fn subscribe_sink(
+        &self,
+        client_id: Uuid,
+        table: &str,
+        tx: mpsc::Sender<ServerMessage>,
+    ) -> Result<String, String> {
+        self.add_subscription(
+            client_id,
+            table.to_string(),
+            table,
+            None,
+            None,
+            None,
+            false,
+            tx,
+            true,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn add_subscription(
+        &self,
+        client_id: Uuid,
+        sub_id: String,
+        table: &str,
+        schema: Option<&str>,
+        filter_str: Option<&str>,
+        events: Option<Vec<String>>,
+        admin: bool,
+        tx: mpsc::Sender<ServerMessage>,
+        lossless: bool,
+    ) -> Result<String, String> {
+        let table_key = if table == ALL_TABLES {
+            if !admin {
+                return Err("Subscribing to all tables requires the admin role".to_string());
+            }
+            if let Some(schema) = schema {
+                if !self.config.is_schema_exposed(schema) {
+                    return Err(format!("Schema not exposed: {}", schema));
+                }
+            }
+            ALL_TABLES.to_string()
         } else {
-            (self.config.default_schema.clone(), table.to_string())
-        };
+            if schema.is_some() {
+                return Err("schema only applies to \"table\": \"*\"".to_string());
+            }
+            let schema_cache = self.schema.read().await;
 
-        let table_key = format!("{}.{}", schema_name, table_name);
+            let (schema_name, table_name) = if table.contains('.') {
+                let parts: Vec<&str> = table.splitn(2, '.').collect();
+                (parts[0].to_string(), parts[1].to_string())
+            } else {
+                let active = self.profiles.read().unwrap().active.clone();
+                (active, table.to_string())
+            };
 
-        let table_info = schema_cache
-            .get_table(&schema_name, &table_name)
-            .ok_or_else(|| format!("Table not found: {}", table_key))?;
+            let table_key = format!("{}.{}", schema_name, table_name);
 
-        if !table_info.change_tracking_enabled {
-            return Err(format!("Change tracking not enabled on {}", table_key));
-        }
+            let table_info = schema_cache
+                .get_table(&schema_name, &table_name)
+                .ok_or_else(|| format!("Table not found: {}", table_key))?;
+
+            if !table_info.change_tracking_enabled {
+                return Err(format!("Change tracking not enabled on {}", table_key));
+            }
+            table_key
+        };
 
         // Parse filters
         let parsed_filters = if let Some(f) = filter_str {
@@ -145,7 +371,13 @@ impl RealtimeEngine {
             for part in f.split('&') {
                 if let Some((key, val)) = part.split_once('=') {
                     match filters::parse_filter(key, val) {
-                        Ok(filter) => fv.push(filter),
+                        Ok(filter) if evaluable(&filter.operator) => fv.push(filter),
+                        Ok(_) => {
+                            return Err(format!(
+                                "Invalid filter: {} can't filter realtime changes",
+                                val.split('.').find(|op| *op != "not").unwrap_or(val)
+                            ))
+                        }
                         Err(e) => return Err(format!("Invalid filter: {}", e)),
                     }
                 }
@@ -174,9 +406,11 @@ impl RealtimeEngine {
         let sub = Subscription {
             id: sub_id,
             table_key: table_key.clone(),
+            schema: schema.map(str::to_string),
             client_tx: tx,
             filter: parsed_filters,
             events: event_set,
+            lossless,
         };
 
         self.all_subs.write().await.insert(sub_uuid, sub);
@@ -224,6 +458,31 @@ impl RealtimeEngine {
         }
     }
 
+    /// Drop a client's `ALL_TABLES` subscriptions, when its token no longer
+    /// carries the admin role. Returns their ids.
+    pub async fn unsubscribe_all_tables(&self, client_id: Uuid) -> Vec<String> {
+        let client_sub_uuids = self
+            .client_subs
+            .read()
+            .await
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_default();
+        let ids: Vec<String> = {
+            let all_subs = self.all_subs.read().await;
+            client_sub_uuids
+                .iter()
+                .filter_map(|uuid| all_subs.get(uuid))
+                .filter(|sub| sub.table_key == ALL_TABLES)
+                .map(|sub| sub.id.clone())
+                .collect()
+        };
+        for id in &ids {
+            self.unsubscribe(client_id, id).await;
+        }
+        ids
+    }
+
     pub async fn remove_client(&self, client_id: Uuid) {
         let sub_uuids = self
             .client_subs
@@ -240,6 +499,45 @@ impl RealtimeEngine {
         }
     }
 
+    /// Subscriptions, delivery counters and poller lag.
+    pub async fn stats(&self) -> RealtimeStats {
+        let clients = self.client_subs.read().await.len();
+        let subscriptions: HashMap<String, usize> = self
+            .table_subs
+            .read()
+            .await
+            .iter()
+            .map(|(table, subs)| (table.clone(), subs.len()))
+            .collect();
+        let counts = self.counts.lock().unwrap().clone();
+
+        let mut names: Vec<&String> = subscriptions.keys().chain(counts.keys()).collect();
+        names.sort();
+        names.dedup();
+        let tables = names
+            .into_iter()
+            .map(|table| {
+                let events = counts.get(table).copied().unwrap_or_default();
+                TableStats {
+                    table: table.clone(),
+                    subscriptions: subscriptions.get(table).copied().unwrap_or(0),
+                    delivered: events.delivered,
+                    dropped: events.dropped,
+                }
+            })
+            .collect();
+
+        let current_version = self.current_version.load(Ordering::SeqCst);
+        let processed_version = self.last_version.load(Ordering::SeqCst);
+        RealtimeStats {
+            clients,
+            current_version,
+            processed_version,
+            lag: (current_version - processed_version).max(0),
+            tables,
+        }
+    }
+
     pub async fn init_version(&self) -> Result<(), String> {
         let mut conn = self.pool.get().await.map_err(|e| e.to_string())?;
         let client = conn.client();
@@ -258,6 +556,7 @@ impl RealtimeEngine {
                     JsonValue::Number(n) => {
                         if let Some(v) = n.as_i64() {
                             self.last_version.store(v, Ordering::SeqCst);
+                            self.current_version.store(v, Ordering::SeqCst);
                         }
                     }
                     JsonValue::Null => {
@@ -279,15 +578,48 @@ impl RealtimeEngine {
         }
     }
 
-    async fn poll_once(&self) -> Result<(), String> {
-        let active_tables: Vec<String> = {
-            let table_subs = self.table_subs.read().await;
-            table_subs
+    /// Tables with a subscriber, counting every change-tracked table in a
+    /// followed schema when there are `ALL_TABLES` subscriptions.
+    async fn active_tables(&self) -> Vec<String> {
+        let table_subs = self.table_subs.read().await;
+        let mut active: Vec<String> = table_subs
+            .iter()
+            .filter(|(key, subs)| *key != ALL_TABLES && !subs.is_empty())
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let wildcard = table_subs.get(ALL_TABLES).cloned().unwrap_or_default();
+        drop(table_subs);
+        if wildcard.is_empty() {
+            return active;
+        }
+        let schemas: Vec<Option<String>> = {
+            let all_subs = self.all_subs.read().await;
+            wildcard
                 .iter()
-                .filter(|(_, subs)| !subs.is_empty())
-                .map(|(k, _)| k.clone())
+                .filter_map(|uuid| all_subs.get(uuid))
+                .map(|sub| sub.schema.clone())
                 .collect()
         };
+        let schema_cache = self.schema.read().await;
+        for table in schema_cache.tables.values() {
+            if !table.change_tracking_enabled || !self.config.is_schema_exposed(&table.schema) {
+                continue;
+            }
+            let followed = schemas.iter().any(|s| {
+                s.as_deref()
+                    .is_none_or(|s| s.eq_ignore_ascii_case(&table.schema))
+            });
+            let key = format!("{}.{}", table.schema, table.name);
+            if followed && !active.contains(&key) {
+                active.push(key);
+            }
+        }
+        active
+    }
+
+    async fn poll_once(&self) -> Result<(), String> {
+        let active_tables = self.active_tables().await;
 
         if active_tables.is_empty() {
             return Ok(());
@@ -317,12 +649,15 @@ impl RealtimeEngine {
             return Ok(());
         };
 
+        self.current_version
+            .store(current_version, Ordering::SeqCst);
         let last = self.last_version.load(Ordering::SeqCst);
         if current_version <= last {
             return Ok(());
         }
 
         let schema_cache = self.schema.read().await;
+        let mut pending = Pending::new();
 
         for table_key in &active_tables {
             let parts: Vec<&str> = table_key.splitn(2, '.').collect();
@@ -422,21 +757,29 @@ impl RealtimeEngine {
                     }
                 }
 
-                // Fan out to subscriptions
-                let sub_uuids = self
-                    .table_subs
-                    .read()
-                    .await
-                    .get(table_key)
-                    .cloned()
-                    .unwrap_or_default();
+                // Fan out to subscriptions, the table's own and all-table ones
+                let sub_uuids: Vec<Uuid> = {
+                    let table_subs = self.table_subs.read().await;
+                    [table_key.as_str(), ALL_TABLES]
+                        .iter()
+                        .filter_map(|key| table_subs.get(*key))
+                        .flatten()
+                        .copied()
+                        .collect()
+                };
 
                 let all_subs = self.all_subs.read().await;
+                let mut events = EventCounts::default();
                 for sub_uuid in &sub_uuids {
                     if let Some(sub) = all_subs.get(sub_uuid) {
                         if !sub.events.contains(&op) {
                             continue;
                         }
+                        if let Some(ref schema) = sub.schema {
+                            if !schema.eq_ignore_ascii_case(schema_name) {
+                                continue;
+                            }
+                        }
 
                         if let Some(ref filter_list) = sub.filter {
                             let mut matches = true;
@@ -466,17 +809,72 @@ impl RealtimeEngine {
                             record: record.clone(),
                         };
 
-                        let _ = sub.client_tx.try_send(msg);
+                        sub.deliver(msg, &mut events, &mut pending);
                     }
                 }
+                let mut counts = self.counts.lock().unwrap();
+                let total = counts.entry(table_key.clone()).or_default();
+                total.delivered += events.delivered;
+                total.dropped += events.dropped;
             }
         }
 
+        // Sinks wait on nothing this poll holds: a sink reading the schema
+        // while a reload waits for this lock would never make room
+        drop(schema_cache);
+        drop(conn);
+        for (tx, msg) in pending {
+            // A sink that's gone away has nothing left to keep current
+            let _ = tx.send(msg).await;
+        }
+
         self.last_version.store(current_version, Ordering::SeqCst);
         Ok(())
     }
 }
 
+/// A changed row's primary key as one string, for sinks that key copies of
+/// the row by it. Each part of a composite key is prefixed with its length
+/// in bytes (`1-1_3-2_3` for `(1, "2_3")`), so no two keys share an ID; the
+/// result stays within the letters, digits, `-` and `_` Meilisearch allows.
+pub fn row_id(
+    primary_key: &[String],
+    record: &serde_json::Map<String, JsonValue>,
+) -> Option<String> {
+    let parts = primary_key
+        .iter()
+        .map(|pk| match record.get(pk)? {
+            JsonValue::String(s) => Some(s.clone()),
+            JsonValue::Null => None,
+            other => Some(other.to_string()),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if let [part] = parts.as_slice() {
+        return Some(part.clone());
+    }
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|part| format!("{}-{}", part.len(), part))
+        .collect();
+    Some(parts.join("_"))
+}
+
+/// Whether realtime can evaluate an operator on changed rows: comparisons,
+/// `in`, `is` and `between`. Others (patterns, full-text and config-defined
+/// operators) need SQL Server and are rejected when subscribing.
+fn evaluable(op: &FilterOp) -> bool {
+    op.comparison().is_some() || matches!(op, FilterOp::In | FilterOp::Is | FilterOp::Between)
+}
+
+/// Order a changed row's value against a filter operand: numerically when
+/// both parse as numbers, as text otherwise.
+fn compare(value: &str, operand: &str) -> std::cmp::Ordering {
+    match (value.parse::<f64>(), operand.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or_else(|| value.cmp(operand)),
+        _ => value.cmp(operand),
+    }
+}
+
 fn filter_matches(filter: &Filter, value: &JsonValue) -> bool {
     let val_str = match value {
         JsonValue::String(s) => s.clone(),
@@ -486,55 +884,38 @@ fn filter_matches(filter: &Filter, value: &JsonValue) -> bool {
         other => other.to_string(),
     };
 
-    let result = match &filter.operator {
-        FilterOp::Eq => match &filter.value {
-            FilterValue::Single(expected) => val_str == *expected,
-            _ => true,
-        },
-        FilterOp::Neq => match &filter.value {
-            FilterValue::Single(expected) => val_str != *expected,
-            _ => true,
-        },
-        FilterOp::In => match &filter.value {
-            FilterValue::List(items) => items.contains(&val_str),
-            _ => true,
-        },
-        FilterOp::Is => match &filter.value {
-            FilterValue::Single(expected) => match expected.to_lowercase().as_str() {
-                "null" => value.is_null(),
-                "true" => value == &JsonValue::Bool(true),
-                "false" => value == &JsonValue::Bool(false),
+    let result = if let Some(comparison) = filter.operator.comparison() {
+        let hit = |operand: &String| comparison.matches.contains(&compare(&val_str, operand));
+        match &filter.value {
+            FilterValue::Single(expected) => hit(expected),
+            FilterValue::Quantified(Quantifier::Any, items) => items.iter().any(hit),
+            FilterValue::Quantified(Quantifier::All, items) => items.iter().all(hit),
+            FilterValue::List(_) => true,
+        }
+    } else {
+        match &filter.operator {
+            FilterOp::In => match &filter.value {
+                FilterValue::List(items) => items.contains(&val_str),
                 _ => true,
             },
-            _ => true,
-        },
-        // For comparison ops, try numeric comparison
-        FilterOp::Gt | FilterOp::Gte | FilterOp::Lt | FilterOp::Lte => {
-            match &filter.value {
-                FilterValue::Single(expected) => {
-                    if let (Ok(a), Ok(b)) = (val_str.parse::<f64>(), expected.parse::<f64>()) {
-                        match &filter.operator {
-                            FilterOp::Gt => a > b,
-                            FilterOp::Gte => a >= b,
-                            FilterOp::Lt => a < b,
-                            FilterOp::Lte => a <= b,
-                            _ => true,
-                        }
-                    } else {
-                        // String comparison fallback
-                        match &filter.operator {
-                            FilterOp::Gt => val_str > *expected,
-                            FilterOp::Gte => val_str >= *expected,
-                            FilterOp::Lt => val_str < *expected,
-                            FilterOp::Lte => val_str <= *expected,
-                            _ => true,
-                        }
-                    }
+            FilterOp::Is => match &filter.value {
+                FilterValue::Single(expected) => match expected.to_lowercase().as_str() {
+                    "null" => value.is_null(),
+                    "true" => value == &JsonValue::Bool(true),
+                    "false" => value == &JsonValue::Bool(false),
+                    _ => true,
+                },
+                _ => true,
+            },
+            FilterOp::Between => match &filter.value {
+                FilterValue::List(items) if items.len() == 2 => {
+                    compare(&val_str, &items[0]).is_ge() && compare(&val_str, &items[1]).is_le()
                 }
                 _ => true,
-            }
+            },
+            // Rejected when subscribing, see `evaluable`
+            _ => true,
         }
-        _ => true, // Like, Ilike, Fts — pass through
     };
 
     if filter.negated {
@@ -543,3 +924,76 @@ fn filter_matches(filter: &Filter, value: &JsonValue) -> bool {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(tx: mpsc::Sender<ServerMessage>, lossless: bool) -> Subscription {
+        Subscription {
+            id: "s".to_string(),
+            table_key: "dbo.orders".to_string(),
+            schema: None,
+            client_tx: tx,
+            filter: None,
+            events: HashSet::new(),
+            lossless,
+        }
+    }
+
+    fn change() -> ServerMessage {
+        ServerMessage::Change {
+            type_: "INSERT".to_string(),
+            id: "s".to_string(),
+            table: "dbo.orders".to_string(),
+            record: serde_json::Map::new(),
+        }
+    }
+
+    #[test]
+    fn test_deliver_to_full_client_drops() {
+        let (tx, _rx) = mpsc::channel(1);
+        let sub = subscription(tx, false);
+        let (mut events, mut pending) = (EventCounts::default(), Pending::new());
+        sub.deliver(change(), &mut events, &mut pending);
+        sub.deliver(change(), &mut events, &mut pending);
+        assert_eq!((events.delivered, events.dropped), (1, 1));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_deliver_to_sink_keeps_every_change() {
+        let (tx, _rx) = mpsc::channel(1);
+        let sub = subscription(tx, true);
+        let (mut events, mut pending) = (EventCounts::default(), Pending::new());
+        for _ in 0..3 {
+            sub.deliver(change(), &mut events, &mut pending);
+        }
+        assert_eq!((events.delivered, events.dropped), (3, 0));
+        assert_eq!(pending.len(), 3);
+    }
+
+    fn record(value: JsonValue) -> serde_json::Map<String, JsonValue> {
+        match value {
+            JsonValue::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_row_id() {
+        let single = vec!["code".to_string()];
+        let composite = vec!["a".to_string(), "b".to_string()];
+
+        let row = record(serde_json::json!({"code": "NZ"}));
+        assert_eq!(row_id(&single, &row).as_deref(), Some("NZ"));
+
+        let first = record(serde_json::json!({"a": 1, "b": "2_3"}));
+        let second = record(serde_json::json!({"a": "1_2", "b": 3}));
+        assert_eq!(row_id(&composite, &first).as_deref(), Some("1-1_3-2_3"));
+        assert_eq!(row_id(&composite, &second).as_deref(), Some("3-1_2_1-3"));
+
+        let null = record(serde_json::json!({"a": 1, "b": null}));
+        assert_eq!(row_id(&composite, &null), None);
+    }
+}
@@ -2,21 +2,29 @@
 //! WebSocket handler for realtime change notifications.
 
 use crate::auth;
+use crate::auth_guard::{self, AuthGuard};
 use crate::config::AppConfig;
 use crate::realtime::{ClientMessage, RealtimeEngine, ServerMessage};
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Query, State};
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use futures_util::{SinkExt, StreamExt};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
+/// How long before its token expires a connection is sent `token_expiring`.
+const TOKEN_EXPIRY_WARNING_SECS: u64 = 60;
+
 /// Combined state for the websocket handler.
 #[derive(Clone)]
 pub struct WsState {
     pub engine: Arc<RealtimeEngine>,
     pub config: AppConfig,
+    /// Counts tokens rejected by `auth` messages, when `auth_max_failures` is set
+    pub guard: Option<Arc<AuthGuard>>,
 }
 
 #[derive(serde::Deserialize)]
@@ -28,29 +36,65 @@ pub struct WsQuery {
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<WsState>,
+    client: Option<Extension<auth_guard::Client>>,
     Query(query): Query<WsQuery>,
 ) -> Response {
+    // A rejected `?token=` is a 401 (which the guard counts), not a
+    // connection as anonymous
     let claims = if let Some(ref token) = query.token {
         let header = format!("Bearer {}", token);
-        auth::authenticate(Some(&header), &state.config).unwrap_or_default()
+        match auth::authenticate(Some(&header), &state.config) {
+            Ok(claims) => claims,
+            Err(e) => return e.into_response(),
+        }
     } else {
         None
     };
+    let guard = state
+        .guard
+        .zip(client)
+        .map(|(guard, Extension(client))| (guard, client.0));
 
-    ws.on_upgrade(move |socket| handle_socket(socket, state.engine, claims))
+    ws.on_upgrade(move |socket| handle_socket(socket, state.engine, state.config, claims, guard))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Sleep until `at` (Unix seconds), or forever when there's nothing due.
+async fn sleep_until_secs(at: Option<u64>) {
+    match at {
+        Some(at) => tokio::time::sleep(Duration::from_secs(at.saturating_sub(now_secs()))).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Whether the token's role is the configured admin role.
+fn is_admin(config: &AppConfig, claims: Option<&auth::Claims>) -> bool {
+    let Some(admin_role) = config.admin_role.as_deref() else {
+        return false;
+    };
+    claims
+        .and_then(|c| auth::resolve_role(c, config))
+        .is_some_and(|role| role.eq_ignore_ascii_case(admin_role))
 }
 
 async fn handle_socket(
     socket: WebSocket,
     engine: Arc<RealtimeEngine>,
-    _claims: Option<auth::Claims>,
+    config: AppConfig,
+    claims: Option<auth::Claims>,
+    guard: Option<(Arc<AuthGuard>, std::net::IpAddr)>,
 ) {
     let client_id = Uuid::new_v4();
     let (mut ws_tx, mut ws_rx) = socket.split();
     let (tx, mut rx) = mpsc::channel::<ServerMessage>(256);
 
     // Forward engine messages to websocket
-    let send_task = tokio::spawn(async move {
+    let mut send_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
             if let Ok(json) = serde_json::to_string(&msg) {
                 if ws_tx.send(Message::Text(json.into())).await.is_err() {
@@ -60,8 +104,50 @@ async fn handle_socket(
         }
     });
 
+    // Token expiry: warn first, then close unless an `auth` message
+    // brought a fresh token
+    let mut exp = claims.as_ref().and_then(|c| c.exp);
+    let mut admin = is_admin(&config, claims.as_ref());
+    // A refresh must be for the same user
+    let subject = claims.as_ref().and_then(|c| c.sub.clone());
+    let mut warned = false;
+    // Set when the connection closes on an error the client should see
+    let mut closing = false;
+
     // Read client messages
-    while let Some(Ok(msg)) = ws_rx.next().await {
+    loop {
+        let due = exp.map(|exp| {
+            if warned {
+                exp
+            } else {
+                exp.saturating_sub(TOKEN_EXPIRY_WARNING_SECS)
+            }
+        });
+        let msg = tokio::select! {
+            msg = ws_rx.next() => msg,
+            _ = sleep_until_secs(due) => {
+                let Some(exp) = exp else { continue };
+                if !warned {
+                    warned = true;
+                    let _ = tx
+                        .send(ServerMessage::TokenExpiring {
+                            type_: "token_expiring",
+                            exp,
+                        })
+                        .await;
+                    continue;
+                }
+                let _ = tx
+                    .send(ServerMessage::Error {
+                        type_: "error",
+                        message: "Token expired".to_string(),
+                    })
+                    .await;
+                closing = true;
+                break;
+            }
+        };
+        let Some(Ok(msg)) = msg else { break };
         match msg {
             Message::Text(text) => {
                 if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
@@ -71,13 +157,16 @@ async fn handle_socket(
                             table,
                             filter,
                             events,
+                            schema,
                         } => match engine
                             .subscribe(
                                 client_id,
                                 id.clone(),
                                 &table,
+                                schema.as_deref(),
                                 filter.as_deref(),
                                 events,
+                                admin,
                                 tx.clone(),
                             )
                             .await
@@ -109,6 +198,60 @@ async fn handle_socket(
                                 })
                                 .await;
                         }
+                        ClientMessage::Auth { token } => {
+                            let header = format!("Bearer {}", token);
+                            match auth::authenticate(Some(&header), &config) {
+                                Ok(claims)
+                                    if claims.as_ref().and_then(|c| c.sub.as_ref())
+                                        != subject.as_ref() =>
+                                {
+                                    let _ = tx
+                                        .send(ServerMessage::Error {
+                                            type_: "error",
+                                            message: "Token is for a different subject".to_string(),
+                                        })
+                                        .await;
+                                }
+                                Ok(claims) => {
+                                    exp = claims.as_ref().and_then(|c| c.exp);
+                                    admin = is_admin(&config, claims.as_ref());
+                                    warned = false;
+                                    if !admin {
+                                        for id in engine.unsubscribe_all_tables(client_id).await {
+                                            let _ = tx
+                                                .send(ServerMessage::Unsubscribed {
+                                                    type_: "unsubscribed",
+                                                    id,
+                                                })
+                                                .await;
+                                        }
+                                    }
+                                    let _ = tx
+                                        .send(ServerMessage::Authenticated {
+                                            type_: "authenticated",
+                                            exp,
+                                        })
+                                        .await;
+                                }
+                                // The current token stays in effect, unless
+                                // guessing got the client banned
+                                Err(e) => {
+                                    let banned = guard.as_ref().is_some_and(|(guard, client)| {
+                                        guard.realtime_failure(*client, &token)
+                                    });
+                                    let _ = tx
+                                        .send(ServerMessage::Error {
+                                            type_: "error",
+                                            message: e.to_string(),
+                                        })
+                                        .await;
+                                    if banned {
+                                        closing = true;
+                                        break;
+                                    }
+                                }
+                            }
+                        }
                         ClientMessage::Ping => {
                             let _ = tx.send(ServerMessage::Pong { type_: "pong" }).await;
                         }
@@ -121,5 +264,10 @@ async fn handle_socket(
     }
 
     engine.remove_client(client_id).await;
+    if closing {
+        // Let the last error reach the client before the socket closes
+        drop(tx);
+        let _ = tokio::time::timeout(Duration::from_secs(1), &mut send_task).await;
+    }
     send_task.abort();
 }
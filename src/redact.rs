@@ -0,0 +1,202 @@
+//! Redaction of secrets and PII before values reach logs or clients.
+//!
+//! Bound parameter values are never logged unless `log_params` is enabled,
+//! and free-form messages (SQL errors, row failures) are scrubbed of
+//! obvious credential patterns: `password=...`, `Bearer <token>`, raw JWTs.
+
+use crate::query::SqlParam;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Key suffixes whose `key=value` / `key: value` values are masked.
+const SENSITIVE_KEYS: &[&str] = &[
+    "password",
+    "pwd",
+    "secret",
+    "token",
+    "apikey",
+    "api_key",
+    "authorization",
+];
+
+/// Scrub token/password patterns from a message.
+pub fn scrub(msg: &str) -> String {
+    let mut out = String::with_capacity(msg.len());
+    let mut prev_bearer = false;
+    let mut pending_key = false;
+
+    for piece in msg.split_inclusive(char::is_whitespace) {
+        let word = piece.trim_end();
+        let trailing = &piece[word.len()..];
+
+        if word.is_empty() {
+            out.push_str(piece);
+            continue;
+        }
+
+        if prev_bearer || pending_key || is_jwt(word) {
+            out.push_str(REDACTED);
+        } else {
+            out.push_str(&scrub_pairs(word));
+        }
+        out.push_str(trailing);
+
+        prev_bearer = word.eq_ignore_ascii_case("bearer");
+        // `password: hunter2` — key and value separated by whitespace
+        pending_key = word.strip_suffix(['=', ':']).is_some_and(is_sensitive_key);
+    }
+    out
+}
+
+/// Describe bound parameters for logging: values only when explicitly
+/// enabled, with NULL shown as `None`.
+pub fn params(params: &[SqlParam], log_values: bool) -> String {
+    if log_values {
+        let scrubbed: Vec<Option<String>> = params
+            .iter()
+            .map(|p| p.to_text().map(|v| scrub(&v)))
+            .collect();
+        format!("{:?}", scrubbed)
+    } else {
+        format!("[{} redacted]", params.len())
+    }
+}
+
+/// Mask values of sensitive `key=value` pairs within a single word
+/// (e.g. connection strings `Server=x;Password=y`).
+fn scrub_pairs(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    for segment in word.split_inclusive([';', '&', ',']) {
+        let body = segment.trim_end_matches([';', '&', ',']);
+        let sep = &segment[body.len()..];
+        match body.find(['=', ':']) {
+            Some(pos) if pos + 1 < body.len() && is_sensitive_key(&body[..pos]) => {
+                out.push_str(&body[..=pos]);
+                out.push_str(REDACTED);
+            }
+            _ => out.push_str(body),
+        }
+        out.push_str(sep);
+    }
+    out
+}
+
+fn is_sensitive_key(key: &str) -> bool {
+    let key = key
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .to_ascii_lowercase();
+    !key.is_empty() && SENSITIVE_KEYS.iter().any(|k| key.ends_with(k))
+}
+
+/// A sampled value made safe to publish (OpenAPI examples): letters become
+/// `a`/`A` and digits `1`, so the shape of emails, dates, UUIDs and codes
+/// survives but the data doesn't. Timestamps keep their `T`/`Z`. `None`
+/// for columns named like credentials.
+pub fn anonymize(column: &str, value: &serde_json::Value) -> Option<serde_json::Value> {
+    use serde_json::Value;
+    let column = column.to_ascii_lowercase();
+    if SENSITIVE_KEYS.iter().any(|k| column.contains(k)) {
+        return None;
+    }
+    let mask = |s: &str, letters: bool| -> String {
+        s.chars()
+            .map(|c| match c {
+                c if c.is_ascii_digit() => '1',
+                c if letters && c.is_uppercase() => 'A',
+                c if letters && c.is_alphabetic() => 'a',
+                c => c,
+            })
+            .collect()
+    };
+    Some(match value {
+        Value::String(s) => {
+            let is_time = s.parse::<chrono::DateTime<chrono::FixedOffset>>().is_ok()
+                || s.parse::<chrono::NaiveDateTime>().is_ok()
+                || s.parse::<chrono::NaiveDate>().is_ok();
+            Value::String(mask(s, !is_time))
+        }
+        Value::Number(n) => mask(&n.to_string(), false)
+            .parse::<serde_json::Number>()
+            .map_or(Value::from(1), Value::Number),
+        Value::Array(items) => {
+            Value::Array(items.iter().filter_map(|v| anonymize(&column, v)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter_map(|(k, v)| Some((k.clone(), anonymize(k, v)?)))
+                .collect(),
+        ),
+        other => other.clone(),
+    })
+}
+
+/// Three base64url segments starting with a JSON header (`eyJ`).
+fn is_jwt(word: &str) -> bool {
+    let token = word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_');
+    token.starts_with("eyJ")
+        && token.split('.').count() == 3
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub_patterns() {
+        assert_eq!(
+            scrub("Login failed: Server=db;Password=hunter2;User=sa"),
+            "Login failed: Server=db;Password=[REDACTED];User=sa"
+        );
+        assert_eq!(
+            scrub("Authorization: Bearer abc.def"),
+            "Authorization: [REDACTED] [REDACTED]"
+        );
+        assert_eq!(
+            scrub("token 'eyJhbGciOi.eyJzdWIiOi.c2ln' rejected"),
+            "token [REDACTED] rejected"
+        );
+        assert_eq!(
+            scrub("Violation of PRIMARY KEY constraint"),
+            "Violation of PRIMARY KEY constraint"
+        );
+    }
+
+    #[test]
+    fn test_anonymize() {
+        use serde_json::json;
+        let masked = |column: &str, value: serde_json::Value| anonymize(column, &value);
+        assert_eq!(
+            masked("email", json!("Ann.Lee@example.com")),
+            Some(json!("Aaa.Aaa@aaaaaaa.aaa"))
+        );
+        assert_eq!(
+            masked("created_at", json!("2024-03-09T17:45:00")),
+            Some(json!("1111-11-11T11:11:11"))
+        );
+        assert_eq!(masked("total", json!(-842.5)), Some(json!(-111.1)));
+        assert_eq!(masked("active", json!(true)), Some(json!(true)));
+        assert_eq!(masked("password_hash", json!("x")), None);
+        assert_eq!(
+            masked("prefs", json!({"theme": "dark", "api_key": "k"})),
+            Some(json!({"theme": "aaaa"}))
+        );
+    }
+
+    #[test]
+    fn test_params_redacted_by_default() {
+        let values = vec![
+            SqlParam::Text("alice@example.com".to_string()),
+            SqlParam::Int(42),
+            SqlParam::Null,
+        ];
+        assert_eq!(params(&values, false), "[3 redacted]");
+        assert_eq!(
+            params(&values, true),
+            r#"[Some("alice@example.com"), Some("42"), None]"#
+        );
+    }
+}
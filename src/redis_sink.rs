@@ -0,0 +1,339 @@
+//! Redis cache sink: mirrors small tables into Redis as one JSON value per
+//! row, loaded at startup and kept current by the change tracking poller.
+
+use crate::config::{AppConfig, MirroredTable};
+use crate::pool::Pool;
+use crate::query::escape_ident;
+use crate::realtime::{self, RealtimeEngine, ServerMessage};
+use crate::schema::{SchemaCache, TableInfo};
+use crate::types;
+use redis::AsyncCommands;
+use serde_json::Value as JsonValue;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use uuid::Uuid;
+
+/// Changes buffered while the initial load runs; past this the poller waits.
+const SINK_BUFFER: usize = 10_000;
+
+/// Keys written per MSET during the initial load.
+const LOAD_BATCH: usize = 1_000;
+
+/// How often tables whose copy is out of date are loaded again.
+const RELOAD_RETRY: Duration = Duration::from_secs(5);
+
+struct RedisSink {
+    /// Reconnects by itself after Redis goes away
+    conn: redis::aio::ConnectionManager,
+    schema: Arc<RwLock<SchemaCache>>,
+    mirror: Mirror,
+    /// Tables to load again: the load failed, or writes were lost with the
+    /// connection (a restarted Redis may have lost everything)
+    stale: HashSet<String>,
+}
+
+/// Where and how rows are copied: the key layout and the stored columns.
+struct Mirror {
+    prefix: String,
+    /// `schema.table` -> mirrored columns
+    tables: HashMap<String, MirroredTable>,
+}
+
+/// A change as the Redis command that applies it.
+#[derive(Debug, PartialEq)]
+enum Write {
+    Set(String, String),
+    Del(String),
+}
+
+/// Subscribe the configured tables, load their current rows, then apply
+/// changes until the poller goes away. Tables are loaded again when their
+/// load fails or the Redis connection drops.
+pub async fn run(
+    engine: Arc<RealtimeEngine>,
+    pool: Arc<Pool>,
+    schema: Arc<RwLock<SchemaCache>>,
+    config: AppConfig,
+) {
+    let Some(cache_config) = config.redis_cache else {
+        return;
+    };
+    let conn = match redis::Client::open(cache_config.url.as_str()) {
+        Ok(client) => redis::aio::ConnectionManager::new(client).await,
+        Err(e) => Err(e),
+    };
+    let conn = match conn {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::error!("Redis cache disabled: {}", e);
+            return;
+        }
+    };
+
+    // Subscribe before loading so no change slips in between
+    let client_id = Uuid::new_v4();
+    let (tx, mut rx) = mpsc::channel::<ServerMessage>(SINK_BUFFER);
+    let mut tables = HashMap::new();
+    for (name, table) in &cache_config.tables {
+        match engine.subscribe_sink(client_id, name, tx.clone()).await {
+            Ok(table_key) => {
+                tables.insert(table_key, table.clone());
+            }
+            Err(e) => tracing::warn!("Redis cache: skipping {}: {}", name, e),
+        }
+    }
+    drop(tx);
+
+    let mut sink = RedisSink {
+        conn,
+        schema,
+        stale: tables.keys().cloned().collect(),
+        mirror: Mirror {
+            prefix: cache_config.prefix.unwrap_or_else(|| "lazypaw".to_string()),
+            tables,
+        },
+    };
+    sink.reload_stale(&pool).await;
+
+    let mut retry = tokio::time::interval(RELOAD_RETRY);
+    retry.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                if let ServerMessage::Change {
+                    type_,
+                    table,
+                    record,
+                    ..
+                } = msg
+                {
+                    if let Err(e) = sink.apply(&type_, &table, record).await {
+                        tracing::warn!("Redis cache update for {} failed: {}", table, e);
+                    }
+                }
+            }
+            _ = retry.tick(), if !sink.stale.is_empty() => sink.reload_stale(&pool).await,
+        }
+    }
+}
+
+impl Mirror {
+    fn key(&self, table_key: &str, id: &str) -> String {
+        format!("{}:{}:{}", self.prefix, table_key, id)
+    }
+
+    /// The row as stored: configured columns plus the primary key.
+    fn value(
+        &self,
+        table_key: &str,
+        primary_key: &[String],
+        mut record: serde_json::Map<String, JsonValue>,
+    ) -> String {
+        if let Some(columns) = self.tables.get(table_key).map(|t| &t.columns) {
+            if !columns.is_empty() {
+                record.retain(|k, _| {
+                    columns.iter().any(|c| c.eq_ignore_ascii_case(k))
+                        || primary_key.iter().any(|pk| pk.eq_ignore_ascii_case(k))
+                });
+            }
+        }
+        JsonValue::Object(record).to_string()
+    }
+
+    /// The write that brings a row's copy in line with a change: a DELETE
+    /// removes it, anything else stores the new row.
+    fn write(
+        &self,
+        op: &str,
+        table_key: &str,
+        primary_key: &[String],
+        record: serde_json::Map<String, JsonValue>,
+    ) -> Result<Write, String> {
+        let id = realtime::row_id(primary_key, &record)
+            .ok_or_else(|| "change has no primary key value".to_string())?;
+        let key = self.key(table_key, &id);
+        if op == "DELETE" {
+            return Ok(Write::Del(key));
+        }
+        Ok(Write::Set(key, self.value(table_key, primary_key, record)))
+    }
+}
+
+impl RedisSink {
+    async fn table_info(&self, table_key: &str) -> Option<TableInfo> {
+        let (schema_name, table_name) = table_key.split_once('.')?;
+        self.schema
+            .read()
+            .await
+            .get_table(schema_name, table_name)
+            .cloned()
+    }
+
+    /// Load every table in `stale`; the ones that fail stay there.
+    async fn reload_stale(&mut self, pool: &Arc<Pool>) {
+        let mut table_keys: Vec<String> = self.stale.iter().cloned().collect();
+        table_keys.sort();
+        for table_key in table_keys {
+            match self.load(pool, &table_key).await {
+                Ok(rows) => {
+                    tracing::info!("Redis cache: loaded {} rows of {}", rows, table_key);
+                    self.stale.remove(&table_key);
+                }
+                Err(e) => tracing::warn!("Redis cache: load of {} failed: {}", table_key, e),
+            }
+        }
+    }
+
+    /// A write failed: when the connection went with it, Redis may have
+    /// restarted or missed other writes, so every table is loaded again.
+    fn write_failed(&mut self, e: &redis::RedisError) {
+        if e.is_connection_dropped() || e.is_connection_refusal() || e.is_io_error() {
+            self.stale = self.mirror.tables.keys().cloned().collect();
+        }
+    }
+
+    /// Write every current row of a table. Returns the number of rows.
+    async fn load(&mut self, pool: &Arc<Pool>, table_key: &str) -> Result<usize, String> {
+        let table = self
+            .table_info(table_key)
+            .await
+            .ok_or_else(|| format!("Table not found: {}", table_key))?;
+        if table.primary_key.is_empty() {
+            return Err("no primary key".to_string());
+        }
+        let sql = format!(
+            "SELECT * FROM [{}].[{}]",
+            escape_ident(&table.schema),
+            escape_ident(&table.name)
+        );
+        let rows = {
+            let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+            claw::Query::new(sql)
+                .query(conn.client())
+                .await
+                .map_err(|e| e.to_string())?
+                .into_first_result()
+                .await
+                .map_err(|e| e.to_string())?
+        };
+
+        let mut items = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let record = types::row_to_json(row);
+            if let Some(id) = realtime::row_id(&table.primary_key, &record) {
+                let value = self.mirror.value(table_key, &table.primary_key, record);
+                items.push((self.mirror.key(table_key, &id), value));
+            }
+        }
+        for batch in items.chunks(LOAD_BATCH) {
+            if let Err(e) = self.conn.mset::<_, _, ()>(batch).await {
+                self.write_failed(&e);
+                return Err(e.to_string());
+            }
+        }
+        Ok(items.len())
+    }
+
+    async fn apply(
+        &mut self,
+        op: &str,
+        table_key: &str,
+        record: serde_json::Map<String, JsonValue>,
+    ) -> Result<(), String> {
+        if !self.mirror.tables.contains_key(table_key) {
+            return Ok(());
+        }
+        let primary_key = self
+            .table_info(table_key)
+            .await
+            .map(|t| t.primary_key)
+            .unwrap_or_default();
+        let result = match self.mirror.write(op, table_key, &primary_key, record)? {
+            Write::Del(key) => self.conn.del::<_, ()>(&key).await,
+            Write::Set(key, value) => self.conn.set::<_, _, ()>(&key, value).await,
+        };
+        if let Err(e) = result {
+            self.write_failed(&e);
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(columns: &[&str]) -> Mirror {
+        let table = MirroredTable {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+        };
+        Mirror {
+            prefix: "app".to_string(),
+            tables: HashMap::from([("dbo.users".to_string(), table)]),
+        }
+    }
+
+    fn record(value: JsonValue) -> serde_json::Map<String, JsonValue> {
+        match value {
+            JsonValue::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(mirror(&[]).key("dbo.users", "7"), "app:dbo.users:7");
+    }
+
+    #[test]
+    fn test_value() {
+        let pk = vec!["id".to_string()];
+        let row = record(serde_json::json!({"id": 7, "name": "Ada", "secret": "x"}));
+
+        let all: JsonValue =
+            serde_json::from_str(&mirror(&[]).value("dbo.users", &pk, row.clone())).unwrap();
+        assert_eq!(
+            all,
+            serde_json::json!({"id": 7, "name": "Ada", "secret": "x"})
+        );
+
+        // Configured columns, plus the key even when it isn't listed
+        let some: JsonValue =
+            serde_json::from_str(&mirror(&["NAME"]).value("dbo.users", &pk, row)).unwrap();
+        assert_eq!(some, serde_json::json!({"id": 7, "name": "Ada"}));
+    }
+
+    #[test]
+    fn test_write() {
+        let mirror = mirror(&["name"]);
+        let pk = vec!["id".to_string()];
+        let row = || record(serde_json::json!({"id": 7, "name": "Ada"}));
+
+        let key = mirror.key("dbo.users", "7");
+        assert_eq!(
+            mirror.write("DELETE", "dbo.users", &pk, row()),
+            Ok(Write::Del(key.clone()))
+        );
+        for op in ["INSERT", "UPDATE"] {
+            match mirror.write(op, "dbo.users", &pk, row()) {
+                Ok(Write::Set(k, value)) => {
+                    assert_eq!(k, key);
+                    assert_eq!(
+                        serde_json::from_str::<JsonValue>(&value).unwrap(),
+                        serde_json::json!({"id": 7, "name": "Ada"})
+                    );
+                }
+                other => panic!("{}: {:?}", op, other),
+            }
+        }
+
+        let keyless = record(serde_json::json!({"name": "Ada"}));
+        assert!(mirror.write("DELETE", "dbo.users", &pk, keyless).is_err());
+    }
+}
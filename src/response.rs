@@ -1,8 +1,11 @@
 //! Response formatting: JSON, CSV, Arrow IPC, Arrow JSON.
 
 use crate::error::Error;
-use axum::http::StatusCode;
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
+use std::collections::VecDeque;
+use std::sync::Mutex;
 
 /// Content types we support.
 #[derive(Debug, Clone, PartialEq)]
@@ -38,9 +41,18 @@ pub fn parse_accept(accept: Option<&str>) -> ResponseFormat {
 #[derive(Debug, Clone, Default)]
 pub struct Preferences {
     pub return_mode: ReturnMode,
-    pub count: bool,
+    pub count: Option<CountMode>,
     pub resolution: Option<String>,
     pub tx: TxPreference,
+    pub missing: Option<String>,
+    /// `isolation=snapshot`: run all reads in one snapshot transaction
+    pub snapshot: bool,
+    /// `lang=it`: session language for engine messages and date names
+    pub lang: Option<String>,
+    /// `dateformat=dmy`: order of date parts in date literals
+    pub dateformat: Option<String>,
+    /// `identity=insert`: write explicit values into identity columns
+    pub identity_insert: bool,
 }
 
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -51,6 +63,28 @@ pub enum ReturnMode {
     Minimal,
 }
 
+/// How `Prefer: count=...` totals are computed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CountMode {
+    /// `COUNT(*)` over the filtered rows
+    Exact,
+    /// The optimizer's row estimate for the filtered query
+    Planned,
+    /// Table row count from partition stats (unfiltered requests only)
+    Estimated,
+}
+
+impl CountMode {
+    /// The value echoed in `Preference-Applied`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CountMode::Exact => "count=exact",
+            CountMode::Planned => "count=planned",
+            CountMode::Estimated => "count=estimated",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub enum TxPreference {
     #[default]
@@ -75,13 +109,29 @@ pub fn parse_prefer(prefer: Option<&str>) -> Preferences {
         } else if part == "return=minimal" {
             prefs.return_mode = ReturnMode::Minimal;
         } else if part == "count=exact" {
-            prefs.count = true;
+            prefs.count = Some(CountMode::Exact);
+        } else if part == "count=planned" {
+            prefs.count = Some(CountMode::Planned);
+        } else if part == "count=estimated" {
+            prefs.count = Some(CountMode::Estimated);
         } else if part == "resolution=merge-duplicates" {
             prefs.resolution = Some("merge-duplicates".to_string());
+        } else if part == "resolution=continue-on-error" {
+            prefs.resolution = Some("continue-on-error".to_string());
+        } else if part == "missing=ignore" {
+            prefs.missing = Some("ignore".to_string());
+        } else if part == "identity=insert" {
+            prefs.identity_insert = true;
+        } else if part == "isolation=snapshot" {
+            prefs.snapshot = true;
         } else if part == "tx=rollback" {
             prefs.tx = TxPreference::Rollback;
         } else if part == "tx=commit" {
             prefs.tx = TxPreference::Commit;
+        } else if let Some(lang) = part.strip_prefix("lang=") {
+            prefs.lang = Some(lang.to_string());
+        } else if let Some(format) = part.strip_prefix("dateformat=") {
+            prefs.dateformat = Some(format.to_string());
         }
     }
 
@@ -93,20 +143,64 @@ pub fn rows_to_json(rows: &[serde_json::Map<String, serde_json::Value>]) -> Stri
     serde_json::to_string(rows).unwrap_or_else(|_| "[]".to_string())
 }
 
+/// Number of elements in a JSON array produced by SQL Server, counted
+/// without parsing it: top-level commas outside strings.
+pub fn json_array_len(json: &str) -> usize {
+    if json.trim() == "[]" {
+        return 0;
+    }
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    let mut commas = 0;
+    for b in json.bytes() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            b',' if depth == 1 => commas += 1,
+            _ => {}
+        }
+    }
+    commas + 1
+}
+
 /// Format rows as CSV.
 pub fn rows_to_csv(
     rows: &[serde_json::Map<String, serde_json::Value>],
     columns: &[String],
 ) -> Result<String, Error> {
+    let (data, _) = csv_rows(rows, columns, true)?;
+    String::from_utf8(data).map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// CSV for `rows`, with the header line if `header`, and the byte offset
+/// each row starts at.
+pub fn csv_rows(
+    rows: &[serde_json::Map<String, serde_json::Value>],
+    columns: &[String],
+    header: bool,
+) -> Result<(Vec<u8>, Vec<usize>), Error> {
     let mut writer = csv::Writer::from_writer(Vec::new());
 
-    // Header
-    writer
-        .write_record(columns)
-        .map_err(|e| Error::Internal(e.to_string()))?;
+    if header {
+        writer
+            .write_record(columns)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+    }
 
     // Rows
+    let mut starts = Vec::with_capacity(rows.len());
     for row in rows {
+        writer.flush().map_err(|e| Error::Internal(e.to_string()))?;
+        starts.push(writer.get_ref().len());
         let record: Vec<String> = columns
             .iter()
             .map(|col| match row.get(col) {
@@ -124,18 +218,59 @@ pub fn rows_to_csv(
     let data = writer
         .into_inner()
         .map_err(|e| Error::Internal(e.to_string()))?;
-    String::from_utf8(data).map_err(|e| Error::Internal(e.to_string()))
+    Ok((data, starts))
 }
 
-/// Format an Arrow RecordBatch as IPC stream bytes.
-pub fn record_batch_to_ipc(batch: &arrow::record_batch::RecordBatch) -> Result<Vec<u8>, Error> {
+/// Build an Arrow RecordBatch from JSON rows (e.g. DML OUTPUT results),
+/// inferring the schema from the values.
+pub fn json_rows_to_record_batch(
+    rows: &[serde_json::Map<String, serde_json::Value>],
+) -> Result<arrow::record_batch::RecordBatch, Error> {
+    if rows.is_empty() {
+        let schema = std::sync::Arc::new(arrow::datatypes::Schema::empty());
+        return Ok(arrow::record_batch::RecordBatch::new_empty(schema));
+    }
+
+    let values: Vec<serde_json::Value> = rows
+        .iter()
+        .map(|r| serde_json::Value::Object(r.clone()))
+        .collect();
+    let schema = arrow_json::reader::infer_json_schema_from_iterator(values.iter().map(Ok))
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    let mut decoder = arrow_json::ReaderBuilder::new(std::sync::Arc::new(schema))
+        .build_decoder()
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    decoder
+        .serialize(&values)
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    decoder
+        .flush()
+        .map_err(|e| Error::Internal(e.to_string()))?
+        .ok_or_else(|| Error::Internal("No rows decoded".to_string()))
+}
+
+/// Format an Arrow RecordBatch as IPC stream bytes, split into record
+/// batches of at most `batch_rows` rows.
+pub fn record_batch_to_ipc(
+    batch: &arrow::record_batch::RecordBatch,
+    batch_rows: usize,
+) -> Result<Vec<u8>, Error> {
     let mut buf = Vec::new();
     {
         let mut writer = arrow_ipc::writer::StreamWriter::try_new(&mut buf, &batch.schema())
             .map_err(|e| Error::Internal(e.to_string()))?;
-        writer
-            .write(batch)
-            .map_err(|e| Error::Internal(e.to_string()))?;
+        // An empty result is still one (empty) batch
+        let mut offset = 0;
+        loop {
+            let rows = batch_rows.max(1).min(batch.num_rows() - offset);
+            writer
+                .write(&batch.slice(offset, rows))
+                .map_err(|e| Error::Internal(e.to_string()))?;
+            offset += rows;
+            if offset >= batch.num_rows() {
+                break;
+            }
+        }
         writer
             .finish()
             .map_err(|e| Error::Internal(e.to_string()))?;
@@ -160,7 +295,7 @@ pub fn record_batch_to_arrow_json(
 
 /// Build the final HTTP response with appropriate headers.
 pub fn build_response(
-    body: Vec<u8>,
+    body: impl Into<Body>,
     content_type: &str,
     status: StatusCode,
     content_range: Option<String>,
@@ -179,6 +314,464 @@ pub fn build_response(
     }
 
     builder
-        .body(axum::body::Body::from(body))
+        .body(body.into())
         .unwrap_or_else(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response())
 }
+
+/// A `Range: bytes=` request resolved against a body.
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// Not a single byte range: serve the whole body
+    Full,
+    /// First and last byte, inclusive
+    Partial(usize, usize),
+    /// Starts past the end of the body
+    Unsatisfiable,
+}
+
+/// Resolve `bytes=N-`, `bytes=N-M` or `bytes=-N` (the last N bytes) against
+/// a body of `len` bytes. Several ranges, other units and malformed specs
+/// get the whole body.
+pub fn byte_range(range: &str, len: usize) -> ByteRange {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    match (first.parse::<usize>(), last.parse::<usize>()) {
+        (Ok(first), _) if first >= len => ByteRange::Unsatisfiable,
+        (Ok(first), Ok(last)) if last >= first => ByteRange::Partial(first, last.min(len - 1)),
+        (Ok(first), Err(_)) if last.is_empty() => ByteRange::Partial(first, len - 1),
+        (Err(_), Ok(suffix)) if first.is_empty() && suffix > 0 && len > 0 => {
+            ByteRange::Partial(len.saturating_sub(suffix), len - 1)
+        }
+        (Err(_), Ok(_)) if first.is_empty() => ByteRange::Unsatisfiable,
+        _ => ByteRange::Full,
+    }
+}
+
+/// Bytes hashed together in an export. A CSV export's segments start on a
+/// row, so a resume can read the rows again from the segment its range
+/// starts in.
+const EXPORT_SEGMENT_BYTES: usize = 1024 * 1024;
+
+/// Where a segment of an export starts, and the hash of its bytes.
+#[derive(Debug, Clone)]
+struct Segment {
+    byte: usize,
+    row: usize,
+    digest: ring::digest::Digest,
+}
+
+/// Hash `body` in segments starting at `bounds` (byte, row), the first at 0.
+fn segments(body: &[u8], bounds: &[(usize, usize)]) -> Vec<Segment> {
+    bounds
+        .iter()
+        .enumerate()
+        .map(|(i, &(byte, row))| {
+            let end = bounds.get(i + 1).map_or(body.len(), |&(next, _)| next);
+            Segment {
+                byte,
+                row,
+                digest: ring::digest::digest(&ring::digest::SHA256, &body[byte..end]),
+            }
+        })
+        .collect()
+}
+
+/// The `ETag` of an export: SHA-256 over its segments' hashes, quoted.
+fn segments_etag(segments: &[Segment]) -> String {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    for segment in segments {
+        context.update(segment.digest.as_ref());
+    }
+    let hex: String = context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("\"{}\"", hex)
+}
+
+/// A rendered export (CSV, Arrow IPC): enough to serve it again, whole or
+/// from a byte offset.
+#[derive(Debug, Clone)]
+pub struct Export {
+    /// Empty once an export too large to keep whole is filed away
+    pub body: Bytes,
+    /// Length of the whole body
+    pub len: usize,
+    /// Hash of the body's segments, quoted
+    pub etag: String,
+    pub content_type: &'static str,
+    pub status: StatusCode,
+    /// `Content-Range` of the rows it holds
+    pub content_range: String,
+    segments: Vec<Segment>,
+}
+
+impl Export {
+    /// An export read back only whole, such as Arrow IPC.
+    pub fn new(
+        body: Vec<u8>,
+        content_type: &'static str,
+        status: StatusCode,
+        content_range: String,
+    ) -> Self {
+        let segments = segments(&body, &[(0, 0)]);
+        Export::with_segments(body, segments, content_type, status, content_range)
+    }
+
+    /// A CSV export from [`csv_rows`] with its header, segmented on the rows
+    /// that begin at `row_starts`.
+    pub fn csv(
+        body: Vec<u8>,
+        row_starts: &[usize],
+        status: StatusCode,
+        content_range: String,
+    ) -> Self {
+        let mut bounds = vec![(0, 0)];
+        for (row, &byte) in row_starts.iter().enumerate() {
+            if byte - bounds[bounds.len() - 1].0 >= EXPORT_SEGMENT_BYTES {
+                bounds.push((byte, row));
+            }
+        }
+        let segments = segments(&body, &bounds);
+        Export::with_segments(
+            body,
+            segments,
+            "text/csv; charset=utf-8",
+            status,
+            content_range,
+        )
+    }
+
+    fn with_segments(
+        body: Vec<u8>,
+        segments: Vec<Segment>,
+        content_type: &'static str,
+        status: StatusCode,
+        content_range: String,
+    ) -> Self {
+        Export {
+            len: body.len(),
+            body: Bytes::from(body),
+            etag: segments_etag(&segments),
+            content_type,
+            status,
+            content_range,
+            segments,
+        }
+    }
+
+    /// Whether the body is still held, so a range is just a slice of it.
+    pub fn is_whole(&self) -> bool {
+        self.body.len() == self.len
+    }
+
+    /// Memory this export takes while kept.
+    fn footprint(&self) -> usize {
+        self.body.len() + self.segments.len() * std::mem::size_of::<Segment>()
+    }
+
+    /// The row to read again from to serve `range`: the first of the last
+    /// segment starting at or before it. `None` when that is the first row,
+    /// and the whole export has to be read anyway.
+    pub fn resume_row(&self, range: &str) -> Option<usize> {
+        let ByteRange::Partial(first, _) = byte_range(range, self.len) else {
+            return None;
+        };
+        self.segments
+            .iter()
+            .rev()
+            .find(|s| s.byte <= first)
+            .map(|s| s.row)
+            .filter(|row| *row > 0)
+    }
+
+    /// Serve `range` of this export from its rows read again from `row` on
+    /// ([`csv_rows`] without the header, and where each row starts). `None`
+    /// when they don't hash to this export's `ETag`: the rows changed since.
+    pub fn resume(
+        &self,
+        row: usize,
+        tail: Vec<u8>,
+        row_starts: &[usize],
+        range: &str,
+    ) -> Option<Response> {
+        let k = self.segments.iter().position(|s| s.row == row)?;
+        let base = self.segments[k].byte;
+        // The kept segments' bounds, within the rows read again
+        let bounds = self.segments[k..]
+            .iter()
+            .map(|s| {
+                let byte = *row_starts.get(s.row - row)?;
+                (base + byte == s.byte).then_some((byte, s.row))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut segments = self.segments[..k].to_vec();
+        segments.extend(self::segments(&tail, &bounds));
+        if segments_etag(&segments) != self.etag {
+            return None;
+        }
+        let ByteRange::Partial(first, last) = byte_range(range, self.len) else {
+            return None;
+        };
+        let body = Bytes::from(tail).slice(first.checked_sub(base)?..=last - base);
+        let mut response = partial_response(body, (first, last, self.len), self.content_type);
+        export_headers(&mut response, &self.etag);
+        Some(response)
+    }
+}
+
+/// Memory kept for resumable exports. An export larger than this keeps only
+/// its segment hashes, and a resume of it reads the rows again from the
+/// segment the range starts in.
+const EXPORT_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Exports recently served, so resuming one with `Range` and `If-Range`
+/// slices the body that was cut off instead of running the query again.
+/// Entries are keyed by the request as well as the `ETag`, so a tag only
+/// resumes the export it came from; the oldest go first.
+#[derive(Debug, Default)]
+pub struct ExportCache {
+    entries: Mutex<VecDeque<(String, Export)>>,
+}
+
+impl ExportCache {
+    /// The export served for `key` with `etag`, if it's still kept.
+    pub fn get(&self, key: &str, etag: &str) -> Option<Export> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .find(|(k, export)| k == key && export.etag == etag.trim())
+            .map(|(_, export)| export.clone())
+    }
+
+    /// Keep an export served for `key`, replacing an older one for it.
+    pub fn insert(&self, key: String, mut export: Export) {
+        if export.body.len() > EXPORT_CACHE_BYTES {
+            export.body = Bytes::new();
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(k, _)| *k != key);
+        let mut size: usize = entries.iter().map(|(_, e)| e.footprint()).sum();
+        while size + export.footprint() > EXPORT_CACHE_BYTES {
+            match entries.pop_front() {
+                Some((_, oldest)) => size -= oldest.footprint(),
+                None => break,
+            }
+        }
+        entries.push_back((key, export));
+    }
+}
+
+/// Response for an export that an interrupted download can resume with
+/// `Range: bytes=`. An `If-Range` naming another tag (the rows changed
+/// since) gets the whole body again.
+pub fn build_export_response(
+    export: Export,
+    range: Option<&str>,
+    if_range: Option<&str>,
+) -> Response {
+    let range = range
+        .filter(|_| if_range.is_none_or(|tag| tag.trim() == export.etag))
+        .map_or(ByteRange::Full, |r| byte_range(r, export.len));
+    let mut response = match range {
+        ByteRange::Full => build_response(
+            export.body,
+            export.content_type,
+            export.status,
+            Some(export.content_range),
+            None,
+        ),
+        ByteRange::Partial(first, last) => partial_response(
+            export.body.slice(first..=last),
+            (first, last, export.len),
+            export.content_type,
+        ),
+        ByteRange::Unsatisfiable => build_response(
+            Bytes::new(),
+            export.content_type,
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            Some(format!("bytes */{}", export.len)),
+            None,
+        ),
+    };
+    export_headers(&mut response, &export.etag);
+    response
+}
+
+/// A 206 with bytes `first..=last` of a body `len` bytes long.
+fn partial_response(
+    body: Bytes,
+    (first, last, len): (usize, usize, usize),
+    content_type: &str,
+) -> Response {
+    build_response(
+        body,
+        content_type,
+        StatusCode::PARTIAL_CONTENT,
+        Some(format!("bytes {}-{}/{}", first, last, len)),
+        None,
+    )
+}
+
+/// Headers every export carries, whole or in part.
+fn export_headers(response: &mut Response, etag: &str) {
+    let headers = response.headers_mut();
+    headers.insert("accept-ranges", HeaderValue::from_static("bytes"));
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert("etag", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_batch_to_ipc_batch_rows() {
+        use arrow::array::Int32Array;
+        use arrow::datatypes::{DataType, Field, Schema};
+        use arrow::record_batch::RecordBatch;
+
+        let schema =
+            std::sync::Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![std::sync::Arc::new(Int32Array::from_iter_values(0..10))],
+        )
+        .unwrap();
+        let batch_sizes = |bytes: Vec<u8>| -> Vec<usize> {
+            arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(bytes), None)
+                .unwrap()
+                .map(|b| b.unwrap().num_rows())
+                .collect()
+        };
+        assert_eq!(
+            batch_sizes(record_batch_to_ipc(&batch, 4).unwrap()),
+            [4, 4, 2]
+        );
+        assert_eq!(batch_sizes(record_batch_to_ipc(&batch, 100).unwrap()), [10]);
+        assert_eq!(
+            batch_sizes(record_batch_to_ipc(&batch.slice(0, 0), 4).unwrap()),
+            [0]
+        );
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let cases = [
+            ("bytes=0-", ByteRange::Partial(0, 9)),
+            ("bytes=4-", ByteRange::Partial(4, 9)),
+            ("bytes=2-5", ByteRange::Partial(2, 5)),
+            ("bytes=2-50", ByteRange::Partial(2, 9)),
+            ("bytes=-3", ByteRange::Partial(7, 9)),
+            ("bytes=-30", ByteRange::Partial(0, 9)),
+            ("bytes=-0", ByteRange::Unsatisfiable),
+            ("bytes=10-", ByteRange::Unsatisfiable),
+            ("bytes=12-20", ByteRange::Unsatisfiable),
+            ("bytes=0-1,4-5", ByteRange::Full),
+            ("bytes=5-2", ByteRange::Full),
+            ("bytes=abc", ByteRange::Full),
+            ("items=0-5", ByteRange::Full),
+        ];
+        for (range, expected) in cases {
+            assert_eq!(byte_range(range, 10), expected, "{}", range);
+        }
+        assert_eq!(byte_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=-5", 0), ByteRange::Unsatisfiable);
+    }
+
+    fn csv_export_rows(
+        count: usize,
+        last: &str,
+    ) -> Vec<serde_json::Map<String, serde_json::Value>> {
+        (0..count)
+            .map(|i| {
+                let name = if i + 1 == count {
+                    last.to_string()
+                } else {
+                    format!("row number {} of the export", i)
+                };
+                serde_json::json!({"id": i, "name": name})
+                    .as_object()
+                    .unwrap()
+                    .clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_large_csv_export() {
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let rows = csv_export_rows(100_000, "last");
+        let (body, row_starts) = csv_rows(&rows, &columns, true).unwrap();
+        assert_eq!(
+            String::from_utf8(body.clone()).unwrap(),
+            rows_to_csv(&rows, &columns).unwrap()
+        );
+        let len = body.len();
+        let export = Export::csv(
+            body.clone(),
+            &row_starts,
+            StatusCode::OK,
+            "0-99999/*".into(),
+        );
+        assert!(len > 2 * EXPORT_SEGMENT_BYTES);
+
+        // Within the first segment the whole export is read anyway
+        assert_eq!(export.resume_row("bytes=100-"), None);
+        let first = len - 1000;
+        let range = format!("bytes={}-", first);
+        let row = export.resume_row(&range).unwrap();
+        assert!(row_starts[row] < first);
+
+        let (tail, tail_starts) = csv_rows(&rows[row..], &columns, false).unwrap();
+        let response = export.resume(row, tail, &tail_starts, &range).unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()["content-range"],
+            format!("bytes {}-{}/{}", first, len - 1, len).as_str()
+        );
+        assert_eq!(response.headers()["etag"], export.etag.as_str());
+
+        // Rows that changed since don't resume
+        let changed = csv_export_rows(100_000, "changed");
+        let (tail, tail_starts) = csv_rows(&changed[row..], &columns, false).unwrap();
+        assert!(export.resume(row, tail, &tail_starts, &range).is_none());
+        let (tail, tail_starts) = csv_rows(&rows[row + 1..], &columns, false).unwrap();
+        assert!(export.resume(row, tail, &tail_starts, &range).is_none());
+    }
+
+    #[test]
+    fn test_export_cache() {
+        let cache = ExportCache::default();
+        let export = Export::new(b"abc".to_vec(), "text/csv", StatusCode::OK, "0-0/*".into());
+        let etag = export.etag.clone();
+        cache.insert("k".into(), export);
+        assert!(cache.get("k", &etag).unwrap().is_whole());
+        assert!(cache.get("k", "\"other\"").is_none());
+        assert!(cache.get("other", &etag).is_none());
+
+        let response = build_export_response(
+            cache.get("k", &etag).unwrap(),
+            Some("bytes=1-"),
+            Some(&etag),
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()["content-range"], "bytes 1-2/3");
+        let response = build_export_response(
+            cache.get("k", &etag).unwrap(),
+            Some("bytes=1-"),
+            Some("\"other\""),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
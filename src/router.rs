@@ -1,6 +1,10 @@
 //! Axum router generation from schema.
 
+use crate::auth_guard::{self, AuthGuard};
+use crate::docs;
+use crate::graph::{self, GraphFormat};
 use crate::handlers::{self, AppState};
+use crate::ipfilter::{self, IpFilter};
 use crate::openapi;
 use crate::realtime::RealtimeEngine;
 use crate::realtime_ws;
@@ -10,24 +14,61 @@ use axum::response::{Html, IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::Router;
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
-/// Build the axum router from the schema.
-pub fn build_router(state: AppState, engine: Option<Arc<RealtimeEngine>>) -> Router {
+/// Build the axum router from the schema. `ip_filter` runs outermost
+/// (inside tracing), so rejected clients never reach CORS or auth; the
+/// `auth_guard` ban check comes next.
+pub fn build_router(
+    state: AppState,
+    engine: Option<Arc<RealtimeEngine>>,
+    ip_filter: Option<IpFilter>,
+    auth_guard: Option<AuthGuard>,
+) -> Router {
+    let cors = cors_layer(&state);
     let mut router = Router::new()
         // OpenAPI spec at root
         .route("/", get(handle_openapi))
         // Swagger UI
         .route("/swagger", get(handle_swagger))
+        // Data catalog
+        .route("/docs", get(handle_docs))
+        // ER diagram (Mermaid, or DOT with ?format=dot)
+        .route("/schema/graph", get(handle_graph))
+        // Tables the caller can read
+        .route("/tables", get(handlers::handle_tables))
+        // Atomic multi-table writes
+        .route("/batch", post(handlers::handle_batch))
+        // Signed links for header-less GETs
+        .route("/sign", post(handlers::handle_sign))
+        // Blue/green schema cutover (admin role only)
+        .route(
+            "/admin/cutover",
+            get(handlers::handle_cutover_status).post(handlers::handle_cutover),
+        )
+        // Connection audit (admin role only)
+        .route("/admin/pool", get(handlers::handle_pool_status))
+        .route(
+            "/admin/pool/{id}/recycle",
+            post(handlers::handle_pool_recycle),
+        )
+        // Realtime subscriptions and poller lag (admin role only)
+        .route("/admin/realtime", get(handlers::handle_realtime_status))
+        .route("/metrics", get(handlers::handle_metrics))
         // RPC endpoint
-        .route("/rpc/{procedure}", post(handlers::handle_rpc));
+        .route(
+            "/rpc/{procedure}",
+            get(handlers::handle_rpc_get).post(handlers::handle_rpc),
+        );
 
     // Realtime websocket endpoint
+    let auth_guard = auth_guard.map(Arc::new);
     if let Some(engine) = engine {
         let ws_state = realtime_ws::WsState {
             engine,
             config: state.config.clone(),
+            guard: auth_guard.clone(),
         };
         router = router.route(
             "/realtime",
@@ -35,24 +76,45 @@ pub fn build_router(state: AppState, engine: Option<Arc<RealtimeEngine>>) -> Rou
         );
     }
 
-    router
+    router = router
         // Table endpoints: /{table} (default schema) and /{schema}/{table}
         .route(
             "/{*path}",
             get(handle_table_get)
+                .head(handle_table_head)
                 .post(handle_table_post)
                 .patch(handle_table_patch)
+                .put(handle_table_put)
                 .delete(handle_table_delete),
         )
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http())
-        .with_state(state)
+        .layer(cors);
+
+    if let Some(guard) = auth_guard {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            guard,
+            auth_guard::middleware,
+        ));
+    }
+    if let Some(filter) = ip_filter {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            Arc::new(filter),
+            ipfilter::middleware,
+        ));
+    }
+
+    router.layer(TraceLayer::new_for_http()).with_state(state)
 }
 
 /// Root handler: returns OpenAPI spec.
 async fn handle_openapi(State(state): State<AppState>) -> Response {
     let schema = state.schema.read().await;
-    let spec = openapi::generate_openapi(&schema, &state.config);
+    if state.config.openapi_examples_role.is_some() {
+        schema
+            .examples
+            .get_or_init(|| handlers::sample_examples(&state, &schema))
+            .await;
+    }
+    let spec = openapi::generate_openapi(&schema, &state.serving_config());
     let json = serde_json::to_string_pretty(&spec).unwrap_or_default();
     (
         StatusCode::OK,
@@ -70,26 +132,124 @@ async fn handle_swagger(State(state): State<AppState>) -> Html<String> {
     Html(openapi::swagger_ui_html(state.config.listen_port))
 }
 
+/// Data catalog handler: HTML, or Markdown for `Accept: text/markdown`.
+async fn handle_docs(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let schema = state.schema.read().await;
+    let wants_markdown = headers
+        .get(axum::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/markdown"));
+    if wants_markdown {
+        (
+            StatusCode::OK,
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "text/markdown; charset=utf-8",
+            )],
+            docs::render_markdown(&schema, &state.serving_config()),
+        )
+            .into_response()
+    } else {
+        Html(docs::render_html(&schema, &state.serving_config())).into_response()
+    }
+}
+
+/// ER diagram handler: `?format=mermaid` (default) or `?format=dot`.
+async fn handle_graph(
+    State(state): State<AppState>,
+    query: axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> Result<Response, crate::error::Error> {
+    let format = match query.get("format") {
+        Some(name) => GraphFormat::parse(name).ok_or_else(|| {
+            crate::error::Error::BadRequest(format!(
+                "Unsupported graph format: {}. Use 'mermaid' or 'dot'.",
+                name
+            ))
+        })?,
+        None => GraphFormat::Mermaid,
+    };
+    let schema = state.schema.read().await;
+    Ok((
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, format.content_type())],
+        graph::render(&schema, &state.config, format),
+    )
+        .into_response())
+}
+
+/// Any origin may call the API, except tables whose `cors_origins` names
+/// the ones allowed (preflights included).
+fn cors_layer(state: &AppState) -> CorsLayer {
+    let state = state.clone();
+    let origin = AllowOrigin::predicate(move |origin, request| {
+        let active = state.active_schema();
+        let allowed = state
+            .config
+            .table_config_for_path(request.uri.path(), &active)
+            .map(|t| &t.cors_origins)
+            .filter(|origins| !origins.is_empty());
+        match (allowed, origin.to_str()) {
+            (None, _) => true,
+            (Some(origins), Ok(origin)) => origins
+                .iter()
+                .any(|o| o == "*" || o.eq_ignore_ascii_case(origin)),
+            (Some(_), Err(_)) => false,
+        }
+    });
+    CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers(Any)
+}
+
 /// Table GET handler — parses wildcard path into path params.
 async fn handle_table_get(
     state: State<AppState>,
     axum::extract::Path(path): axum::extract::Path<String>,
     headers: HeaderMap,
-    query: axum::extract::Query<std::collections::HashMap<String, String>>,
+    query: axum::extract::Query<handlers::QueryParams>,
 ) -> Result<Response, crate::error::Error> {
     let path_params = parse_wildcard_path(&path);
     handlers::handle_get(state, axum::extract::Path(path_params), headers, query).await
 }
 
+/// Table HEAD handler: the GET pipeline with the body dropped, so clients
+/// can read `Content-Range` (and counts) without transferring rows.
+async fn handle_table_head(
+    state: State<AppState>,
+    path: axum::extract::Path<String>,
+    headers: HeaderMap,
+    query: axum::extract::Query<handlers::QueryParams>,
+) -> Result<Response, crate::error::Error> {
+    let response = handle_table_get(state, path, headers, query).await?;
+    let (mut parts, body) = response.into_parts();
+    // Content-Length still describes the body a GET would return
+    if let Some(len) = axum::body::HttpBody::size_hint(&body).exact() {
+        parts
+            .headers
+            .insert(axum::http::header::CONTENT_LENGTH, len.into());
+    }
+    Ok(Response::from_parts(parts, axum::body::Body::empty()))
+}
+
 /// Table POST handler.
 async fn handle_table_post(
     state: State<AppState>,
     axum::extract::Path(path): axum::extract::Path<String>,
     headers: HeaderMap,
+    query: axum::extract::Query<handlers::QueryParams>,
     body: axum::body::Bytes,
 ) -> Result<Response, crate::error::Error> {
     let path_params = parse_wildcard_path(&path);
-    handlers::handle_post(state, axum::extract::Path(path_params), headers, body).await
+    handlers::handle_post(
+        state,
+        axum::extract::Path(path_params),
+        headers,
+        query,
+        body,
+    )
+    .await
 }
 
 /// Table PATCH handler.
@@ -97,7 +257,7 @@ async fn handle_table_patch(
     state: State<AppState>,
     axum::extract::Path(path): axum::extract::Path<String>,
     headers: HeaderMap,
-    query: axum::extract::Query<std::collections::HashMap<String, String>>,
+    query: axum::extract::Query<handlers::QueryParams>,
     body: axum::body::Bytes,
 ) -> Result<Response, crate::error::Error> {
     let path_params = parse_wildcard_path(&path);
@@ -111,12 +271,35 @@ async fn handle_table_patch(
     .await
 }
 
+/// Table PUT handler. A segment past the table is the row's key, so three
+/// segments are passed through as schema, table and key.
+async fn handle_table_put(
+    state: State<AppState>,
+    axum::extract::Path(path): axum::extract::Path<String>,
+    headers: HeaderMap,
+    query: axum::extract::Query<handlers::QueryParams>,
+    body: axum::body::Bytes,
+) -> Result<Response, crate::error::Error> {
+    let mut path_params = parse_wildcard_path(&path);
+    if let Some(key) = path.split('/').filter(|s| !s.is_empty()).nth(2) {
+        path_params.push(("key".to_string(), key.to_string()));
+    }
+    handlers::handle_put(
+        state,
+        axum::extract::Path(path_params),
+        headers,
+        query,
+        body,
+    )
+    .await
+}
+
 /// Table DELETE handler.
 async fn handle_table_delete(
     state: State<AppState>,
     axum::extract::Path(path): axum::extract::Path<String>,
     headers: HeaderMap,
-    query: axum::extract::Query<std::collections::HashMap<String, String>>,
+    query: axum::extract::Query<handlers::QueryParams>,
 ) -> Result<Response, crate::error::Error> {
     let path_params = parse_wildcard_path(&path);
     handlers::handle_delete(state, axum::extract::Path(path_params), headers, query).await
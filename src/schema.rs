@@ -23,16 +23,109 @@ pub struct ColumnInfo {
     pub is_identity: bool,
     pub has_default: bool,
     pub is_computed: bool,
+    /// Covered by a full-text index (usable with fts/plfts/wfts filters)
+    pub is_fulltext_indexed: bool,
+    /// Collation of character columns (`None` for other types)
+    pub collation: Option<String>,
+    /// `MS_Description` extended property
+    pub description: Option<String>,
 }
 
 /// A foreign key relationship.
 #[derive(Debug, Clone, Serialize)]
 pub struct ForeignKey {
     pub constraint_name: String,
-    pub column_name: String,
+    /// Referencing columns, in constraint order (several for a composite FK)
+    pub columns: Vec<String>,
     pub ref_schema: String,
     pub ref_table: String,
-    pub ref_column: String,
+    /// Referenced columns, paired with `columns`
+    pub ref_columns: Vec<String>,
+}
+
+impl ForeignKey {
+    /// Embed info for following this FK from the referencing table.
+    fn many_to_one(&self) -> EmbedInfo {
+        EmbedInfo {
+            target_schema: self.ref_schema.clone(),
+            target_table: self.ref_table.clone(),
+            join_type: EmbedJoinType::ManyToOne,
+            source_columns: self.columns.clone(),
+            target_columns: self.ref_columns.clone(),
+        }
+    }
+
+    /// Embed info for following this FK back from the referenced table.
+    fn one_to_many(&self, schema: &str, table: &str) -> EmbedInfo {
+        EmbedInfo {
+            target_schema: schema.to_string(),
+            target_table: table.to_string(),
+            join_type: EmbedJoinType::OneToMany,
+            source_columns: self.ref_columns.clone(),
+            target_columns: self.columns.clone(),
+        }
+    }
+
+    /// Default embed name when the table name alone is ambiguous: from the
+    /// FK column, or the constraint name for a composite FK.
+    fn alias(&self) -> String {
+        match self.columns.as_slice() {
+            [column] => fk_embed_alias(column),
+            _ => self.constraint_name.clone(),
+        }
+    }
+}
+
+/// A parameter of a stored procedure or function.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamInfo {
+    /// Name without the leading `@`
+    pub name: String,
+    /// Base type name (`int`, `nvarchar`); `table` for table-valued parameters
+    pub data_type: String,
+    /// In characters for character types; -1 for `max`
+    pub max_length: Option<i32>,
+    pub precision: Option<i32>,
+    pub scale: Option<i32>,
+    /// Only natively compiled modules enforce `NOT NULL` parameters
+    pub is_nullable: bool,
+    pub is_output: bool,
+}
+
+/// A stored procedure or user-defined function.
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutineInfo {
+    pub name: String,
+    pub schema: String,
+    /// `sys.objects` type: `P`, `FN` (scalar function), `IF` / `TF`
+    /// (table-valued functions)
+    pub kind: String,
+    /// Parameters in call order
+    pub params: Vec<ParamInfo>,
+}
+
+impl RoutineInfo {
+    /// Full qualified name: [schema].[routine]
+    pub fn full_name(&self) -> String {
+        format!(
+            "[{}].[{}]",
+            self.schema.replace(']', "]]"),
+            self.name.replace(']', "]]")
+        )
+    }
+
+    /// Scalar or table-valued function rather than a procedure.
+    pub fn is_function(&self) -> bool {
+        self.kind != "P"
+    }
+
+    /// A parameter by name, with or without the leading `@`.
+    pub fn param(&self, name: &str) -> Option<&ParamInfo> {
+        let name = name.trim_start_matches('@');
+        self.params
+            .iter()
+            .find(|p| p.name.eq_ignore_ascii_case(name))
+    }
 }
 
 /// A table or view in the schema.
@@ -46,12 +139,74 @@ pub struct TableInfo {
     pub unique_constraints: Vec<Vec<String>>,
     pub is_view: bool,
     pub change_tracking_enabled: bool,
+    /// Table has enabled DML triggers (plain OUTPUT is not allowed)
+    pub has_triggers: bool,
+    /// Accepts INSERT/UPDATE/DELETE as is (always true for base tables);
+    /// see [`TableInfo::accepts`] for views with INSTEAD OF triggers
+    pub is_updatable: bool,
+    /// Writes handled by the view's INSTEAD OF triggers
+    pub instead_of: InsteadOf,
+    /// `MS_Description` extended property
+    pub description: Option<String>,
+}
+
+/// A kind of write, for asking what a table or view accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dml {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Which writes INSTEAD OF triggers take over, per triggering event.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct InsteadOf {
+    pub insert: bool,
+    pub update: bool,
+    pub delete: bool,
+}
+
+impl Dml {
+    /// The statement keyword, e.g. `INSERT`.
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Dml::Insert => "INSERT",
+            Dml::Update => "UPDATE",
+            Dml::Delete => "DELETE",
+        }
+    }
+}
+
+impl InsteadOf {
+    /// Whether a trigger takes over `dml`.
+    pub fn handles(&self, dml: Dml) -> bool {
+        match dml {
+            Dml::Insert => self.insert,
+            Dml::Update => self.update,
+            Dml::Delete => self.delete,
+        }
+    }
+
+    /// Whether any write goes through a trigger.
+    pub fn any(&self) -> bool {
+        self.insert || self.update || self.delete
+    }
 }
 
 impl TableInfo {
+    /// Whether `dml` can run against this table or view, directly or
+    /// through an INSTEAD OF trigger.
+    pub fn accepts(&self, dml: Dml) -> bool {
+        self.is_updatable || self.instead_of.handles(dml)
+    }
+
     /// Full qualified name: [schema].[table]
     pub fn full_name(&self) -> String {
-        format!("[{}].[{}]", self.schema, self.name)
+        format!(
+            "[{}].[{}]",
+            self.schema.replace(']', "]]"),
+            self.name.replace(']', "]]")
+        )
     }
 
     /// Get column info by name.
@@ -61,9 +216,75 @@ impl TableInfo {
             .find(|c| c.name.eq_ignore_ascii_case(name))
     }
 
+    /// The `rowversion` column, used for optimistic concurrency.
+    pub fn rowversion_column(&self) -> Option<&ColumnInfo> {
+        self.columns.iter().find(|c| c.is_rowversion())
+    }
+
     /// Columns that can be used in INSERT (non-identity, non-computed).
     pub fn insertable_columns(&self) -> Vec<&ColumnInfo> {
-        self.columns.iter().filter(|c| !c.is_identity).collect()
+        self.columns
+            .iter()
+            .filter(|c| c.read_only_reason().is_none())
+            .collect()
+    }
+}
+
+impl ColumnInfo {
+    /// Whether this is a rowversion (`timestamp`) column.
+    pub fn is_rowversion(&self) -> bool {
+        matches!(
+            self.data_type.to_lowercase().as_str(),
+            "timestamp" | "rowversion"
+        )
+    }
+
+    /// Why the column can't be written, if it can't.
+    pub fn read_only_reason(&self) -> Option<&'static str> {
+        if self.is_identity {
+            Some("identity column")
+        } else if self.is_computed {
+            Some("computed column")
+        } else if self.is_rowversion() {
+            Some("rowversion column")
+        } else {
+            None
+        }
+    }
+
+    /// T-SQL type declaration for this column (e.g. `nvarchar(50)`, `decimal(10,2)`).
+    pub fn type_decl(&self) -> String {
+        type_decl(&self.data_type, self.max_length, self.precision, self.scale)
+    }
+}
+
+impl ParamInfo {
+    /// T-SQL type declaration for a variable holding this parameter.
+    pub fn type_decl(&self) -> String {
+        type_decl(&self.data_type, self.max_length, self.precision, self.scale)
+    }
+}
+
+fn type_decl(
+    data_type: &str,
+    max_length: Option<i32>,
+    precision: Option<i32>,
+    scale: Option<i32>,
+) -> String {
+    let dt = data_type.to_lowercase();
+    match dt.as_str() {
+        "char" | "varchar" | "nchar" | "nvarchar" | "binary" | "varbinary" => match max_length {
+            Some(-1) => format!("{}(max)", dt),
+            Some(n) if n > 0 => format!("{}({})", dt, n),
+            _ => dt,
+        },
+        "decimal" | "numeric" => match (precision, scale) {
+            (Some(p), Some(s)) => format!("{}({},{})", dt, p, s),
+            _ => dt,
+        },
+        // rowversion values can't be inserted; carry the raw bytes instead
+        "timestamp" | "rowversion" => "binary(8)".to_string(),
+        _ => dt,
     }
 }
 
@@ -77,6 +298,11 @@ pub struct SchemaCache {
     pub tables: HashMap<(String, String), TableInfo>,
     /// Reverse FK index: (ref_schema, ref_table) -> list of tables that reference it
     pub reverse_fks: ReverseFkMap,
+    /// Key: (schema, name) -> procedures and functions callable through `/rpc`
+    pub routines: HashMap<(String, String), RoutineInfo>,
+    /// Key: (schema, table_name) -> sampled OpenAPI example, filled on the
+    /// first `GET /` and loaded again with the schema
+    pub examples: tokio::sync::OnceCell<HashMap<(String, String), serde_json::Value>>,
 }
 
 impl SchemaCache {
@@ -96,6 +322,16 @@ impl SchemaCache {
         })
     }
 
+    /// Look up a procedure or function by schema and name (case-insensitive).
+    pub fn get_routine(&self, schema: &str, name: &str) -> Option<&RoutineInfo> {
+        if let Some(r) = self.routines.get(&(schema.to_string(), name.to_string())) {
+            return Some(r);
+        }
+        self.routines.iter().find_map(|((s, n), info)| {
+            (s.eq_ignore_ascii_case(schema) && n.eq_ignore_ascii_case(name)).then_some(info)
+        })
+    }
+
     /// Find tables that reference the given table (reverse FK lookup).
     pub fn referencing_tables(
         &self,
@@ -109,55 +345,146 @@ impl SchemaCache {
             .unwrap_or_default()
     }
 
+    /// Every relationship a table can embed, with its default embed name.
+    ///
+    /// The name is the related table's name, unless several FKs link the same
+    /// pair of tables: then each gets a name from its FK column
+    /// (`billing_address_id` → `billing_address` on the many-to-one side,
+    /// `orders_billing_address` on the one-to-many side). Relationships into
+    /// another schema are qualified with it: `sales.orders`.
+    pub fn embed_relationships(&self, schema: &str, table: &str) -> Vec<(String, EmbedInfo)> {
+        let Some(source) = self.get_table(schema, table) else {
+            return Vec::new();
+        };
+        let mut rels = Vec::new();
+
+        for fk in &source.foreign_keys {
+            let shared = source
+                .foreign_keys
+                .iter()
+                .filter(|other| {
+                    other.ref_schema.eq_ignore_ascii_case(&fk.ref_schema)
+                        && other.ref_table.eq_ignore_ascii_case(&fk.ref_table)
+                })
+                .count();
+            let name = if shared > 1 {
+                fk.alias()
+            } else {
+                fk.ref_table.clone()
+            };
+            rels.push((
+                qualify_embed_name(schema, &fk.ref_schema, name),
+                fk.many_to_one(),
+            ));
+        }
+
+        let refs = self.referencing_tables(schema, table);
+        for (ref_schema, ref_table, fk) in &refs {
+            let shared = refs
+                .iter()
+                .filter(|(s, t, _)| {
+                    s.eq_ignore_ascii_case(ref_schema) && t.eq_ignore_ascii_case(ref_table)
+                })
+                .count();
+            let name = if shared > 1 {
+                format!("{}_{}", ref_table, fk.alias())
+            } else {
+                ref_table.clone()
+            };
+            rels.push((
+                qualify_embed_name(schema, ref_schema, name),
+                fk.one_to_many(ref_schema, ref_table),
+            ));
+        }
+
+        rels
+    }
+
     /// Find FK from source table to target table by embed name.
+    ///
+    /// Distinct relationship names (see [`Self::embed_relationships`]) are
+    /// tried first. Otherwise the name is matched against related tables,
+    /// bare (`orders`) or schema-qualified (`archive.orders`); a bare name
+    /// prefers the source's own schema. An `!fk` hint, bare or qualified
+    /// (`archive.fk_orders_user`), narrows the match to one constraint.
+    /// Several FKs into the same table resolve to the first of them, but a
+    /// bare name matching tables in different schemas is ambiguous.
     pub fn find_embed(
         &self,
         source_schema: &str,
         source_table: &str,
         embed_name: &str,
         hint_fk: Option<&str>,
-    ) -> Option<EmbedInfo> {
-        let source = self.get_table(source_schema, source_table)?;
+    ) -> Result<EmbedInfo, EmbedLookupError> {
+        let source = self
+            .get_table(source_schema, source_table)
+            .ok_or(EmbedLookupError::NotFound)?;
+
+        if hint_fk.is_none() {
+            if let Some((_, info)) = self
+                .embed_relationships(source_schema, source_table)
+                .into_iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(embed_name))
+            {
+                return Ok(info);
+            }
+        }
+
+        // (constraint as `schema.name`, relationship), FK owner's schema first
+        let hint_allows = |schema: &str, fk: &ForeignKey| {
+            hint_fk.is_none_or(|hint| names_table(hint, schema, &fk.constraint_name))
+        };
+        let mut candidates: Vec<(String, EmbedInfo)> = Vec::new();
 
-        // 1. Check if embed_name matches a table that source has an FK to
+        // 1. Tables the source has an FK to
         for fk in &source.foreign_keys {
-            if fk.ref_table.eq_ignore_ascii_case(embed_name) {
-                if let Some(hint) = hint_fk {
-                    if !fk.constraint_name.eq_ignore_ascii_case(hint) {
-                        continue;
-                    }
-                }
-                return Some(EmbedInfo {
-                    target_schema: fk.ref_schema.clone(),
-                    target_table: fk.ref_table.clone(),
-                    join_type: EmbedJoinType::ManyToOne,
-                    source_column: fk.column_name.clone(),
-                    target_column: fk.ref_column.clone(),
-                });
+            if names_table(embed_name, &fk.ref_schema, &fk.ref_table)
+                && hint_allows(source_schema, fk)
+            {
+                let constraint = format!("{}.{}", source_schema, fk.constraint_name);
+                candidates.push((constraint, fk.many_to_one()));
             }
         }
 
-        // 2. Check reverse FKs — tables that have FK pointing to source
-        let refs = self.referencing_tables(source_schema, source_table);
-        for (ref_schema, ref_table, fk) in refs {
-            if ref_table.eq_ignore_ascii_case(embed_name) {
-                if let Some(hint) = hint_fk {
-                    if !fk.constraint_name.eq_ignore_ascii_case(hint) {
-                        continue;
-                    }
-                }
-                return Some(EmbedInfo {
-                    target_schema: ref_schema.clone(),
-                    target_table: ref_table.clone(),
-                    join_type: EmbedJoinType::OneToMany,
-                    source_column: fk.ref_column.clone(),
-                    target_column: fk.column_name.clone(),
-                });
+        // 2. Reverse FKs — tables that have FK pointing to source
+        for (ref_schema, ref_table, fk) in self.referencing_tables(source_schema, source_table) {
+            if names_table(embed_name, ref_schema, ref_table) && hint_allows(ref_schema, fk) {
+                let constraint = format!("{}.{}", ref_schema, fk.constraint_name);
+                candidates.push((constraint, fk.one_to_many(ref_schema, ref_table)));
             }
         }
 
-        None
+        if candidates
+            .iter()
+            .any(|(_, info)| info.target_schema.eq_ignore_ascii_case(source_schema))
+        {
+            candidates.retain(|(_, info)| info.target_schema.eq_ignore_ascii_case(source_schema));
+        }
+        let Some((_, first)) = candidates.first() else {
+            return Err(EmbedLookupError::NotFound);
+        };
+        let same_table = |info: &EmbedInfo| {
+            info.target_schema
+                .eq_ignore_ascii_case(&first.target_schema)
+                && info.target_table.eq_ignore_ascii_case(&first.target_table)
+        };
+        if candidates.iter().all(|(_, info)| same_table(info)) {
+            return Ok(candidates.swap_remove(0).1);
+        }
+
+        let mut options: Vec<String> = candidates
+            .into_iter()
+            .map(|(constraint, info)| {
+                format!(
+                    "{}.{}!{}",
+                    info.target_schema, info.target_table, constraint
+                )
+            })
+            .collect();
+        options.sort();
+        Err(EmbedLookupError::Ambiguous(options))
     }
+
     /// Check if all tables belong to a single schema.
     pub fn has_multiple_schemas(&self) -> bool {
         let mut schemas = std::collections::HashSet::new();
@@ -168,14 +495,63 @@ impl SchemaCache {
     }
 }
 
+/// Prefix an embed name with the target schema when it differs from the source's.
+fn qualify_embed_name(source_schema: &str, target_schema: &str, name: String) -> String {
+    if source_schema.eq_ignore_ascii_case(target_schema) {
+        name
+    } else {
+        format!("{}.{}", target_schema, name)
+    }
+}
+
+/// Whether an embed name refers to a table, bare (`orders`) or schema-qualified
+/// (`sales.orders`).
+fn names_table(embed_name: &str, schema: &str, table: &str) -> bool {
+    match embed_name.split_once('.') {
+        Some((s, t)) => s.eq_ignore_ascii_case(schema) && t.eq_ignore_ascii_case(table),
+        None => embed_name.eq_ignore_ascii_case(table),
+    }
+}
+
+/// Default embed name derived from an FK column: `billing_address_id` →
+/// `billing_address`, `ShippingAddressID` → `ShippingAddress`.
+fn fk_embed_alias(column: &str) -> String {
+    let camel_id = (column.ends_with("Id") || column.ends_with("ID"))
+        && column[..column.len() - 2]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_ascii_lowercase());
+    let stem = if column.to_ascii_lowercase().ends_with("_id") {
+        &column[..column.len() - 3]
+    } else if camel_id {
+        &column[..column.len() - 2]
+    } else {
+        column
+    };
+    if stem.is_empty() {
+        column.to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// Why an embed name didn't resolve to a single relationship.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmbedLookupError {
+    NotFound,
+    /// Matches in several schemas, each as `schema.table!schema.constraint`
+    Ambiguous(Vec<String>),
+}
+
 /// Info about how to embed a related table.
 #[derive(Debug, Clone)]
 pub struct EmbedInfo {
     pub target_schema: String,
     pub target_table: String,
     pub join_type: EmbedJoinType,
-    pub source_column: String,
-    pub target_column: String,
+    /// Join columns on the source table, paired with `target_columns`
+    pub source_columns: Vec<String>,
+    pub target_columns: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -223,6 +599,10 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
                 unique_constraints: Vec::new(),
                 is_view,
                 change_tracking_enabled: false,
+                has_triggers: false,
+                is_updatable: !is_view,
+                instead_of: InsteadOf::default(),
+                description: None,
             },
         );
     }
@@ -232,9 +612,9 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
         .execute(
             "SELECT c.TABLE_SCHEMA, c.TABLE_NAME, c.COLUMN_NAME, c.DATA_TYPE, \
                     c.CHARACTER_MAXIMUM_LENGTH, c.NUMERIC_PRECISION, c.NUMERIC_SCALE, \
-                    c.IS_NULLABLE, c.ORDINAL_POSITION, c.COLUMN_DEFAULT, \
-                    COLUMNPROPERTY(OBJECT_ID(c.TABLE_SCHEMA + '.' + c.TABLE_NAME), c.COLUMN_NAME, 'IsIdentity') AS IS_IDENTITY, \
-                    COLUMNPROPERTY(OBJECT_ID(c.TABLE_SCHEMA + '.' + c.TABLE_NAME), c.COLUMN_NAME, 'IsComputed') AS IS_COMPUTED \
+                    c.IS_NULLABLE, c.ORDINAL_POSITION, c.COLUMN_DEFAULT, c.COLLATION_NAME, \
+                    COLUMNPROPERTY(OBJECT_ID(QUOTENAME(c.TABLE_SCHEMA) + '.' + QUOTENAME(c.TABLE_NAME)), c.COLUMN_NAME, 'IsIdentity') AS IS_IDENTITY, \
+                    COLUMNPROPERTY(OBJECT_ID(QUOTENAME(c.TABLE_SCHEMA) + '.' + QUOTENAME(c.TABLE_NAME)), c.COLUMN_NAME, 'IsComputed') AS IS_COMPUTED \
              FROM INFORMATION_SCHEMA.COLUMNS c \
              ORDER BY c.TABLE_SCHEMA, c.TABLE_NAME, c.ORDINAL_POSITION",
             &[],
@@ -266,6 +646,7 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
             .ok()
             .flatten()
             .is_some();
+        let collation: Option<&str> = row.try_get("COLLATION_NAME").ok().flatten();
 
         let key = (schema.to_string(), table.to_string());
         if let Some(table_info) = tables.get_mut(&key) {
@@ -280,6 +661,9 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
                 is_identity: is_identity == 1,
                 has_default,
                 is_computed: is_computed == 1,
+                is_fulltext_indexed: false,
+                collation: collation.map(str::to_string),
+                description: None,
             });
         }
     }
@@ -313,7 +697,7 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
         }
     }
 
-    // 4. Load foreign keys
+    // 4. Load foreign keys (one row per column; composite FKs span several)
     let fk_rows = client
         .execute(
             "SELECT \
@@ -326,7 +710,7 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
                  COL_NAME(fkc.referenced_object_id, fkc.referenced_column_id) AS REF_COLUMN \
              FROM sys.foreign_keys fk \
              JOIN sys.foreign_key_columns fkc ON fk.object_id = fkc.constraint_object_id \
-             ORDER BY fk.name",
+             ORDER BY fk.object_id, fkc.constraint_column_id",
             &[],
         )
         .await
@@ -335,8 +719,8 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
         .await
         .map_err(|e| Error::Sql(e.to_string()))?;
 
-    let mut reverse_fks: ReverseFkMap = HashMap::new();
-
+    // Group column rows into constraints: (schema, table, fk)
+    let mut fks: Vec<(String, String, ForeignKey)> = Vec::new();
     for row in &fk_rows {
         let fk_name: &str = row.get("FK_NAME").unwrap_or("");
         let schema: &str = row.get("TABLE_SCHEMA").unwrap_or("dbo");
@@ -346,25 +730,39 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
         let ref_table: &str = row.get("REF_TABLE").unwrap_or("");
         let ref_col: &str = row.get("REF_COLUMN").unwrap_or("");
 
-        let fk = ForeignKey {
-            constraint_name: fk_name.to_string(),
-            column_name: col.to_string(),
-            ref_schema: ref_schema.to_string(),
-            ref_table: ref_table.to_string(),
-            ref_column: ref_col.to_string(),
-        };
+        match fks.last_mut() {
+            Some((s, t, fk)) if s == schema && t == table && fk.constraint_name == fk_name => {
+                fk.columns.push(col.to_string());
+                fk.ref_columns.push(ref_col.to_string());
+            }
+            _ => fks.push((
+                schema.to_string(),
+                table.to_string(),
+                ForeignKey {
+                    constraint_name: fk_name.to_string(),
+                    columns: vec![col.to_string()],
+                    ref_schema: ref_schema.to_string(),
+                    ref_table: ref_table.to_string(),
+                    ref_columns: vec![ref_col.to_string()],
+                },
+            )),
+        }
+    }
 
-        let key = (schema.to_string(), table.to_string());
+    let mut reverse_fks: ReverseFkMap = HashMap::new();
+
+    for (schema, table, fk) in fks {
+        let key = (schema.clone(), table.clone());
         if let Some(table_info) = tables.get_mut(&key) {
             table_info.foreign_keys.push(fk.clone());
         }
 
         // Reverse FK index
-        let ref_key = (ref_schema.to_lowercase(), ref_table.to_lowercase());
+        let ref_key = (fk.ref_schema.to_lowercase(), fk.ref_table.to_lowercase());
         reverse_fks
             .entry(ref_key)
             .or_default()
-            .push((schema.to_string(), table.to_string(), fk));
+            .push((schema, table, fk));
     }
 
     // 5. Load unique constraints
@@ -435,10 +833,462 @@ pub async fn load_schema(pool: &Arc<Pool>) -> Result<SchemaCache, Error> {
         }
     }
 
-    tracing::info!("Schema loaded: {} tables/views", count);
+    // 7. Load enabled DML triggers, with the events INSTEAD OF triggers
+    //    take over (trigger_events type 1 = INSERT, 2 = UPDATE, 3 = DELETE)
+    let trigger_rows = client
+        .execute(
+            "SELECT OBJECT_SCHEMA_NAME(tr.parent_id) AS schema_name, \
+                    OBJECT_NAME(tr.parent_id) AS table_name, \
+                    MAX(CASE WHEN tr.is_instead_of_trigger = 1 AND te.type = 1 \
+                        THEN 1 ELSE 0 END) AS instead_of_insert, \
+                    MAX(CASE WHEN tr.is_instead_of_trigger = 1 AND te.type = 2 \
+                        THEN 1 ELSE 0 END) AS instead_of_update, \
+                    MAX(CASE WHEN tr.is_instead_of_trigger = 1 AND te.type = 3 \
+                        THEN 1 ELSE 0 END) AS instead_of_delete \
+             FROM sys.triggers tr \
+             JOIN sys.trigger_events te ON te.object_id = tr.object_id \
+             WHERE tr.parent_class = 1 AND tr.is_disabled = 0 \
+             GROUP BY tr.parent_id",
+            &[],
+        )
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?
+        .into_first_result()
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+
+    for row in &trigger_rows {
+        let schema: &str = row.get("schema_name").unwrap_or("dbo");
+        let table: &str = row.get("table_name").unwrap_or("");
+        let flag = |col: &str| {
+            let value: i32 = row.get(col).unwrap_or(0);
+            value == 1
+        };
+        let key = (schema.to_string(), table.to_string());
+        if let Some(table_info) = tables.get_mut(&key) {
+            table_info.has_triggers = true;
+            table_info.instead_of = InsteadOf {
+                insert: flag("instead_of_insert"),
+                update: flag("instead_of_update"),
+                delete: flag("instead_of_delete"),
+            };
+        }
+    }
+
+    // 8. Detect updatable views (single base table, no aggregation); the
+    //    writes INSTEAD OF triggers take over are allowed on top of that
+    let view_rows = client
+        .execute(
+            "SELECT s.name AS schema_name, v.name AS view_name, m.definition, \
+                    (SELECT COUNT(DISTINCT d.referenced_id) \
+                     FROM sys.sql_expression_dependencies d \
+                     JOIN sys.objects o ON o.object_id = d.referenced_id AND o.type = 'U' \
+                     WHERE d.referencing_id = v.object_id) AS base_tables \
+             FROM sys.views v \
+             JOIN sys.schemas s ON v.schema_id = s.schema_id \
+             LEFT JOIN sys.sql_modules m ON m.object_id = v.object_id",
+            &[],
+        )
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?
+        .into_first_result()
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+
+    for row in &view_rows {
+        let schema: &str = row.get("schema_name").unwrap_or("dbo");
+        let view: &str = row.get("view_name").unwrap_or("");
+        let definition: &str = row.get("definition").unwrap_or("");
+        let base_tables: i32 = row.get("base_tables").unwrap_or(0);
+        let key = (schema.to_string(), view.to_string());
+        if let Some(table_info) = tables.get_mut(&key) {
+            table_info.is_updatable = base_tables == 1 && !has_aggregation(definition);
+        }
+    }
+
+    // 9. Load full-text indexed columns
+    let fts_rows = client
+        .execute(
+            "SELECT OBJECT_SCHEMA_NAME(ic.object_id) AS schema_name, \
+                    OBJECT_NAME(ic.object_id) AS table_name, \
+                    c.name AS column_name \
+             FROM sys.fulltext_index_columns ic \
+             JOIN sys.columns c ON c.object_id = ic.object_id AND c.column_id = ic.column_id",
+            &[],
+        )
+        .await;
+    // Full-text search may not be installed — treat as no indexed columns
+    if let Ok(fts_stream) = fts_rows {
+        if let Ok(fts_result) = fts_stream.into_first_result().await {
+            for row in &fts_result {
+                let schema: &str = row.get("schema_name").unwrap_or("dbo");
+                let table: &str = row.get("table_name").unwrap_or("");
+                let column: &str = row.get("column_name").unwrap_or("");
+                let key = (schema.to_string(), table.to_string());
+                if let Some(col) = tables
+                    .get_mut(&key)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == column))
+                {
+                    col.is_fulltext_indexed = true;
+                }
+            }
+        }
+    }
+
+    // 10. Load MS_Description extended properties (minor_id 0 = the table itself)
+    let desc_rows = client
+        .execute(
+            "SELECT OBJECT_SCHEMA_NAME(ep.major_id) AS schema_name, \
+                    OBJECT_NAME(ep.major_id) AS table_name, \
+                    COL_NAME(ep.major_id, ep.minor_id) AS column_name, \
+                    CAST(ep.value AS NVARCHAR(4000)) AS description \
+             FROM sys.extended_properties ep \
+             WHERE ep.class = 1 AND ep.name = 'MS_Description'",
+            &[],
+        )
+        .await;
+    // Missing VIEW DEFINITION just means no descriptions
+    if let Ok(desc_stream) = desc_rows {
+        if let Ok(desc_result) = desc_stream.into_first_result().await {
+            for row in &desc_result {
+                let schema: &str = row.get("schema_name").unwrap_or("dbo");
+                let table: &str = row.get("table_name").unwrap_or("");
+                let column: Option<&str> = row.get("column_name");
+                let Some(description) = row.get::<&str, _>("description") else {
+                    continue;
+                };
+                let key = (schema.to_string(), table.to_string());
+                let Some(table_info) = tables.get_mut(&key) else {
+                    continue;
+                };
+                match column {
+                    Some(column) => {
+                        if let Some(col) = table_info.columns.iter_mut().find(|c| c.name == column)
+                        {
+                            col.description = Some(description.to_string());
+                        }
+                    }
+                    None => table_info.description = Some(description.to_string()),
+                }
+            }
+        }
+    }
+
+    // 11. Load procedures and functions with their parameters
+    let routine_rows = client
+        .execute(
+            "SELECT SCHEMA_NAME(o.schema_id) AS schema_name, o.name AS routine_name, \
+                    o.type AS kind, p.name AS param_name, \
+                    CASE WHEN t.is_table_type = 1 THEN 'table' \
+                         ELSE TYPE_NAME(t.system_type_id) END AS data_type, \
+                    CAST(CASE WHEN TYPE_NAME(t.system_type_id) IN ('nchar', 'nvarchar') \
+                              AND p.max_length > 0 THEN p.max_length / 2 \
+                              ELSE p.max_length END AS INT) AS max_length, \
+                    CAST(p.precision AS INT) AS precision, CAST(p.scale AS INT) AS scale, \
+                    CAST(ISNULL(p.is_nullable, 1) AS INT) AS is_nullable, \
+                    CAST(p.is_output AS INT) AS is_output \
+             FROM sys.objects o \
+             LEFT JOIN sys.parameters p ON p.object_id = o.object_id AND p.parameter_id > 0 \
+             LEFT JOIN sys.types t ON t.user_type_id = p.user_type_id \
+             WHERE o.type IN ('P', 'FN', 'IF', 'TF') AND o.is_ms_shipped = 0 \
+             ORDER BY schema_name, routine_name, p.parameter_id",
+            &[],
+        )
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?
+        .into_first_result()
+        .await
+        .map_err(|e| Error::Sql(e.to_string()))?;
+
+    let mut routines: HashMap<(String, String), RoutineInfo> = HashMap::new();
+    for row in &routine_rows {
+        let schema: &str = row.get("schema_name").unwrap_or("dbo");
+        let name: &str = row.get("routine_name").unwrap_or("");
+        let kind: &str = row.get("kind").unwrap_or("P");
+        let routine = routines
+            .entry((schema.to_string(), name.to_string()))
+            .or_insert_with(|| RoutineInfo {
+                name: name.to_string(),
+                schema: schema.to_string(),
+                kind: kind.trim().to_string(),
+                params: Vec::new(),
+            });
+        let Some(param) = row.get::<&str, _>("param_name") else {
+            continue;
+        };
+        let is_nullable: i32 = row.get("is_nullable").unwrap_or(1);
+        let is_output: i32 = row.get("is_output").unwrap_or(0);
+        routine.params.push(ParamInfo {
+            name: param.trim_start_matches('@').to_string(),
+            data_type: row
+                .get::<&str, _>("data_type")
+                .unwrap_or("sql_variant")
+                .to_string(),
+            max_length: row.get("max_length"),
+            precision: row.get("precision"),
+            scale: row.get("scale"),
+            is_nullable: is_nullable != 0,
+            is_output: is_output != 0,
+        });
+    }
+
+    tracing::info!(
+        "Schema loaded: {} tables/views, {} routines",
+        count,
+        routines.len()
+    );
 
     Ok(SchemaCache {
         tables,
         reverse_fks,
+        routines,
+        examples: Default::default(),
     })
 }
+
+/// A view definition reduced to what the updatability check needs: words
+/// (uppercased) and brackets. Comments and string literals are dropped and
+/// quoted identifiers never read as keywords.
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    Comma,
+    Other,
+}
+
+fn sql_tokens(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            // Block comments nest in T-SQL
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match (c, chars.peek()) {
+                        ('/', Some('*')) => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        ('*', Some('/')) => {
+                            chars.next();
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            '\'' | '"' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                while let Some(c) = chars.next() {
+                    if c == close {
+                        // A doubled closing character is an escaped one
+                        if chars.peek() != Some(&close) {
+                            break;
+                        }
+                        chars.next();
+                    }
+                }
+                tokens.push(Token::Other);
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            c if c.is_alphanumeric() || matches!(c, '_' | '@' | '#') => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$')) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word.to_uppercase()));
+            }
+            c if c.is_whitespace() => {}
+            _ => tokens.push(Token::Other),
+        }
+    }
+    tokens
+}
+
+/// Check for constructs that make a view non-updatable: grouping,
+/// aggregates, DISTINCT and set operators in the view's own query, its
+/// derived tables or its CTEs. Subqueries elsewhere (a WHERE filter, a
+/// scalar column) don't affect which rows the view writes through to.
+fn has_aggregation(definition: &str) -> bool {
+    const AGGREGATES: &[&str] = &[
+        "COUNT",
+        "COUNT_BIG",
+        "SUM",
+        "AVG",
+        "MIN",
+        "MAX",
+        "STDEV",
+        "STDEVP",
+        "VAR",
+        "VARP",
+        "STRING_AGG",
+        "CHECKSUM_AGG",
+        "GROUPING",
+    ];
+
+    let tokens = sql_tokens(definition);
+    let word = |i: usize| match tokens.get(i) {
+        Some(Token::Word(w)) => w.as_str(),
+        _ => "",
+    };
+    // Per bracket level: whether it's checked, and whether it's in a FROM
+    let mut levels = vec![(true, false)];
+    for (i, token) in tokens.iter().enumerate() {
+        let (checked, in_from) = *levels.last().expect("outer level");
+        match token {
+            Token::Word(w) => {
+                if checked
+                    && (matches!(w.as_str(), "DISTINCT" | "UNION" | "EXCEPT" | "INTERSECT")
+                        || (w == "GROUP" && word(i + 1) == "BY")
+                        || (AGGREGATES.contains(&w.as_str())
+                            && tokens.get(i + 1) == Some(&Token::Open)))
+                {
+                    return true;
+                }
+                let level = levels.last_mut().expect("outer level");
+                match w.as_str() {
+                    "FROM" => level.1 = true,
+                    "SELECT" | "WHERE" | "GROUP" | "HAVING" | "ORDER" => level.1 = false,
+                    _ => {}
+                }
+            }
+            Token::Open => {
+                let derived = match i.checked_sub(1).map(|p| &tokens[p]) {
+                    Some(Token::Word(w)) => matches!(w.as_str(), "FROM" | "JOIN" | "APPLY" | "AS"),
+                    Some(Token::Comma) => in_from,
+                    _ => false,
+                };
+                let subquery = word(i + 1) == "SELECT";
+                levels.push((checked && (derived || !subquery), false));
+            }
+            Token::Close if levels.len() > 1 => {
+                levels.pop();
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(data_type: &str, max_length: Option<i32>, precision: Option<i32>) -> ParamInfo {
+        ParamInfo {
+            name: "p".to_string(),
+            data_type: data_type.to_string(),
+            max_length,
+            precision,
+            scale: precision.map(|_| 2),
+            is_nullable: true,
+            is_output: true,
+        }
+    }
+
+    #[test]
+    fn test_has_aggregation() {
+        let cases = [
+            ("CREATE VIEW v AS SELECT id, name FROM dbo.users", false),
+            (
+                "CREATE VIEW v AS SELECT dept, COUNT(*) AS n FROM t GROUP BY dept",
+                true,
+            ),
+            ("CREATE VIEW v AS SELECT sum (amount) AS total FROM t", true),
+            ("CREATE VIEW v AS SELECT DISTINCT dept FROM t", true),
+            (
+                "CREATE VIEW v AS SELECT a FROM t UNION ALL SELECT a FROM u",
+                true,
+            ),
+            // Names that merely contain a keyword
+            (
+                "CREATE VIEW v AS SELECT dbo.ADMIN(id) AS a, max_len FROM t",
+                false,
+            ),
+            (
+                "CREATE VIEW v AS SELECT [Count], \"Sum\", group_by_key FROM t",
+                false,
+            ),
+            // Comments and string literals
+            ("CREATE VIEW v AS -- GROUP BY dept\nSELECT id FROM t", false),
+            (
+                "CREATE VIEW v AS /* COUNT(*) /* nested */ SUM( */ SELECT id FROM t",
+                false,
+            ),
+            (
+                "CREATE VIEW v AS SELECT id, 'SUM(x) isn''t DISTINCT' AS s FROM t",
+                false,
+            ),
+            // Subqueries that only filter or compute a column
+            (
+                "CREATE VIEW v AS SELECT id FROM t WHERE amount > (SELECT AVG(amount) FROM t)",
+                false,
+            ),
+            (
+                "CREATE VIEW v AS SELECT id FROM t \
+                 WHERE EXISTS (SELECT DISTINCT 1 FROM u WHERE u.t_id = t.id)",
+                false,
+            ),
+            (
+                "CREATE VIEW v AS SELECT id, (SELECT MAX(x) FROM u) AS m FROM t",
+                false,
+            ),
+            // Derived tables and CTEs are part of the view's own rows
+            (
+                "CREATE VIEW v AS SELECT d.dept FROM (SELECT dept FROM t GROUP BY dept) AS d",
+                true,
+            ),
+            (
+                "CREATE VIEW v AS SELECT a.id FROM t AS a, (SELECT DISTINCT id FROM u) b",
+                true,
+            ),
+            (
+                "CREATE VIEW v AS WITH c AS (SELECT dept, COUNT(*) AS n FROM t GROUP BY dept) \
+                 SELECT dept FROM c",
+                true,
+            ),
+        ];
+        for (definition, expected) in cases {
+            assert_eq!(has_aggregation(definition), expected, "{}", definition);
+        }
+    }
+
+    #[test]
+    fn test_param_type_decl() {
+        assert_eq!(
+            param("nvarchar", Some(50), None).type_decl(),
+            "nvarchar(50)"
+        );
+        assert_eq!(
+            param("VARBINARY", Some(-1), None).type_decl(),
+            "varbinary(max)"
+        );
+        assert_eq!(
+            param("decimal", None, Some(10)).type_decl(),
+            "decimal(10,2)"
+        );
+        assert_eq!(param("numeric", None, None).type_decl(), "numeric");
+        assert_eq!(param("timestamp", None, None).type_decl(), "binary(8)");
+        assert_eq!(param("int", Some(4), None).type_decl(), "int");
+    }
+}
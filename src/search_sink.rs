@@ -0,0 +1,242 @@
+//! Search index sink: keeps Elasticsearch or Meilisearch in sync with
+//! selected tables, fed by the realtime change tracking poller.
+
+use crate::config::{AppConfig, IndexedTable, SearchEngineKind, SearchIndexConfig};
+use crate::realtime::{self, RealtimeEngine, ServerMessage};
+use crate::schema::SchemaCache;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+use uuid::Uuid;
+
+/// Buffered changes before the poller waits for the sink to catch up.
+const SINK_BUFFER: usize = 10_000;
+
+struct SearchSink {
+    config: SearchIndexConfig,
+    http: reqwest::Client,
+    schema: Arc<RwLock<SchemaCache>>,
+    /// `schema.table` -> index settings
+    tables: HashMap<String, IndexedTable>,
+}
+
+/// Subscribe the configured tables and apply their changes to the index
+/// until the poller goes away.
+pub async fn run(engine: Arc<RealtimeEngine>, schema: Arc<RwLock<SchemaCache>>, config: AppConfig) {
+    let Some(index_config) = config.search_index else {
+        return;
+    };
+    let client_id = Uuid::new_v4();
+    let (tx, mut rx) = mpsc::channel::<ServerMessage>(SINK_BUFFER);
+
+    let mut tables = HashMap::new();
+    for (name, table) in &index_config.tables {
+        match engine.subscribe_sink(client_id, name, tx.clone()).await {
+            Ok(table_key) => {
+                tracing::info!(
+                    "Search index: {} -> {}",
+                    table_key,
+                    index_name(&table_key, table)
+                );
+                tables.insert(table_key, table.clone());
+            }
+            Err(e) => tracing::warn!("Search index: skipping {}: {}", name, e),
+        }
+    }
+    drop(tx);
+    if tables.is_empty() {
+        return;
+    }
+
+    let sink = SearchSink {
+        config: index_config,
+        http: reqwest::Client::new(),
+        schema,
+        tables,
+    };
+    while let Some(msg) = rx.recv().await {
+        if let ServerMessage::Change {
+            type_,
+            table,
+            record,
+            ..
+        } = msg
+        {
+            if let Err(e) = sink.apply(&type_, &table, record).await {
+                tracing::warn!("Search index update for {} failed: {}", table, e);
+            }
+        }
+    }
+}
+
+/// Index for a table: configured name, else the table name in lowercase
+/// (both engines reject uppercase index names).
+fn index_name(table_key: &str, table: &IndexedTable) -> String {
+    table.index.clone().unwrap_or_else(|| {
+        let name = table_key.split_once('.').map_or(table_key, |(_, t)| t);
+        name.to_lowercase()
+    })
+}
+
+impl SearchSink {
+    async fn apply(
+        &self,
+        op: &str,
+        table_key: &str,
+        mut record: serde_json::Map<String, JsonValue>,
+    ) -> Result<(), String> {
+        let Some(table) = self.tables.get(table_key) else {
+            return Ok(());
+        };
+        let primary_key = {
+            let schema_cache = self.schema.read().await;
+            let (schema_name, table_name) = table_key.split_once('.').unwrap_or(("", table_key));
+            schema_cache
+                .get_table(schema_name, table_name)
+                .map(|t| t.primary_key.clone())
+                .unwrap_or_default()
+        };
+        let id = realtime::row_id(&primary_key, &record)
+            .ok_or_else(|| "change has no primary key value".to_string())?;
+        let index = index_name(table_key, table);
+
+        if op == "DELETE" {
+            return self.delete(&index, &id).await;
+        }
+        if !table.columns.is_empty() {
+            record.retain(|k, _| {
+                table.columns.iter().any(|c| c.eq_ignore_ascii_case(k))
+                    || primary_key.iter().any(|pk| pk.eq_ignore_ascii_case(k))
+            });
+        }
+        self.upsert(&index, &id, record).await
+    }
+
+    /// Request to `url/<segments>`, each segment percent-encoded.
+    fn request(
+        &self,
+        method: reqwest::Method,
+        segments: &[&str],
+    ) -> Result<reqwest::RequestBuilder, String> {
+        let mut url = reqwest::Url::parse(&self.config.url).map_err(|e| e.to_string())?;
+        url.path_segments_mut()
+            .map_err(|_| format!("Invalid search index URL: {}", self.config.url))?
+            .pop_if_empty()
+            .extend(segments);
+        let request = self.http.request(method, url);
+        Ok(match (&self.config.api_key, self.config.kind) {
+            (Some(key), SearchEngineKind::Elasticsearch) => {
+                request.header("authorization", format!("ApiKey {}", key))
+            }
+            (Some(key), SearchEngineKind::Meilisearch) => request.bearer_auth(key),
+            (None, _) => request,
+        })
+    }
+
+    async fn upsert(
+        &self,
+        index: &str,
+        id: &str,
+        mut document: serde_json::Map<String, JsonValue>,
+    ) -> Result<(), String> {
+        let request = match self.config.kind {
+            SearchEngineKind::Elasticsearch => self
+                .request(reqwest::Method::PUT, &[index, "_doc", id])?
+                .json(&document),
+            SearchEngineKind::Meilisearch => {
+                // Meilisearch keeps the ID in the document itself
+                document.insert("id".to_string(), JsonValue::String(id.to_string()));
+                self.request(reqwest::Method::POST, &["indexes", index, "documents"])?
+                    .query(&[("primaryKey", "id")])
+                    .json(&[document])
+            }
+        };
+        send(request, false).await
+    }
+
+    async fn delete(&self, index: &str, id: &str) -> Result<(), String> {
+        let segments: &[&str] = match self.config.kind {
+            SearchEngineKind::Elasticsearch => &[index, "_doc", id],
+            SearchEngineKind::Meilisearch => &["indexes", index, "documents", id],
+        };
+        // Deleting a document that was never indexed is fine
+        send(self.request(reqwest::Method::DELETE, segments)?, true).await
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder, allow_missing: bool) -> Result<(), String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() || (allow_missing && status == reqwest::StatusCode::NOT_FOUND) {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("{}: {}", status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(kind: SearchEngineKind, api_key: Option<&str>) -> SearchSink {
+        SearchSink {
+            config: SearchIndexConfig {
+                kind,
+                url: "http://search:9200/".to_string(),
+                api_key: api_key.map(str::to_string),
+                tables: HashMap::new(),
+            },
+            http: reqwest::Client::new(),
+            schema: Arc::new(RwLock::new(SchemaCache {
+                tables: HashMap::new(),
+                reverse_fks: HashMap::new(),
+                routines: HashMap::new(),
+                examples: Default::default(),
+            })),
+            tables: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_index_name() {
+        let table = IndexedTable::default();
+        assert_eq!(index_name("dbo.Products", &table), "products");
+        let named = IndexedTable {
+            index: Some("catalog".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(index_name("dbo.Products", &named), "catalog");
+    }
+
+    #[test]
+    fn test_request_url() {
+        let request = sink(SearchEngineKind::Elasticsearch, None)
+            .request(reqwest::Method::PUT, &["products", "_doc", "1-7_3-a/b"])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "http://search:9200/products/_doc/1-7_3-a%2Fb"
+        );
+        assert!(request.headers().get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_request_auth() {
+        let auth = |kind| {
+            let request = sink(kind, Some("k3y"))
+                .request(reqwest::Method::DELETE, &["products"])
+                .unwrap()
+                .build()
+                .unwrap();
+            request.headers()["authorization"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(auth(SearchEngineKind::Elasticsearch), "ApiKey k3y");
+        assert_eq!(auth(SearchEngineKind::Meilisearch), "Bearer k3y");
+    }
+}
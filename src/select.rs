@@ -5,6 +5,12 @@
 //! - `?select=*,orders(*)` — embed related table via FK
 //! - `?select=*,orders!fk_name(id,amount)` — disambiguate FK + column selection
 //! - `?select=*,orders(items(*))` — nested embedding
+//! - `?select=*,orders!inner(*)` — only parents that have matching embedded rows
+//! - `?select=*,orders(*).order(created_at.desc).limit(5)` — top-N children per parent
+//! - `?select=id,...customer(name,city)` — hoist many-to-one columns onto the parent
+//! - `?select=id,data->settings->>theme` — JSON path into an NVARCHAR JSON column
+//! - `?select=price::float,created_at::date` — cast a column to another SQL type
+//! - `?select="Order Date","Unit Price"` — quote names with spaces, dots or commas
 
 use crate::error::Error;
 
@@ -17,6 +23,30 @@ pub enum SelectNode {
     Column(String),
     /// Embed a related table with optional FK hint and sub-select
     Embed(EmbedSelect),
+    /// A computed field: a JSON path into a column and/or a cast
+    Field(FieldSelect),
+}
+
+/// A renamed or computed select field.
+#[derive(Debug, Clone)]
+pub struct FieldSelect {
+    /// Source column
+    pub column: String,
+    /// Optional JSON path into the source column
+    pub json_path: Option<JsonPath>,
+    /// Optional target SQL type (from `::type`)
+    pub cast: Option<String>,
+    /// Output name (explicit `alias:`, the last path key, or the column)
+    pub alias: String,
+}
+
+/// A JSON path into an NVARCHAR column: `data->settings->>theme`.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    pub column: String,
+    pub keys: Vec<String>,
+    /// Last step was `->>`: scalar text rather than a JSON fragment
+    pub as_text: bool,
 }
 
 /// An embedding specification.
@@ -24,10 +54,22 @@ pub enum SelectNode {
 pub struct EmbedSelect {
     /// The name of the related table to embed
     pub name: String,
+    /// Output key in the parent row (from `alias:name(...)`)
+    pub alias: Option<String>,
     /// Optional FK constraint name hint (from `!fk_name`)
     pub fk_hint: Option<String>,
+    /// Inner join semantics (from `!inner`): filter parents without matches
+    pub inner: bool,
     /// Sub-select within the embedded table
     pub columns: Vec<SelectNode>,
+    /// Maximum number of embedded rows per parent (from `.limit(n)`)
+    pub limit: Option<i64>,
+    /// Embedded rows to skip per parent (from `.offset(n)`)
+    pub offset: Option<i64>,
+    /// Ordering of embedded rows, in `order=` syntax (from `.order(...)`)
+    pub order: Option<String>,
+    /// Spread (`...name(...)`): columns are hoisted onto the parent row
+    pub spread: bool,
 }
 
 /// Parse a full select expression string.
@@ -57,55 +99,276 @@ fn parse_select_token(token: &str) -> Result<SelectNode, Error> {
         return Ok(SelectNode::Star);
     }
 
-    // Check for embedding: name(...) or name!fk_hint(...)
-    if let Some(paren_start) = token.find('(') {
-        if !token.ends_with(')') {
-            return Err(Error::BadRequest(format!(
-                "Unmatched parenthesis in select: {}",
+    // Spread: ...name(...) is an embed whose columns join the parent's
+    if let Some(rest) = token.strip_prefix("...") {
+        return match parse_select_token(rest)? {
+            SelectNode::Embed(embed) => Ok(SelectNode::Embed(EmbedSelect {
+                spread: true,
+                ..embed
+            })),
+            _ => Err(Error::BadRequest(format!(
+                "Spread requires an embedded resource: {}",
                 token
-            )));
-        }
+            ))),
+        };
+    }
+
+    // Check for embedding: name(...) or name!fk_hint(...)
+    // (parentheses after `::` belong to a cast type like `decimal(10,2)`)
+    let cast_pos = find_unquoted(token, "::");
+    if let Some(paren_start) = find_unquoted(token, "(").filter(|&p| cast_pos.is_none_or(|c| p < c))
+    {
+        let paren_end = matching_paren(token, paren_start).ok_or_else(|| {
+            Error::BadRequest(format!("Unmatched parenthesis in select: {}", token))
+        })?;
 
         let prefix = &token[..paren_start];
-        let inner = &token[paren_start + 1..token.len() - 1];
-
-        // Check for FK hint: name!fk_name
-        let (name, fk_hint) = if let Some(bang_pos) = prefix.find('!') {
-            (
-                prefix[..bang_pos].to_string(),
-                Some(prefix[bang_pos + 1..].to_string()),
-            )
-        } else {
-            (prefix.to_string(), None)
+        let inner = &token[paren_start + 1..paren_end];
+        let modifiers = parse_embed_modifiers(token, &token[paren_end + 1..])?;
+
+        // Check for rename: alias:name(...)
+        let (alias, prefix) = match find_unquoted(prefix, ":") {
+            Some(pos) => (Some(unquote_ident(&prefix[..pos])), &prefix[pos + 1..]),
+            None => (None, prefix),
         };
 
+        // Check for hints: name!fk_name, name!inner, name!fk_name!inner
+        let mut segments = split_unquoted(prefix, '!').into_iter();
+        let name = unquote_ident(segments.next().unwrap_or_default());
+        let mut fk_hint = None;
+        let mut inner_join = false;
+        for segment in segments {
+            match segment {
+                "inner" => inner_join = true,
+                "left" => inner_join = false,
+                hint => fk_hint = Some(unquote_ident(hint)),
+            }
+        }
+
         // Parse inner columns recursively
         let columns = parse_select(inner)?;
 
         Ok(SelectNode::Embed(EmbedSelect {
             name,
+            alias,
             fk_hint,
+            inner: inner_join,
             columns,
+            limit: modifiers.limit,
+            offset: modifiers.offset,
+            order: modifiers.order,
+            spread: false,
         }))
     } else {
-        // Check for rename: alias:column (not implementing rename for now, just parse column)
-        let col = if let Some(colon_pos) = token.find(':') {
-            token[colon_pos + 1..].to_string()
+        // Check for cast suffix: column::type
+        let (token, cast) = match rfind_unquoted(token, "::") {
+            Some(pos) => (&token[..pos], Some(parse_cast_type(&token[pos + 2..])?)),
+            None => (token, None),
+        };
+        // Check for rename: alias:column
+        let (alias, col) = match find_unquoted(token, ":") {
+            Some(colon_pos) => (
+                Some(unquote_ident(&token[..colon_pos])),
+                &token[colon_pos + 1..],
+            ),
+            None => (None, token),
+        };
+        let json_path = parse_json_path(col)?;
+        let column = json_path
+            .as_ref()
+            .map(|p| p.column.clone())
+            .unwrap_or_else(|| unquote_ident(col));
+        if json_path.is_none() && cast.is_none() && alias.as_ref().is_none_or(|a| *a == column) {
+            return Ok(SelectNode::Column(column));
+        }
+        let alias = alias.unwrap_or_else(|| match &json_path {
+            Some(p) => p.keys[p.keys.len() - 1].clone(),
+            None => column.clone(),
+        });
+        Ok(SelectNode::Field(FieldSelect {
+            column,
+            json_path,
+            cast,
+            alias,
+        }))
+    }
+}
+
+/// Pagination and ordering applied per parent to an embed.
+#[derive(Default)]
+struct EmbedModifiers {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    order: Option<String>,
+}
+
+/// Byte index of the `)` closing the `(` at `open`.
+fn matching_paren(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, ch) in s.char_indices().skip_while(|&(i, _)| i < open) {
+        match ch {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse the `.limit(n)`, `.offset(n)` and `.order(spec)` suffixes after an
+/// embed's column list.
+fn parse_embed_modifiers(token: &str, mut rest: &str) -> Result<EmbedModifiers, Error> {
+    let mut modifiers = EmbedModifiers::default();
+    while !rest.is_empty() {
+        let invalid = || Error::BadRequest(format!("Invalid embed modifier in select: {}", token));
+        let after_dot = rest.strip_prefix('.').ok_or_else(invalid)?;
+        let open = after_dot.find('(').ok_or_else(invalid)?;
+        let close = matching_paren(after_dot, open).ok_or_else(invalid)?;
+        let arg = after_dot[open + 1..close].trim();
+        let count = || {
+            arg.parse::<i64>()
+                .ok()
+                .filter(|n| *n >= 0)
+                .ok_or_else(invalid)
+        };
+        match &after_dot[..open] {
+            "limit" => modifiers.limit = Some(count()?),
+            "offset" => modifiers.offset = Some(count()?),
+            "order" if !arg.is_empty() => modifiers.order = Some(arg.to_string()),
+            _ => return Err(invalid()),
+        }
+        rest = &after_dot[close + 1..];
+    }
+    Ok(modifiers)
+}
+
+/// Target types accepted in `::type` casts. `text` is shorthand for `nvarchar(max)`.
+const CAST_TYPES: &[&str] = &[
+    "bigint",
+    "int",
+    "smallint",
+    "tinyint",
+    "bit",
+    "decimal",
+    "numeric",
+    "float",
+    "real",
+    "money",
+    "date",
+    "time",
+    "datetime",
+    "datetime2",
+    "datetimeoffset",
+    "char",
+    "varchar",
+    "nchar",
+    "nvarchar",
+    "uniqueidentifier",
+];
+
+/// Validate a cast target: a known type name, optionally with `(n)`,
+/// `(p,s)` or `(max)`. The result is inlined into `CAST(... AS <type>)`.
+pub fn parse_cast_type(ty: &str) -> Result<String, Error> {
+    let ty = ty.trim().to_ascii_lowercase();
+    if ty == "text" {
+        return Ok("nvarchar(max)".to_string());
+    }
+    let (name, args) = match ty.find('(') {
+        Some(pos) if ty.ends_with(')') => (&ty[..pos], Some(&ty[pos + 1..ty.len() - 1])),
+        Some(_) => return Err(Error::BadRequest(format!("Invalid cast type: {}", ty))),
+        None => (ty.as_str(), None),
+    };
+    let args_ok = args.is_none_or(|a| {
+        a == "max"
+            || a.split(',').count() <= 2
+                && a.split(',')
+                    .all(|n| !n.trim().is_empty() && n.trim().chars().all(|c| c.is_ascii_digit()))
+    });
+    if !CAST_TYPES.contains(&name) || !args_ok {
+        return Err(Error::BadRequest(format!("Unsupported cast type: {}", ty)));
+    }
+    Ok(ty.replace(' ', ""))
+}
+
+/// Parse `col->key->>key` into a JSON path; `None` for a plain column name.
+///
+/// Keys are restricted to letters, digits, `_`, `-` and `$` since they are
+/// inlined into the JSON path literal.
+pub fn parse_json_path(expr: &str) -> Result<Option<JsonPath>, Error> {
+    let Some(arrow) = find_unquoted(expr, "->") else {
+        return Ok(None);
+    };
+    let column = unquote_ident(&expr[..arrow]);
+    if column.is_empty() {
+        return Err(Error::BadRequest(format!(
+            "Missing column in JSON path: {}",
+            expr
+        )));
+    }
+
+    let mut keys = Vec::new();
+    let mut as_text = false;
+    let mut rest = &expr[arrow..];
+    while !rest.is_empty() {
+        if as_text {
+            return Err(Error::BadRequest(format!(
+                "->> must be the last step of a JSON path: {}",
+                expr
+            )));
+        }
+        let after = if let Some(r) = rest.strip_prefix("->>") {
+            as_text = true;
+            r
+        } else if let Some(r) = rest.strip_prefix("->") {
+            r
         } else {
-            token.to_string()
+            return Err(Error::BadRequest(format!("Invalid JSON path: {}", expr)));
         };
-        Ok(SelectNode::Column(col))
+        let end = after.find("->").unwrap_or(after.len());
+        let key = &after[..end];
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '$'))
+        {
+            return Err(Error::BadRequest(format!(
+                "Invalid JSON path key '{}' in {}",
+                key, expr
+            )));
+        }
+        keys.push(key.to_string());
+        rest = &after[end..];
     }
+
+    Ok(Some(JsonPath {
+        column,
+        keys,
+        as_text,
+    }))
 }
 
-/// Split a string by top-level commas (not inside parentheses).
-fn split_top_level(s: &str) -> Vec<String> {
+/// Split a string by top-level commas (not inside parentheses or quotes).
+pub fn split_top_level(s: &str) -> Vec<String> {
     let mut parts = Vec::new();
     let mut current = String::new();
     let mut depth = 0;
+    let mut quoted = false;
 
     for ch in s.chars() {
         match ch {
+            '"' => {
+                quoted = !quoted;
+                current.push(ch);
+            }
+            _ if quoted => current.push(ch),
             '(' => {
                 depth += 1;
                 current.push(ch);
@@ -129,6 +392,72 @@ fn split_top_level(s: &str) -> Vec<String> {
     parts
 }
 
+/// An identifier as written in a select, filter or order: double quotes
+/// (`"Order Date"`, `"a.b"`) allow any character, with `""` standing for a
+/// literal quote. Unquoted names come back as they are.
+pub fn unquote_ident(s: &str) -> String {
+    let s = s.trim();
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => inner.replace("\"\"", "\""),
+        None => s.to_string(),
+    }
+}
+
+/// `unquote_ident` for a column that may carry a JSON path:
+/// `"Order Data"->tags` → `Order Data->tags`.
+pub fn unquote_column(expr: &str) -> String {
+    match find_unquoted(expr, "->") {
+        Some(arrow) => format!("{}{}", unquote_ident(&expr[..arrow]), &expr[arrow..]),
+        None => unquote_ident(expr),
+    }
+}
+
+/// Byte offsets where `pat` occurs outside double quotes.
+fn unquoted_matches<'a>(s: &'a str, pat: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let mut quoted = false;
+    s.char_indices().filter_map(move |(i, ch)| {
+        if ch == '"' {
+            quoted = !quoted;
+            return None;
+        }
+        (!quoted && s[i..].starts_with(pat)).then_some(i)
+    })
+}
+
+/// First occurrence of `pat` outside double quotes.
+pub fn find_unquoted(s: &str, pat: &str) -> Option<usize> {
+    unquoted_matches(s, pat).next()
+}
+
+/// Last occurrence of `pat` outside double quotes.
+pub fn rfind_unquoted(s: &str, pat: &str) -> Option<usize> {
+    unquoted_matches(s, pat).last()
+}
+
+/// Split on `sep` outside double quotes.
+pub fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut buf = [0; 4];
+    let positions: Vec<usize> = unquoted_matches(s, sep.encode_utf8(&mut buf)).collect();
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for pos in positions {
+        parts.push(&s[start..pos]);
+        start = pos + sep.len_utf8();
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// A comma-separated column list (`on_conflict=`, `columns=`,
+/// `distinct_on=`), unquoted.
+pub fn split_idents(s: &str) -> Vec<String> {
+    split_top_level(s)
+        .iter()
+        .map(|c| unquote_ident(c))
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
 /// Extract the list of plain column names from a select expression
 /// (ignoring embeds and stars).
 pub fn select_columns(nodes: &[SelectNode]) -> Vec<&str> {
@@ -136,7 +465,7 @@ pub fn select_columns(nodes: &[SelectNode]) -> Vec<&str> {
     for node in nodes {
         match node {
             SelectNode::Column(name) => cols.push(name.as_str()),
-            SelectNode::Star | SelectNode::Embed(_) => {}
+            SelectNode::Star | SelectNode::Embed(_) | SelectNode::Field(_) => {}
         }
     }
     cols
@@ -147,6 +476,13 @@ pub fn has_star(nodes: &[SelectNode]) -> bool {
     nodes.iter().any(|n| matches!(n, SelectNode::Star))
 }
 
+impl EmbedSelect {
+    /// Key the embedded rows appear under: the alias, else the name.
+    pub fn output_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
 /// Extract embed specifications from the select.
 pub fn select_embeds(nodes: &[SelectNode]) -> Vec<&EmbedSelect> {
     nodes
@@ -199,6 +535,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_inner_embed_hint() {
+        let nodes = parse_select("*,orders!fk_customer!inner(*)").unwrap();
+        if let SelectNode::Embed(e) = &nodes[1] {
+            assert_eq!(e.name, "orders");
+            assert_eq!(e.fk_hint.as_deref(), Some("fk_customer"));
+            assert!(e.inner);
+        } else {
+            panic!("Expected embed");
+        }
+    }
+
+    #[test]
+    fn test_json_path_field() {
+        let nodes = parse_select("id,data->settings->>theme,cfg:data->flags").unwrap();
+        assert_eq!(nodes.len(), 3);
+        if let SelectNode::Field(f) = &nodes[1] {
+            let path = f.json_path.as_ref().unwrap();
+            assert_eq!(f.alias, "theme");
+            assert_eq!(f.column, "data");
+            assert_eq!(path.keys, ["settings", "theme"]);
+            assert!(path.as_text);
+        } else {
+            panic!("Expected field");
+        }
+        assert!(
+            matches!(&nodes[2], SelectNode::Field(f) if f.alias == "cfg" && !f.json_path.as_ref().unwrap().as_text)
+        );
+        assert!(parse_select("data->>a->b").is_err());
+        assert!(parse_select("data->'x'").is_err());
+    }
+
+    #[test]
+    fn test_cast_suffix() {
+        let nodes =
+            parse_select("price::float,day:created_at::date,amount::decimal(10,2)").unwrap();
+        assert!(
+            matches!(&nodes[0], SelectNode::Field(f) if f.alias == "price" && f.cast.as_deref() == Some("float"))
+        );
+        assert!(
+            matches!(&nodes[1], SelectNode::Field(f) if f.alias == "day" && f.column == "created_at")
+        );
+        assert!(
+            matches!(&nodes[2], SelectNode::Field(f) if f.cast.as_deref() == Some("decimal(10,2)"))
+        );
+        assert!(parse_select("price::float;drop").is_err());
+    }
+
+    #[test]
+    fn test_embed_modifiers() {
+        let nodes = parse_select("*,orders(id,total).order(created_at.desc).limit(5)").unwrap();
+        if let SelectNode::Embed(e) = &nodes[1] {
+            assert_eq!(e.columns.len(), 2);
+            assert_eq!(e.order.as_deref(), Some("created_at.desc"));
+            assert_eq!(e.limit, Some(5));
+            assert!(e.offset.is_none());
+        } else {
+            panic!("Expected embed");
+        }
+        assert!(parse_select("*,orders(*).limit(-1)").is_err());
+        assert!(parse_select("*,orders(*).top(3)").is_err());
+        assert!(parse_select("*,orders(*)x").is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let nodes = parse_select("name:CUST_NM_TXT,id:id,buyer:customers!fk_buyer(*)").unwrap();
+        assert!(
+            matches!(&nodes[0], SelectNode::Field(f) if f.alias == "name" && f.column == "CUST_NM_TXT")
+        );
+        assert!(matches!(&nodes[1], SelectNode::Column(c) if c == "id"));
+        if let SelectNode::Embed(e) = &nodes[2] {
+            assert_eq!(e.name, "customers");
+            assert_eq!(e.output_name(), "buyer");
+            assert_eq!(e.fk_hint.as_deref(), Some("fk_buyer"));
+        } else {
+            panic!("Expected embed");
+        }
+    }
+
+    #[test]
+    fn test_quoted_identifiers() {
+        let nodes = parse_select(
+            r#""Order Date","a,b":"x.y","Unit ""Price"""::int,"Order Details"(qty),"Meta Data"->>tag"#,
+        )
+        .unwrap();
+        assert!(matches!(&nodes[0], SelectNode::Column(c) if c == "Order Date"));
+        assert!(matches!(&nodes[1], SelectNode::Field(f) if f.alias == "a,b" && f.column == "x.y"));
+        assert!(matches!(
+            &nodes[2],
+            SelectNode::Field(f) if f.column == "Unit \"Price\"" && f.cast.as_deref() == Some("int")
+        ));
+        assert!(matches!(&nodes[3], SelectNode::Embed(e) if e.name == "Order Details"));
+        assert!(matches!(
+            &nodes[4],
+            SelectNode::Field(f) if f.column == "Meta Data" && f.alias == "tag"
+        ));
+        assert_eq!(split_idents(r#"id, "Order Date""#), ["id", "Order Date"]);
+    }
+
+    #[test]
+    fn test_spread_embed() {
+        let nodes = parse_select("id,...customer(name,city)").unwrap();
+        if let SelectNode::Embed(e) = &nodes[1] {
+            assert_eq!(e.name, "customer");
+            assert!(e.spread);
+            assert_eq!(e.columns.len(), 2);
+        } else {
+            panic!("Expected embed");
+        }
+        assert!(parse_select("...name").is_err());
+    }
+
     #[test]
     fn test_nested_embed() {
         let nodes = parse_select("*,orders(items(*))").unwrap();
@@ -0,0 +1,89 @@
+//! HTTP server accept loop with connection tuning.
+//!
+//! Serves the axum router through hyper-util so HTTP/2 cleartext (h2c),
+//! HTTP/1 keep-alive and header read timeouts, HTTP/2 keep-alive pings,
+//! stream limits, and TCP_NODELAY can be configured.
+
+use crate::config::AppConfig;
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+/// Build the hyper connection builder from config.
+fn connection_builder(config: &AppConfig) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+
+    {
+        let mut http1 = builder.http1();
+        http1.timer(TokioTimer::new()).keep_alive(config.keep_alive);
+        if let Some(secs) = config.header_read_timeout {
+            http1.header_read_timeout(Duration::from_secs(secs));
+        }
+    }
+
+    if config.http2 {
+        let mut http2 = builder.http2();
+        http2.timer(TokioTimer::new());
+        if let Some(streams) = config.http2_max_streams {
+            http2.max_concurrent_streams(streams);
+        }
+        if let Some(secs) = config.http2_keep_alive_interval {
+            http2
+                .keep_alive_interval(Duration::from_secs(secs))
+                .keep_alive_timeout(Duration::from_secs(secs));
+        }
+        builder
+    } else {
+        builder.http1_only()
+    }
+}
+
+/// Tags each request with the connection's peer address, for extractors
+/// and middleware that use `ConnectInfo<SocketAddr>`.
+fn with_peer<B>(peer: SocketAddr) -> impl FnMut(http::Request<B>) -> http::Request<B> + Clone {
+    move |mut request| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    }
+}
+
+/// Accept connections and serve the router until the listener fails.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    config: &AppConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = connection_builder(config);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        if config.tcp_nodelay {
+            if let Err(e) = stream.set_nodelay(true) {
+                tracing::debug!("Could not set TCP_NODELAY for {}: {}", peer, e);
+            }
+        }
+
+        let builder = builder.clone();
+        let service = TowerToHyperService::new(app.clone().map_request(with_peer(peer)));
+        tokio::spawn(async move {
+            if let Err(e) = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Connection from {} closed with error: {}", peer, e);
+            }
+        });
+    }
+}
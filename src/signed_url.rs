@@ -0,0 +1,236 @@
+//! Signed URLs: short-lived links that let a single table GET through
+//! without an `Authorization` header, for `<img src>` and download links.
+//!
+//! The link carries the minting caller's claims (`sig_grant`), its expiry
+//! (`sig_exp`) and an HMAC-SHA256 (`sig`) over the table the path resolved
+//! to when it was minted, the remaining query parameters, the expiry and
+//! the grant. The request then runs as the caller that minted it.
+
+use crate::auth::Claims;
+use crate::config::AppConfig;
+use crate::error::Error;
+use crate::handlers::QueryParams;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{crypto, Algorithm, DecodingKey, EncodingKey};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SIGNATURE_PARAM: &str = "sig";
+pub const EXPIRES_PARAM: &str = "sig_exp";
+pub const GRANT_PARAM: &str = "sig_grant";
+const PARAMS: [&str; 3] = [SIGNATURE_PARAM, EXPIRES_PARAM, GRANT_PARAM];
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// What the signature covers. The resolved `(schema, table)` rather than the
+/// path, so the link can't be pointed at a same-named table in another
+/// schema. Parameters are sorted so the link verifies however the query is
+/// decoded; JSON keeps the fields unambiguous.
+fn message(
+    (schema, table): (&str, &str),
+    params: &[(&str, &str)],
+    expires: u64,
+    grant: &str,
+) -> String {
+    let mut params = params.to_vec();
+    params.sort_unstable();
+    serde_json::json!([[schema, table], params, expires, grant]).to_string()
+}
+
+/// Sign `path_and_query` (`/orders?id=eq.7`), whose path resolves to
+/// `target` (`("dbo", "orders")`), for `claims` until `expires` (Unix
+/// seconds). Returns the link's path and query.
+pub fn sign(
+    secret: &str,
+    path_and_query: &str,
+    target: (&str, &str),
+    claims: &Claims,
+    expires: u64,
+) -> Result<String, Error> {
+    let mut url = reqwest::Url::parse("http://lazypaw/")
+        .and_then(|base| base.join(path_and_query))
+        .map_err(|e| Error::BadRequest(format!("Invalid path: {}", e)))?;
+    let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    if let Some((key, _)) = pairs.iter().find(|(k, _)| PARAMS.contains(&k.as_str())) {
+        return Err(Error::BadRequest(format!("Reserved parameter: {}", key)));
+    }
+
+    // Timestamps of the minting token don't apply to the link
+    let grant_claims = Claims {
+        exp: None,
+        iat: None,
+        nbf: None,
+        ..claims.clone()
+    };
+    let grant = URL_SAFE_NO_PAD
+        .encode(serde_json::to_vec(&grant_claims).map_err(|e| Error::Internal(e.to_string()))?);
+    let params: Vec<(&str, &str)> = pairs
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let signature = crypto::sign(
+        message(target, &params, expires, &grant).as_bytes(),
+        &EncodingKey::from_secret(secret.as_bytes()),
+        Algorithm::HS256,
+    )
+    .map_err(|e| Error::Internal(format!("Signing failed: {}", e)))?;
+
+    url.query_pairs_mut()
+        .append_pair(EXPIRES_PARAM, &expires.to_string())
+        .append_pair(GRANT_PARAM, &grant)
+        .append_pair(SIGNATURE_PARAM, &signature);
+    Ok(match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    })
+}
+
+/// Claims of a signed GET of `target`, the `(schema, table)` its path
+/// resolved to, or `None` when the request isn't signed. A signed request
+/// can't pick a schema with `Accept-Profile` (`profile`).
+pub fn verify(
+    secret: Option<&str>,
+    target: (&str, &str),
+    profile: Option<&str>,
+    query_params: &QueryParams,
+) -> Result<Option<Claims>, Error> {
+    let Some(signature) = query_params.get(SIGNATURE_PARAM) else {
+        return Ok(None);
+    };
+    let secret =
+        secret.ok_or_else(|| Error::Unauthorized("Signed URLs are not enabled".to_string()))?;
+    if profile.is_some() {
+        return Err(Error::BadRequest(
+            "Signed URLs don't take Accept-Profile".to_string(),
+        ));
+    }
+    let invalid = || Error::Unauthorized("Invalid signed URL".to_string());
+    let expires: u64 = query_params
+        .get(EXPIRES_PARAM)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(invalid)?;
+    let grant = query_params.get(GRANT_PARAM).ok_or_else(invalid)?;
+
+    let params: Vec<(&str, &str)> = query_params
+        .pairs()
+        .filter(|(k, _)| !PARAMS.contains(&k.as_str()))
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let valid = crypto::verify(
+        signature,
+        message(target, &params, expires, grant).as_bytes(),
+        &DecodingKey::from_secret(secret.as_bytes()),
+        Algorithm::HS256,
+    )
+    .unwrap_or(false);
+    if !valid {
+        return Err(invalid());
+    }
+    if expires <= now() {
+        return Err(Error::Unauthorized("Signed URL expired".to_string()));
+    }
+
+    let claims = URL_SAFE_NO_PAD
+        .decode(grant)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(invalid)?;
+    Ok(Some(claims))
+}
+
+/// Expiry for a link requested to live `expires_in` seconds, capped by
+/// `--signed-url-max-secs`.
+pub fn expiry(config: &AppConfig, expires_in: Option<u64>) -> u64 {
+    let max = config.signed_url_max_secs;
+    now() + expires_in.unwrap_or(max).clamp(1, max.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    fn claims() -> Claims {
+        serde_json::from_value(serde_json::json!({"role": "app_user", "sub": "42"})).unwrap()
+    }
+
+    /// The query of a signed link, as the router would hand it over.
+    fn query(link: &str) -> QueryParams {
+        let url = reqwest::Url::parse("http://lazypaw/")
+            .unwrap()
+            .join(link)
+            .unwrap();
+        QueryParams::from(url.query_pairs().into_owned().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let link = sign(
+            SECRET,
+            "/orders?id=eq.7",
+            ("dbo", "orders"),
+            &claims(),
+            now() + 60,
+        )
+        .unwrap();
+        assert!(link.starts_with("/orders?id=eq.7&sig_exp="));
+        let verified = verify(Some(SECRET), ("dbo", "orders"), None, &query(&link))
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified.role.as_deref(), Some("app_user"));
+        assert_eq!(verified.sub.as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_unsigned_request() {
+        let unsigned = query("/orders?id=eq.7");
+        assert!(verify(Some(SECRET), ("dbo", "orders"), None, &unsigned)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_expired_link() {
+        let link = sign(SECRET, "/orders", ("dbo", "orders"), &claims(), now() - 1).unwrap();
+        let err = verify(Some(SECRET), ("dbo", "orders"), None, &query(&link)).unwrap_err();
+        assert!(matches!(err, Error::Unauthorized(msg) if msg.contains("expired")));
+    }
+
+    #[test]
+    fn test_tampered_link() {
+        let link = sign(
+            SECRET,
+            "/orders?id=eq.7",
+            ("dbo", "orders"),
+            &claims(),
+            now() + 60,
+        )
+        .unwrap();
+        let tampered = link.replace("id=eq.7", "id=eq.8");
+        assert!(verify(Some(SECRET), ("dbo", "orders"), None, &query(&tampered)).is_err());
+
+        let wrong_key = verify(Some("other-secret"), ("dbo", "orders"), None, &query(&link));
+        assert!(wrong_key.is_err());
+    }
+
+    #[test]
+    fn test_other_schema() {
+        let link = sign(SECRET, "/orders", ("dbo", "orders"), &claims(), now() + 60).unwrap();
+        assert!(verify(Some(SECRET), ("archive", "orders"), None, &query(&link)).is_err());
+        assert!(matches!(
+            verify(
+                Some(SECRET),
+                ("dbo", "orders"),
+                Some("archive"),
+                &query(&link)
+            ),
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
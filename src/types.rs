@@ -1,5 +1,7 @@
 //! SQL Server type → JSON/Arrow type mapping.
 
+use crate::config::ColumnFormat;
+use crate::error::Error;
 use claw::SqlValue;
 use serde_json::Value as JsonValue;
 
@@ -26,6 +28,281 @@ pub fn sql_type_to_openapi(data_type: &str) -> (&'static str, &'static str) {
     }
 }
 
+/// Normalize a bound value for the column's type before it reaches SQL Server,
+/// which is picky about `bit` and `uniqueidentifier` string conversions.
+///
+/// - `bit`: `true`/`false`/`1`/`0` (any case) → `1`/`0`
+/// - `uniqueidentifier`: braced, unbraced, upper/lower case → lowercase hyphenated
+/// - `money` / `smallmoney`: `1,234.56`, `1.234,56`, `1234,56` → `1234.56`
+/// - integers: `5.0`, `5e2` → `5`, `500` (see [`plain_decimal`])
+/// - `decimal` / `numeric`: `1.5e3` → `1500`
+/// - `datetime`, `datetime2`, `smalldatetime`: RFC 3339 with `Z` or an
+///   offset (`2024-06-01T12:00:00.000Z`) → the UTC time, which those types
+///   can't carry an offset for
+///
+/// Other types pass through unchanged.
+pub fn normalize_param(data_type: &str, column: &str, value: &str) -> Result<String, Error> {
+    let data_type = data_type.to_lowercase();
+    let normalized = match data_type.as_str() {
+        "bit" => normalize_bit(value).map(str::to_string),
+        "uniqueidentifier" => normalize_guid(value),
+        "money" => normalize_money(value, MONEY_MAX),
+        "smallmoney" => normalize_money(value, SMALLMONEY_MAX),
+        "tinyint" | "smallint" | "int" | "bigint" => {
+            plain_decimal(value).filter(|v| !v.contains('.'))
+        }
+        "decimal" | "numeric" => plain_decimal(value),
+        "datetime" | "datetime2" | "smalldatetime" => {
+            match chrono::DateTime::parse_from_rfc3339(value.trim()) {
+                Ok(t) => Some(utc_datetime(&data_type, t.naive_utc())),
+                Err(_) => return Ok(value.to_string()),
+            }
+        }
+        _ => return Ok(value.to_string()),
+    };
+    normalized.ok_or_else(|| {
+        Error::BadRequest(format!(
+            "Invalid {} value for column {}: {}",
+            data_type, column, value
+        ))
+    })
+}
+
+/// Largest `money` / `smallmoney` magnitudes, in ten-thousandths.
+const MONEY_MAX: i128 = 922_337_203_685_477_5807;
+const SMALLMONEY_MAX: i128 = 2_147_483_647;
+
+/// Parse a money amount independently of the server's locale. SQL Server's
+/// own string conversion drops commas (`1234,56` becomes 123456), so the
+/// decimal separator is decided here:
+///
+/// - with both `.` and `,`, the last one is the decimal separator
+/// - a single `.` is decimal; a single `,` is decimal unless followed by
+///   exactly three digits (`1,234` is one thousand two hundred thirty-four)
+/// - a separator appearing more than once is grouping
+///
+/// Grouping must come in threes and at most four decimals are accepted (the
+/// type's scale). Currency symbols and spaces are ignored.
+fn normalize_money(value: &str, max: i128) -> Option<String> {
+    let v: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '$' | '€' | '£' | '¥'))
+        .collect();
+    let (negative, v) = match v.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, v.strip_prefix('+').unwrap_or(&v)),
+    };
+
+    let decimal_sep = match (v.rfind('.'), v.rfind(',')) {
+        (Some(d), Some(c)) => Some(if d > c { '.' } else { ',' }),
+        (Some(_), None) if v.matches('.').count() == 1 => Some('.'),
+        (None, Some(c)) if v.matches(',').count() == 1 && v.len() - c - 1 != 3 => Some(','),
+        _ => None,
+    };
+    let (int_part, frac_part) = match decimal_sep {
+        Some(sep) => v.rsplit_once(sep)?,
+        None => (v, ""),
+    };
+
+    // Whatever separator remains in the integer part is grouping
+    let groups: Vec<&str> = int_part.split(['.', ',']).collect();
+    if groups.len() > 1
+        && (groups[0].is_empty() || groups[0].len() > 3 || groups[1..].iter().any(|g| g.len() != 3))
+    {
+        return None;
+    }
+    let digits: String = groups.concat();
+    if (digits.is_empty() && frac_part.is_empty())
+        || frac_part.len() > 4
+        || !digits
+            .chars()
+            .chain(frac_part.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let scaled = format!("{}{:0<4}", digits, frac_part)
+        .parse::<i128>()
+        .ok()?;
+    if scaled > max + i128::from(negative) {
+        return None;
+    }
+
+    let digits = digits.trim_start_matches('0');
+    let mut out = String::new();
+    if negative && scaled != 0 {
+        out.push('-');
+    }
+    out.push_str(if digits.is_empty() { "0" } else { digits });
+    if !frac_part.is_empty() {
+        out.push('.');
+        out.push_str(frac_part);
+    }
+    Some(out)
+}
+
+/// A decimal number in plain notation, for types whose string conversion
+/// takes no exponent: `5.0` → `5`, `1.5e3` → `1500`, `-2.50E-1` → `-0.25`.
+/// Digits are shifted as text, so nothing is lost to floating point.
+/// `None` for anything else, including `inf` and `NaN`.
+pub fn plain_decimal(value: &str) -> Option<String> {
+    let v = value.trim();
+    let (negative, v) = match v.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, v.strip_prefix('+').unwrap_or(v)),
+    };
+    let (mantissa, exponent) = match v.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().ok()?),
+        None => (v, 0),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if (int_part.is_empty() && frac_part.is_empty())
+        || !int_part
+            .chars()
+            .chain(frac_part.chars())
+            .all(|c| c.is_ascii_digit())
+        // Further than any SQL Server numeric reaches
+        || exponent.abs() > 400
+    {
+        return None;
+    }
+
+    // Move the decimal point `exponent` places within the digits
+    let digits = format!("{}{}", int_part, frac_part);
+    let point = int_part.len() as i64 + exponent as i64;
+    let (int_digits, frac_digits) = if point <= 0 {
+        (
+            String::new(),
+            format!("{}{}", "0".repeat(point.unsigned_abs() as usize), digits),
+        )
+    } else if point as usize >= digits.len() {
+        (
+            format!("{}{}", digits, "0".repeat(point as usize - digits.len())),
+            String::new(),
+        )
+    } else {
+        let (i, f) = digits.split_at(point as usize);
+        (i.to_string(), f.to_string())
+    };
+
+    let int_digits = int_digits.trim_start_matches('0');
+    let frac_digits = frac_digits.trim_end_matches('0');
+    let mut out = String::new();
+    if negative && !(int_digits.is_empty() && frac_digits.is_empty()) {
+        out.push('-');
+    }
+    out.push_str(if int_digits.is_empty() {
+        "0"
+    } else {
+        int_digits
+    });
+    if !frac_digits.is_empty() {
+        out.push('.');
+        out.push_str(frac_digits);
+    }
+    Some(out)
+}
+
+/// A UTC time in the ISO 8601 form the type converts from: `datetime`
+/// takes three fractional digits, `datetime2` seven, `smalldatetime` none.
+fn utc_datetime(data_type: &str, t: chrono::NaiveDateTime) -> String {
+    match data_type {
+        "smalldatetime" => t.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        "datetime" => t.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+        _ => {
+            let mut s = t.format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+            if let Some(dot) = s.find('.') {
+                s.truncate((dot + 8).min(s.len()));
+            }
+            s
+        }
+    }
+}
+
+fn normalize_bit(value: &str) -> Option<&'static str> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some("1"),
+        "false" | "0" => Some("0"),
+        _ => None,
+    }
+}
+
+fn normalize_guid(value: &str) -> Option<String> {
+    let v = value.trim();
+    let v = v
+        .strip_prefix('{')
+        .and_then(|v| v.strip_suffix('}'))
+        .unwrap_or(v);
+    let hex: String = v.chars().filter(|c| *c != '-').collect();
+    let dashes_ok = !v.contains('-') || v.split('-').map(str::len).eq([8, 4, 4, 4, 12].into_iter());
+    if hex.len() != 32 || !dashes_ok || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = hex.to_ascii_lowercase();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+/// Whether a filter value converts to the column's type, so a typo like
+/// `age=gt.abc` is a 400 rather than a conversion error from SQL Server.
+/// Numbers, dates and times are checked; other types are left to
+/// [`normalize_param`] or the server.
+pub fn value_fits_type(data_type: &str, value: &str) -> bool {
+    use chrono::{DateTime, NaiveDate, NaiveTime};
+
+    let v = value.trim();
+    let date = |d: &str| {
+        let parts: Vec<&str> = d.split(['-', '/', '.']).collect();
+        if parts.len() != 3
+            || !parts
+                .iter()
+                .all(|p| (1..=4).contains(&p.len()) && p.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return false;
+        }
+        // Year-first dates must be real; other orders (01/02/2024) depend on
+        // the session's DATEFORMAT, so only their shape is checked
+        parts[0].len() != 4 || NaiveDate::parse_from_str(&parts.join("-"), "%Y-%m-%d").is_ok()
+    };
+    let time = |t: &str| {
+        ["%H:%M:%S%.f", "%H:%M"]
+            .iter()
+            .any(|fmt| NaiveTime::parse_from_str(t, fmt).is_ok())
+    };
+    let datetime = |v: &str| match v.split_once(['T', ' ']) {
+        Some((d, t)) => date(d) && time(t),
+        None => date(v),
+    };
+    // Integral values in any notation (`5`, `5.0`, `5e2`)
+    let integer = || plain_decimal(v).filter(|p| !p.contains('.'));
+    match data_type.to_lowercase().as_str() {
+        "tinyint" => integer().is_some_and(|p| p.parse::<u8>().is_ok()),
+        "smallint" => integer().is_some_and(|p| p.parse::<i16>().is_ok()),
+        "int" => integer().is_some_and(|p| p.parse::<i32>().is_ok()),
+        "bigint" => integer().is_some_and(|p| p.parse::<i64>().is_ok()),
+        // Exponents are rewritten by `normalize_param`
+        "decimal" | "numeric" => plain_decimal(v).is_some(),
+        "float" | "real" => {
+            v.chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))
+                && v.parse::<f64>().is_ok()
+        }
+        "date" => date(v),
+        "time" => time(v),
+        "datetime" | "datetime2" | "smalldatetime" | "datetimeoffset" => {
+            DateTime::parse_from_rfc3339(v).is_ok() || datetime(v)
+        }
+        _ => true,
+    }
+}
+
 /// Convert a claw SqlValue to a serde_json Value.
 pub fn sql_value_to_json(val: &SqlValue<'_>) -> JsonValue {
     match val {
@@ -56,26 +333,7 @@ pub fn sql_value_to_json(val: &SqlValue<'_>) -> JsonValue {
             JsonValue::String(base64::engine::general_purpose::STANDARD.encode(v.as_ref()))
         }
         SqlValue::Binary(None) => JsonValue::Null,
-        SqlValue::Numeric(Some(v)) => {
-            // Render as string to preserve precision
-            let raw = v.value();
-            let scale = v.scale();
-            if scale == 0 {
-                if let Some(n) = serde_json::Number::from_f64(raw as f64) {
-                    return JsonValue::Number(n);
-                }
-                JsonValue::String(raw.to_string())
-            } else {
-                let s = format_decimal(raw, scale);
-                // Try to parse as f64 for JSON number
-                if let Ok(f) = s.parse::<f64>() {
-                    if let Some(n) = serde_json::Number::from_f64(f) {
-                        return JsonValue::Number(n);
-                    }
-                }
-                JsonValue::String(s)
-            }
-        }
+        SqlValue::Numeric(Some(v)) => numeric_json(format_decimal(v.value(), v.scale())),
         SqlValue::Numeric(None) => JsonValue::Null,
         SqlValue::Xml(Some(v)) => JsonValue::String(format!("{}", v)),
         SqlValue::Xml(None) => JsonValue::Null,
@@ -162,6 +420,116 @@ pub fn sql_value_to_json(val: &SqlValue<'_>) -> JsonValue {
     }
 }
 
+/// Apply per-column serialization overrides to rows already converted to JSON.
+pub fn apply_column_formats(
+    rows: &mut [serde_json::Map<String, JsonValue>],
+    formats: &[(&str, &ColumnFormat)],
+) {
+    if formats.is_empty() {
+        return;
+    }
+    for row in rows {
+        for (column, format) in formats {
+            if let Some(val) = row.get_mut(*column) {
+                *val = format_value(std::mem::take(val), format);
+            }
+        }
+    }
+}
+
+/// Render one value in a configured format. Values the format doesn't apply
+/// to (NULLs, unparseable JSON, non-base64 text) are left unchanged.
+fn format_value(val: JsonValue, format: &ColumnFormat) -> JsonValue {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+
+    match (format, val) {
+        (ColumnFormat::Json, JsonValue::String(s)) => {
+            serde_json::from_str(&s).unwrap_or(JsonValue::String(s))
+        }
+        (ColumnFormat::Base64Url, JsonValue::String(s)) => match STANDARD.decode(&s) {
+            Ok(bytes) => JsonValue::String(URL_SAFE_NO_PAD.encode(bytes)),
+            Err(_) => JsonValue::String(s),
+        },
+        (ColumnFormat::DataUri(mime), JsonValue::String(s)) if STANDARD.decode(&s).is_ok() => {
+            JsonValue::String(format!("data:{};base64,{}", mime, s))
+        }
+        (ColumnFormat::String(scale), JsonValue::Number(n)) => {
+            let text = n.to_string();
+            let plain = plain_decimal(&text).unwrap_or(text);
+            JsonValue::String(match scale {
+                Some(scale) => round_decimal(&plain, *scale as usize),
+                None => plain,
+            })
+        }
+        // Decimals too precise for a JSON number already arrive as text
+        (ColumnFormat::String(Some(scale)), JsonValue::String(s)) => match plain_decimal(&s) {
+            Some(plain) => JsonValue::String(round_decimal(&plain, *scale as usize)),
+            None => JsonValue::String(s),
+        },
+        (_, other) => other,
+    }
+}
+
+/// Round a plain-notation decimal to `scale` places, half away from zero,
+/// working on the digits so no precision is lost to a float.
+fn round_decimal(plain: &str, scale: usize) -> String {
+    let (negative, digits) = match plain.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, plain),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    let kept = &frac_part[..frac_part.len().min(scale)];
+    let mut out = format!("{}{:0<width$}", int_part, kept, width = scale).into_bytes();
+    if frac_part.as_bytes().get(scale).is_some_and(|d| *d >= b'5') {
+        let mut carried = true;
+        for d in out.iter_mut().rev() {
+            if *d == b'9' {
+                *d = b'0';
+            } else {
+                *d += 1;
+                carried = false;
+                break;
+            }
+        }
+        if carried {
+            out.insert(0, b'1');
+        }
+    }
+
+    let out = String::from_utf8(out).expect("ASCII digits");
+    let (int_digits, frac_digits) = out.split_at(out.len() - scale);
+    let int_digits = match int_digits.trim_start_matches('0') {
+        "" => "0",
+        rest => rest,
+    };
+    let zero = out.bytes().all(|d| d == b'0');
+    let mut rounded = String::new();
+    if negative && !zero {
+        rounded.push('-');
+    }
+    rounded.push_str(int_digits);
+    if scale > 0 {
+        rounded.push('.');
+        rounded.push_str(frac_digits);
+    }
+    rounded
+}
+
+/// A decimal's exact text as a JSON number when a float holds it exactly,
+/// otherwise as a string so no digits are lost.
+fn numeric_json(text: String) -> JsonValue {
+    let exact = text
+        .parse::<f64>()
+        .ok()
+        .filter(|f| plain_decimal(&f.to_string()) == plain_decimal(&text))
+        .and_then(serde_json::Number::from_f64);
+    match exact {
+        Some(n) => JsonValue::Number(n),
+        None => JsonValue::String(text),
+    }
+}
+
 /// Convert a Row into a JSON object.
 pub fn row_to_json(row: &claw::Row) -> serde_json::Map<String, JsonValue> {
     let mut obj = serde_json::Map::new();
@@ -190,3 +558,186 @@ fn format_decimal(value: i128, scale: u8) -> String {
         width = scale as usize
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_money() {
+        let cases = [
+            ("1,234.56", Some("1234.56")),
+            ("1234,56", Some("1234.56")),
+            ("1.234,56", Some("1234.56")),
+            ("1,234", Some("1234")),
+            ("1.234.567", Some("1234567")),
+            ("$ 12.5", Some("12.5")),
+            ("-1,234.56", Some("-1234.56")),
+            ("-0.00", Some("0.00")),
+            ("922337203685477.5807", Some("922337203685477.5807")),
+            ("-922337203685477.5808", Some("-922337203685477.5808")),
+            ("922337203685477.5808", None),
+            ("1.23456", None),
+            ("12,34,567", None),
+            ("abc", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                normalize_money(value, MONEY_MAX).as_deref(),
+                expected,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            normalize_money("214748.3647", SMALLMONEY_MAX).as_deref(),
+            Some("214748.3647")
+        );
+        assert_eq!(
+            normalize_money("-214748.3648", SMALLMONEY_MAX).as_deref(),
+            Some("-214748.3648")
+        );
+        assert_eq!(normalize_money("214748.3648", SMALLMONEY_MAX), None);
+    }
+
+    #[test]
+    fn test_normalize_bit() {
+        let cases = [
+            ("true", Some("1")),
+            ("TRUE", Some("1")),
+            (" 1 ", Some("1")),
+            ("false", Some("0")),
+            ("False", Some("0")),
+            ("0", Some("0")),
+            ("yes", None),
+            ("2", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(normalize_bit(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_normalize_guid() {
+        let guid = "6f9619ff-8b86-d011-b42d-00c04fc964ff";
+        let cases = [
+            ("6f9619ff-8b86-d011-b42d-00c04fc964ff", Some(guid)),
+            ("{6f9619ff-8b86-d011-b42d-00c04fc964ff}", Some(guid)),
+            ("6F9619FF-8B86-D011-B42D-00C04FC964FF", Some(guid)),
+            ("6f9619ff8b86d011b42d00c04fc964ff", Some(guid)),
+            (" {6F9619FF8B86D011B42D00C04FC964FF} ", Some(guid)),
+            ("6f9619ff-8b86-d011-b42d-00c04fc964f", None),
+            ("6f9619ff-8b86d011-b42d-00c04fc964ff", None),
+            ("{6f9619ff-8b86-d011-b42d-00c04fc964ff", None),
+            ("6f9619ff-8b86-d011-b42d-00c04fc964fg", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(normalize_guid(value).as_deref(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_numeric_json() {
+        assert_eq!(numeric_json("12.50".into()), serde_json::json!(12.5));
+        assert_eq!(numeric_json("-3".into()), serde_json::json!(-3.0));
+        assert_eq!(
+            numeric_json("12345678901234567.89".into()),
+            JsonValue::String("12345678901234567.89".into())
+        );
+        assert_eq!(
+            numeric_json("12345678901234567891".into()),
+            JsonValue::String("12345678901234567891".into())
+        );
+    }
+
+    #[test]
+    fn test_format_value() {
+        use serde_json::json;
+        let cases = [
+            (
+                ColumnFormat::Json,
+                json!("{\"a\":[1,2]}"),
+                json!({"a": [1, 2]}),
+            ),
+            (ColumnFormat::Json, json!("not json"), json!("not json")),
+            (ColumnFormat::Base64Url, json!("+/+/AA=="), json!("-_-_AA")),
+            (ColumnFormat::Base64Url, json!("%%%"), json!("%%%")),
+            (
+                ColumnFormat::DataUri("image/png".into()),
+                json!("iVBORw=="),
+                json!("data:image/png;base64,iVBORw=="),
+            ),
+            (
+                ColumnFormat::DataUri("image/png".into()),
+                json!("plain text"),
+                json!("plain text"),
+            ),
+            (ColumnFormat::String(None), json!(12.5), json!("12.5")),
+            (
+                ColumnFormat::String(None),
+                json!(1e20),
+                json!("100000000000000000000"),
+            ),
+            (ColumnFormat::String(Some(2)), json!(12.5), json!("12.50")),
+            (ColumnFormat::String(Some(2)), json!(2.675), json!("2.68")),
+            (ColumnFormat::String(Some(1)), json!(-9.96), json!("-10.0")),
+            (ColumnFormat::String(Some(0)), json!(0.4), json!("0")),
+            (ColumnFormat::String(Some(1)), json!(-0.04), json!("0.0")),
+            (
+                ColumnFormat::String(Some(2)),
+                json!("12345678901234567.895"),
+                json!("12345678901234567.90"),
+            ),
+            (ColumnFormat::String(Some(2)), json!("n/a"), json!("n/a")),
+            (ColumnFormat::Json, JsonValue::Null, JsonValue::Null),
+            (
+                ColumnFormat::String(Some(2)),
+                JsonValue::Null,
+                JsonValue::Null,
+            ),
+        ];
+        for (format, value, expected) in cases {
+            assert_eq!(
+                format_value(value.clone(), &format),
+                expected,
+                "{:?} {}",
+                format,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_column_formats() {
+        use serde_json::json;
+        let mut rows = vec![
+            json!({"price": 1.5, "meta": "[1]", "name": "a"}),
+            json!({"price": 2, "meta": null, "name": "b"}),
+        ]
+        .into_iter()
+        .map(|row| match row {
+            JsonValue::Object(map) => map,
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+        let price = ColumnFormat::String(Some(2));
+        apply_column_formats(
+            &mut rows,
+            &[
+                ("price", &price),
+                ("meta", &ColumnFormat::Json),
+                ("missing", &price),
+            ],
+        );
+        assert_eq!(
+            JsonValue::Array(rows.into_iter().map(JsonValue::Object).collect()),
+            json!([
+                {"price": "1.50", "meta": [1], "name": "a"},
+                {"price": "2.00", "meta": null, "name": "b"},
+            ])
+        );
+    }
+}
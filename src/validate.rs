@@ -0,0 +1,410 @@
+//! Write validation: column metadata and declarative `[tables.<name>]`
+//! rules.
+//!
+//! Checks run on insert and update bodies before any SQL is built, so
+//! unknown columns, missing or null NOT NULL values, oversized strings and
+//! out-of-range numbers come back as one structured 400 listing every field
+//! instead of a conversion error from SQL Server, and databases that can't
+//! take CHECK constraints still reject bad writes.
+
+use crate::config::{TableConfig, ValidationRule};
+use crate::error::Error;
+use crate::schema::{ColumnInfo, TableInfo};
+use crate::types;
+use serde_json::{Map, Value};
+
+/// Check every object of a body against the table's columns and its
+/// configured rules (`cfg`). `insert` also requires the NOT NULL columns
+/// without a default. With several rows each problem is prefixed with its
+/// row index.
+pub fn check_objects(
+    table: &TableInfo,
+    cfg: Option<&TableConfig>,
+    objects: &[&Map<String, Value>],
+    insert: bool,
+) -> Result<(), Error> {
+    let mut problems = Vec::new();
+    for (i, obj) in objects.iter().enumerate() {
+        let mut found = check_columns(table, obj, insert);
+        if let Some(cfg) = cfg {
+            found.extend(check_object(cfg, obj));
+        }
+        for problem in found {
+            if objects.len() > 1 {
+                problems.push(format!("[{}] {}", i, problem));
+            } else {
+                problems.push(problem);
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Validation(problems))
+    }
+}
+
+/// Problems with one object against the table's column metadata.
+/// Read-only columns are left to `writable_columns`; views written through
+/// INSTEAD OF triggers may fill in what the body leaves out.
+fn check_columns(table: &TableInfo, obj: &Map<String, Value>, insert: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (key, value) in obj {
+        let Some(col) = table.column(key) else {
+            problems.push(format!("{}: unknown column", key));
+            continue;
+        };
+        if col.read_only_reason().is_some() {
+            continue;
+        }
+        if value.is_null() {
+            if !col.is_nullable {
+                problems.push(format!("{}: cannot be null", col.name));
+            }
+        } else {
+            check_column_value(col, value, &mut problems);
+        }
+    }
+
+    if insert && !table.instead_of.insert {
+        for col in &table.columns {
+            let required = !col.is_nullable && !col.has_default && col.read_only_reason().is_none();
+            if required && !obj.keys().any(|k| k.eq_ignore_ascii_case(&col.name)) {
+                problems.push(format!("{}: is required", col.name));
+            }
+        }
+    }
+    problems
+}
+
+/// Whether a value converts to the column's type and fits its length,
+/// precision or range. Binary and JSON values aren't checked.
+fn check_column_value(col: &ColumnInfo, value: &Value, problems: &mut Vec<String>) {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Object(_) | Value::Array(_) => value.to_string(),
+        Value::Bool(_) | Value::Null => return,
+    };
+    let data_type = col.data_type.to_lowercase();
+    match data_type.as_str() {
+        "char" | "varchar" | "nchar" | "nvarchar" => {
+            let Some(limit) = col.max_length.filter(|n| *n > 0) else {
+                return;
+            };
+            // n-types count UTF-16 code units, UTF-8 collations bytes
+            let utf8 = col
+                .collation
+                .as_deref()
+                .is_some_and(|c| c.to_uppercase().contains("UTF8"));
+            let length = if data_type.starts_with('n') {
+                text.encode_utf16().count()
+            } else if utf8 {
+                text.len()
+            } else {
+                text.chars().count()
+            };
+            if length > limit as usize {
+                problems.push(format!(
+                    "{}: longer than {} characters ({})",
+                    col.name, limit, length
+                ));
+            }
+        }
+        "tinyint" | "smallint" | "int" | "bigint" => {
+            let (min, max) = match data_type.as_str() {
+                "tinyint" => (0, u8::MAX as i128),
+                "smallint" => (i16::MIN as i128, i16::MAX as i128),
+                "int" => (i32::MIN as i128, i32::MAX as i128),
+                _ => (i64::MIN as i128, i64::MAX as i128),
+            };
+            let integer = types::plain_decimal(&text).filter(|p| !p.contains('.'));
+            match integer.as_deref().map(str::parse::<i128>) {
+                Some(Ok(n)) if n < min || n > max => problems.push(format!(
+                    "{}: {} is out of range for {}",
+                    col.name, text, data_type
+                )),
+                Some(Ok(_)) => {}
+                _ => problems.push(format!(
+                    "{}: {} is not a valid {}",
+                    col.name, text, data_type
+                )),
+            }
+        }
+        "decimal" | "numeric" | "money" | "smallmoney" => {
+            let plain = match data_type.as_str() {
+                "money" | "smallmoney" => types::normalize_param(&data_type, &col.name, &text).ok(),
+                _ => types::plain_decimal(&text),
+            };
+            let Some(plain) = plain else {
+                problems.push(format!(
+                    "{}: {} is not a valid {}",
+                    col.name, text, data_type
+                ));
+                return;
+            };
+            // Extra decimals are rounded; only the integer digits can overflow
+            let integer = plain
+                .trim_start_matches('-')
+                .split('.')
+                .next()
+                .unwrap_or("")
+                .trim_start_matches('0');
+            let digits = match data_type.as_str() {
+                "money" => 15,
+                "smallmoney" => 6,
+                _ => (col.precision.unwrap_or(18) - col.scale.unwrap_or(0)).max(0) as usize,
+            };
+            if integer.len() > digits {
+                problems.push(format!(
+                    "{}: {} is out of range for {}",
+                    col.name,
+                    text,
+                    col.type_decl()
+                ));
+            }
+        }
+        _ => {
+            if !types::value_fits_type(&data_type, &text) {
+                problems.push(format!(
+                    "{}: {} is not a valid {}",
+                    col.name, text, data_type
+                ));
+            }
+        }
+    }
+}
+
+/// Problems with one object against the configured rules. Absent and null
+/// values skip the column rules; NOT NULL is checked against the metadata.
+fn check_object(cfg: &TableConfig, obj: &Map<String, Value>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut columns: Vec<&String> = cfg.validate.keys().collect();
+    columns.sort();
+    for column in columns {
+        if let Some(value) = sent_value(obj, column) {
+            check_value(column, &cfg.validate[column], value, &mut problems);
+        }
+    }
+
+    for group in &cfg.required_together {
+        let missing: Vec<&str> = group
+            .iter()
+            .filter(|c| sent_value(obj, c).is_none())
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() && missing.len() < group.len() {
+            problems.push(format!(
+                "{}: must be sent together (missing {})",
+                group.join(", "),
+                missing.join(", ")
+            ));
+        }
+    }
+
+    problems
+}
+
+fn check_value(column: &str, rule: &ValidationRule, value: &Value, problems: &mut Vec<String>) {
+    if let Some(pattern) = &rule.pattern {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if !pattern.is_match(&text) {
+            problems.push(format!(
+                "{}: does not match pattern {}",
+                column,
+                pattern.as_str()
+            ));
+        }
+    }
+
+    if rule.min.is_none() && rule.max.is_none() {
+        return;
+    }
+    let number = match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    let Some(number) = number else {
+        problems.push(format!("{}: must be a number", column));
+        return;
+    };
+    if let Some(min) = rule.min.filter(|min| number < *min) {
+        problems.push(format!("{}: must be at least {}", column, min));
+    }
+    if let Some(max) = rule.max.filter(|max| number > *max) {
+        problems.push(format!("{}: must be at most {}", column, max));
+    }
+}
+
+/// A non-null value for the column (keys match case-insensitively, like
+/// SQL Server column names).
+fn sent_value<'a>(obj: &'a Map<String, Value>, column: &str) -> Option<&'a Value> {
+    obj.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(column))
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_null())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn column(data_type: &str) -> ColumnInfo {
+        ColumnInfo {
+            name: "c".to_string(),
+            data_type: data_type.to_string(),
+            max_length: None,
+            precision: None,
+            scale: None,
+            is_nullable: true,
+            ordinal_position: 1,
+            is_identity: false,
+            has_default: false,
+            is_computed: false,
+            is_fulltext_indexed: false,
+            collation: None,
+            description: None,
+        }
+    }
+
+    fn problems(col: &ColumnInfo, value: Value) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_column_value(col, &value, &mut problems);
+        problems
+    }
+
+    fn accepts(col: &ColumnInfo, values: &[Value]) {
+        for value in values {
+            assert!(
+                problems(col, value.clone()).is_empty(),
+                "{} rejected {}",
+                col.data_type,
+                value
+            );
+        }
+    }
+
+    fn rejects(col: &ColumnInfo, values: &[Value]) {
+        for value in values {
+            assert!(
+                !problems(col, value.clone()).is_empty(),
+                "{} accepted {}",
+                col.data_type,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_integers() {
+        let int = column("int");
+        accepts(
+            &int,
+            &[
+                json!(5),
+                json!(5.0),
+                json!("5"),
+                json!(" -7 "),
+                json!("5e2"),
+                json!("2147483647"),
+            ],
+        );
+        rejects(
+            &int,
+            &[
+                json!(5.5),
+                json!("abc"),
+                json!("2147483648"),
+                json!("1e10"),
+                json!(""),
+            ],
+        );
+        let tinyint = column("tinyint");
+        accepts(&tinyint, &[json!(0), json!(255)]);
+        rejects(&tinyint, &[json!(-1), json!(256)]);
+    }
+
+    #[test]
+    fn test_decimals() {
+        let mut decimal = column("decimal");
+        decimal.precision = Some(5);
+        decimal.scale = Some(2);
+        accepts(
+            &decimal,
+            &[
+                json!(123.45),
+                json!("-999.99"),
+                json!("1.5e2"),
+                json!("12345e-3"),
+                json!(1e2),
+            ],
+        );
+        rejects(
+            &decimal,
+            &[
+                json!("1000"),
+                json!("1e3"),
+                json!("1,5"),
+                json!("NaN"),
+                json!("inf"),
+            ],
+        );
+        let money = column("money");
+        accepts(&money, &[json!("1,234.56"), json!(19.99)]);
+        rejects(&money, &[json!("1.23456"), json!("abc")]);
+    }
+
+    #[test]
+    fn test_floats() {
+        let float = column("float");
+        accepts(&float, &[json!(1.5), json!("-2.5E-3")]);
+        rejects(&float, &[json!("inf"), json!("NaN"), json!("1.2.3")]);
+    }
+
+    #[test]
+    fn test_datetimes() {
+        for data_type in ["datetime", "datetime2", "smalldatetime", "datetimeoffset"] {
+            let col = column(data_type);
+            accepts(
+                &col,
+                &[
+                    json!("2024-06-01"),
+                    json!("2024-06-01 12:30"),
+                    json!("2024-06-01T12:30:45.123"),
+                    json!("2024-06-01T12:30:45.123Z"),
+                    json!("2024-06-01T12:30:45+02:00"),
+                ],
+            );
+            rejects(
+                &col,
+                &[
+                    json!("2024-02-30"),
+                    json!("2024-06-01T25:00"),
+                    json!("yesterday"),
+                ],
+            );
+        }
+        accepts(&column("date"), &[json!("2024-06-01")]);
+        rejects(&column("date"), &[json!("2024-13-01")]);
+        accepts(&column("time"), &[json!("12:30"), json!("12:30:45.5")]);
+        rejects(&column("time"), &[json!("12:60")]);
+    }
+
+    #[test]
+    fn test_strings() {
+        let mut nvarchar = column("nvarchar");
+        nvarchar.max_length = Some(3);
+        accepts(&nvarchar, &[json!("abc"), json!("\u{e9}\u{e9}\u{e9}")]);
+        rejects(&nvarchar, &[json!("abcd"), json!("\u{1F600}\u{1F600}")]);
+        let mut varchar_utf8 = column("varchar");
+        varchar_utf8.max_length = Some(3);
+        varchar_utf8.collation = Some("Latin1_General_100_CI_AS_SC_UTF8".to_string());
+        rejects(&varchar_utf8, &[json!("\u{e9}\u{e9}")]);
+    }
+}